@@ -0,0 +1,20 @@
+//! Regenerates the regression snapshots of the sheet generation tests in
+//! `tests/fixtures/snapshots`.
+//!
+//! `cargo run --example render --features render`
+
+use std::fs;
+
+use spritecollab_srv::assets::snapshots::{render_snapshots, snapshot_dir};
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let dir = snapshot_dir();
+    fs::create_dir_all(&dir)?;
+    for (name, png) in render_snapshots().await? {
+        let path = dir.join(name);
+        fs::write(&path, png)?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,303 @@
+//! Activities, ie. new or updated portraits and sprites of a form, found by comparing the
+//! trackers of two commits. They are sent as events, see [`crate::events`], and stored in the
+//! activity store, if it is enabled. The statistics queried from the store are defined here
+//! too, so the GraphQL schema is the same with and without the store.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::assets::fs_check::AssetCategory;
+use crate::datafiles::group_id::GroupId;
+use crate::datafiles::parse_credit_id;
+use crate::datafiles::tracker::{Group, MonsterFormCollector, Tracker};
+use crate::service::full_form_path;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Activity {
+    pub monster_id: i32,
+    /// Full path of the form, eg. `0025/0000/0001`.
+    pub form_path: String,
+    pub category: AssetCategory,
+    /// The primary credit ID of the portraits or sprites.
+    pub credit: String,
+    /// The other credit IDs of the portraits or sprites.
+    pub secondary_credits: Vec<String>,
+    pub modified_date: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ActivityIssueKind {
+    /// The portraits or sprites were modified, but nobody is credited for them.
+    MissingCredits,
+    /// The tracker of the commit could not be read, so its activities are unknown.
+    UnreadableTracker,
+}
+
+impl ActivityIssueKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActivityIssueKind::MissingCredits => "MissingCredits",
+            ActivityIssueKind::UnreadableTracker => "UnreadableTracker",
+        }
+    }
+}
+
+/// A change of a commit that could not be turned into an activity. It is stored alongside the
+/// activities of the commit instead of failing the whole commit.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ActivityIssue {
+    /// Full path of the form, empty if the issue is not about a form.
+    pub form_path: String,
+    pub category: Option<AssetCategory>,
+    pub kind: ActivityIssueKind,
+    pub message: String,
+}
+
+impl ActivityIssue {
+    pub fn unreadable_tracker(error: &anyhow::Error) -> Self {
+        Self {
+            form_path: String::new(),
+            category: None,
+            kind: ActivityIssueKind::UnreadableTracker,
+            message: error.to_string(),
+        }
+    }
+}
+
+/// How many activities a credit ID is credited for.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContributorStats {
+    pub credit_id: String,
+    pub portraits: i64,
+    pub sprites: i64,
+    pub last_activity: DateTime<Utc>,
+}
+
+/// An activity, as stored for one of the credit IDs credited for it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CreditActivity {
+    pub commit: String,
+    pub monster_id: i32,
+    pub form_path: String,
+    pub category: AssetCategory,
+    pub modified_date: DateTime<Utc>,
+    /// Whether the credit ID is the primary credit.
+    pub primary: bool,
+}
+
+/// An activity as returned by `GET /api/v1/activities`, see [`crate::api`], and sent to
+/// webhooks, see [`crate::webhooks`].
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedActivity {
+    pub commit: String,
+    pub monster_id: i32,
+    pub form_path: String,
+    pub category: AssetCategory,
+    pub credit: String,
+    pub secondary_credits: Vec<String>,
+    pub modified_date: DateTime<Utc>,
+}
+
+impl ExportedActivity {
+    pub fn new(commit: &str, activity: Activity) -> Self {
+        Self {
+            commit: commit.to_string(),
+            monster_id: activity.monster_id,
+            form_path: activity.form_path,
+            category: activity.category,
+            credit: activity.credit,
+            secondary_credits: activity.secondary_credits,
+            modified_date: activity.modified_date,
+        }
+    }
+}
+
+/// Position after an activity of a credit, for pagination. Activities are sorted by their
+/// modification date (newest first), then by commit, form path and category.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ActivityCursor {
+    /// The modification date as stored, see [`crate::activity_store`].
+    pub modified_date: String,
+    pub commit: String,
+    pub form_path: String,
+    pub category: String,
+}
+
+impl ActivityCursor {
+    const SEPARATOR: char = '|';
+
+    pub fn encode(&self) -> String {
+        [
+            &self.modified_date,
+            &self.commit,
+            &self.form_path,
+            &self.category,
+        ]
+        .map(String::as_str)
+        .join(&Self::SEPARATOR.to_string())
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let mut parts = cursor.split(Self::SEPARATOR).map(str::to_string);
+        let cursor = Self {
+            modified_date: parts.next()?,
+            commit: parts.next()?,
+            form_path: parts.next()?,
+            category: parts.next()?,
+        };
+        parts.next().is_none().then_some(cursor)
+    }
+}
+
+impl From<&CreditActivity> for ActivityCursor {
+    fn from(activity: &CreditActivity) -> Self {
+        Self {
+            modified_date: format_date(activity.modified_date),
+            commit: activity.commit.clone(),
+            form_path: activity.form_path.clone(),
+            category: activity.category.to_string(),
+        }
+    }
+}
+
+impl From<&ExportedActivity> for ActivityCursor {
+    fn from(activity: &ExportedActivity) -> Self {
+        Self {
+            modified_date: format_date(activity.modified_date),
+            commit: activity.commit.clone(),
+            form_path: activity.form_path.clone(),
+            category: activity.category.to_string(),
+        }
+    }
+}
+
+/// Formats a date as it is stored in the activity store: RFC 3339 in UTC, which sorts like the
+/// dates.
+pub fn format_date(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Activities in `new`, ie. forms whose portraits or sprites have a newer modification date
+/// than in `old`, or that are new.
+pub fn find_activities(old: &Tracker, new: &Tracker) -> Vec<Activity> {
+    let mut activities = Vec::new();
+    for group_id in new.keys() {
+        let monster_id = **group_id as i32;
+        let collector = match MonsterFormCollector::collect(new, monster_id) {
+            Some(collector) => collector,
+            None => continue,
+        };
+        for (path, group) in collector.map(|(path, _, group)| (path, group)) {
+            let old_group = find_group(old, monster_id, &path);
+            let categories = [
+                (
+                    AssetCategory::Portrait,
+                    group.portrait_modified,
+                    old_group.and_then(|g| g.portrait_modified),
+                    &group.portrait_credit,
+                ),
+                (
+                    AssetCategory::Sprite,
+                    group.sprite_modified,
+                    old_group.and_then(|g| g.sprite_modified),
+                    &group.sprite_credit,
+                ),
+            ];
+            for (category, modified, old_modified, credit) in categories {
+                let modified_date = match modified {
+                    Some(modified) if old_modified.is_none_or(|old| modified > old) => modified,
+                    _ => continue,
+                };
+                activities.push(Activity {
+                    monster_id,
+                    form_path: full_form_path(monster_id, &path),
+                    category,
+                    credit: parse_credit_id(&credit.primary),
+                    secondary_credits: credit.secondary.iter().map(parse_credit_id).collect(),
+                    modified_date,
+                });
+            }
+        }
+    }
+    activities
+}
+
+/// Separates the activities that can't be stored, because nobody is credited for them, as
+/// issues.
+pub fn separate_issues(activities: Vec<Activity>) -> (Vec<Activity>, Vec<ActivityIssue>) {
+    let (activities, missing_credits): (Vec<_>, Vec<_>) = activities
+        .into_iter()
+        .partition(|activity| !activity.credit.is_empty());
+    let issues = missing_credits
+        .into_iter()
+        .map(|activity| ActivityIssue {
+            message: format!(
+                "The {} of {} were modified on {}, but have no primary credit.",
+                match activity.category {
+                    AssetCategory::Portrait => "portraits",
+                    AssetCategory::Sprite => "sprites",
+                },
+                activity.form_path,
+                format_date(activity.modified_date)
+            ),
+            form_path: activity.form_path,
+            category: Some(activity.category),
+            kind: ActivityIssueKind::MissingCredits,
+        })
+        .collect();
+    (activities, issues)
+}
+
+fn find_group<'a>(tracker: &'a Tracker, monster_id: i32, path: &[i32]) -> Option<&'a Group> {
+    let mut group = tracker.get(&GroupId(monster_id as i64))?;
+    for idx in path {
+        group = group.subgroups.get(&GroupId(*idx as i64))?;
+    }
+    Some(group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SyntheticRepo;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[test]
+    fn finds_activities_of_commits() {
+        let repo = SyntheticRepo::create("scsrv-activity-test");
+        let initial = repo.tracker();
+        // The sample data has no modification dates.
+        assert!(find_activities(&Tracker::default(), &initial).is_empty());
+
+        repo.edit_tracker(|tracker| {
+            tracker["0001"]["portrait_modified"] = json!("2023-11-15 10:00:00");
+            tracker["0001"]["portrait_credit"] =
+                json!({"primary": "FixtureAuthor", "secondary": ["Helper"], "total": 2});
+            tracker["0001"]["sprite_modified"] = json!("2023-11-15 11:00:00");
+        });
+        repo.commit("Update the portraits and sprites of 0001");
+        let updated = repo.tracker();
+        let (activities, issues) = separate_issues(find_activities(&initial, &updated));
+        assert_eq!(
+            activities,
+            vec![Activity {
+                monster_id: 1,
+                form_path: "0001".to_string(),
+                category: AssetCategory::Portrait,
+                credit: "FixtureAuthor".to_string(),
+                secondary_credits: vec!["Helper".to_string()],
+                modified_date: Utc.with_ymd_and_hms(2023, 11, 15, 10, 0, 0).unwrap(),
+            }]
+        );
+        // Nobody is credited for the sprites.
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].kind, ActivityIssueKind::MissingCredits);
+        assert_eq!(issues[0].category, Some(AssetCategory::Sprite));
+
+        repo.edit_tracker(|tracker| tracker["0001"]["name"] = json!("Renamed"));
+        repo.commit("Rename 0001");
+        assert!(find_activities(&updated, &repo.tracker()).is_empty());
+    }
+}
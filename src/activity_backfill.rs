@@ -0,0 +1,317 @@
+//! `spritecollab-srv backfill-activities [--after <commit>] [--jobs <n>]`: Walks the history of
+//! the repository in the workdir, oldest commit first, and stores the activities of every commit
+//! in the activity store. Commits that are already stored are skipped, so it can be run again,
+//! eg. after it was interrupted. With `--after`, it resumes after the given commit.
+//!
+//! Reading the trackers takes most of the time, so the trackers of a batch of commits are read
+//! on `--jobs` threads (default: the number of CPUs), each with its own repository, while the
+//! activities of the previous batch are stored. The activities are still stored in the order of
+//! the commits.
+//!
+//! Only available with the `activity-store` feature.
+
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, available_parallelism};
+
+use anyhow::{anyhow, Error};
+use chrono::DateTime;
+use git2::{Oid, Repository, Sort};
+use log::{info, warn};
+use tokio::task::{spawn_blocking, JoinHandle};
+
+use crate::activity::{find_activities, separate_issues, ActivityIssue};
+use crate::activity_exceptions::apply_credit_exceptions;
+use crate::activity_store::ActivityStore;
+use crate::datafiles::tracker::Tracker;
+use crate::ServerConfig;
+
+/// Progress is logged every this many commits.
+const PROGRESS_INTERVAL: usize = 100;
+/// Each thread reads the trackers of this many commits per batch.
+const COMMITS_PER_JOB: usize = 4;
+const USAGE: &str = "Usage: spritecollab-srv backfill-activities [--after <commit>] [--jobs <n>]";
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct BackfillArgs {
+    /// The commit to resume after.
+    pub after: Option<Oid>,
+    /// Number of threads the trackers are read on.
+    pub jobs: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct BackfillStats {
+    /// Commits that were stored.
+    pub commits: usize,
+    /// Commits that were already stored.
+    pub skipped: usize,
+    /// Commits whose tracker could not be read. They are stored with an issue.
+    pub failed: usize,
+    pub activities: usize,
+    /// Changes that are stored as issues, see [`ActivityIssue`].
+    pub issues: usize,
+    /// Activities whose credit was corrected, see [`crate::activity_exceptions`].
+    pub corrected: usize,
+}
+
+/// Parses the arguments after `backfill-activities`.
+pub fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<BackfillArgs, Error> {
+    let mut parsed = BackfillArgs {
+        after: None,
+        jobs: available_parallelism().map_or(1, NonZeroUsize::get),
+    };
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--after", Some(commit)) => parsed.after = Some(Oid::from_str(&commit)?),
+            ("--jobs", Some(jobs)) => {
+                parsed.jobs = jobs
+                    .parse()
+                    .ok()
+                    .filter(|jobs| *jobs > 0)
+                    .ok_or_else(|| anyhow!(USAGE))?
+            }
+            _ => return Err(anyhow!(USAGE)),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Stores the activities of all commits on the first-parent history of `HEAD`, or of the
+/// commits after `args.after`. Fails on shallow clones, whose history is incomplete.
+pub async fn backfill_activities(
+    store: &ActivityStore,
+    repo_path: &Path,
+    args: BackfillArgs,
+) -> Result<BackfillStats, Error> {
+    let repo = Repository::open(repo_path)?;
+    // The activities of the missing commits would be lost, and resuming with `--after` would
+    // never add them.
+    if repo.is_shallow() {
+        return Err(anyhow!(
+            "The repository at {} is a shallow clone without the full history. Unshallow it with `git fetch --unshallow`, or delete it so the server clones it in full (it ignores SCSRV_GIT_CLONE_DEPTH with the activity store).",
+            repo_path.display()
+        ));
+    }
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.simplify_first_parent()?;
+    walk.push_head()?;
+    let oids = walk.collect::<Result<Vec<_>, _>>()?;
+    let oids = match args.after {
+        Some(after) => {
+            let position = oids
+                .iter()
+                .position(|oid| *oid == after)
+                .ok_or_else(|| anyhow!("{} is not in the history of HEAD.", after))?;
+            oids[position..].to_vec()
+        }
+        None => oids,
+    };
+
+    let mut stats = BackfillStats::default();
+    let exceptions = &ServerConfig::get().activity_credit_exceptions;
+    let empty_tracker = Tracker::default();
+    let mut base: Option<Tracker> = None;
+    let mut previous_oid: Option<Oid> = None;
+    let total = oids.len();
+    let mut done = 0;
+    let mut batches = oids
+        .chunks(args.jobs * COMMITS_PER_JOB)
+        .map(<[Oid]>::to_vec);
+    let mut pending = batches
+        .next()
+        .map(|batch| spawn_read_batch(repo_path.to_path_buf(), batch, None, args.jobs));
+    while let Some(read) = pending.take() {
+        let (commits, last_tracker_id) = read.await??;
+        // The next batch is read while this one is stored.
+        pending = batches.next().map(|batch| {
+            spawn_read_batch(repo_path.to_path_buf(), batch, last_tracker_id, args.jobs)
+        });
+        for commit in commits {
+            if done > 0 && done % PROGRESS_INTERVAL == 0 {
+                // The last commit can be passed to `--after` to resume from here.
+                info!(
+                    "Backfilled {}/{} commits, up to {}: {} stored, {} already stored, {} failed, {} activities.",
+                    done,
+                    total,
+                    previous_oid.map(|oid| oid.to_string()).unwrap_or_default(),
+                    stats.commits,
+                    stats.skipped,
+                    stats.failed,
+                    stats.activities
+                );
+            }
+            done += 1;
+            let oid = commit.oid;
+            // The commit `after` was already stored, it is only the base of the next commit.
+            if Some(oid) != args.after {
+                if store.has_commit(&oid.to_string()).await? {
+                    stats.skipped += 1;
+                } else {
+                    let (activities, issues) = match &commit.tracker {
+                        Ok(None) => (Vec::new(), Vec::new()),
+                        Ok(Some(tracker)) => {
+                            let mut activities =
+                                find_activities(base.as_ref().unwrap_or(&empty_tracker), tracker);
+                            stats.corrected += apply_credit_exceptions(
+                                exceptions,
+                                &oid.to_string(),
+                                &mut activities,
+                            );
+                            separate_issues(activities)
+                        }
+                        Err(e) => {
+                            warn!("Failed reading the tracker of {}: {}", oid, e);
+                            stats.failed += 1;
+                            (Vec::new(), vec![ActivityIssue::unreadable_tracker(e)])
+                        }
+                    };
+                    let date = DateTime::from_timestamp(commit.time, 0)
+                        .ok_or_else(|| anyhow!("Invalid date of commit {}.", oid))?;
+                    store
+                        .record_commit(
+                            &oid.to_string(),
+                            previous_oid.map(|oid| oid.to_string()).as_deref(),
+                            date,
+                            &activities,
+                            &issues,
+                        )
+                        .await?;
+                    stats.commits += 1;
+                    stats.activities += activities.len();
+                    stats.issues += issues.len();
+                }
+            }
+            // The next commit is compared to the last tracker that could be read.
+            if let Ok(Some(tracker)) = commit.tracker {
+                base = Some(tracker);
+            }
+            previous_oid = Some(oid);
+        }
+    }
+    info!(
+        "Backfill done: {} commits stored, {} already stored, {} failed, {} activities ({} with corrected credits), {} issues.",
+        stats.commits, stats.skipped, stats.failed, stats.activities, stats.corrected, stats.issues
+    );
+    Ok(stats)
+}
+
+/// A commit of a batch, with its tracker.
+struct CommitTracker {
+    oid: Oid,
+    /// The commit time, in seconds since the epoch.
+    time: i64,
+    /// `None` if the tracker is the same as in the commit before.
+    tracker: Result<Option<Tracker>, Error>,
+}
+
+type BatchRead = JoinHandle<Result<(Vec<CommitTracker>, Option<Oid>), Error>>;
+
+/// Reads the trackers of a batch of commits in the background, see [`read_batch`].
+fn spawn_read_batch(
+    repo_path: PathBuf,
+    oids: Vec<Oid>,
+    previous_tracker_id: Option<Oid>,
+    jobs: usize,
+) -> BatchRead {
+    spawn_blocking(move || read_batch(&repo_path, &oids, previous_tracker_id, jobs))
+}
+
+/// Reads the trackers of `oids` on up to `jobs` threads. Only trackers that differ from the one
+/// of the commit before are read, `previous_tracker_id` is the ID of the tracker of the commit
+/// before the first one. Returns the commits in the order of `oids`, and the ID of the last
+/// tracker.
+fn read_batch(
+    repo_path: &Path,
+    oids: &[Oid],
+    mut previous_tracker_id: Option<Oid>,
+    jobs: usize,
+) -> Result<(Vec<CommitTracker>, Option<Oid>), Error> {
+    let repo = Repository::open(repo_path)?;
+    let mut commits = Vec::with_capacity(oids.len());
+    // Looking up the IDs of the trackers is cheap, parsing them is not.
+    let mut to_read = Vec::new();
+    for oid in oids {
+        let commit = repo.find_commit(*oid)?;
+        let tracker = match commit.tree()?.get_path(Path::new("tracker.json")) {
+            Ok(entry) if Some(entry.id()) == previous_tracker_id => Ok(None),
+            Ok(entry) => {
+                to_read.push((commits.len(), entry.id()));
+                previous_tracker_id = Some(entry.id());
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        };
+        commits.push(CommitTracker {
+            oid: *oid,
+            time: commit.time().seconds(),
+            tracker,
+        });
+    }
+
+    let next = AtomicUsize::new(0);
+    let read = Mutex::new(Vec::with_capacity(to_read.len()));
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(to_read.len()) {
+            scope.spawn(|| {
+                // git2 objects can't be shared between threads.
+                let repo = Repository::open(repo_path);
+                while let Some((idx, tracker_id)) =
+                    to_read.get(next.fetch_add(1, Ordering::Relaxed))
+                {
+                    let tracker = match &repo {
+                        Ok(repo) => read_tracker_blob(repo, *tracker_id),
+                        Err(e) => Err(anyhow!("Failed opening the repository: {}", e)),
+                    };
+                    read.lock().unwrap().push((*idx, tracker));
+                }
+            });
+        }
+    });
+    for (idx, tracker) in read.into_inner().unwrap() {
+        commits[idx].tracker = tracker.map(Some);
+    }
+    Ok((commits, previous_tracker_id))
+}
+
+fn read_tracker_blob(repo: &Repository, id: Oid) -> Result<Tracker, Error> {
+    Ok(serde_json::from_slice(repo.find_blob(id)?.content())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datafiles::group_id::GroupId;
+    use crate::testing::SyntheticRepo;
+    use serde_json::json;
+
+    #[test]
+    fn reads_changed_trackers() {
+        let repo = SyntheticRepo::create("scsrv-backfill-test");
+        let initial = repo.head();
+        let unchanged = repo.commit("Nothing changed");
+        repo.edit_tracker(|tracker| tracker["0001"]["name"] = json!("Renamed"));
+        let renamed = repo.commit("Rename 0001");
+        repo.write("tracker.json", "{");
+        let broken = repo.commit("Break the tracker");
+
+        let (commits, last_tracker_id) =
+            read_batch(repo.path(), &[initial, unchanged, renamed, broken], None, 2).unwrap();
+        assert_eq!(
+            commits.iter().map(|commit| commit.oid).collect::<Vec<_>>(),
+            vec![initial, unchanged, renamed, broken]
+        );
+        assert!(matches!(commits[0].tracker, Ok(Some(_))));
+        assert!(matches!(commits[1].tracker, Ok(None)));
+        match &commits[2].tracker {
+            Ok(Some(tracker)) => assert_eq!(tracker.get(&GroupId(1)).unwrap().name, "Renamed"),
+            _ => panic!("The tracker of {} was not read.", renamed),
+        }
+        assert!(commits[3].tracker.is_err());
+        assert_eq!(commits[3].time - commits[0].time, 3 * 60);
+        assert!(last_tracker_id.is_some());
+    }
+}
@@ -0,0 +1,138 @@
+//! Corrections of the credits of historical activities, read at startup from the JSON file
+//! configured with `SCSRV_ACTIVITY_CREDIT_EXCEPTIONS`. Some old commits credit the wrong
+//! author in the tracker, or nobody at all. Each exception replaces the primary credit of the
+//! activities of a commit, optionally only of a form or category:
+//!
+//! ```json
+//! [
+//!   { "commit": "<commit>", "formPath": "0025/0000/0001", "category": "Portrait", "credit": "<credit ID>", "note": "..." }
+//! ]
+//! ```
+//!
+//! The loaded exceptions are returned by the admin API at
+//! `GET /admin/activity/credit_exceptions`.
+
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::activity::Activity;
+use crate::assets::fs_check::AssetCategory;
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CreditException {
+    /// The full ID of the commit.
+    pub commit: String,
+    /// Full path of the form, eg. `0025/0000/0001`. All forms if not set.
+    #[serde(default)]
+    pub form_path: Option<String>,
+    /// Both categories if not set.
+    #[serde(default)]
+    pub category: Option<AssetCategory>,
+    /// The credit ID that is credited instead.
+    pub credit: String,
+    /// Why the exception exists.
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+impl CreditException {
+    fn matches(&self, commit: &str, activity: &Activity) -> bool {
+        self.commit == commit
+            && self
+                .form_path
+                .as_ref()
+                .is_none_or(|form_path| *form_path == activity.form_path)
+            && self
+                .category
+                .is_none_or(|category| category == activity.category)
+    }
+}
+
+/// Reads the exceptions from a JSON file.
+pub fn read_credit_exceptions(path: &Path) -> Result<Vec<CreditException>, String> {
+    let content =
+        fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let exceptions: Vec<CreditException> = serde_json::from_slice(&content)
+        .map_err(|e| format!("could not parse {}: {}", path.display(), e))?;
+    for exception in &exceptions {
+        if exception.commit.len() != 40 || !exception.commit.bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            return Err(format!(
+                "'{}' in {} is not the full ID of a commit",
+                exception.commit,
+                path.display()
+            ));
+        }
+    }
+    Ok(exceptions)
+}
+
+/// Replaces the primary credit of the activities of `commit` that have an exception. The first
+/// matching exception wins. Returns the number of corrected activities.
+pub fn apply_credit_exceptions(
+    exceptions: &[CreditException],
+    commit: &str,
+    activities: &mut [Activity],
+) -> usize {
+    let mut corrected = 0;
+    for activity in activities {
+        if let Some(exception) = exceptions.iter().find(|e| e.matches(commit, activity)) {
+            activity.credit = exception.credit.clone();
+            corrected += 1;
+        }
+    }
+    corrected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::unique_temp_path;
+    use chrono::Utc;
+
+    #[test]
+    fn corrects_matching_activities() {
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let path = unique_temp_path("scsrv-credit-exceptions-test").with_extension("json");
+        fs::write(
+            &path,
+            format!(
+                r#"[{{"commit": "{}", "category": "Sprite", "credit": "1234"}}]"#,
+                commit
+            ),
+        )
+        .unwrap();
+        let exceptions = read_credit_exceptions(&path).unwrap();
+        let portrait = Activity {
+            monster_id: 25,
+            form_path: "0025".to_string(),
+            category: AssetCategory::Portrait,
+            credit: String::new(),
+            secondary_credits: vec![],
+            modified_date: Utc::now(),
+        };
+        let sprite = Activity {
+            category: AssetCategory::Sprite,
+            ..portrait.clone()
+        };
+        let mut activities = vec![portrait, sprite];
+
+        assert_eq!(
+            apply_credit_exceptions(&exceptions, "abc", &mut activities),
+            0
+        );
+        assert_eq!(
+            apply_credit_exceptions(&exceptions, commit, &mut activities),
+            1
+        );
+        assert_eq!(activities[0].credit, "");
+        assert_eq!(activities[1].credit, "1234");
+
+        fs::write(&path, r#"[{"commit": "abc", "credit": "1234"}]"#).unwrap();
+        assert!(read_credit_exceptions(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
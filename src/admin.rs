@@ -0,0 +1,146 @@
+//! Admin API for operators, protected by the token configured with `SCSRV_ADMIN_TOKEN`
+//! (`Authorization: Bearer <token>`). It is disabled if no token is configured.
+//!
+//! - `GET /admin/cache/keys?prefix=<prefix>`: All cache keys starting with the prefix.
+//! - `DELETE /admin/cache/keys/{key}`: Evicts a single cache entry, eg. a stale sheet, so it
+//!   is generated again on the next request. The key is percent-encoded.
+//! - `GET /admin/api_keys`: The usage counters of the API keys, by name.
+//! - `GET /admin/activity/credit_exceptions`: The loaded corrections of activity credits, see
+//!   [`crate::activity_exceptions`].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hyper::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::http::HeaderValue;
+use hyper::{HeaderMap, Method, Response, StatusCode};
+use log::{info, warn};
+use percent_encoding::percent_decode_str;
+use serde_json::json;
+
+use crate::cache::ScCache;
+use crate::graphql::make_json_response;
+use crate::schema::ErrorCode;
+use crate::{ServerConfig, SpriteCollab};
+
+/// Prefix of all paths of the admin API.
+pub const ADMIN_PREFIX: &str = "/admin/";
+const CACHE_KEYS_PATH: &str = "/admin/cache/keys";
+const API_KEYS_PATH: &str = "/admin/api_keys";
+const CREDIT_EXCEPTIONS_PATH: &str = "/admin/activity/credit_exceptions";
+
+/// Handles a request to a path starting with [`ADMIN_PREFIX`].
+pub async fn make_admin_response(
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    request_headers: &HeaderMap,
+    sprite_collab: Arc<SpriteCollab>,
+) -> Response<String> {
+    let token = match &ServerConfig::get().admin_token {
+        Some(token) => token,
+        None => return make_admin_error_response(StatusCode::NOT_FOUND, None, "Not found."),
+    };
+    if !is_authorized(request_headers, token) {
+        let mut response = make_admin_error_response(
+            StatusCode::UNAUTHORIZED,
+            None,
+            "Missing or invalid admin token.",
+        );
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        return response;
+    }
+
+    let path = path.trim_end_matches('/');
+    match (method, path) {
+        (&Method::GET, CACHE_KEYS_PATH) => {
+            let query: HashMap<String, String> =
+                form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                    .into_owned()
+                    .collect();
+            let prefix = query.get("prefix").map(String::as_str).unwrap_or_default();
+            match sprite_collab.cache_keys(prefix).await {
+                Ok(keys) => make_json_response(StatusCode::OK, json!({ "keys": keys }).to_string()),
+                Err(e) => cache_unavailable(e),
+            }
+        }
+        (&Method::DELETE, path) if path.starts_with(CACHE_KEYS_PATH) => {
+            let raw_key = path[CACHE_KEYS_PATH.len()..].trim_start_matches('/');
+            let key = match percent_decode_str(raw_key).decode_utf8() {
+                Ok(key) if !key.is_empty() => key,
+                _ => {
+                    return make_admin_error_response(
+                        StatusCode::BAD_REQUEST,
+                        Some(ErrorCode::InvalidArgument),
+                        "Invalid cache key.",
+                    )
+                }
+            };
+            match sprite_collab.evict(&key).await {
+                Ok(true) => {
+                    info!("Evicted cache entry '{}'.", key);
+                    make_json_response(StatusCode::OK, json!({ "evicted": key }).to_string())
+                }
+                Ok(false) => make_admin_error_response(
+                    StatusCode::NOT_FOUND,
+                    Some(ErrorCode::NotFound),
+                    "The cache key does not exist.",
+                ),
+                Err(e) => cache_unavailable(e),
+            }
+        }
+        (&Method::GET, API_KEYS_PATH) => make_json_response(
+            StatusCode::OK,
+            json!({ "apiKeys": sprite_collab.api_key_usage().stats() }).to_string(),
+        ),
+        (&Method::GET, CREDIT_EXCEPTIONS_PATH) => make_json_response(
+            StatusCode::OK,
+            json!({ "creditExceptions": ServerConfig::get().activity_credit_exceptions })
+                .to_string(),
+        ),
+        _ => make_admin_error_response(StatusCode::NOT_FOUND, None, "Unknown admin endpoint."),
+    }
+}
+
+/// Compares the bearer token in constant time, so it can't be guessed from the response
+/// times.
+fn is_authorized(request_headers: &HeaderMap, token: &str) -> bool {
+    let given = request_headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    match given {
+        Some(given) if given.len() == token.len() => {
+            given
+                .bytes()
+                .zip(token.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+        }
+        _ => false,
+    }
+}
+
+fn cache_unavailable(e: anyhow::Error) -> Response<String> {
+    warn!("Admin API: Redis request failed: {:?}", e);
+    make_admin_error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        Some(ErrorCode::CacheUnavailable),
+        "The cache could not be reached.",
+    )
+}
+
+fn make_admin_error_response(
+    status: StatusCode,
+    code: Option<ErrorCode>,
+    message: &str,
+) -> Response<String> {
+    let body = match code {
+        Some(code) => json!({ "message": message, "extensions": { "code": code.as_str() } }),
+        None => json!({ "message": message }),
+    };
+    make_json_response(status, body.to_string())
+}
@@ -0,0 +1,605 @@
+//! REST API for clients that can not easily use GraphQL, eg. game mods and scripts. It returns
+//! the same data as the GraphQL API (resolved by [`crate::service`]) as JSON:
+//!
+//! - `GET /api/v1/monster/{id}`: A monster and a summary of all of its forms.
+//! - `GET /api/v1/monster/{id}/form/{path}`: A single form with its portraits and sprites, eg.
+//!   `/api/v1/monster/25/form/0000/0001`. Without the path, the base form.
+//! - `GET /api/v1/monster/{id}/credits`: All contributors to the forms of a monster.
+//! - `GET /api/v1/credits`: All entries of the credit names.
+//! - `GET /api/v1/credits/export?format=csv|json&monster=<id>`: The contributions to the forms
+//!   of a monster or of all monsters, with the credit names of their authors, for attribution
+//!   files.
+//! - `GET /api/v1/portrait_sheet_layout`: Which emotion is at which position in the portrait
+//!   sheets.
+//! - `GET /api/v1/activities?since=<date>&monster=<id>&limit=<n>&after=<cursor>`: Activities
+//!   from the activity store, oldest first, so clients like SpriteBot can poll for new
+//!   contributions. Only available if the server stores activities.
+//!
+//! Besides that, `GET /api/tracker.json` returns the tracker in the format of SpriteBot.
+//!
+//! Field names are the same as in the GraphQL schema. Errors are returned as
+//! `{"message": ..., "extensions": {"code": ...}}` with the same error codes.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use hyper::header::CONTENT_TYPE;
+use hyper::http::HeaderValue;
+use hyper::{Response, StatusCode};
+use juniper::{graphql_value, FieldError, FieldResult};
+use once_cell::sync::OnceCell;
+use route_recognizer::Router;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::activity::{ActivityCursor, ExportedActivity};
+use crate::assets::url::{get_url, AssetType};
+use crate::assets::util::parse_query;
+use crate::datafiles::group_id::GroupId;
+use crate::datafiles::tracker::{FormMatch, Group, MapImpl, MonsterFormCollector};
+use crate::graphql::make_json_response;
+use crate::openapi::RouteParam;
+#[cfg(not(feature = "activity-store"))]
+use crate::schema::activity_store_unavailable;
+#[cfg(feature = "activity-store")]
+use crate::schema::{activity_store, activity_store_failed};
+use crate::schema::{
+    Context, Credit, ErrorCode, MonsterBounty, MonsterForm, Phase, Portrait, PortraitSheetLayout,
+    SpriteUnion,
+};
+use crate::service;
+use crate::SpriteCollab;
+
+/// Prefix of all paths of the current version of the REST API.
+pub const API_PREFIX: &str = "/api/v1/";
+/// Path of the export of the tracker.
+pub const TRACKER_EXPORT_PATH: &str = "/api/tracker.json";
+/// Response header with the commit the data is read from, the same as `assetsCommit` of `meta`.
+pub const COMMIT_HEADER: &str = "X-SC-Commit";
+
+#[derive(Clone, Copy, Debug)]
+pub enum ApiEndpoint {
+    Monster,
+    MonsterForm,
+    MonsterCredits,
+    Credits,
+    CreditsExport,
+    PortraitSheetLayout,
+    Activities,
+}
+
+/// A route of the REST API. Used to match request paths and to generate the OpenAPI document.
+pub struct ApiRoute {
+    /// Path pattern in `route_recognizer` syntax.
+    pub pattern: &'static str,
+    pub endpoint: ApiEndpoint,
+    pub summary: &'static str,
+    pub path_params: &'static [RouteParam],
+    pub query_params: &'static [RouteParam],
+}
+
+const MONSTER_ID_PARAM: RouteParam = RouteParam {
+    name: "id",
+    description: "The ID of the monster, eg. 25.",
+};
+
+static API_ROUTER: OnceCell<Router<ApiEndpoint>> = OnceCell::new();
+
+/// All routes of the REST API.
+pub const API_ROUTES: &[ApiRoute] = &[
+    ApiRoute {
+        pattern: "/api/v1/monster/:id",
+        endpoint: ApiEndpoint::Monster,
+        summary: "A monster and a summary of all of its forms.",
+        path_params: &[MONSTER_ID_PARAM],
+        query_params: &[],
+    },
+    ApiRoute {
+        pattern: "/api/v1/monster/:id/form",
+        endpoint: ApiEndpoint::MonsterForm,
+        summary: "The base form of a monster with its portraits and sprites.",
+        path_params: &[MONSTER_ID_PARAM],
+        query_params: &[],
+    },
+    ApiRoute {
+        pattern: "/api/v1/monster/:id/form/*path",
+        endpoint: ApiEndpoint::MonsterForm,
+        summary: "A single form of a monster with its portraits and sprites.",
+        path_params: &[
+            MONSTER_ID_PARAM,
+            RouteParam {
+                name: "path",
+                description:
+                    "The path of the form (without the monster ID), seperated by /, eg. 0000/0001.",
+            },
+        ],
+        query_params: &[],
+    },
+    ApiRoute {
+        pattern: "/api/v1/monster/:id/credits",
+        endpoint: ApiEndpoint::MonsterCredits,
+        summary: "All contributors to the portraits and sprites of all forms of a monster, with the forms they contributed to.",
+        path_params: &[MONSTER_ID_PARAM],
+        query_params: &[],
+    },
+    ApiRoute {
+        pattern: "/api/v1/credits",
+        endpoint: ApiEndpoint::Credits,
+        summary: "All entries of the credit names.",
+        path_params: &[],
+        query_params: &[],
+    },
+    ApiRoute {
+        pattern: "/api/v1/credits/export",
+        endpoint: ApiEndpoint::CreditsExport,
+        summary: "The contributions to the current portraits and sprites of all forms, with the credit names of their authors, for attribution files. Obsolete contributions are not included.",
+        path_params: &[],
+        query_params: &[
+            RouteParam {
+                name: "format",
+                description: "csv or json (default).",
+            },
+            RouteParam {
+                name: "monster",
+                description: "Only the forms of the monster with this ID.",
+            },
+        ],
+    },
+    ApiRoute {
+        pattern: "/api/v1/portrait_sheet_layout",
+        endpoint: ApiEndpoint::PortraitSheetLayout,
+        summary: "The layout of the portrait sheets: Tile size, sheet dimensions and which emotion is at which tile.",
+        path_params: &[],
+        query_params: &[],
+    },
+    ApiRoute {
+        pattern: "/api/v1/activities",
+        endpoint: ApiEndpoint::Activities,
+        summary: "Activities (added or updated portraits or sprites of a form), oldest first. Only available if the server stores activities.",
+        path_params: &[],
+        query_params: &[
+            RouteParam {
+                name: "since",
+                description: "Only activities modified after this date (RFC 3339).",
+            },
+            RouteParam {
+                name: "monster",
+                description: "Only activities of the monster with this ID.",
+            },
+            RouteParam {
+                name: "limit",
+                description: "Number of activities to return (default: 100, at most 1000).",
+            },
+            RouteParam {
+                name: "after",
+                description: "Only activities after the activity with this cursor, the nextCursor of the previous page.",
+            },
+        ],
+    },
+];
+
+/// Default and maximum number of activities returned by `/api/v1/activities`.
+const DEFAULT_ACTIVITIES_LIMIT: i64 = 100;
+const MAX_ACTIVITIES_LIMIT: i64 = 1000;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiMonster {
+    id: i32,
+    raw_id: String,
+    name: String,
+    last_modified: Option<DateTime<Utc>>,
+    forms: Vec<ApiFormSummary>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiFormSummary {
+    monster_id: i32,
+    path: String,
+    full_path: String,
+    name: String,
+    full_name: String,
+    is_shiny: bool,
+    is_female: bool,
+    canon: bool,
+    preview_url: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiForm {
+    #[serde(flatten)]
+    summary: ApiFormSummary,
+    portraits: ApiPortraits,
+    sprites: ApiSprites,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiPortraits {
+    required: bool,
+    bounty: MonsterBounty,
+    phase: Phase,
+    phase_raw: i32,
+    credit_primary: Option<Credit>,
+    credit_secondary: Vec<Credit>,
+    sheet_url: String,
+    recolor_sheet_url: String,
+    annotated_sheet_url: String,
+    zip_url: Option<String>,
+    emotions: Vec<Portrait>,
+    emotions_flipped: Vec<Portrait>,
+    modified_date: Option<DateTime<Utc>>,
+    history_url: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiSprites {
+    required: bool,
+    bounty: MonsterBounty,
+    phase: Phase,
+    phase_raw: i32,
+    credit_primary: Option<Credit>,
+    credit_secondary: Vec<Credit>,
+    anim_data_xml: Option<String>,
+    zip_url: Option<String>,
+    recolor_sheet_url: Option<String>,
+    actions: Vec<SpriteUnion>,
+    modified_date: Option<DateTime<Utc>>,
+    history_url: String,
+}
+
+/// The body of a successful response.
+enum ApiBody {
+    Json(String),
+    Csv(String),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiActivities {
+    activities: Vec<ExportedActivity>,
+    /// Pass as `after` to get the next page. `None` if this is the last page.
+    next_cursor: Option<String>,
+}
+
+/// Handles a `GET` request to a path starting with [`API_PREFIX`].
+pub async fn make_api_response(
+    path: &str,
+    query: Option<&str>,
+    sprite_collab: Arc<SpriteCollab>,
+) -> Response<String> {
+    let context = Context::new(sprite_collab);
+    match route(&context, path, query).await {
+        Ok(ApiBody::Json(body)) => make_json_response(StatusCode::OK, body),
+        Ok(ApiBody::Csv(body)) => make_csv_response(body),
+        Err(e) => make_api_error_response(e),
+    }
+}
+
+async fn route(context: &Context, path: &str, query: Option<&str>) -> FieldResult<ApiBody> {
+    let router = API_ROUTER.get_or_init(|| {
+        let mut router = Router::new();
+        for route in API_ROUTES {
+            router.add(route.pattern, route.endpoint);
+        }
+        router
+    });
+    let m = router.recognize(path.trim_end_matches('/')).map_err(|_| {
+        ErrorCode::NotFound.error("Unknown API endpoint.", graphql_value!({ "path": path }))
+    })?;
+    let params = m.params();
+    let json = match m.handler() {
+        ApiEndpoint::Monster => to_json(monster(context, parse_monster_id(&params["id"])?)?),
+        ApiEndpoint::MonsterForm => {
+            // The base form has no path.
+            let form_path = params.find("path").unwrap_or_default();
+            to_json(form(context, parse_monster_id(&params["id"])?, form_path).await?)
+        }
+        ApiEndpoint::MonsterCredits => {
+            to_json(service::monster_contributors(context, parse_monster_id(&params["id"])?).await?)
+        }
+        ApiEndpoint::Credits => to_json(service::all_credits(&context.collab)),
+        ApiEndpoint::CreditsExport => return credits_export(context, query).await,
+        ApiEndpoint::PortraitSheetLayout => to_json(PortraitSheetLayout::new(
+            &context.collab.data().sprite_config,
+        )),
+        ApiEndpoint::Activities => to_json(activities(context, query).await?),
+    };
+    json.map(ApiBody::Json)
+}
+
+/// Responds with the tracker in the format of SpriteBot's `tracker.json`, from the same data
+/// that the rest of the API serves, so it matches the commit in the [`COMMIT_HEADER`]. It can be
+/// limited to some monsters with `?monsters=1,25`.
+pub fn make_tracker_export_response(
+    query: Option<&str>,
+    sprite_collab: &SpriteCollab,
+) -> Response<String> {
+    let (tracker, assets_commit) = {
+        let data = sprite_collab.data();
+        (data.tracker.clone(), data.assets_commit.clone())
+    };
+    let query = parse_query(query);
+    let body = match query.get("monsters") {
+        Some(monsters) => parse_monster_ids(monsters).and_then(|monster_ids| {
+            let filtered: MapImpl<&GroupId, &Group> = tracker
+                .iter()
+                .filter(|(group_id, _)| monster_ids.contains(group_id))
+                .collect();
+            to_json(filtered)
+        }),
+        None => to_json(&*tracker),
+    };
+    let mut response = match body {
+        Ok(body) => make_json_response(StatusCode::OK, body),
+        Err(e) => return make_api_error_response(e),
+    };
+    if let Ok(commit) = HeaderValue::from_str(&assets_commit) {
+        response.headers_mut().insert(COMMIT_HEADER, commit);
+    }
+    response
+}
+
+/// Parses a comma-separated list of monster IDs.
+fn parse_monster_ids(raw: &str) -> FieldResult<Vec<GroupId>> {
+    raw.split(',')
+        .filter(|id| !id.is_empty())
+        .map(|id| parse_monster_id(id.trim()).map(|id| GroupId(id as i64)))
+        .collect()
+}
+
+fn parse_monster_id(raw: &str) -> FieldResult<i32> {
+    raw.parse::<i32>().map_err(|e| {
+        let e_dbg = format!("{:?}", e);
+        ErrorCode::InvalidArgument
+            .error("Invalid monster ID.", graphql_value!({ "details": e_dbg }))
+    })
+}
+
+async fn activities(context: &Context, query: Option<&str>) -> FieldResult<ApiActivities> {
+    let query = parse_query(query);
+    let since = match query.get("since") {
+        Some(since) => Some(
+            DateTime::parse_from_rfc3339(since)
+                .map_err(|e| {
+                    let e_dbg = format!("{:?}", e);
+                    ErrorCode::InvalidArgument
+                        .error("Invalid date.", graphql_value!({ "details": e_dbg }))
+                })?
+                .with_timezone(&Utc),
+        ),
+        None => None,
+    };
+    let monster_id = query
+        .get("monster")
+        .map(|monster_id| parse_monster_id(monster_id))
+        .transpose()?;
+    let limit = match query.get("limit").map(|limit| limit.parse::<i64>()) {
+        None => DEFAULT_ACTIVITIES_LIMIT,
+        Some(Ok(limit @ 1..=MAX_ACTIVITIES_LIMIT)) => limit,
+        Some(_) => {
+            return Err(ErrorCode::InvalidArgument.error(
+                "limit must be between 1 and 1000",
+                graphql_value!({ "max": (MAX_ACTIVITIES_LIMIT as i32) }),
+            ))
+        }
+    };
+    let after = match query.get("after") {
+        Some(after) => Some(ActivityCursor::decode(after).ok_or_else(|| {
+            ErrorCode::InvalidArgument.error(
+                "Invalid cursor",
+                graphql_value!({ "after": (after.as_str()) }),
+            )
+        })?),
+        None => None,
+    };
+    // One more than requested, to know whether there is a next page.
+    #[cfg(feature = "activity-store")]
+    let mut activities = activity_store(context)?
+        .activities(since, monster_id, after.as_ref(), limit + 1)
+        .await
+        .map_err(activity_store_failed)?;
+    #[cfg(not(feature = "activity-store"))]
+    let mut activities: Vec<ExportedActivity> = {
+        let _ = (context, since, monster_id, after);
+        Err(activity_store_unavailable())?
+    };
+    let has_next_page = activities.len() > limit as usize;
+    activities.truncate(limit as usize);
+    Ok(ApiActivities {
+        next_cursor: has_next_page
+            .then(|| {
+                activities
+                    .last()
+                    .map(|activity| ActivityCursor::from(activity).encode())
+            })
+            .flatten(),
+        activities,
+    })
+}
+
+async fn credits_export(context: &Context, query: Option<&str>) -> FieldResult<ApiBody> {
+    let query = parse_query(query);
+    let csv = match query.get("format").map(String::as_str) {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(format) => {
+            return Err(ErrorCode::InvalidArgument.error(
+                "format must be csv or json",
+                graphql_value!({ "format": format }),
+            ))
+        }
+    };
+    let monster_id = query
+        .get("monster")
+        .map(|monster_id| parse_monster_id(monster_id))
+        .transpose()?;
+    let rows = service::credits_export(context, monster_id).await?;
+    if csv {
+        to_csv(&rows).map(ApiBody::Csv)
+    } else {
+        to_json(rows).map(ApiBody::Json)
+    }
+}
+
+fn monster(context: &Context, monster_id: i32) -> FieldResult<ApiMonster> {
+    let data = context.collab.data();
+    let group = service::monster_group(&data.tracker, monster_id)?;
+    Ok(ApiMonster {
+        id: monster_id,
+        raw_id: format!("{:04}", monster_id),
+        name: group.name.clone(),
+        last_modified: group.last_modified(),
+        forms: service::monster_forms(&data.tracker, monster_id)?
+            .iter()
+            .map(|form| form_summary(context, form))
+            .collect(),
+    })
+}
+
+async fn form(context: &Context, monster_id: i32, form_path: &str) -> FieldResult<ApiForm> {
+    let form_needle = service::parse_form_path(form_path)?;
+    let form = service::find_form(
+        &context.collab.data().tracker,
+        monster_id,
+        form_needle.into_iter().map(FormMatch::Exact),
+    )?
+    .ok_or_else(|| {
+        ErrorCode::NotFound.error("Form not found", graphql_value!({ "path": form_path }))
+    })?;
+    Ok(ApiForm {
+        summary: form_summary(context, &form),
+        portraits: portraits(context, &form).await?,
+        sprites: sprites(context, &form).await?,
+    })
+}
+
+fn form_summary(context: &Context, form: &MonsterForm) -> ApiFormSummary {
+    ApiFormSummary {
+        monster_id: form.id,
+        path: service::form_path(&form.form_id),
+        full_path: service::full_form_path(form.id, &form.form_id),
+        name: form.data.name.clone(),
+        full_name: form.name_path.join(" "),
+        is_shiny: MonsterFormCollector::is_shiny(&form.form_id),
+        is_female: MonsterFormCollector::is_female(&form.form_id),
+        canon: form.data.canon,
+        preview_url: service::preview_url(&context.url_base, form),
+    }
+}
+
+async fn portraits(context: &Context, form: &MonsterForm) -> FieldResult<ApiPortraits> {
+    let group: &Group = &form.data;
+    let url = |asset_type| get_url(asset_type, &context.url_base, form.id, &form.form_id);
+    Ok(ApiPortraits {
+        required: group.portrait_required,
+        bounty: MonsterBounty::new(group.modreward, &group.portrait_bounty),
+        phase: Phase::from(group.portrait_complete),
+        phase_raw: group.portrait_complete as i32,
+        credit_primary: service::credit(context, &group.portrait_credit.primary).await,
+        credit_secondary: service::credits(context, &group.portrait_credit.secondary).await,
+        sheet_url: url(AssetType::PortraitSheet),
+        recolor_sheet_url: url(AssetType::PortraitRecolorSheet),
+        annotated_sheet_url: url(AssetType::PortraitAnnotatedSheet),
+        zip_url: (!group.portrait_files.is_empty()).then(|| url(AssetType::PortraitZip)),
+        emotions: service::portraits(context, group, form.id, &form.form_id, false).await?,
+        emotions_flipped: service::portraits(context, group, form.id, &form.form_id, true).await?,
+        modified_date: group.portrait_modified,
+        history_url: url(AssetType::PortraitCreditsTxt),
+    })
+}
+
+async fn sprites(context: &Context, form: &MonsterForm) -> FieldResult<ApiSprites> {
+    let group: &Group = &form.data;
+    let url = |asset_type| get_url(asset_type, &context.url_base, form.id, &form.form_id);
+    let available = !group.sprite_files.is_empty();
+    Ok(ApiSprites {
+        required: group.sprite_required,
+        bounty: MonsterBounty::new(group.modreward, &group.sprite_bounty),
+        phase: Phase::from(group.sprite_complete),
+        phase_raw: group.sprite_complete as i32,
+        credit_primary: service::credit(context, &group.sprite_credit.primary).await,
+        credit_secondary: service::credits(context, &group.sprite_credit.secondary).await,
+        anim_data_xml: available.then(|| url(AssetType::SpriteAnimDataXml)),
+        zip_url: available.then(|| url(AssetType::SpriteZip)),
+        recolor_sheet_url: available.then(|| url(AssetType::SpriteRecolorSheet)),
+        actions: service::sprite_actions(context, group, form.id, &form.form_id).await?,
+        modified_date: group.sprite_modified,
+        history_url: url(AssetType::SpriteCreditsTxt),
+    })
+}
+
+fn to_json<T: Serialize>(value: T) -> FieldResult<String> {
+    serde_json::to_string(&value).map_err(|e| {
+        let e_as_str = e.to_string();
+        ErrorCode::Internal.error(
+            "Internal Server Error: Failed serializing the response.",
+            graphql_value!({ "details": e_as_str }),
+        )
+    })
+}
+
+fn to_csv<T: Serialize>(rows: &[T]) -> FieldResult<String> {
+    let failed = |e_as_str: String| {
+        ErrorCode::Internal.error(
+            "Internal Server Error: Failed serializing the response.",
+            graphql_value!({ "details": e_as_str }),
+        )
+    };
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row).map_err(|e| failed(e.to_string()))?;
+    }
+    let csv = writer.into_inner().map_err(|e| failed(e.to_string()))?;
+    String::from_utf8(csv).map_err(|e| failed(e.to_string()))
+}
+
+fn make_csv_response(body: String) -> Response<String> {
+    let mut response = Response::new(body);
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/csv; charset=utf-8"),
+    );
+    response
+}
+
+/// Makes a JSON error response out of an error of the service layer, with the HTTP status
+/// matching its error code.
+fn make_api_error_response(e: FieldError) -> Response<String> {
+    let extensions = serde_json::to_value(e.extensions()).unwrap_or(Value::Null);
+    let status = match extensions.get("code").and_then(Value::as_str) {
+        Some("NOT_FOUND") => StatusCode::NOT_FOUND,
+        Some("INVALID_PATH") | Some("INVALID_ARGUMENT") => StatusCode::BAD_REQUEST,
+        Some("CACHE_UNAVAILABLE") | Some("DATA_STALE") => StatusCode::SERVICE_UNAVAILABLE,
+        Some("UNAVAILABLE") => StatusCode::NOT_IMPLEMENTED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    make_json_response(
+        status,
+        json!({ "message": e.message(), "extensions": extensions }).to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::context;
+
+    #[tokio::test]
+    async fn routes_the_base_form() {
+        let (context, _) = context(|_| {}).await;
+        for path in ["/api/v1/monster/1/form", "/api/v1/monster/1/form/0000"] {
+            let json: Value = match route(&context, path, None).await {
+                Ok(ApiBody::Json(json)) => serde_json::from_str(&json).unwrap(),
+                _ => panic!("{} did not return JSON", path),
+            };
+            assert_eq!(json["fullPath"], "0001", "{}", path);
+        }
+        assert!(route(&context, "/api/v1/monster/1/form/0005", None)
+            .await
+            .is_err());
+    }
+}
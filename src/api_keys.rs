@@ -0,0 +1,284 @@
+//! Optional API keys with per-key request quotas, configured with `SCSRV_API_KEYS`.
+//!
+//! Clients send their key as `Authorization: Bearer <key>` to the GraphQL API, the REST API and
+//! the assets. Requests with a key count against its quota, which is a number of requests per
+//! hour. Requests without a key are only allowed if `SCSRV_REQUIRE_API_KEY` is not set, or for
+//! signed asset URLs. CORS preflights (`OPTIONS`) never need a key, since browsers don't send
+//! one with them. The usage of each key is returned by the admin API at `GET /admin/api_keys`.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hyper::header::{AUTHORIZATION, RETRY_AFTER, WWW_AUTHENTICATE};
+use hyper::http::HeaderValue;
+use hyper::{HeaderMap, Response, StatusCode};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::assets::signed_urls::has_valid_signature;
+use crate::graphql::make_json_response;
+use crate::schema::ErrorCode;
+use crate::{ServerConfig, SpriteCollab};
+
+/// Paths of requests that need an API key, if they are required.
+const PROTECTED_PREFIXES: &[&str] = &["/graphql", "/api/", "/assets/"];
+/// Quotas are counted in fixed windows of this length.
+pub const QUOTA_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// An API key, configured as `name:key` or `name:key:quota`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ApiKey {
+    /// Identifies the key in the logs and the usage counters.
+    pub name: String,
+    pub key: String,
+    /// Requests per [`QUOTA_WINDOW`]. `None` if unlimited.
+    pub quota: Option<u64>,
+}
+
+impl FromStr for ApiKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let (name, key) = match (parts.next(), parts.next()) {
+            (Some(name), Some(key)) if !name.is_empty() && !key.is_empty() => (name, key),
+            _ => return Err(format!("expected name:key[:quota], got '{}'", s)),
+        };
+        let quota = match parts.next() {
+            Some(quota) => Some(
+                quota
+                    .parse::<u64>()
+                    .map_err(|e| format!("invalid quota of API key '{}': {}", name, e))?,
+            ),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return Err(format!("expected name:key[:quota], got '{}'", s));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            key: key.to_string(),
+            quota,
+        })
+    }
+}
+
+/// Usage counters of an API key since the start of the server.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyStats {
+    pub requests: u64,
+    /// Requests rejected because the quota was exceeded.
+    pub rejected: u64,
+    /// Requests in the current quota window.
+    pub window_requests: u64,
+    pub quota: Option<u64>,
+}
+
+/// Why a request was rejected.
+#[derive(Debug, Eq, PartialEq)]
+pub enum ApiKeyRejection {
+    Missing,
+    Unknown,
+    QuotaExceeded { retry_after: Duration },
+}
+
+struct Usage {
+    stats: ApiKeyStats,
+    window_start: Instant,
+}
+
+/// Checks the API keys of requests and counts their usage.
+#[derive(Default)]
+pub struct ApiKeyUsage {
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl ApiKeyUsage {
+    /// Checks the key sent with a request and counts the request. Returns the name of the key,
+    /// or `None` if the request has no key and that is allowed.
+    pub fn check(
+        &self,
+        keys: &[ApiKey],
+        require_key: bool,
+        request_headers: &HeaderMap,
+        now: Instant,
+    ) -> Result<Option<String>, ApiKeyRejection> {
+        let given = match bearer_token(request_headers) {
+            Some(given) => given,
+            None if require_key => return Err(ApiKeyRejection::Missing),
+            None => return Ok(None),
+        };
+        let key = keys
+            .iter()
+            .find(|key| constant_time_eq(key.key.as_bytes(), given.as_bytes()))
+            .ok_or(ApiKeyRejection::Unknown)?;
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(key.name.clone()).or_insert_with(|| Usage {
+            stats: ApiKeyStats::default(),
+            window_start: now,
+        });
+        if now.duration_since(usage.window_start) >= QUOTA_WINDOW {
+            usage.window_start = now;
+            usage.stats.window_requests = 0;
+        }
+        usage.stats.quota = key.quota;
+        usage.stats.requests += 1;
+        if key
+            .quota
+            .is_some_and(|quota| usage.stats.window_requests >= quota)
+        {
+            usage.stats.rejected += 1;
+            let retry_after = QUOTA_WINDOW.saturating_sub(now.duration_since(usage.window_start));
+            return Err(ApiKeyRejection::QuotaExceeded { retry_after });
+        }
+        usage.stats.window_requests += 1;
+        Ok(Some(key.name.clone()))
+    }
+
+    /// The usage counters of all keys that were used, by name.
+    pub fn stats(&self) -> HashMap<String, ApiKeyStats> {
+        self.usage
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, usage)| (name.clone(), usage.stats.clone()))
+            .collect()
+    }
+}
+
+/// Checks the API key of a request, if API keys are configured. Returns the response to send
+/// instead if the request is rejected.
+pub fn check_api_key(
+    sprite_collab: &SpriteCollab,
+    path: &str,
+    query: Option<&str>,
+    request_headers: &HeaderMap,
+) -> Option<Response<String>> {
+    let config = ServerConfig::get();
+    if config.api_keys.is_empty()
+        || !PROTECTED_PREFIXES
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+    {
+        return None;
+    }
+    // Signed URLs are handed out by the API to clients without a key.
+    let require_key = config.require_api_key && !has_valid_signature(path, query);
+    sprite_collab
+        .api_key_usage()
+        .check(
+            &config.api_keys,
+            require_key,
+            request_headers,
+            Instant::now(),
+        )
+        .err()
+        .map(make_rejection_response)
+}
+
+pub fn make_rejection_response(rejection: ApiKeyRejection) -> Response<String> {
+    let (status, code, message) = match &rejection {
+        ApiKeyRejection::Missing => (
+            StatusCode::UNAUTHORIZED,
+            ErrorCode::Unauthorized,
+            "An API key is required, send it as 'Authorization: Bearer <key>'.",
+        ),
+        ApiKeyRejection::Unknown => (
+            StatusCode::UNAUTHORIZED,
+            ErrorCode::Unauthorized,
+            "Invalid API key.",
+        ),
+        ApiKeyRejection::QuotaExceeded { .. } => (
+            StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::QuotaExceeded,
+            "The quota of the API key is exceeded.",
+        ),
+    };
+    let mut response = make_json_response(
+        status,
+        json!({ "message": message, "extensions": { "code": code.as_str() } }).to_string(),
+    );
+    match rejection {
+        ApiKeyRejection::QuotaExceeded { retry_after } => {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs().max(1)));
+        }
+        _ => {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+    }
+    response
+}
+
+fn bearer_token(request_headers: &HeaderMap) -> Option<&str> {
+    request_headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", key)).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn enforces_quotas_per_window() {
+        let keys = vec![
+            "bot:abc:2".parse::<ApiKey>().unwrap(),
+            "site:def".parse::<ApiKey>().unwrap(),
+        ];
+        let usage = ApiKeyUsage::default();
+        let start = Instant::now();
+        let check = |key: &str, now| usage.check(&keys, true, &headers(key), now);
+
+        assert_eq!(check("abc", start), Ok(Some("bot".to_string())));
+        assert_eq!(check("abc", start), Ok(Some("bot".to_string())));
+        assert_eq!(
+            check("abc", start + Duration::from_secs(60)),
+            Err(ApiKeyRejection::QuotaExceeded {
+                retry_after: QUOTA_WINDOW - Duration::from_secs(60)
+            })
+        );
+        assert_eq!(
+            check("abc", start + QUOTA_WINDOW),
+            Ok(Some("bot".to_string()))
+        );
+        assert_eq!(check("def", start), Ok(Some("site".to_string())));
+        assert_eq!(check("xyz", start), Err(ApiKeyRejection::Unknown));
+        assert_eq!(
+            usage.check(&keys, true, &HeaderMap::new(), start),
+            Err(ApiKeyRejection::Missing)
+        );
+        assert_eq!(
+            usage.check(&keys, false, &HeaderMap::new(), start),
+            Ok(None)
+        );
+
+        let stats = usage.stats();
+        assert_eq!(stats["bot"].requests, 4);
+        assert_eq!(stats["bot"].rejected, 1);
+        assert_eq!(stats["bot"].window_requests, 1);
+        assert!("bot".parse::<ApiKey>().is_err());
+        assert!("bot:abc:many".parse::<ApiKey>().is_err());
+    }
+}
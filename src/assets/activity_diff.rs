@@ -0,0 +1,265 @@
+//! Before/after images of activities, at
+//! `/assets/activity-diff/<commit>/<portrait|sprite>/<form path>.png`, eg.
+//! `/assets/activity-diff/<commit>/portrait/0025/0000/0001.png`. The version of the form in the
+//! first parent of the commit is on the left, the version in the commit on the right. If one
+//! of them doesn't exist, eg. because the commit added the portrait, its side stays empty.
+//!
+//! Portraits show the `Normal` emotion and sprites the first frame of the `Idle` animation;
+//! another emotion or action can be requested with `?name=`. The images can be scaled with
+//! `scale` or `max_width`, like sheets.
+
+use std::path::Path;
+
+use git2::{ErrorCode, Oid, Repository, Tree};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::http::HeaderValue;
+use hyper::Response;
+use image::{imageops, RgbaImage};
+
+use crate::assets::fs_check::AssetCategory;
+use crate::assets::img_util::{run_blocking, to_png, SheetScale};
+use crate::assets::preview::{first_frame_of, PREVIEW_ACTION, PREVIEW_EMOTION};
+use crate::assets::size_limit::make_too_large_response;
+use crate::assets::util::parse_query;
+use crate::assets::{make_box_body, make_err_response, AssetBody, IMMUTABLE};
+use crate::datafiles::anim_data_xml::AnimDataXml;
+use crate::sprite_collab::GIT_REPO_DIR;
+use crate::ServerConfig;
+
+/// Path the activity diffs are served under.
+pub const ACTIVITY_DIFF_PATH: &str = "/assets/activity-diff";
+/// Transparent space between the two versions.
+const GAP: u32 = 8;
+
+/// URL of the before/after image of the portraits or sprites of a form in a commit.
+pub fn activity_diff_url(commit: &str, category: AssetCategory, form_path: &str) -> String {
+    let category = match category {
+        AssetCategory::Portrait => "portrait",
+        AssetCategory::Sprite => "sprite",
+    };
+    format!(
+        "{}{}/{}/{}/{}.png",
+        ServerConfig::get().this_server_url(),
+        ACTIVITY_DIFF_PATH,
+        commit,
+        category,
+        form_path
+    )
+}
+
+/// Serves an activity diff, `path` is the path relative to [`ACTIVITY_DIFF_PATH`]. Returns
+/// `None` if the path is invalid, or neither the commit nor its parent have the file.
+pub async fn serve_activity_diff(path: &str, query: Option<&str>) -> Option<Response<AssetBody>> {
+    let (commit, rest) = path.strip_prefix('/')?.split_once('/')?;
+    if commit.len() != 40 || !commit.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let commit = Oid::from_str(commit).ok()?;
+    let (category, form_path) = rest.strip_suffix(".png")?.split_once('/')?;
+    let category = match category {
+        "portrait" => AssetCategory::Portrait,
+        "sprite" => AssetCategory::Sprite,
+        _ => return None,
+    };
+    if !form_path
+        .split('/')
+        .all(|dir| dir.len() == 4 && dir.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    let query = parse_query(query);
+    let name = match query.get("name") {
+        Some(name) => name.clone(),
+        None if category == AssetCategory::Portrait => PREVIEW_EMOTION.to_string(),
+        None => PREVIEW_ACTION.to_string(),
+    };
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '^'))
+    {
+        return None;
+    }
+    let scale = SheetScale::from_query(&query);
+
+    let repo_path = ServerConfig::get().workdir.join(GIT_REPO_DIR);
+    let form_path = form_path.to_string();
+    let result = run_blocking(move |_| {
+        make_activity_diff(&repo_path, commit, category, &form_path, &name)?
+            .map(|img| to_png(scale.apply(img)))
+            .transpose()
+    })
+    .await;
+    let png = match result {
+        Ok(png) => png?,
+        Err(e) => {
+            return Some(
+                make_too_large_response(&e)
+                    .unwrap_or_else(|| make_err_response(e, path).map(make_box_body)),
+            )
+        }
+    };
+    let mut response = Response::new(make_box_body(Full::new(Bytes::from(png))));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE));
+    Some(response)
+}
+
+/// Renders the version of the portrait `name` (an emotion) or the first frame of the sprite
+/// animation `name` of the form before `commit` next to the version in `commit`. Returns `None`
+/// if the commit doesn't exist, or neither version exists.
+pub fn make_activity_diff(
+    repo_path: &Path,
+    commit: Oid,
+    category: AssetCategory,
+    form_path: &str,
+    name: &str,
+) -> Result<Option<RgbaImage>, anyhow::Error> {
+    let repo = Repository::open(repo_path)?;
+    let commit = match repo.find_commit(commit) {
+        Ok(commit) => commit,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let after = read_version(&repo, &commit.tree()?, category, form_path, name)?;
+    let before = match commit.parent(0) {
+        Ok(parent) => read_version(&repo, &parent.tree()?, category, form_path, name)?,
+        Err(_) => None,
+    };
+    if before.is_none() && after.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(side_by_side(before.as_ref(), after.as_ref())))
+}
+
+/// Reads the portrait or the first frame of the sprite animation from a tree. Returns `None`
+/// if it doesn't exist in the tree.
+fn read_version(
+    repo: &Repository,
+    tree: &Tree,
+    category: AssetCategory,
+    form_path: &str,
+    name: &str,
+) -> Result<Option<RgbaImage>, anyhow::Error> {
+    let read_file = |path: String| -> Result<Option<Vec<u8>>, anyhow::Error> {
+        match tree.get_path(Path::new(&path)) {
+            Ok(entry) => Ok(Some(repo.find_blob(entry.id())?.content().to_vec())),
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    };
+    match category {
+        AssetCategory::Portrait => {
+            match read_file(format!("portrait/{}/{}.png", form_path, name))? {
+                Some(png) => Ok(Some(image::load_from_memory(&png)?.into_rgba8())),
+                None => Ok(None),
+            }
+        }
+        AssetCategory::Sprite => {
+            let xml = match read_file(format!("sprite/{}/AnimData.xml", form_path))? {
+                Some(xml) => AnimDataXml::from_reader(xml.as_slice())?,
+                None => return Ok(None),
+            };
+            if !xml.anims.anim.iter().any(|anim| anim.name == name) {
+                return Ok(None);
+            }
+            let (anim_name, frame_width, frame_height) = first_frame_of(&xml, name)?;
+            match read_file(format!("sprite/{}/{}-Anim.png", form_path, anim_name))? {
+                Some(png) => Ok(Some(
+                    image::load_from_memory(&png)?
+                        .crop_imm(0, 0, frame_width, frame_height)
+                        .into_rgba8(),
+                )),
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+/// Places `before` left of `after`. A missing version takes the space of the other one.
+fn side_by_side(before: Option<&RgbaImage>, after: Option<&RgbaImage>) -> RgbaImage {
+    let dimensions = |img: Option<&RgbaImage>| img.map(RgbaImage::dimensions);
+    let (before_width, before_height) =
+        dimensions(before).or(dimensions(after)).unwrap_or_default();
+    let (after_width, after_height) = dimensions(after).or(dimensions(before)).unwrap_or_default();
+    let mut img = RgbaImage::new(
+        before_width + GAP + after_width,
+        before_height.max(after_height),
+    );
+    if let Some(before) = before {
+        imageops::replace(&mut img, before, 0, 0);
+    }
+    if let Some(after) = after {
+        imageops::replace(&mut img, after, (before_width + GAP) as i64, 0);
+    }
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SyntheticRepo;
+    use image::Rgba;
+
+    #[test]
+    fn places_versions_side_by_side() {
+        let before = RgbaImage::from_pixel(2, 3, Rgba([255, 0, 0, 255]));
+        let after = RgbaImage::from_pixel(4, 2, Rgba([0, 0, 255, 255]));
+
+        let img = side_by_side(Some(&before), Some(&after));
+        assert_eq!(img.dimensions(), (2 + GAP + 4, 3));
+        assert_eq!(img.get_pixel(1, 2), &Rgba([255, 0, 0, 255]));
+        assert_eq!(img.get_pixel(2 + GAP, 0), &Rgba([0, 0, 255, 255]));
+        assert_eq!(img.get_pixel(2 + GAP, 2), &Rgba([0, 0, 0, 0]));
+
+        let img = side_by_side(None, Some(&after));
+        assert_eq!(img.dimensions(), (4 + GAP + 4, 2));
+        assert_eq!(img.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn renders_versions_of_commits() {
+        let repo = SyntheticRepo::create("scsrv-activity-diff-test");
+        let initial = repo.commit("Nothing changed");
+        let red = RgbaImage::from_pixel(40, 40, Rgba([255, 0, 0, 255]));
+        red.save(repo.path().join("portrait/0001/Normal.png"))
+            .unwrap();
+        let updated = repo.commit("Update the Normal portrait of 0001");
+
+        let img = make_activity_diff(
+            repo.path(),
+            updated,
+            AssetCategory::Portrait,
+            "0001",
+            "Normal",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(img.dimensions(), (40 + GAP + 40, 40));
+        assert_ne!(img.get_pixel(0, 20), &Rgba([255, 0, 0, 255]));
+        assert_eq!(img.get_pixel(40 + GAP, 20), &Rgba([255, 0, 0, 255]));
+
+        let sprite =
+            make_activity_diff(repo.path(), initial, AssetCategory::Sprite, "0001", "Idle")
+                .unwrap()
+                .unwrap();
+        // The sprites didn't change.
+        let width = (sprite.width() - GAP) / 2;
+        assert_eq!(
+            imageops::crop_imm(&sprite, 0, 0, width, sprite.height()).to_image(),
+            imageops::crop_imm(&sprite, width + GAP, 0, width, sprite.height()).to_image()
+        );
+        assert!(make_activity_diff(
+            repo.path(),
+            updated,
+            AssetCategory::Portrait,
+            "0002",
+            "Normal"
+        )
+        .unwrap()
+        .is_none());
+    }
+}
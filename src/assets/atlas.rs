@@ -0,0 +1,421 @@
+//! Frame atlases of a sprite action for game engines: Where each frame is on the `-Anim.png`
+//! sheet of the action, as TexturePacker JSON (array format, with the frame durations and a
+//! frame tag per direction), as a Godot `SpriteFrames` resource, or as the sprite sheet of a
+//! Unity `.meta` file. The sheet has one row per direction and one column per frame, in the
+//! frame size of the AnimData.xml.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::assets::img_util::run_blocking;
+use crate::assets::MAX_COPY_OF_DEPTH;
+use crate::cache::CacheBehaviour;
+use crate::datafiles::anim_data_xml::{Anim, AnimDataXml};
+
+/// The directions of the rows of sheets with 8 rows. Sheets with another number of rows (eg.
+/// a single one) name their rows by number.
+const DIRECTIONS: [&str; 8] = [
+    "Down",
+    "DownRight",
+    "Right",
+    "UpRight",
+    "Up",
+    "UpLeft",
+    "Left",
+    "DownLeft",
+];
+
+/// Durations in the AnimData.xml are in frames of the game, which runs at 60 FPS.
+const TICKS_PER_SECOND: i64 = 60;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AtlasFormat {
+    TexturePacker,
+    Godot,
+    Unity,
+}
+
+impl AtlasFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            AtlasFormat::TexturePacker => "json",
+            AtlasFormat::Godot => "tres",
+            AtlasFormat::Unity => "meta",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            AtlasFormat::TexturePacker => "application/json",
+            AtlasFormat::Godot | AtlasFormat::Unity => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+/// A frame on the sheet, in pixels from the top left.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct AtlasFrame {
+    direction: String,
+    x: u32,
+    y: u32,
+    /// In ticks, see [`TICKS_PER_SECOND`].
+    duration: i64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Atlas {
+    action: String,
+    /// The file name of the sheet, which is the sheet of the copied action for copies.
+    image: String,
+    width: u32,
+    height: u32,
+    frame_width: u32,
+    frame_height: u32,
+    /// By direction, then by frame.
+    frames: Vec<AtlasFrame>,
+}
+
+/// Makes the atlas of `action` in `format`. `form` (eg. `0025/0000/0001`) is only used to
+/// derive a stable GUID for Unity.
+pub async fn make_sprite_atlas(
+    sprite_base_path: &Path,
+    form: String,
+    action: String,
+    format: AtlasFormat,
+) -> Result<CacheBehaviour<String>, anyhow::Error> {
+    let sprite_base_path = sprite_base_path.to_path_buf();
+    run_blocking(move |_| {
+        let anim_data = AnimDataXml::open(sprite_base_path.join("AnimData.xml"))?;
+        let atlas = Atlas::new(&sprite_base_path, &anim_data, &action)?;
+        Ok(CacheBehaviour::Cache(match format {
+            AtlasFormat::TexturePacker => atlas.to_texture_packer()?,
+            AtlasFormat::Godot => atlas.to_godot(),
+            AtlasFormat::Unity => atlas.to_unity(&form),
+        }))
+    })
+    .await
+}
+
+impl Atlas {
+    fn new(
+        sprite_base_path: &Path,
+        anim_data: &AnimDataXml,
+        action: &str,
+    ) -> Result<Self, anyhow::Error> {
+        let anim = source_anim(anim_data, action)?;
+        let (frame_width, frame_height) = match (anim.frame_width, anim.frame_height) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => (width as u32, height as u32),
+            _ => return Err(anyhow!("The action {} has no frame size.", anim.name)),
+        };
+        let image = format!("{}-Anim.png", anim.name);
+        let (width, height) = image::image_dimensions(sprite_base_path.join(&image))?;
+        let durations = anim
+            .durations
+            .as_ref()
+            .and_then(|d| d.duration.clone())
+            .unwrap_or_default();
+        let rows = height / frame_height;
+        let columns = (width / frame_width).min(durations.len() as u32);
+        let mut frames = Vec::new();
+        for row in 0..rows {
+            let direction = direction_name(row, rows);
+            for column in 0..columns {
+                frames.push(AtlasFrame {
+                    direction: direction.clone(),
+                    x: column * frame_width,
+                    y: row * frame_height,
+                    duration: durations[column as usize],
+                });
+            }
+        }
+        Ok(Self {
+            action: action.to_string(),
+            image,
+            width,
+            height,
+            frame_width,
+            frame_height,
+            frames,
+        })
+    }
+
+    /// The frames of each direction, in the order of the rows.
+    fn directions(&self) -> Vec<(&str, Vec<(usize, &AtlasFrame)>)> {
+        let mut directions: Vec<(&str, Vec<(usize, &AtlasFrame)>)> = Vec::new();
+        for (i, frame) in self.frames.iter().enumerate() {
+            match directions.last_mut() {
+                Some((direction, frames)) if *direction == frame.direction => {
+                    frames.push((i, frame))
+                }
+                _ => directions.push((&frame.direction, vec![(i, frame)])),
+            }
+        }
+        directions
+    }
+
+    fn frame_name(&self, direction: &str, index: usize) -> String {
+        format!("{}_{}_{}", self.action, direction, index)
+    }
+
+    fn to_texture_packer(&self) -> Result<String, anyhow::Error> {
+        let mut frames = Vec::new();
+        let mut frame_tags = Vec::new();
+        for (direction, direction_frames) in self.directions() {
+            for (index, (_, frame)) in direction_frames.iter().enumerate() {
+                let rect = TexturePackerRect {
+                    x: frame.x,
+                    y: frame.y,
+                    w: self.frame_width,
+                    h: self.frame_height,
+                };
+                frames.push(TexturePackerFrame {
+                    filename: self.frame_name(direction, index),
+                    frame: rect,
+                    rotated: false,
+                    trimmed: false,
+                    sprite_source_size: TexturePackerRect { x: 0, y: 0, ..rect },
+                    source_size: TexturePackerSize {
+                        w: self.frame_width,
+                        h: self.frame_height,
+                    },
+                    duration: frame.duration * 1000 / TICKS_PER_SECOND,
+                });
+            }
+            frame_tags.push(TexturePackerFrameTag {
+                name: format!("{}_{}", self.action, direction),
+                from: direction_frames.first().map_or(0, |(i, _)| *i),
+                to: direction_frames.last().map_or(0, |(i, _)| *i),
+                direction: "forward",
+            });
+        }
+        Ok(serde_json::to_string_pretty(&TexturePackerAtlas {
+            frames,
+            meta: TexturePackerMeta {
+                app: "https://sprites.pmdcollab.org",
+                image: self.image.clone(),
+                format: "RGBA8888",
+                size: TexturePackerSize {
+                    w: self.width,
+                    h: self.height,
+                },
+                scale: "1",
+                frame_tags,
+            },
+        })?)
+    }
+
+    /// A `SpriteFrames` resource with an animation per direction. The animations play at the
+    /// speed of the game, so the duration of each frame is its number of ticks.
+    fn to_godot(&self) -> String {
+        let mut tres = String::new();
+        writeln!(
+            tres,
+            "[gd_resource type=\"SpriteFrames\" load_steps={} format=3]\n",
+            self.frames.len() + 2
+        )
+        .unwrap();
+        writeln!(
+            tres,
+            "[ext_resource type=\"Texture2D\" path=\"res://{}\" id=\"1\"]\n",
+            self.image
+        )
+        .unwrap();
+        for (i, frame) in self.frames.iter().enumerate() {
+            writeln!(
+                tres,
+                "[sub_resource type=\"AtlasTexture\" id=\"AtlasTexture_{}\"]\natlas = ExtResource(\"1\")\nregion = Rect2({}, {}, {}, {})\n",
+                i, frame.x, frame.y, self.frame_width, self.frame_height
+            )
+            .unwrap();
+        }
+        let animations = self
+            .directions()
+            .into_iter()
+            .map(|(direction, frames)| {
+                let frames = frames
+                    .iter()
+                    .map(|(i, frame)| {
+                        format!(
+                            "{{\n\"duration\": {}.0,\n\"texture\": SubResource(\"AtlasTexture_{}\")\n}}",
+                            frame.duration, i
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!(
+                    "{{\n\"frames\": [{}],\n\"loop\": true,\n\"name\": &\"{}_{}\",\n\"speed\": {}.0\n}}",
+                    frames, self.action, direction, TICKS_PER_SECOND
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(tres, "[resource]\nanimations = [{}]", animations).unwrap();
+        tres
+    }
+
+    /// The `.meta` file of the sheet, to import it as multiple sprites. Unity counts `y` from
+    /// the bottom of the texture.
+    fn to_unity(&self, form: &str) -> String {
+        // Unity GUIDs are 32 hex digits. Derived from the form and the sheet, they stay the
+        // same when the file is downloaded again.
+        let guid = format!("{:x}", Sha256::digest(format!("{}/{}", form, self.image)));
+        let mut meta = format!("fileFormatVersion: 2\nguid: {}\n", &guid[..32]);
+        meta.push_str(
+            "TextureImporter:\n  serializedVersion: 12\n  textureType: 8\n  spriteMode: 2\n  spritePixelsToUnits: 100\n  alphaIsTransparency: 1\n  filterMode: 0\n  textureCompression: 0\n  spriteSheet:\n    serializedVersion: 2\n    sprites:\n",
+        );
+        for (direction, frames) in self.directions() {
+            for (index, (_, frame)) in frames.iter().enumerate() {
+                writeln!(
+                    meta,
+                    "    - serializedVersion: 2\n      name: {}\n      rect:\n        serializedVersion: 2\n        x: {}\n        y: {}\n        width: {}\n        height: {}\n      alignment: 0\n      pivot: {{x: 0.5, y: 0.5}}",
+                    self.frame_name(direction, index),
+                    frame.x,
+                    self.height - frame.y - self.frame_height,
+                    self.frame_width,
+                    self.frame_height
+                )
+                .unwrap();
+            }
+        }
+        meta
+    }
+}
+
+/// The name of row `row` of a sheet with `rows` rows, see [`DIRECTIONS`].
+pub(crate) fn direction_name(row: u32, rows: u32) -> String {
+    if rows as usize == DIRECTIONS.len() {
+        DIRECTIONS[row as usize].to_string()
+    } else {
+        row.to_string()
+    }
+}
+
+/// The entry of `action` in the AnimData.xml, or of the action it is a copy of.
+pub(crate) fn source_anim<'a>(
+    anim_data: &'a AnimDataXml,
+    action: &str,
+) -> Result<&'a Anim, anyhow::Error> {
+    let find = |name: &str| {
+        anim_data
+            .anims
+            .anim
+            .iter()
+            .find(|anim| anim.name == name)
+            .ok_or_else(|| anyhow!("The AnimData.xml has no action {}.", name))
+    };
+    let mut anim = find(action)?;
+    for _ in 0..MAX_COPY_OF_DEPTH {
+        match &anim.copy_of {
+            Some(copy_of) => anim = find(copy_of)?,
+            None => return Ok(anim),
+        }
+    }
+    Err(anyhow!(
+        "The copies of the action {} are too deeply nested.",
+        action
+    ))
+}
+
+#[derive(Serialize)]
+struct TexturePackerAtlas {
+    frames: Vec<TexturePackerFrame>,
+    meta: TexturePackerMeta,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TexturePackerFrame {
+    filename: String,
+    frame: TexturePackerRect,
+    rotated: bool,
+    trimmed: bool,
+    sprite_source_size: TexturePackerRect,
+    source_size: TexturePackerSize,
+    /// In milliseconds.
+    duration: i64,
+}
+
+#[derive(Clone, Copy, Serialize)]
+struct TexturePackerRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+struct TexturePackerSize {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TexturePackerMeta {
+    app: &'static str,
+    image: String,
+    format: &'static str,
+    size: TexturePackerSize,
+    scale: &'static str,
+    frame_tags: Vec<TexturePackerFrameTag>,
+}
+
+#[derive(Serialize)]
+struct TexturePackerFrameTag {
+    name: String,
+    from: usize,
+    to: usize,
+    direction: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::snapshots::fixtures_dir;
+
+    #[test]
+    fn lays_out_frames_of_actions() {
+        let sprites = fixtures_dir().join("spritecollab/sprite/0001");
+        let anim_data = AnimDataXml::open(sprites.join("AnimData.xml")).unwrap();
+
+        let walk = Atlas::new(&sprites, &anim_data, "Walk").unwrap();
+        assert_eq!((walk.width, walk.height), (48, 128));
+        assert_eq!(walk.frames.len(), 24);
+        assert_eq!(
+            walk.frames[4],
+            AtlasFrame {
+                direction: "DownRight".to_string(),
+                x: 16,
+                y: 16,
+                duration: 8,
+            }
+        );
+
+        // Copies use the sheet of the action they copy.
+        let sleep = Atlas::new(&sprites, &anim_data, "Sleep").unwrap();
+        assert_eq!(sleep.image, "Idle-Anim.png");
+        assert_eq!(sleep.frames.len(), 16);
+        assert!(Atlas::new(&sprites, &anim_data, "Attack").is_err());
+
+        let json: serde_json::Value =
+            serde_json::from_str(&walk.to_texture_packer().unwrap()).unwrap();
+        assert_eq!(json["frames"][4]["filename"], "Walk_DownRight_1");
+        assert_eq!(json["frames"][4]["duration"], 133);
+        assert_eq!(json["meta"]["frameTags"][1]["from"], 3);
+        assert_eq!(json["meta"]["frameTags"][1]["to"], 5);
+
+        let tres = walk.to_godot();
+        assert!(tres.starts_with("[gd_resource type=\"SpriteFrames\" load_steps=26 format=3]"));
+        assert!(tres.contains("region = Rect2(16, 16, 16, 16)"));
+        assert!(tres.contains("\"name\": &\"Walk_DownLeft\""));
+
+        let meta = walk.to_unity("0001");
+        assert_eq!(meta.lines().nth(1).unwrap().len(), "guid: ".len() + 32);
+        // The first row of the sheet is at the top of the texture.
+        assert!(meta.contains("name: Walk_Down_0\n      rect:\n        serializedVersion: 2\n        x: 0\n        y: 112\n"));
+    }
+}
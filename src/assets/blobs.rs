@@ -0,0 +1,141 @@
+//! Serving of any version of a file of the SpriteCollab repository from the git object
+//! database, at `/assets/blob/<oid>.<ext>`, where `<oid>` is the ID of the blob and `<ext>` one
+//! of the extensions of [`crate::assets::files`]. This lets history UIs show the portraits and
+//! sprites of an activity before and after it, even if they were replaced since.
+//!
+//! Blobs never change, so they are served with `Cache-Control: immutable` and their ID as
+//! `ETag`.
+
+use std::path::Path;
+
+use git2::{ObjectType, Oid, Repository};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use hyper::http::HeaderValue;
+use hyper::{HeaderMap, Response, StatusCode};
+
+use crate::assets::files::content_type_of;
+use crate::assets::img_util::run_blocking;
+use crate::assets::{make_box_body, make_err_response, AssetBody, IMMUTABLE};
+use crate::sprite_collab::GIT_REPO_DIR;
+use crate::ServerConfig;
+
+/// Path the blobs are served under.
+pub const BLOBS_PATH: &str = "/assets/blob";
+/// Larger blobs are not served. The largest files of the repository are sprite sheets of a few
+/// MiB.
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+/// The outcome of reading a blob from the object database.
+#[derive(Debug, Eq, PartialEq)]
+pub enum BlobContent {
+    Found(Vec<u8>),
+    /// The object does not exist, or is not a blob.
+    NotFound,
+    /// The blob is larger than [`MAX_BLOB_SIZE`], it has this size.
+    TooLarge(usize),
+}
+
+/// Serves a blob, `path` is the path relative to [`BLOBS_PATH`]. Returns `None` if the path is
+/// not `/<oid>.<ext>` or the blob does not exist.
+pub async fn serve_blob(path: &str, request_headers: &HeaderMap) -> Option<Response<AssetBody>> {
+    let (oid, extension) = path.strip_prefix('/')?.split_once('.')?;
+    if oid.len() != 40 || !oid.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let oid = Oid::from_str(oid).ok()?;
+    let content_type = content_type_of(extension)?;
+    let etag = HeaderValue::from_str(&format!("\"{}\"", oid)).ok()?;
+
+    // The client already has the blob if it sends its ID, it doesn't have to be read again.
+    let not_modified = request_headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim() == etag);
+    let mut response = if not_modified {
+        let mut response = Response::new(make_box_body(Full::new(Bytes::new())));
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        response
+    } else {
+        let repo_path = ServerConfig::get().workdir.join(GIT_REPO_DIR);
+        let content = match run_blocking(move |_| read_blob(&repo_path, oid)).await {
+            Ok(BlobContent::Found(content)) => content,
+            Ok(BlobContent::NotFound) => return None,
+            Ok(BlobContent::TooLarge(size)) => return Some(make_too_large_response(size)),
+            Err(e) => return Some(make_err_response(e, path).map(make_box_body)),
+        };
+        let mut response = Response::new(make_box_body(Full::new(Bytes::from(content))));
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        response
+    };
+    let headers = response.headers_mut();
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE));
+    headers.insert(ETAG, etag);
+    Some(response)
+}
+
+fn make_too_large_response(size: usize) -> Response<AssetBody> {
+    let mut response = Response::new(format!(
+        "<html><body><h1>The file is too large ({} bytes) to be served.</h1><img src=\"https://http.cat/413\"></body></html>",
+        size
+    ));
+    *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=UTF-8"),
+    );
+    response.map(make_box_body)
+}
+
+/// Reads a blob of the repository at `repo_path`. The size is checked before the blob is read.
+pub fn read_blob(repo_path: &Path, oid: Oid) -> Result<BlobContent, anyhow::Error> {
+    let repo = Repository::open(repo_path)?;
+    let odb = repo.odb()?;
+    let (size, kind) = match odb.read_header(oid) {
+        Ok(header) => header,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(BlobContent::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+    if kind != ObjectType::Blob {
+        return Ok(BlobContent::NotFound);
+    }
+    if size > MAX_BLOB_SIZE {
+        return Ok(BlobContent::TooLarge(size));
+    }
+    let content = odb.read(oid)?.data().to_vec();
+    Ok(BlobContent::Found(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::unique_temp_path;
+
+    #[test]
+    fn reads_only_blobs() {
+        let dir = unique_temp_path("scsrv-blobs-test");
+        let repo = Repository::init(&dir).unwrap();
+        let blob = repo.blob(b"portrait").unwrap();
+        let tree = repo.treebuilder(None).unwrap().write().unwrap();
+
+        assert_eq!(
+            read_blob(&dir, blob).unwrap(),
+            BlobContent::Found(b"portrait".to_vec())
+        );
+        assert_eq!(read_blob(&dir, tree).unwrap(), BlobContent::NotFound);
+        assert_eq!(
+            read_blob(
+                &dir,
+                Oid::from_str("0123456789abcdef0123456789abcdef01234567").unwrap()
+            )
+            .unwrap(),
+            BlobContent::NotFound
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
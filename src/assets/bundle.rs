@@ -0,0 +1,375 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::anyhow;
+use http_body_util::{BodyExt, Limited};
+use hyper::body::{Body, Bytes, Frame, Incoming};
+use hyper::{Request, Response};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use zip::ZipWriter;
+
+use crate::assets::fs_check::AssetCategory;
+use crate::assets::img_util::{run_blocking, Cancellation};
+use crate::assets::size_limit::{check_asset_size, make_too_large_response};
+use crate::assets::util::join_monster_and_form;
+use crate::assets::{
+    make_bad_request_response, make_box_body, make_err_response, AssetBody, ZipResponse,
+};
+use crate::datafiles::tracker::{FormMatch, MonsterFormCollector, Tracker};
+use crate::SpriteCollab;
+
+/// Maximum number of forms that can be requested in one bundle.
+pub const MAX_BUNDLE_FORMS: usize = 500;
+const MAX_BUNDLE_REQUEST_SIZE: usize = 1024 * 1024;
+/// Number of chunks of the ZIP that are buffered before the writing waits for the client.
+const BUNDLE_CHANNEL_SIZE: usize = 16;
+
+#[derive(Deserialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+enum BundleCategory {
+    Portrait,
+    Sprite,
+}
+
+impl BundleCategory {
+    fn dir_name(&self) -> &'static str {
+        match self {
+            BundleCategory::Portrait => "portrait",
+            BundleCategory::Sprite => "sprite",
+        }
+    }
+
+    fn asset_category(&self) -> AssetCategory {
+        match self {
+            BundleCategory::Portrait => AssetCategory::Portrait,
+            BundleCategory::Sprite => AssetCategory::Sprite,
+        }
+    }
+}
+
+/// A form to bundle the files of one category of: category, monster ID and form path.
+type BundleEntry = (BundleCategory, i32, Vec<i32>);
+
+/// Body of a bundle request, eg:
+/// `{"forms": ["0025", "0025/0001"], "categories": ["portrait", "sprite"]}`
+#[derive(Deserialize, Debug)]
+struct BundleRequest {
+    forms: Vec<String>,
+    categories: Vec<BundleCategory>,
+}
+
+/// Handles `POST /assets/bundle`: Returns a single ZIP containing the files of all requested
+/// forms and categories. The directory structure inside the ZIP matches the layout of the
+/// SpriteCollab repository (eg. `portrait/0025/0001/Normal.png`). The ZIP is streamed, one file
+/// at a time, so it is never held in memory as a whole.
+pub async fn make_bundle_response(
+    req: Request<Incoming>,
+    sprite_collab: Arc<SpriteCollab>,
+) -> Response<AssetBody> {
+    let request_path = req.uri().path().to_string();
+    let entries = match read_bundle_request(req).await {
+        Ok(entries) => entries,
+        Err(e) => return make_bad_request_response(&e),
+    };
+    let unknown = unknown_forms(&sprite_collab.data().tracker, &entries);
+    if !unknown.is_empty() {
+        return make_bad_request_response(&format!("Unknown forms: {}", unknown.join(", ")));
+    }
+    // The size is checked before the response is started, so it can still be a 413.
+    let files = match run_blocking(move |cancellation| {
+        list_bundle_files(&sprite_collab, &entries, cancellation)
+    })
+    .await
+    {
+        Ok(files) => files,
+        Err(e) => {
+            return make_too_large_response(&e)
+                .unwrap_or_else(|| make_err_response(e, &request_path).map(make_box_body))
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel(BUNDLE_CHANNEL_SIZE);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = ChannelWriter::new(sender);
+        if let Err(e) = write_bundle_zip(&mut writer, files) {
+            // Aborts the response, the client must not mistake it for a complete ZIP.
+            let error = io::Error::other(e.to_string());
+            let _ = writer.sender.blocking_send(Err(error));
+        }
+    });
+    let response: Result<Response<AssetBody>, _> =
+        ZipResponse(make_box_body(BundleBody(receiver)), "bundle.zip").try_into();
+    response.unwrap_or_else(|e| make_err_response(e, &request_path).map(make_box_body))
+}
+
+/// Reads and validates the request, returns the forms to bundle.
+async fn read_bundle_request(req: Request<Incoming>) -> Result<BTreeSet<BundleEntry>, String> {
+    let body = Limited::new(req.into_body(), MAX_BUNDLE_REQUEST_SIZE)
+        .collect()
+        .await
+        .map_err(|e| format!("Failed reading request body: {e}"))?
+        .to_bytes();
+    let request: BundleRequest =
+        serde_json::from_slice(&body).map_err(|e| format!("Invalid request body: {e}"))?;
+    if request.forms.len() > MAX_BUNDLE_FORMS {
+        return Err(format!(
+            "Too many forms requested, at most {} are allowed.",
+            MAX_BUNDLE_FORMS
+        ));
+    }
+
+    let mut entries = BTreeSet::new();
+    for form in &request.forms {
+        let (monster_id, form_path) =
+            parse_bundle_form(form).ok_or_else(|| format!("Invalid form path: {form}"))?;
+        for category in &request.categories {
+            entries.insert((*category, monster_id, form_path.clone()));
+        }
+    }
+    Ok(entries)
+}
+
+/// Parses a form path like `0025/0001` (or `0025-0001`) into the monster ID and form path.
+fn parse_bundle_form(form: &str) -> Option<(i32, Vec<i32>)> {
+    let mut ids = form
+        .split(['/', '-'])
+        .map(|segment| segment.parse::<i32>().ok().filter(|id| *id >= 0))
+        .collect::<Option<Vec<i32>>>()?
+        .into_iter();
+    let monster_id = ids.next()?;
+    Some((monster_id, ids.collect()))
+}
+
+/// The forms of the request that are not in the tracker, eg. `0025/0099`.
+fn unknown_forms(tracker: &Tracker, entries: &BTreeSet<BundleEntry>) -> Vec<String> {
+    entries
+        .iter()
+        .map(|(_, monster_id, form_path)| (*monster_id, form_path))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|(monster_id, form_path)| {
+            MonsterFormCollector::collect(tracker, *monster_id)
+                .and_then(|collector| {
+                    collector
+                        .find_form(form_path.iter().copied().map(FormMatch::Exact))
+                        .map(|_| ())
+                })
+                .is_none()
+        })
+        .map(|(monster_id, form_path)| join_monster_and_form(monster_id, form_path, '/'))
+        .collect()
+}
+
+/// Blocking, returns the files to bundle (name in the ZIP, path). Fails if they are larger than
+/// the asset size limit together.
+fn list_bundle_files(
+    sprite_collab: &SpriteCollab,
+    entries: &BTreeSet<BundleEntry>,
+    cancellation: &Cancellation,
+) -> Result<Vec<(String, PathBuf)>, anyhow::Error> {
+    let mut files = Vec::new();
+    let mut size = 0;
+    for (category, monster_id, form_path) in entries {
+        cancellation.check()?;
+        let base_path =
+            sprite_collab
+                .asset_store()
+                .form_dir(category.asset_category(), *monster_id, form_path);
+        let joined = join_monster_and_form(*monster_id, form_path, '/');
+        if !base_path.is_dir() {
+            continue;
+        }
+        for path in fs::read_dir(&base_path)? {
+            let path = path?;
+            if path.file_type()?.is_file() {
+                size += path.metadata()?.len();
+                check_asset_size(size)?;
+                let file_name = path.file_name();
+                let file_name = file_name
+                    .to_str()
+                    .ok_or_else(|| anyhow!("Invalid file name in {:?}", base_path))?;
+                files.push((
+                    format!("{}/{}/{}", category.dir_name(), joined, file_name),
+                    path.path(),
+                ));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Blocking, zips the files into `writer`. Stops after the current file once the client
+/// disconnected.
+fn write_bundle_zip(
+    writer: &mut ChannelWriter,
+    files: Vec<(String, PathBuf)>,
+) -> Result<(), anyhow::Error> {
+    let mut zip = ZipWriter::new(&mut *writer);
+    zip.set_flush_on_finish_file(true);
+
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    for (file_name, path) in files {
+        zip.start_file(file_name, options)?;
+        zip.write_all(&fs::read(path)?)?;
+    }
+    zip.finish()?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Sends a ZIP to a [`BundleBody`] while it is written. `ZipWriter` seeks back into the file it
+/// is writing to fill in its size and checksum, so the bytes of a file are only sent once it is
+/// flushed after it, see [`ZipWriter::set_flush_on_finish_file`].
+struct ChannelWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    /// The bytes that were not sent yet, they start at `sent` in the ZIP.
+    buf: Vec<u8>,
+    sent: u64,
+    position: u64,
+}
+
+impl ChannelWriter {
+    fn new(sender: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        Self {
+            sender,
+            buf: Vec::new(),
+            sent: 0,
+            position: 0,
+        }
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let offset = (self.position - self.sent) as usize;
+        let overwritten = data.len().min(self.buf.len() - offset);
+        self.buf[offset..offset + overwritten].copy_from_slice(&data[..overwritten]);
+        self.buf.extend_from_slice(&data[overwritten..]);
+        self.position += data.len() as u64;
+        Ok(data.len())
+    }
+
+    /// Sends the bytes before the current position.
+    fn flush(&mut self) -> io::Result<()> {
+        let chunk = self
+            .buf
+            .drain(..(self.position - self.sent) as usize)
+            .collect::<Vec<_>>();
+        self.sent = self.position;
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        self.sender
+            .blocking_send(Ok(Bytes::from(chunk)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the client disconnected"))
+    }
+}
+
+/// Only needed for [`ZipWriter::set_flush_on_finish_file`], the ZIP is never read back.
+impl Read for ChannelWriter {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the bundle can't be read back",
+        ))
+    }
+}
+
+impl Seek for ChannelWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let end = self.sent + self.buf.len() as u64;
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => end.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) if (self.sent..=end).contains(&position) => {
+                self.position = position;
+                Ok(position)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't seek outside of the bytes that were not sent yet",
+            )),
+        }
+    }
+}
+
+/// The body of a bundle, the chunks of the ZIP as they are written.
+struct BundleBody(mpsc::Receiver<io::Result<Bytes>>);
+
+impl Body for BundleBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.get_mut()
+            .0
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map(Frame::data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::Path;
+
+    use zip::ZipArchive;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn streams_the_zip_one_file_at_a_time() {
+        let sprite_dir =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/spritecollab/sprite/0001");
+        let mut files = fs::read_dir(&sprite_dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_str().unwrap().to_string();
+                (format!("sprite/0001/{}", name), path)
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        let expected = files.clone();
+
+        let (sender, receiver) = mpsc::channel(1);
+        let writing = tokio::task::spawn_blocking(move || {
+            write_bundle_zip(&mut ChannelWriter::new(sender), files)
+        });
+        let mut body = BundleBody(receiver);
+        let mut zip = Vec::new();
+        let mut chunks = 0;
+        while let Some(frame) = body.frame().await {
+            zip.extend_from_slice(&frame.unwrap().into_data().unwrap());
+            chunks += 1;
+        }
+        writing.await.unwrap().unwrap();
+
+        // One chunk per file and one with the central directory.
+        assert_eq!(chunks, expected.len() + 1);
+        let mut archive = ZipArchive::new(Cursor::new(zip)).unwrap();
+        for (name, path) in expected {
+            let mut content = Vec::new();
+            archive
+                .by_name(&name)
+                .unwrap()
+                .read_to_end(&mut content)
+                .unwrap();
+            assert_eq!(content, fs::read(path).unwrap());
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! The caching headers of generated assets. Assets served under the current assets commit
+//! never change, so they are cached forever, see [`IMMUTABLE`]. Assets served without a commit
+//! (if the data has none, or on mirrors) are cached until the next refresh could change them.
+//! `SCSRV_ASSET_MAX_AGE` sets the max age of single asset types instead, by the names of
+//! [`asset_type_name`]. All successful responses, except for stale ones, get the date of the
+//! assets commit as `Last-Modified`.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hyper::header::{CACHE_CONTROL, LAST_MODIFIED, VARY};
+use hyper::http::HeaderValue;
+use hyper::{HeaderMap, Response};
+
+use crate::assets::url::{AssetType, ASSET_ROUTES};
+use crate::assets::{AssetBody, IMMUTABLE, STALE_HEADER};
+use crate::{ServerConfig, SpriteCollab};
+
+pub(crate) const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// The name of an asset type in `SCSRV_ASSET_MAX_AGE`.
+pub fn asset_type_name(asset_type: &AssetType) -> &'static str {
+    match asset_type {
+        AssetType::PortraitCreditsTxt => "portrait_credits",
+        AssetType::SpriteCreditsTxt => "sprite_credits",
+        AssetType::PortraitSheet => "portrait_sheet",
+        AssetType::PortraitRecolorSheet => "portrait_recolor_sheet",
+        AssetType::PortraitAnnotatedSheet => "portrait_annotated_sheet",
+        AssetType::Portrait(_) | AssetType::PortraitFlipped(_) => "portrait",
+        AssetType::SpriteAnimDataXml => "sprite_anim_data",
+        AssetType::SpriteZip => "sprite_zip",
+        AssetType::SpriteSplitZip => "sprite_split_zip",
+        AssetType::PortraitZip => "portrait_zip",
+        AssetType::SpriteRecolorSheet => "sprite_recolor_sheet",
+        AssetType::PortraitPaletteDiff | AssetType::PortraitPaletteDiffSheet => {
+            "portrait_palette_diff"
+        }
+        AssetType::SpritePaletteDiff | AssetType::SpritePaletteDiffSheet => "sprite_palette_diff",
+        AssetType::SpriteAnim(_) | AssetType::SpriteOffsets(_) | AssetType::SpriteShadows(_) => {
+            "sprite"
+        }
+        AssetType::SpriteAtlas(_, _) => "sprite_atlas",
+        AssetType::Preview => "preview",
+    }
+}
+
+/// Whether `name` is the name of an asset type that is served from an asset route.
+pub fn is_asset_type_name(name: &str) -> bool {
+    ASSET_ROUTES
+        .iter()
+        .any(|route| asset_type_name(&route.asset_type) == name)
+}
+
+/// Sets the caching headers of a successful response with a generated asset. `versioned`
+/// is whether it was requested under the current assets commit.
+pub fn apply_cache_headers(
+    response: &mut Response<AssetBody>,
+    asset_type: &AssetType<'_>,
+    versioned: bool,
+    sprite_collab: &SpriteCollab,
+) {
+    if !response.status().is_success() {
+        return;
+    }
+    let modified = sprite_collab.with_meta(|meta| meta.assets_update_date);
+    let config = ServerConfig::get();
+    let max_age = config
+        .asset_max_age
+        .get(asset_type_name(asset_type))
+        .copied();
+    set_cache_headers(
+        response.headers_mut(),
+        max_age,
+        versioned,
+        config.refresh_interval,
+        Some(modified),
+    );
+}
+
+fn set_cache_headers(
+    headers: &mut HeaderMap,
+    max_age: Option<Duration>,
+    versioned: bool,
+    refresh_interval: Duration,
+    modified: Option<DateTime<Utc>>,
+) {
+    // Stale assets are replaced soon, under the same URL.
+    let stale = headers.contains_key(STALE_HEADER);
+    let cache_control = if stale {
+        "no-cache".to_string()
+    } else {
+        match (max_age, versioned) {
+            (Some(max_age), _) => format!("public, max-age={}", max_age.as_secs()),
+            (None, true) => IMMUTABLE.to_string(),
+            (None, false) => format!("public, max-age={}", refresh_interval.as_secs()),
+        }
+    };
+    if let Ok(value) = HeaderValue::from_str(&cache_control) {
+        headers.insert(CACHE_CONTROL, value);
+    }
+    // The date is the one of the current commit, not of stale assets.
+    if let Some(modified) = modified.filter(|_| !stale) {
+        if let Ok(value) = HeaderValue::from_str(&modified.format(HTTP_DATE_FORMAT).to_string()) {
+            headers.insert(LAST_MODIFIED, value);
+        }
+    }
+    // Text and JSON assets may be compressed, see `crate::compression`.
+    headers.insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn sets_cache_headers() {
+        let refresh_interval = Duration::from_secs(900);
+        let modified = Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap();
+        let mut headers = HeaderMap::new();
+        set_cache_headers(&mut headers, None, true, refresh_interval, Some(modified));
+        assert_eq!(headers[CACHE_CONTROL], IMMUTABLE);
+        assert_eq!(headers[LAST_MODIFIED], "Fri, 01 Mar 2024 12:30:00 GMT");
+        assert_eq!(headers[VARY], "Accept-Encoding");
+
+        let mut headers = HeaderMap::new();
+        set_cache_headers(&mut headers, None, false, refresh_interval, None);
+        assert_eq!(headers[CACHE_CONTROL], "public, max-age=900");
+        assert!(!headers.contains_key(LAST_MODIFIED));
+
+        let mut headers = HeaderMap::new();
+        let max_age = Some(Duration::from_secs(60));
+        set_cache_headers(&mut headers, max_age, true, refresh_interval, None);
+        assert_eq!(headers[CACHE_CONTROL], "public, max-age=60");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(STALE_HEADER, HeaderValue::from_static("true"));
+        set_cache_headers(
+            &mut headers,
+            max_age,
+            true,
+            refresh_interval,
+            Some(modified),
+        );
+        assert_eq!(headers[CACHE_CONTROL], "no-cache");
+        assert!(!headers.contains_key(LAST_MODIFIED));
+
+        assert!(is_asset_type_name("sprite_zip"));
+        assert!(!is_asset_type_name("portrait"));
+    }
+}
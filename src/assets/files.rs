@@ -0,0 +1,154 @@
+//! Serving of the raw files of the SpriteCollab repository (portraits, sprite sheets,
+//! AnimData.xml files and the data files) from the checkout in the workdir, at
+//! `/assets/files/<path in the repository>`, eg.
+//! `/assets/files/portrait/0025/0000/0001/Normal.png`. This mirrors the layout of the upstream
+//! raw file URLs, so the server does not depend on the upstream repository being available.
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE, IF_MODIFIED_SINCE, LAST_MODIFIED};
+use hyper::http::HeaderValue;
+use hyper::{HeaderMap, Response, StatusCode};
+use percent_encoding::percent_decode_str;
+use tokio::fs;
+
+use crate::assets::cache_headers::HTTP_DATE_FORMAT;
+use crate::assets::fs_check::AssetCategory;
+use crate::assets::store::AssetStore;
+use crate::assets::{make_box_body, make_err_response, AssetBody};
+use crate::sprite_collab::GIT_REPO_DIR;
+use crate::ServerConfig;
+
+/// Path the repository files are served under.
+pub const FILES_PATH: &str = "/assets/files";
+/// The data files in the root of the repository that are served.
+pub const DATA_FILES: &[&str] = &[
+    "tracker.json",
+    "sprite_config.json",
+    "credit_names.txt",
+    "translations.json",
+];
+
+/// Serves a file of the repository, `path` is the path relative to [`FILES_PATH`]. Returns
+/// `None` if the path is not a portrait, sprite or data file, or if it does not exist.
+pub async fn serve_repository_file(
+    path: &str,
+    request_headers: &HeaderMap,
+    asset_store: &dyn AssetStore,
+) -> Option<Response<AssetBody>> {
+    let file_path = resolve_repository_file(path, asset_store)?;
+    let content_type = content_type_of(file_path.extension()?.to_str()?)?;
+    let modified = fs::metadata(&file_path)
+        .await
+        .ok()
+        .filter(|metadata| metadata.is_file())?
+        .modified()
+        .ok()
+        .map(truncate_to_secs);
+
+    if let (Some(modified), Some(since)) = (modified, if_modified_since(request_headers)) {
+        if modified <= since {
+            let mut response = Response::new(make_box_body(Full::new(Bytes::new())));
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            add_caching_headers(response.headers_mut(), Some(modified));
+            return Some(response);
+        }
+    }
+
+    let content = match fs::read(&file_path).await {
+        Ok(content) => content,
+        Err(e) => return Some(make_err_response(e, path).map(make_box_body)),
+    };
+    let mut response = Response::new(make_box_body(Full::new(Bytes::from(content))));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    add_caching_headers(headers, modified);
+    Some(response)
+}
+
+/// The content type of the files with the extension, `None` if they are not served.
+pub(crate) fn content_type_of(extension: &str) -> Option<&'static str> {
+    match extension {
+        "png" => Some("image/png"),
+        "xml" => Some("application/xml"),
+        "json" => Some("application/json"),
+        "txt" => Some("text/plain; charset=utf-8"),
+        _ => None,
+    }
+}
+
+/// Returns the path of the file. Only the [`DATA_FILES`] and files in numeric form directories
+/// of the portrait and sprite directories can be resolved, the latter in the `asset_store`.
+fn resolve_repository_file(path: &str, asset_store: &dyn AssetStore) -> Option<PathBuf> {
+    let path = percent_decode_str(path.trim_start_matches('/'))
+        .decode_utf8()
+        .ok()?;
+    if DATA_FILES.contains(&path.as_ref()) {
+        return Some(ServerConfig::get().workdir.join(GIT_REPO_DIR).join(&*path));
+    }
+    let mut segments = path.split('/').collect::<Vec<_>>();
+    let file_name = segments.pop()?;
+    let (category, form_dirs) = segments.split_first()?;
+    let category = match *category {
+        "portrait" => AssetCategory::Portrait,
+        "sprite" => AssetCategory::Sprite,
+        _ => return None,
+    };
+    if !form_dirs
+        .iter()
+        .all(|dir| dir.len() == 4 && dir.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    let form_ids = form_dirs
+        .iter()
+        .map(|dir| dir.parse::<i32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if file_name.starts_with('.')
+        || file_name.contains('\\')
+        || !file_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '^' | '.'))
+    {
+        return None;
+    }
+    let (monster_id, form_path) = form_ids.split_first()?;
+    Some(
+        asset_store
+            .form_dir(category, *monster_id, form_path)
+            .join(file_name),
+    )
+}
+
+fn if_modified_since(request_headers: &HeaderMap) -> Option<SystemTime> {
+    let value = request_headers.get(IF_MODIFIED_SINCE)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| SystemTime::from(date.with_timezone(&Utc)))
+}
+
+/// Lets clients cache the file until the next refresh of the data could change it.
+fn add_caching_headers(headers: &mut HeaderMap, modified: Option<SystemTime>) {
+    let max_age = ServerConfig::get().refresh_interval.as_secs();
+    if let Ok(value) = HeaderValue::from_str(&format!("public, max-age={}", max_age)) {
+        headers.insert(CACHE_CONTROL, value);
+    }
+    if let Some(modified) = modified {
+        let date = DateTime::<Utc>::from(modified).format(HTTP_DATE_FORMAT);
+        if let Ok(value) = HeaderValue::from_str(&date.to_string()) {
+            headers.insert(LAST_MODIFIED, value);
+        }
+    }
+}
+
+/// HTTP dates only have a precision of seconds.
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since_epoch) => SystemTime::UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs()),
+        Err(_) => time,
+    }
+}
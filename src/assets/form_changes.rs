@@ -0,0 +1,226 @@
+//! Changes of the portrait and sprite files of a form between two commits, from the diff of
+//! the trees of the commits. Files of subforms are not included.
+
+use std::path::Path;
+
+use git2::{Commit, DiffFile, DiffOptions, ErrorCode, Oid, Repository};
+
+use crate::assets::fs_check::AssetCategory;
+use crate::service::full_form_path;
+
+/// Suffixes of the files of a sprite action, eg. `Idle-Anim.png`.
+const SPRITE_FILE_SUFFIXES: &[&str] = &["-Anim.png", "-Offsets.png", "-Shadow.png"];
+
+/// What a file in the directory of a form belongs to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SpritePathInfo {
+    pub category: AssetCategory,
+    pub monster_id: i32,
+    pub form_path: Vec<i32>,
+    /// The emotion (eg. `Happy^`) or action (eg. `Idle`) of the file. `None` for files of the
+    /// whole form, like `credits.txt` or `AnimData.xml`.
+    pub name: Option<String>,
+}
+
+impl SpritePathInfo {
+    /// Parses a path relative to the repository, eg. `sprite/0025/0000/0001/Idle-Anim.png`.
+    /// Returns `None` if the file is not in the directory of a form.
+    pub fn try_from_path(path: &str) -> Option<Self> {
+        let mut dirs = path.split('/').collect::<Vec<_>>();
+        let file_name = dirs.pop()?;
+        let category = match dirs.first() {
+            Some(&"portrait") => AssetCategory::Portrait,
+            Some(&"sprite") => AssetCategory::Sprite,
+            _ => return None,
+        };
+        let mut ids = dirs[1..]
+            .iter()
+            .map(|dir| {
+                Some(dir)
+                    .filter(|dir| dir.len() == 4 && dir.bytes().all(|b| b.is_ascii_digit()))
+                    .and_then(|dir| dir.parse::<i32>().ok())
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if ids.is_empty() {
+            return None;
+        }
+        let monster_id = ids.remove(0);
+        let name = match category {
+            AssetCategory::Portrait => file_name.strip_suffix(".png"),
+            AssetCategory::Sprite => SPRITE_FILE_SUFFIXES
+                .iter()
+                .find_map(|suffix| file_name.strip_suffix(suffix)),
+        };
+        Some(Self {
+            category,
+            monster_id,
+            form_path: ids,
+            name: name.filter(|name| !name.is_empty()).map(str::to_string),
+        })
+    }
+}
+
+/// A file of a form that was added, modified or deleted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FormFileChange {
+    /// The path relative to the repository.
+    pub path: String,
+    pub info: SpritePathInfo,
+    /// The blob in the first commit, `None` if the file was added.
+    pub from_oid: Option<Oid>,
+    /// The blob in the second commit, `None` if the file was deleted.
+    pub to_oid: Option<Oid>,
+}
+
+/// The changed files of the portraits and sprites of a form between the commits `from` and
+/// `to` (IDs, or unique prefixes of them). Returns `None` if one of the commits doesn't exist.
+pub fn form_changes(
+    repo_path: &Path,
+    from: &str,
+    to: &str,
+    monster_id: i32,
+    form_path: &[i32],
+) -> Result<Option<Vec<FormFileChange>>, anyhow::Error> {
+    let repo = Repository::open(repo_path)?;
+    let (from, to) = match (find_commit(&repo, from)?, find_commit(&repo, to)?) {
+        (Some(from), Some(to)) => (from.tree()?, to.tree()?),
+        _ => return Ok(None),
+    };
+    let dir = full_form_path(monster_id, form_path);
+    let mut options = DiffOptions::new();
+    options
+        .pathspec(format!("portrait/{}", dir))
+        .pathspec(format!("sprite/{}", dir));
+    let diff = repo.diff_tree_to_tree(Some(&from), Some(&to), Some(&mut options))?;
+    Ok(Some(
+        diff.deltas()
+            .filter_map(|delta| {
+                let path = delta
+                    .new_file()
+                    .path()
+                    .or(delta.old_file().path())?
+                    .to_str()?
+                    .to_string();
+                let info = SpritePathInfo::try_from_path(&path)?;
+                // The pathspecs also match the directories of the subforms.
+                (info.monster_id == monster_id && info.form_path == form_path).then(|| {
+                    FormFileChange {
+                        path,
+                        info,
+                        from_oid: blob_id(&delta.old_file()),
+                        to_oid: blob_id(&delta.new_file()),
+                    }
+                })
+            })
+            .collect(),
+    ))
+}
+
+/// Whether `commit` looks like a commit ID or an abbreviation of one. Other revisions, like
+/// `HEAD~1`, are not accepted from clients.
+pub fn is_commit_id_prefix(commit: &str) -> bool {
+    (4..=40).contains(&commit.len()) && commit.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// A commit by its ID or a unique prefix of it. `None` if it doesn't exist or the prefix is
+/// ambiguous.
+pub(crate) fn find_commit<'r>(
+    repo: &'r Repository,
+    commit: &str,
+) -> Result<Option<Commit<'r>>, anyhow::Error> {
+    let object = match repo.revparse_single(commit) {
+        Ok(object) => object,
+        Err(e) if matches!(e.code(), ErrorCode::NotFound | ErrorCode::Ambiguous) => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(object.peel_to_commit().ok())
+}
+
+fn blob_id(file: &DiffFile) -> Option<Oid> {
+    Some(file.id()).filter(|oid| !oid.is_zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SyntheticRepo;
+
+    #[test]
+    fn parses_form_files() {
+        assert_eq!(
+            SpritePathInfo::try_from_path("sprite/0025/0000/0001/Idle-Anim.png"),
+            Some(SpritePathInfo {
+                category: AssetCategory::Sprite,
+                monster_id: 25,
+                form_path: vec![0, 1],
+                name: Some("Idle".to_string()),
+            })
+        );
+        assert_eq!(
+            SpritePathInfo::try_from_path("portrait/0025/Happy^.png").and_then(|info| info.name),
+            Some("Happy^".to_string())
+        );
+        assert_eq!(
+            SpritePathInfo::try_from_path("portrait/0025/credits.txt").map(|info| info.name),
+            Some(None)
+        );
+        assert_eq!(SpritePathInfo::try_from_path("portrait/Normal.png"), None);
+        assert_eq!(
+            SpritePathInfo::try_from_path("sprite/abcd/Idle-Anim.png"),
+            None
+        );
+        assert_eq!(SpritePathInfo::try_from_path("tracker.json"), None);
+    }
+
+    #[test]
+    fn diffs_files_of_a_form() {
+        let repo = SyntheticRepo::create("scsrv-form-changes-test");
+        let initial = repo.head();
+        repo.write("portrait/0001/Normal.png", "changed");
+        repo.remove("sprite/0001/Walk-Shadow.png");
+        repo.write("portrait/0001/0000/0001/Normal.png", "subform");
+        let changed = repo.commit("Change 0001");
+
+        let changes = form_changes(
+            repo.path(),
+            &initial.to_string(),
+            &changed.to_string()[..10],
+            1,
+            &[],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| (
+                    change.path.as_str(),
+                    change.from_oid.is_some(),
+                    change.to_oid.is_some()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("portrait/0001/Normal.png", true, true),
+                ("sprite/0001/Walk-Shadow.png", true, false)
+            ]
+        );
+        let subform = form_changes(
+            repo.path(),
+            &initial.to_string(),
+            &changed.to_string(),
+            1,
+            &[0, 1],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(subform.len(), 1);
+        assert_eq!(subform[0].from_oid, None);
+        assert!(
+            form_changes(repo.path(), "0123456789", &changed.to_string(), 1, &[])
+                .unwrap()
+                .is_none()
+        );
+    }
+}
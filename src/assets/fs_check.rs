@@ -1,19 +1,24 @@
-//! This module double checks if sprite and portrait files actually exist.
+//! This module double checks if sprite and portrait files actually exist, in the
+//! [`AssetStore`] of the server.
 
-use std::borrow::Cow;
 use std::fmt::{Display, Formatter};
-use std::path::PathBuf;
+use std::path::Path;
 use std::sync::Arc;
 
-use crate::assets::util::join_monster_and_form;
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::store::AssetStore;
 use crate::cache::CacheBehaviour;
 use crate::cache::ScCache;
+use crate::datafiles::anim_data_xml::AnimDataXml;
 use crate::datafiles::local_credits_file::{get_credits, LocalCreditRow};
+use crate::datafiles::sprite_config::{ActionName, Emotion};
 use crate::datafiles::tracker::MapImpl;
 use crate::datafiles::{DataReadError, DataReadResult};
-use crate::Config;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum AssetCategory {
     Sprite,
     Portrait,
@@ -29,8 +34,8 @@ impl Display for AssetCategory {
 }
 
 enum FileLookup<'a, I: Iterator<Item = &'a String> + Clone> {
-    Sprite(I, i32, &'a [i32]),
-    Portrait(I, i32, &'a [i32]),
+    Sprite(&'a dyn AssetStore, I, i32, &'a [i32]),
+    Portrait(&'a dyn AssetStore, I, i32, &'a [i32]),
 }
 
 impl<'a, C> FileLookup<'a, C>
@@ -43,28 +48,17 @@ where
 
     fn all(&self) -> C {
         match self {
-            FileLookup::Sprite(all, _, _) => all.clone(),
-            FileLookup::Portrait(all, _, _) => all.clone(),
-        }
-    }
-
-    fn path(&self, act: &str) -> PathBuf {
-        match self {
-            FileLookup::Sprite(_, mon, path) => {
-                let joined_p = join_monster_and_form(*mon, path, '/');
-                PathBuf::from(Config::Workdir.get())
-                    .join(format!("spritecollab/sprite/{}/{}-Anim.png", joined_p, act))
-            }
-            FileLookup::Portrait(_, mon, path) => {
-                let joined_p = join_monster_and_form(*mon, path, '/');
-                PathBuf::from(Config::Workdir.get())
-                    .join(format!("spritecollab/portrait/{}/{}.png", joined_p, act))
-            }
+            FileLookup::Sprite(_, all, _, _) => all.clone(),
+            FileLookup::Portrait(_, all, _, _) => all.clone(),
         }
     }
 
     fn do_single_lookup(&self, act: &str) -> Option<String> {
-        if self.path(act).exists() {
+        let exists = match self {
+            FileLookup::Sprite(store, _, mon, path) => store.has_sprite(*mon, path, act),
+            FileLookup::Portrait(store, _, mon, path) => store.has_portrait(*mon, path, act),
+        };
+        if exists {
             Some(act.to_string())
         } else {
             None
@@ -81,12 +75,12 @@ impl FileLookupCache {
         I: Iterator<Item = &'a String> + Send + Sync + Clone,
     {
         let data = match lookup {
-            FileLookup::Sprite(_, mon, pat) => {
+            FileLookup::Sprite(_, _, mon, pat) => {
                 cache
                     .cached(format!("spr_files|{}/{:?}", mon, pat), || lookup.lookup())
                     .await
             }
-            FileLookup::Portrait(_, mon, pat) => {
+            FileLookup::Portrait(_, _, mon, pat) => {
                 cache
                     .cached(format!("prt_files|{}/{:?}", mon, pat), || lookup.lookup())
                     .await
@@ -116,13 +110,14 @@ impl FileLookupCache {
 
 pub async fn iter_existing_sprite_files<C: ScCache + Send + Sync>(
     cache: &C,
+    store: &dyn AssetStore,
     sprite_files: &MapImpl<String, bool>,
     monster_idx: i32,
     form_path: &[i32],
 ) -> Result<impl IntoIterator<Item = (String, bool)>, C::Error> {
     let mut lookup_cache = FileLookupCache::new(
         cache,
-        FileLookup::Sprite(sprite_files.keys(), monster_idx, form_path),
+        FileLookup::Sprite(store, sprite_files.keys(), monster_idx, form_path),
     )
     .await?;
     Ok(sprite_files
@@ -133,23 +128,25 @@ pub async fn iter_existing_sprite_files<C: ScCache + Send + Sync>(
 
 pub async fn get_existing_sprite_file<C: ScCache + Send + Sync>(
     cache: &C,
+    store: &dyn AssetStore,
     sprite_files: &MapImpl<String, bool>,
-    action: &str,
+    action: &ActionName,
     monster_idx: i32,
     form_path: &[i32],
 ) -> Result<Option<bool>, C::Error> {
     let lookup_cache = FileLookupCache::new(
         cache,
-        FileLookup::Sprite(sprite_files.keys(), monster_idx, form_path),
+        FileLookup::Sprite(store, sprite_files.keys(), monster_idx, form_path),
     )
     .await?;
     Ok(sprite_files
-        .get(action)
-        .and_then(|locked| lookup_cache.if_has(action, *locked)))
+        .get(action.as_str())
+        .and_then(|locked| lookup_cache.if_has(action.as_str(), *locked)))
 }
 
 pub async fn iter_existing_portrait_files<C: ScCache + Send + Sync>(
     cache: &C,
+    store: &dyn AssetStore,
     portrait_files: &MapImpl<String, bool>,
     flipped: bool,
     monster_idx: i32,
@@ -157,7 +154,7 @@ pub async fn iter_existing_portrait_files<C: ScCache + Send + Sync>(
 ) -> Result<impl IntoIterator<Item = (String, bool)>, C::Error> {
     let mut lookup_cache = FileLookupCache::new(
         cache,
-        FileLookup::Portrait(portrait_files.keys(), monster_idx, form_path),
+        FileLookup::Portrait(store, portrait_files.keys(), monster_idx, form_path),
     )
     .await?;
     Ok(portrait_files
@@ -175,29 +172,26 @@ pub async fn iter_existing_portrait_files<C: ScCache + Send + Sync>(
 
 pub async fn get_existing_portrait_file<C: ScCache + Send + Sync>(
     cache: &C,
+    store: &dyn AssetStore,
     portrait_files: &MapImpl<String, bool>,
-    emotion: &str,
-    flipped: bool,
+    emotion: &Emotion,
     monster_idx: i32,
     form_path: &[i32],
 ) -> Result<Option<bool>, C::Error> {
     let lookup_cache = FileLookupCache::new(
         cache,
-        FileLookup::Portrait(portrait_files.keys(), monster_idx, form_path),
+        FileLookup::Portrait(store, portrait_files.keys(), monster_idx, form_path),
     )
     .await?;
-    let emotion = if flipped {
-        Cow::from(format!("{}^", emotion))
-    } else {
-        Cow::from(emotion)
-    };
+    let emotion = emotion.to_string();
     Ok(portrait_files
-        .get(emotion.as_ref())
+        .get(&emotion)
         .and_then(|locked| lookup_cache.if_has(&emotion, *locked)))
 }
 
 pub async fn get_local_credits_file<C: ScCache + Send + Sync>(
     cache: &C,
+    store: &dyn AssetStore,
     asset_type: AssetCategory,
     monster_idx: i32,
     form_path: &[i32],
@@ -206,18 +200,11 @@ pub async fn get_local_credits_file<C: ScCache + Send + Sync>(
         .cached_may_fail(
             format!("credits_{}|{}/{:?}", asset_type, monster_idx, form_path),
             || async {
-                let joined_p = join_monster_and_form(monster_idx, form_path, '/');
-                let path = match asset_type {
-                    AssetCategory::Sprite => PathBuf::from(Config::Workdir.get())
-                        .join(format!("spritecollab/sprite/{}/credits.txt", joined_p)),
-                    AssetCategory::Portrait => PathBuf::from(Config::Workdir.get())
-                        .join(format!("spritecollab/portrait/{}/credits.txt", joined_p)),
-                };
-                if path.exists() {
-                    Ok(CacheBehaviour::Cache(Some(tokio::fs::read(path).await?)))
-                } else {
-                    Ok(CacheBehaviour::Cache(None))
-                }
+                Ok(CacheBehaviour::Cache(store.read_credits(
+                    asset_type,
+                    monster_idx,
+                    form_path,
+                )?))
             },
         )
         .await?
@@ -228,3 +215,194 @@ pub async fn get_local_credits_file<C: ScCache + Send + Sync>(
         Err(e) => Ok(Err(e)),
     }
 }
+
+/// Statistics of the portrait or sprite files of a form on disk.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FormFileStats {
+    /// Portraits or sprite actions listed in the tracker whose file exists.
+    pub existing: usize,
+    /// Files in the directory of the form, without the directories of its subforms.
+    pub files: usize,
+    /// Total size of these files in bytes.
+    pub total_size: u64,
+    /// The last modification time of any of these files.
+    pub last_modified: Option<DateTime<Utc>>,
+    /// Sprite actions that are a copy of another action according to the AnimData.xml. Always
+    /// 0 for portraits.
+    pub copy_of_actions: usize,
+}
+
+pub async fn get_form_file_stats<C: ScCache + Send + Sync>(
+    cache: &C,
+    store: &dyn AssetStore,
+    asset_type: AssetCategory,
+    files: &MapImpl<String, bool>,
+    monster_idx: i32,
+    form_path: &[i32],
+) -> Result<FormFileStats, C::Error> {
+    let dir = store.form_dir(asset_type, monster_idx, form_path);
+    let mut stats = cache
+        .cached(
+            format!("stats_{}|{}/{:?}", asset_type, monster_idx, form_path),
+            || async { CacheBehaviour::Cache(read_dir_stats(asset_type, &dir).await) },
+        )
+        .await?;
+    let lookup = match asset_type {
+        AssetCategory::Sprite => FileLookup::Sprite(store, files.keys(), monster_idx, form_path),
+        AssetCategory::Portrait => {
+            FileLookup::Portrait(store, files.keys(), monster_idx, form_path)
+        }
+    };
+    stats.existing = FileLookupCache::new(cache, lookup).await?.0.len();
+    Ok(stats)
+}
+
+/// All statistics except for [`FormFileStats::existing`]. Files that can't be read are skipped.
+async fn read_dir_stats(asset_type: AssetCategory, dir: &Path) -> FormFileStats {
+    let mut stats = FormFileStats::default();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        // The form has no files.
+        Err(_) => return stats,
+    };
+    loop {
+        let entry = match entries.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Failed to list the files in {:?}: {:?}", dir, e);
+                break;
+            }
+        };
+        let metadata = match entry.metadata().await {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("Failed to read the metadata of {:?}: {:?}", entry.path(), e);
+                continue;
+            }
+        };
+        stats.files += 1;
+        stats.total_size += metadata.len();
+        if let Ok(modified) = metadata.modified() {
+            let modified = DateTime::<Utc>::from(modified);
+            stats.last_modified = Some(stats.last_modified.map_or(modified, |m| m.max(modified)));
+        }
+    }
+    if asset_type == AssetCategory::Sprite {
+        if let Ok(xml) = AnimDataXml::open(dir.join("AnimData.xml")) {
+            stats.copy_of_actions = xml.get_action_copies().len();
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::snapshots::fixtures_dir;
+    use crate::assets::store::LocalAssetStore;
+    use crate::datafiles::group_id::GroupId;
+    use crate::datafiles::sprite_config::read_sprite_config;
+    use crate::datafiles::tracker::read_tracker;
+    use crate::testing::{MockCache, SyntheticRepo};
+
+    #[tokio::test]
+    async fn reads_dir_stats() {
+        let sprite_dir = fixtures_dir().join("spritecollab/sprite/0001");
+        let stats = read_dir_stats(AssetCategory::Sprite, &sprite_dir).await;
+        assert_eq!(stats.files, 7);
+        assert_eq!(stats.copy_of_actions, 1);
+        let total_size: u64 = std::fs::read_dir(&sprite_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum();
+        assert_eq!(stats.total_size, total_size);
+        assert!(stats.last_modified.is_some());
+
+        let missing = read_dir_stats(AssetCategory::Portrait, &sprite_dir.join("0001")).await;
+        assert_eq!(missing, FormFileStats::default());
+    }
+
+    #[tokio::test]
+    async fn checks_files_of_the_store() {
+        let repo = SyntheticRepo::create("scsrv-fs-check-test");
+        let store = LocalAssetStore::new(repo.path());
+        let cache = MockCache::default();
+        let tracker = read_tracker(fixtures_dir().join("spritecollab/tracker.json"))
+            .await
+            .unwrap();
+        let group = tracker.get(&GroupId(1)).unwrap();
+        let sprite_config =
+            read_sprite_config(fixtures_dir().join("spritecollab/sprite_config.json"))
+                .await
+                .unwrap();
+
+        let mut portraits =
+            iter_existing_portrait_files(&cache, &store, &group.portrait_files, false, 1, &[])
+                .await
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>();
+        portraits.sort();
+        assert_eq!(
+            portraits,
+            vec![
+                ("Angry".to_string(), true),
+                ("Happy".to_string(), false),
+                ("Normal".to_string(), false)
+            ]
+        );
+        assert_eq!(
+            get_existing_portrait_file(
+                &cache,
+                &store,
+                &group.portrait_files,
+                &sprite_config.emotion("normal^"),
+                1,
+                &[]
+            )
+            .await
+            .unwrap(),
+            Some(false)
+        );
+        // Sleep is in the tracker, but has no file.
+        assert_eq!(
+            get_existing_sprite_file(
+                &cache,
+                &store,
+                &group.sprite_files,
+                &sprite_config.action("Sleep"),
+                1,
+                &[]
+            )
+            .await
+            .unwrap(),
+            None
+        );
+
+        let credits = get_local_credits_file(&cache, &store, AssetCategory::Portrait, 1, &[])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(credits.len(), 1);
+        assert_eq!(credits[0].credit_id, "FixtureAuthor");
+        let stats = get_form_file_stats(
+            &cache,
+            &store,
+            AssetCategory::Sprite,
+            &group.sprite_files,
+            1,
+            &[],
+        )
+        .await
+        .unwrap();
+        assert_eq!(stats.existing, 2);
+        // The files of the fixture and the credits.
+        assert_eq!(stats.files, 8);
+
+        // The files of each category were listed once, and reused by the later lookups.
+        assert_eq!(cache.misses(), 4);
+        assert_eq!(cache.hits(), 2);
+    }
+}
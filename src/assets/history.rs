@@ -0,0 +1,248 @@
+//! Sheets and ZIPs of a form as it was at an older commit, with `?commit=<commit>` (an ID, or
+//! a unique prefix of it). The files of the form are read from the git object database and
+//! extracted into a temporary directory in the workdir, from which the asset is generated like
+//! from the checkout. The directory is removed once the asset is generated.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use git2::{ObjectType, Oid, Repository, Tree};
+use log::warn;
+
+use crate::assets::form_changes::{find_commit, is_commit_id_prefix};
+use crate::assets::fs_check::AssetCategory;
+use crate::datafiles::tracker::Group;
+use crate::service::full_form_path;
+use crate::sprite_collab::GIT_REPO_DIR;
+use crate::ServerConfig;
+
+/// Directory in the workdir the files of older commits are extracted into.
+const HISTORY_DIR: &str = "history";
+
+/// Distinguishes the directories of concurrent extractions.
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// Resolves `commit` and checks that the portraits or sprites of the form exist in it. Returns
+/// `None` if the commit or the directory of the form in it doesn't exist.
+pub fn find_form_commit(
+    repo_path: &Path,
+    commit: &str,
+    category: AssetCategory,
+    monster_id: i32,
+    form_path: &[i32],
+) -> Result<Option<Oid>, anyhow::Error> {
+    if !is_commit_id_prefix(commit) {
+        return Ok(None);
+    }
+    let repo = Repository::open(repo_path)?;
+    let commit = match find_commit(&repo, commit)? {
+        Some(commit) => commit,
+        None => return Ok(None),
+    };
+    let form_tree = form_tree(&repo, &commit.tree()?, category, monster_id, form_path)?;
+    Ok(form_tree.map(|_| commit.id()))
+}
+
+/// The directory with the portraits or sprites of a form, either in the checkout or extracted
+/// from an older commit. An extracted directory is removed when this is dropped.
+pub struct FormDir {
+    path: PathBuf,
+    extracted: bool,
+}
+
+impl FormDir {
+    /// `current` is the directory of the form in the checkout, it is used if `commit` is `None`.
+    /// The commit must have been found with [`find_form_commit`].
+    pub async fn open(
+        current: PathBuf,
+        commit: Option<Oid>,
+        category: AssetCategory,
+        monster_id: i32,
+        form_path: Vec<i32>,
+    ) -> Result<Self, anyhow::Error> {
+        let commit = match commit {
+            Some(commit) => commit,
+            None => {
+                return Ok(Self {
+                    path: current,
+                    extracted: false,
+                })
+            }
+        };
+        let workdir = &ServerConfig::get().workdir;
+        let repo_path = workdir.join(GIT_REPO_DIR);
+        let slf = Self {
+            path: workdir.join(HISTORY_DIR).join(format!(
+                "{}-{}",
+                commit,
+                NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+            )),
+            extracted: true,
+        };
+        let path = slf.path.clone();
+        tokio::task::spawn_blocking(move || {
+            extract_form(&repo_path, commit, category, monster_id, &form_path, &path)
+        })
+        .await??;
+        Ok(slf)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `group` with the portraits that exist in this directory, so the sheets of an older
+    /// commit contain the emotions of that commit.
+    pub fn portrait_group(&self, group: &Group) -> Result<Group, anyhow::Error> {
+        let mut group = group.clone();
+        if self.extracted {
+            let mut emotions = Vec::new();
+            for entry in fs::read_dir(&self.path)? {
+                if let Some(emotion) = entry?
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(".png"))
+                {
+                    emotions.push(emotion.to_string());
+                }
+            }
+            emotions.sort();
+            let locked = group.portrait_files.clone();
+            group.portrait_files = emotions
+                .into_iter()
+                .map(|emotion| {
+                    let is_locked = locked.get(&emotion).copied().unwrap_or_default();
+                    (emotion, is_locked)
+                })
+                .collect();
+        }
+        Ok(group)
+    }
+}
+
+impl Drop for FormDir {
+    fn drop(&mut self) {
+        if self.extracted {
+            if let Err(e) = fs::remove_dir_all(&self.path) {
+                if e.kind() != ErrorKind::NotFound {
+                    warn!("Failed removing {}: {}", self.path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// The tree of the portraits or sprites of a form, `None` if it doesn't exist.
+fn form_tree<'r>(
+    repo: &'r Repository,
+    tree: &Tree,
+    category: AssetCategory,
+    monster_id: i32,
+    form_path: &[i32],
+) -> Result<Option<Tree<'r>>, anyhow::Error> {
+    let category = match category {
+        AssetCategory::Portrait => "portrait",
+        AssetCategory::Sprite => "sprite",
+    };
+    let path = format!("{}/{}", category, full_form_path(monster_id, form_path));
+    match tree.get_path(Path::new(&path)) {
+        Ok(entry) if entry.kind() == Some(ObjectType::Tree) => {
+            Ok(Some(repo.find_tree(entry.id())?))
+        }
+        Ok(_) => Ok(None),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes the files directly in the directory of the form at `commit` to `target`, without
+/// the directories of its subforms.
+fn extract_form(
+    repo_path: &Path,
+    commit: Oid,
+    category: AssetCategory,
+    monster_id: i32,
+    form_path: &[i32],
+    target: &Path,
+) -> Result<(), anyhow::Error> {
+    let repo = Repository::open(repo_path)?;
+    let tree = repo.find_commit(commit)?.tree()?;
+    fs::create_dir_all(target)?;
+    if let Some(form_tree) = form_tree(&repo, &tree, category, monster_id, form_path)? {
+        for entry in form_tree.iter() {
+            if let (Some(ObjectType::Blob), Some(name)) = (entry.kind(), entry.name()) {
+                fs::write(target.join(name), repo.find_blob(entry.id())?.content())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{unique_temp_path, SyntheticRepo};
+
+    #[test]
+    fn extracts_files_of_older_commits() {
+        let repo = SyntheticRepo::create("scsrv-history-test");
+        let initial = repo.head();
+        repo.remove("portrait/0001/Angry.png");
+        repo.write("portrait/0001/0000/0001/Normal.png", "subform");
+        repo.commit("Remove the Angry portrait of 0001");
+
+        let commit = find_form_commit(
+            repo.path(),
+            &initial.to_string()[..8],
+            AssetCategory::Portrait,
+            1,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(commit, Some(initial));
+        assert_eq!(
+            find_form_commit(
+                repo.path(),
+                &initial.to_string(),
+                AssetCategory::Portrait,
+                2,
+                &[]
+            )
+            .unwrap(),
+            None
+        );
+        assert_eq!(
+            find_form_commit(repo.path(), "HEAD", AssetCategory::Portrait, 1, &[]).unwrap(),
+            None
+        );
+
+        let target = unique_temp_path("scsrv-history-test-extracted");
+        extract_form(
+            repo.path(),
+            initial,
+            AssetCategory::Portrait,
+            1,
+            &[],
+            &target,
+        )
+        .unwrap();
+        let mut files = fs::read_dir(&target)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "Angry.png",
+                "Happy.png",
+                "Normal.png",
+                "Normal^.png",
+                "credits.txt"
+            ]
+        );
+        fs::remove_dir_all(&target).unwrap();
+    }
+}
@@ -1,14 +1,194 @@
+use std::collections::HashMap;
 use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use image::{Rgba, RgbaImage};
+use anyhow::anyhow;
+use image::imageops::{resize, FilterType};
+use image::{GenericImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::assets::size_limit::check_image_size;
+
+/// Maximum number of colors (excluding transparency) an asset in SpriteCollab may use.
+pub const MAX_ASSET_COLORS: usize = 16;
+/// The largest factor a sheet can be scaled up by.
+pub const MAX_SHEET_SCALE: u32 = 8;
+/// The largest width a sheet can be scaled to.
+pub const MAX_SCALED_SHEET_WIDTH: u32 = 4096;
+
+/// Set when the asset that is generated is not needed anymore, eg. because the client
+/// disconnected or the request timed out.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    /// Returns an error if cancelled, so that the work can be stopped with `?`.
+    pub fn check(&self) -> Result<(), anyhow::Error> {
+        if self.0.load(Ordering::Relaxed) {
+            Err(anyhow!("The generation of the asset was cancelled."))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Cancels the work when the future waiting for it is dropped.
+struct CancelOnDrop(Cancellation);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0 .0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Runs image work (decoding, compositing and encoding) on the blocking thread pool, so it
+/// does not stall the async executor. If the returned future is dropped, `func` should stop
+/// at the next [`Cancellation::check`].
+pub async fn run_blocking<F, T>(func: F) -> Result<T, anyhow::Error>
+where
+    F: FnOnce(&Cancellation) -> Result<T, anyhow::Error> + Send + 'static,
+    T: Send + 'static,
+{
+    let cancellation = Cancellation::default();
+    let _cancel_on_drop = CancelOnDrop(cancellation.clone());
+    tokio::task::spawn_blocking(move || func(&cancellation)).await?
+}
+
+/// The palette of a recolor sheet has more colors than fit into its palette strip.
+#[derive(Error, Debug)]
+#[error("the image has {colors} colors, but the palette strip only has room for {max_colors}")]
+pub struct PaletteOverflowError {
+    pub colors: usize,
+    pub max_colors: usize,
+}
+
+/// A recolor sheet as PNG, with the number of colors in its palette strip.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RecolorSheet {
+    pub png: Vec<u8>,
+    pub palette_size: usize,
+}
+
+impl RecolorSheet {
+    /// Whether the palette has more colors than allowed for assets in SpriteCollab.
+    pub fn exceeds_color_limit(&self) -> bool {
+        self.palette_size > MAX_ASSET_COLORS
+    }
+}
+
+/// The size a sheet is requested in, with the `scale` or `max_width` query parameters.
+/// Sheets are always scaled with nearest neighbour, so pixel art stays sharp.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SheetScale {
+    #[default]
+    Original,
+    /// Scale up by an integer factor, up to [`MAX_SHEET_SCALE`].
+    Factor(u32),
+    /// Scale up by the largest integer factor that fits into this width, or down to this width
+    /// if the sheet is wider.
+    MaxWidth(u32),
+}
+
+impl SheetScale {
+    /// Reads the scale from the query parameters. Invalid values are ignored.
+    pub fn from_query(query: &HashMap<String, String>) -> Self {
+        if let Some(factor) = query.get("scale").and_then(|v| v.parse::<u32>().ok()) {
+            SheetScale::Factor(factor)
+        } else if let Some(width) = query.get("max_width").and_then(|v| v.parse::<u32>().ok()) {
+            SheetScale::MaxWidth(width)
+        } else {
+            SheetScale::Original
+        }
+    }
+
+    /// Scales the image. The result is never wider than [`MAX_SCALED_SHEET_WIDTH`], unless the
+    /// image already is.
+    pub fn apply(self, img: RgbaImage) -> RgbaImage {
+        let (width, height) = img.dimensions();
+        if width == 0 || height == 0 {
+            return img;
+        }
+        let max_factor = (MAX_SCALED_SHEET_WIDTH / width).clamp(1, MAX_SHEET_SCALE);
+        let (new_width, new_height) = match self {
+            SheetScale::Original => return img,
+            SheetScale::Factor(factor) => {
+                let factor = factor.clamp(1, max_factor);
+                (width * factor, height * factor)
+            }
+            SheetScale::MaxWidth(max_width) if max_width < width => {
+                let max_width = max_width.max(1);
+                (
+                    max_width,
+                    ((height as u64 * max_width as u64 / width as u64) as u32).max(1),
+                )
+            }
+            SheetScale::MaxWidth(max_width) => {
+                let factor = (max_width / width).clamp(1, max_factor);
+                (width * factor, height * factor)
+            }
+        };
+        if (new_width, new_height) == (width, height) {
+            return img;
+        }
+        resize(&img, new_width, new_height, FilterType::Nearest)
+    }
+}
+
+/// The background sheets and previews are composited onto, with `?bg=RRGGBB`. By default, or
+/// with `?bg=transparent`, they stay transparent, which is hard to see in dark mode, eg. on
+/// Discord.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Background {
+    #[default]
+    Transparent,
+    Color(Rgba<u8>),
+}
+
+impl Background {
+    /// Reads the background from the query parameters. Invalid values are ignored.
+    pub fn from_query(query: &HashMap<String, String>) -> Self {
+        let hex = match query.get("bg") {
+            Some(bg) => bg.strip_prefix('#').unwrap_or(bg),
+            None => return Background::Transparent,
+        };
+        if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Background::Transparent;
+        }
+        match u32::from_str_radix(hex, 16) {
+            Ok(rgb) => {
+                Background::Color(Rgba([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255]))
+            }
+            Err(_) => Background::Transparent,
+        }
+    }
+
+    /// Composites the image onto the background. The result is opaque.
+    pub fn apply(self, mut img: RgbaImage) -> RgbaImage {
+        if let Background::Color(background) = self {
+            for pixel in img.pixels_mut() {
+                let alpha = pixel[3] as u32;
+                for (channel, background) in pixel.0.iter_mut().zip(background.0).take(3) {
+                    *channel = ((*channel as u32 * alpha + background as u32 * (255 - alpha) + 127)
+                        / 255) as u8;
+                }
+                pixel[3] = 255;
+            }
+        }
+        img
+    }
+}
 
 pub fn to_png(img: RgbaImage) -> Result<Vec<u8>, anyhow::Error> {
+    check_image_size(img.width(), img.height())?;
     let mut png = Vec::new();
     img.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)?;
     Ok(png)
 }
 
-pub fn add_palette_to(img: &mut RgbaImage) {
+/// Returns all unique non-transparent colors of the image, in the order they first appear.
+pub fn collect_palette(img: &RgbaImage) -> Vec<Rgba<u8>> {
     let mut palette: Vec<Rgba<u8>> = Vec::with_capacity(32);
     for px in img.pixels() {
         if px.0[3] == 0 {
@@ -18,7 +198,107 @@ pub fn add_palette_to(img: &mut RgbaImage) {
             palette.push(*px);
         }
     }
-    for (x, px) in palette.into_iter().enumerate() {
-        img.put_pixel(x as u32, 0, px);
+    palette
+}
+
+/// Makes a recolor sheet out of the image: The palette of the image is put into a new row of
+/// pixels above it. The image is scaled by `scale`, the palette strip always stays a single
+/// row. Fails if the palette is wider than the (scaled) image.
+pub fn make_recolor_sheet(
+    img: &RgbaImage,
+    scale: SheetScale,
+) -> Result<RecolorSheet, anyhow::Error> {
+    // Taken before scaling, since scaling down can drop colors.
+    let palette = collect_palette(img);
+    let img = &scale.apply(img.clone());
+    if palette.len() > img.width() as usize {
+        return Err(PaletteOverflowError {
+            colors: palette.len(),
+            max_colors: img.width() as usize,
+        }
+        .into());
+    }
+    let mut sheet = RgbaImage::new(img.width(), img.height() + 1);
+    sheet.copy_from(img, 0, 1)?;
+    for (x, px) in palette.iter().enumerate() {
+        sheet.put_pixel(x as u32, 0, *px);
+    }
+    Ok(RecolorSheet {
+        png: to_png(sheet)?,
+        palette_size: palette.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scaled_size(scale: SheetScale, width: u32, height: u32) -> (u32, u32) {
+        scale.apply(RgbaImage::new(width, height)).dimensions()
+    }
+
+    #[test]
+    fn scale_by_factor() {
+        assert_eq!(scaled_size(SheetScale::Factor(2), 200, 80), (400, 160));
+        assert_eq!(scaled_size(SheetScale::Factor(0), 200, 80), (200, 80));
+        assert_eq!(scaled_size(SheetScale::Factor(100), 10, 10), (80, 80));
+        // Limited to the maximum width.
+        assert_eq!(scaled_size(SheetScale::Factor(4), 2000, 10), (4000, 20));
+    }
+
+    #[test]
+    fn scale_to_max_width() {
+        // Up by an integer factor.
+        assert_eq!(scaled_size(SheetScale::MaxWidth(512), 200, 80), (400, 160));
+        // Down to the width.
+        assert_eq!(scaled_size(SheetScale::MaxWidth(100), 200, 80), (100, 40));
+        assert_eq!(scaled_size(SheetScale::MaxWidth(0), 200, 80), (1, 1));
+    }
+
+    #[test]
+    fn scale_keeps_pixels_sharp() {
+        let mut img = RgbaImage::new(2, 1);
+        img.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        img.put_pixel(1, 0, Rgba([0, 0, 255, 255]));
+        let scaled = SheetScale::Factor(3).apply(img);
+        for x in 0..3 {
+            assert_eq!(scaled.get_pixel(x, 2), &Rgba([255, 0, 0, 255]));
+            assert_eq!(scaled.get_pixel(x + 3, 2), &Rgba([0, 0, 255, 255]));
+        }
+    }
+
+    #[test]
+    fn recolor_sheet_keeps_single_palette_row() {
+        let mut img = RgbaImage::new(4, 4);
+        img.put_pixel(1, 1, Rgba([1, 2, 3, 255]));
+        let sheet = make_recolor_sheet(&img, SheetScale::Factor(2)).unwrap();
+        let png = image::load_from_memory(&sheet.png).unwrap().to_rgba8();
+        assert_eq!(png.dimensions(), (8, 9));
+        assert_eq!(png.get_pixel(0, 0), &Rgba([1, 2, 3, 255]));
+        assert_eq!(png.get_pixel(2, 3), &Rgba([1, 2, 3, 255]));
+    }
+
+    #[test]
+    fn composites_onto_background() {
+        let query =
+            |bg: &str| Background::from_query(&HashMap::from([("bg".to_string(), bg.to_string())]));
+        assert_eq!(
+            query("1e1f22"),
+            Background::Color(Rgba([0x1e, 0x1f, 0x22, 255]))
+        );
+        assert_eq!(
+            query("#FFFFFF"),
+            Background::Color(Rgba([255, 255, 255, 255]))
+        );
+        assert_eq!(query("transparent"), Background::Transparent);
+        assert_eq!(query("+12345"), Background::Transparent);
+
+        let mut img = RgbaImage::new(3, 1);
+        img.put_pixel(1, 0, Rgba([255, 0, 0, 255]));
+        img.put_pixel(2, 0, Rgba([255, 255, 255, 128]));
+        let img = Background::Color(Rgba([0, 0, 0, 255])).apply(img);
+        assert_eq!(img.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(img.get_pixel(1, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(img.get_pixel(2, 0), &Rgba([128, 128, 128, 255]));
     }
 }
@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt::Debug;
+use std::future::Future;
 use std::io::{Cursor, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -7,30 +8,87 @@ use std::sync::Arc;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, Full};
 use hyper::body::{Body, Bytes};
+use hyper::header::{ACCEPT_RANGES, CONTENT_RANGE, ETAG};
 use hyper::http::HeaderValue;
-use hyper::{Method, Response, StatusCode};
+use hyper::{HeaderMap, Method, Response, StatusCode};
 use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use tokio::fs;
 use zip::ZipWriter;
 
+use crate::assets::activity_diff::{serve_activity_diff, ACTIVITY_DIFF_PATH};
+use crate::assets::atlas::{make_sprite_atlas, AtlasFormat};
+use crate::assets::blobs::{serve_blob, BLOBS_PATH};
+use crate::assets::cache_headers::apply_cache_headers;
+use crate::assets::files::{serve_repository_file, FILES_PATH};
+use crate::assets::fs_check::AssetCategory;
+use crate::assets::history::{find_form_commit, FormDir};
+use crate::assets::img_util::{run_blocking, Background, Cancellation, RecolorSheet, SheetScale};
+use crate::assets::not_found::make_form_not_found_response;
+use crate::assets::object_storage::{is_stored_asset_type, serve_stored_asset};
+use crate::assets::palette_diff::{
+    make_palette_diff, make_palette_diff_sheet, PaletteDiff, PaletteDiffSource,
+};
 use crate::assets::portrait_sheets::{
-    make_portrait_recolor_sheet, make_portrait_sheet, PortraitSheetEmotions,
+    make_portrait_annotated_sheet, make_portrait_recolor_sheet, make_portrait_sheet,
+    EmotionSelection, PortraitSheetEmotions,
 };
+use crate::assets::preview::make_preview;
+use crate::assets::range::{entity_tag, requested_range, ByteRange};
+use crate::assets::signed_urls::verify_asset_request;
+use crate::assets::size_limit::{check_asset_size, make_too_large_response};
+use crate::assets::split_sprites::make_split_sprite_zip;
+use crate::assets::sprite_manifest::SpriteManifest;
 use crate::assets::sprite_sheets::make_sprite_recolor_sheet;
-use crate::assets::url::{match_url, AssetType};
-use crate::assets::util::{force_non_shiny_group, join_monster_and_form};
+use crate::assets::url::{get_url, match_url, split_assets_commit, AssetType, AssetUrlBase};
+use crate::assets::util::{force_non_shiny_group, force_shiny_group, parse_query};
 use crate::cache::CacheBehaviour;
-use crate::cache::ScCache;
+use crate::datafiles::anim_data_xml::AnimDataXml;
 use crate::datafiles::tracker::{FormMatch, MonsterFormCollector};
-use crate::{Config, SpriteCollab};
+use crate::mirror::{fetch_asset, MirroredAsset};
+use crate::service::full_form_path;
+use crate::sprite_collab::{CachedVersion, GIT_REPO_DIR};
+use crate::{ServerConfig, SpriteCollab};
 
+pub mod activity_diff;
+mod atlas;
+mod bitmap_font;
+pub mod blobs;
+pub mod bundle;
+pub mod cache_headers;
+pub mod files;
+pub mod form_changes;
 pub mod fs_check;
+pub mod history;
 mod img_util;
-mod portrait_sheets;
+mod not_found;
+pub mod object_storage;
+mod palette_diff;
+pub(crate) mod portrait_sheets;
+mod preview;
+pub mod prewarm;
+pub mod range;
+pub mod signed_urls;
+pub mod size_limit;
+#[cfg(any(test, feature = "render"))]
+pub mod snapshots;
+mod split_sprites;
+mod sprite_manifest;
 mod sprite_sheets;
+pub mod store;
 pub mod url;
 pub mod util;
 
+/// The files that exist for each sprite action, as `<Action>-<Suffix>.png`.
+pub(crate) const SPRITE_FILE_SUFFIXES: [&str; 3] = ["Anim", "Offsets", "Shadow"];
+const MAX_COPY_OF_DEPTH: usize = 10;
+const SPRITE_MANIFEST_FILE_NAME: &str = "manifest.json";
+/// Generated assets are served under the commit they are generated from, so they never change.
+pub(crate) const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Set on responses with an asset of an older commit, while the current one is generated.
+pub(crate) const STALE_HEADER: &str = "X-SC-Stale";
+
 pub type AssetBody = BoxBody<Bytes, Box<dyn Error + Send + Sync + 'static>>;
 
 pub fn make_box_body<B, E>(body: B) -> AssetBody
@@ -47,150 +105,639 @@ where
 pub async fn match_and_process_assets_path(
     method: &Method,
     path: &str,
+    query: Option<&str>,
+    request_headers: &HeaderMap,
     sprite_collab: Arc<SpriteCollab>,
 ) -> Option<Response<AssetBody>> {
     if method != Method::GET {
         return None;
     }
-    if let Some((monster_idx, form_path, asset_type)) = match_url(path) {
-        let portrait_tile_x;
+    let assets_commit = split_assets_commit(path);
+    let asset_path = assets_commit
+        .as_ref()
+        .map_or(path, |(_, asset_path)| asset_path.as_str());
+    if let Some(route_match) = match_url(asset_path) {
+        if let Some(response) = verify_asset_request(&route_match.asset_type(), asset_path, query) {
+            return Some(response.map(make_box_body));
+        }
+    }
+    if ServerConfig::get().mirror_of.is_some() {
+        if let Some((commit, _)) = &assets_commit {
+            let url_base = AssetUrlBase::new(ServerConfig::get().this_server_url(), &sprite_collab);
+            if *commit != url_base.assets_commit {
+                return redirect_to_current_commit(asset_path, query, &url_base);
+            }
+        }
+        let mut response = serve_mirrored_asset(&sprite_collab, asset_path, query).await;
+        if let Some(route_match) = match_url(asset_path) {
+            apply_cache_headers(
+                &mut response,
+                &route_match.asset_type(),
+                false,
+                &sprite_collab,
+            );
+        }
+        return Some(response);
+    }
+    if let Some(file_path) = path.strip_prefix(FILES_PATH) {
+        return serve_repository_file(file_path, request_headers, sprite_collab.asset_store())
+            .await;
+    }
+    if let Some(blob_path) = path.strip_prefix(BLOBS_PATH) {
+        return serve_blob(blob_path, request_headers).await;
+    }
+    if let Some(diff_path) = path.strip_prefix(ACTIVITY_DIFF_PATH) {
+        return serve_activity_diff(diff_path, query).await;
+    }
+    let url_base = AssetUrlBase::new(ServerConfig::get().this_server_url(), &sprite_collab);
+    match assets_commit {
+        Some((commit, asset_path)) if commit == url_base.assets_commit => {
+            if let Some(storage) = &ServerConfig::get().object_storage {
+                let stored = query.is_none()
+                    && match_url(&asset_path)
+                        .is_some_and(|route_match| is_stored_asset_type(&route_match.asset_type()));
+                if stored {
+                    return serve_stored_asset(
+                        &sprite_collab,
+                        storage,
+                        &asset_path,
+                        request_headers,
+                    )
+                    .await;
+                }
+            }
+            let mut response =
+                process_assets_path(&asset_path, query, request_headers, sprite_collab.clone())
+                    .await?;
+            if let Some(route_match) = match_url(&asset_path) {
+                apply_cache_headers(
+                    &mut response,
+                    &route_match.asset_type(),
+                    true,
+                    &sprite_collab,
+                );
+            }
+            Some(response)
+        }
+        Some((_, asset_path)) => redirect_to_current_commit(&asset_path, query, &url_base),
+        None if url_base.assets_commit.is_empty() => {
+            let mut response =
+                process_assets_path(path, query, request_headers, sprite_collab.clone()).await?;
+            if let Some(route_match) = match_url(path) {
+                apply_cache_headers(
+                    &mut response,
+                    &route_match.asset_type(),
+                    false,
+                    &sprite_collab,
+                );
+            }
+            Some(response)
+        }
+        None => redirect_to_current_commit(path, query, &url_base),
+    }
+}
+
+/// Serves an asset of the primary instance, if this is a mirror. The assets are cached like
+/// generated assets, so they are fetched again after a new commit. `asset_path` does not contain
+/// the commit.
+async fn serve_mirrored_asset(
+    sprite_collab: &Arc<SpriteCollab>,
+    asset_path: &str,
+    query: Option<&str>,
+) -> Response<AssetBody> {
+    let path_and_query = match query {
+        Some(query) => format!("{}?{}", asset_path, query),
+        None => asset_path.to_string(),
+    };
+    cached_asset(
+        sprite_collab,
+        format!("mirror|{}", path_and_query),
+        asset_path,
+        move || fetch_asset(path_and_query),
+        |asset: MirroredAsset| asset,
+    )
+    .await
+}
+
+/// Redirects the path of a generated asset of an old commit, or without a commit, to the
+/// asset of the current commit.
+fn redirect_to_current_commit(
+    asset_path: &str,
+    query: Option<&str>,
+    url_base: &AssetUrlBase,
+) -> Option<Response<AssetBody>> {
+    match_url(asset_path)?;
+    let mut location = format!(
+        "{}{}",
+        url_base.generated_assets_url(),
+        asset_path.strip_prefix("/assets")?
+    );
+    if let Some(query) = query {
+        location.push('?');
+        location.push_str(query);
+    }
+    Some(make_redirect_response(&location))
+}
+
+/// Generates the asset at `path`, which does not contain the commit.
+async fn process_assets_path(
+    path: &str,
+    query: Option<&str>,
+    request_headers: &HeaderMap,
+    sprite_collab: Arc<SpriteCollab>,
+) -> Option<Response<AssetBody>> {
+    let query = parse_query(query);
+    if let Some(route_match) = match_url(path) {
+        let monster_idx = route_match.monster_id;
+        let asset_type = route_match.asset_type();
+        // Only the configured branch or tag can be served.
+        if let Some(git_ref) = query.get("ref") {
+            if git_ref != &ServerConfig::get().git_ref {
+                return None;
+            }
+        }
         let portrait_size;
-        let emotions_incl_flipped;
+        let sheet_emotions;
+        let selection_key;
         let tracker;
         {
             let data = sprite_collab.data();
-            portrait_tile_x = data.sprite_config.portrait_tile_x;
             portrait_size = data.sprite_config.portrait_size;
-            emotions_incl_flipped = data
-                .sprite_config
-                .emotions
-                .iter()
-                .cloned()
-                .chain(
-                    data.sprite_config
-                        .emotions
-                        .iter()
-                        .map(|e| format!("{}^", e)),
-                )
-                .collect::<Vec<_>>();
+            // Only the sheets can leave out emotions, see `EmotionSelection`.
+            let selection = match asset_type {
+                AssetType::PortraitSheet
+                | AssetType::PortraitAnnotatedSheet
+                | AssetType::PortraitRecolorSheet => {
+                    match EmotionSelection::from_query(&query, &data.sprite_config) {
+                        Ok(selection) => selection,
+                        Err(e) => return Some(make_bad_request_response(&e)),
+                    }
+                }
+                _ => EmotionSelection::default(),
+            };
+            selection_key = selection.cache_key();
+            sheet_emotions = PortraitSheetEmotions::selected(&data.sprite_config, &selection);
             tracker = data.tracker.clone();
         }
-        let collector = MonsterFormCollector::collect(&tracker, monster_idx)?;
-        let (form_path, _, group) = match asset_type {
-            AssetType::PortraitRecolorSheet => collector.find_form(
-                force_non_shiny_group(&form_path)
-                    .into_iter()
-                    .map(FormMatch::Exact),
-            )?,
-            AssetType::SpriteRecolorSheet => collector.find_form(
-                force_non_shiny_group(&form_path)
+        let collector = match MonsterFormCollector::collect(&tracker, monster_idx) {
+            Some(collector) => collector,
+            None => {
+                return Some(make_form_not_found_response(
+                    request_headers,
+                    monster_idx,
+                    &Vec::from(route_match.form_path.clone()),
+                    None,
+                ))
+            }
+        };
+        let found_form = match asset_type {
+            AssetType::PortraitRecolorSheet
+            | AssetType::PortraitPaletteDiff
+            | AssetType::PortraitPaletteDiffSheet
+            | AssetType::SpritePaletteDiff
+            | AssetType::SpritePaletteDiffSheet
+            | AssetType::SpriteRecolorSheet => collector.find_form(
+                force_non_shiny_group(&route_match.form_path)
                     .into_iter()
                     .map(FormMatch::Exact),
-            )?,
-            _ => collector.find_form(form_path.into_iter().map(FormMatch::Exact))?,
+            ),
+            _ => collector.find_form(route_match.form_path.iter().copied().map(FormMatch::Exact)),
+        };
+        let (form_path, _, group) = match found_form {
+            Some(found_form) => found_form,
+            None => {
+                return Some(make_form_not_found_response(
+                    request_headers,
+                    monster_idx,
+                    &Vec::from(route_match.form_path.clone()),
+                    Some(&collector),
+                ))
+            }
         };
 
-        let joined_p = join_monster_and_form(monster_idx, &form_path, '/');
-        let portrait_base_path = PathBuf::from(Config::Workdir.get())
-            .join(format!("spritecollab/portrait/{}", joined_p));
-        let sprite_base_path =
-            PathBuf::from(Config::Workdir.get()).join(format!("spritecollab/sprite/{}", joined_p));
+        let scale = SheetScale::from_query(&query);
+        let background = Background::from_query(&query);
+        let asset_store = sprite_collab.asset_store();
+        let portrait_base_path =
+            asset_store.form_dir(AssetCategory::Portrait, monster_idx, &form_path);
+        let sprite_base_path = asset_store.form_dir(AssetCategory::Sprite, monster_idx, &form_path);
+        // Sheets and ZIPs can also be generated from the files of the form at an older commit.
+        let commit = match query.get("commit") {
+            Some(commit) => {
+                let category = match asset_type {
+                    AssetType::PortraitSheet
+                    | AssetType::PortraitAnnotatedSheet
+                    | AssetType::PortraitRecolorSheet
+                    | AssetType::PortraitZip => AssetCategory::Portrait,
+                    AssetType::SpriteRecolorSheet
+                    | AssetType::SpriteZip
+                    | AssetType::SpriteSplitZip => AssetCategory::Sprite,
+                    _ => return None,
+                };
+                let repo_path = ServerConfig::get().workdir.join(GIT_REPO_DIR);
+                let (commit, form_path) = (commit.clone(), form_path.to_vec());
+                let found = run_blocking(move |_| {
+                    find_form_commit(&repo_path, &commit, category, monster_idx, &form_path)
+                })
+                .await;
+                match found {
+                    Ok(Some(commit)) => Some(commit),
+                    Ok(None) => return None,
+                    Err(e) => return Some(make_err_response(e, path).map(make_box_body)),
+                }
+            }
+            None => None,
+        };
+        let commit_key = commit
+            .map(|commit| format!("|{}", commit))
+            .unwrap_or_default();
 
+        let group = group.clone();
         match asset_type {
-            AssetType::PortraitCreditsTxt => Some(process_nested_result(
-                sprite_collab
-                    .cached_may_fail(
-                        format!("portrait_credits_txt|{}/{:?}", monster_idx, form_path),
-                        || make_credits_txt(&portrait_base_path),
-                    )
-                    .await
-                    .map(|r| r.map(make_box_body).map(Response::new)),
-                path,
-            )),
-            AssetType::SpriteCreditsTxt => Some(process_nested_result(
-                sprite_collab
-                    .cached_may_fail(
-                        format!("sprite_credits_txt|{}/{:?}", monster_idx, form_path),
-                        || make_credits_txt(&sprite_base_path),
+            AssetType::PortraitCreditsTxt => Some(
+                cached_asset(
+                    &sprite_collab,
+                    format!("portrait_credits_txt|{}/{:?}", monster_idx, form_path),
+                    path,
+                    move || async move { make_credits_txt(&portrait_base_path).await },
+                    |txt: String| TxtResponse(make_box_body(txt)),
+                )
+                .await,
+            ),
+            AssetType::SpriteCreditsTxt => Some(
+                cached_asset(
+                    &sprite_collab,
+                    format!("sprite_credits_txt|{}/{:?}", monster_idx, form_path),
+                    path,
+                    move || async move { make_credits_txt(&sprite_base_path).await },
+                    |txt: String| TxtResponse(make_box_body(txt)),
+                )
+                .await,
+            ),
+            AssetType::PortraitSheet => Some(
+                cached_asset(
+                    &sprite_collab,
+                    format!(
+                        "portrait_sheet|{}/{:?}|{:?}|{:?}{}{}",
+                        monster_idx, form_path, scale, background, selection_key, commit_key
+                    ),
+                    path,
+                    move || async move {
+                        let dir = FormDir::open(
+                            portrait_base_path,
+                            commit,
+                            AssetCategory::Portrait,
+                            monster_idx,
+                            form_path,
+                        )
+                        .await?;
+                        make_portrait_sheet(
+                            &dir.portrait_group(&group)?,
+                            sheet_emotions,
+                            dir.path(),
+                            portrait_size,
+                            scale,
+                            background,
+                        )
+                        .await
+                    },
+                    |png: Vec<u8>| PngResponse(bytes_body(png)),
+                )
+                .await,
+            ),
+            AssetType::PortraitAnnotatedSheet => Some(
+                cached_asset(
+                    &sprite_collab,
+                    format!(
+                        "portrait_annotated_sheet|{}/{:?}|{:?}|{:?}{}{}",
+                        monster_idx, form_path, scale, background, selection_key, commit_key
+                    ),
+                    path,
+                    move || async move {
+                        let dir = FormDir::open(
+                            portrait_base_path,
+                            commit,
+                            AssetCategory::Portrait,
+                            monster_idx,
+                            form_path,
+                        )
+                        .await?;
+                        make_portrait_annotated_sheet(
+                            &dir.portrait_group(&group)?,
+                            sheet_emotions,
+                            dir.path(),
+                            portrait_size,
+                            scale,
+                            background,
+                        )
+                        .await
+                    },
+                    |png: Vec<u8>| PngResponse(bytes_body(png)),
+                )
+                .await,
+            ),
+            AssetType::PortraitRecolorSheet => Some(
+                cached_asset(
+                    &sprite_collab,
+                    format!(
+                        "portrait_recolor_sheet|{}/{:?}|{:?}{}{}",
+                        monster_idx, form_path, scale, selection_key, commit_key
+                    ),
+                    path,
+                    move || async move {
+                        let dir = FormDir::open(
+                            portrait_base_path,
+                            commit,
+                            AssetCategory::Portrait,
+                            monster_idx,
+                            form_path,
+                        )
+                        .await?;
+                        make_portrait_recolor_sheet(
+                            &dir.portrait_group(&group)?,
+                            sheet_emotions,
+                            dir.path(),
+                            portrait_size,
+                            scale,
+                        )
+                        .await
+                    },
+                    RecolorSheetResponse,
+                )
+                .await,
+            ),
+            AssetType::SpriteZip => {
+                let resolve_copies = query
+                    .get("resolve_copies")
+                    .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                    .unwrap_or_default();
+                Some(
+                    cached_zip(
+                        &sprite_collab,
+                        format!(
+                            "sprite_zip|{}/{:?}|{}{}",
+                            monster_idx, form_path, resolve_copies, commit_key
+                        ),
+                        path,
+                        request_headers,
+                        move || async move {
+                            let dir = FormDir::open(
+                                sprite_base_path,
+                                commit,
+                                AssetCategory::Sprite,
+                                monster_idx,
+                                form_path,
+                            )
+                            .await?;
+                            make_sprite_zip(dir.path(), resolve_copies).await
+                        },
+                        "sprite.zip",
                     )
-                    .await
-                    .map(|r| r.map(make_box_body).map(Response::new)),
-                path,
-            )),
-            AssetType::PortraitSheet => Some(process_nested_result(
-                sprite_collab
-                    .cached_may_fail(
-                        format!("portrait_sheet|{}/{:?}", monster_idx, form_path),
-                        || {
-                            make_portrait_sheet(
-                                group,
-                                PortraitSheetEmotions::new(emotions_incl_flipped, portrait_tile_x),
-                                &portrait_base_path,
-                                portrait_size,
+                    .await,
+                )
+            }
+            AssetType::SpriteSplitZip => {
+                if group.sprite_files.is_empty() {
+                    return None;
+                }
+                Some(
+                    cached_zip(
+                        &sprite_collab,
+                        format!(
+                            "sprite_split_zip|{}/{:?}{}",
+                            monster_idx, form_path, commit_key
+                        ),
+                        path,
+                        request_headers,
+                        move || async move {
+                            let dir = FormDir::open(
+                                sprite_base_path,
+                                commit,
+                                AssetCategory::Sprite,
+                                monster_idx,
+                                form_path,
                             )
+                            .await?;
+                            make_split_sprite_zip(dir.path()).await
                         },
+                        "sprites_split.zip",
                     )
-                    .await
-                    .map(|r| {
-                        r.map(Bytes::from)
-                            .map(Full::new)
-                            .map(make_box_body)
-                            .map(PngResponse)
-                    }),
-                path,
-            )),
-            AssetType::PortraitRecolorSheet => Some(process_nested_result(
-                sprite_collab
-                    .cached_may_fail(
-                        format!("portrait_recolor_sheet|{}/{:?}", monster_idx, form_path),
-                        || {
-                            make_portrait_recolor_sheet(
-                                group,
-                                PortraitSheetEmotions::new(emotions_incl_flipped, portrait_tile_x),
+                    .await,
+                )
+            }
+            AssetType::PortraitZip => Some(
+                cached_zip(
+                    &sprite_collab,
+                    format!("portrait_zip|{}/{:?}{}", monster_idx, form_path, commit_key),
+                    path,
+                    request_headers,
+                    move || async move {
+                        let dir = FormDir::open(
+                            portrait_base_path,
+                            commit,
+                            AssetCategory::Portrait,
+                            monster_idx,
+                            form_path,
+                        )
+                        .await?;
+                        make_portrait_zip(dir.path()).await
+                    },
+                    "portrait.zip",
+                )
+                .await,
+            ),
+            AssetType::SpriteRecolorSheet => Some(
+                cached_asset(
+                    &sprite_collab,
+                    format!(
+                        "sprite_recolor_sheet|{}/{:?}|{:?}{}",
+                        monster_idx, form_path, scale, commit_key
+                    ),
+                    path,
+                    move || async move {
+                        let dir = FormDir::open(
+                            sprite_base_path,
+                            commit,
+                            AssetCategory::Sprite,
+                            monster_idx,
+                            form_path,
+                        )
+                        .await?;
+                        make_sprite_recolor_sheet(
+                            dir.path(),
+                            ServerConfig::get().debug_dump_dir.as_deref(),
+                            scale,
+                        )
+                        .await
+                    },
+                    RecolorSheetResponse,
+                )
+                .await,
+            ),
+            AssetType::PortraitPaletteDiff
+            | AssetType::PortraitPaletteDiffSheet
+            | AssetType::SpritePaletteDiff
+            | AssetType::SpritePaletteDiffSheet => {
+                // The route may be called with the normal or the shiny form, both must exist.
+                let (shiny_form_path, _, shiny_group) = collector.find_form(
+                    force_shiny_group(&form_path)
+                        .into_iter()
+                        .map(FormMatch::Exact),
+                )?;
+                let (normal, shiny) = match asset_type {
+                    AssetType::PortraitPaletteDiff | AssetType::PortraitPaletteDiffSheet => {
+                        if group.portrait_files.is_empty() || shiny_group.portrait_files.is_empty()
+                        {
+                            return None;
+                        }
+                        (
+                            PaletteDiffSource::portraits(
+                                &group,
+                                sheet_emotions.clone(),
                                 &portrait_base_path,
                                 portrait_size,
+                            ),
+                            PaletteDiffSource::portraits(
+                                shiny_group,
+                                sheet_emotions,
+                                &asset_store.form_dir(
+                                    AssetCategory::Portrait,
+                                    monster_idx,
+                                    &shiny_form_path,
+                                ),
+                                portrait_size,
+                            ),
+                        )
+                    }
+                    _ => {
+                        if group.sprite_files.is_empty() || shiny_group.sprite_files.is_empty() {
+                            return None;
+                        }
+                        (
+                            PaletteDiffSource::sprites(&sprite_base_path),
+                            PaletteDiffSource::sprites(&asset_store.form_dir(
+                                AssetCategory::Sprite,
+                                monster_idx,
+                                &shiny_form_path,
+                            )),
+                        )
+                    }
+                };
+                if matches!(
+                    asset_type,
+                    AssetType::PortraitPaletteDiff | AssetType::SpritePaletteDiff
+                ) {
+                    Some(
+                        cached_asset(
+                            &sprite_collab,
+                            format!(
+                                "palette_diff|{:?}|{}/{:?}",
+                                asset_type, monster_idx, form_path
+                            ),
+                            path,
+                            move || async move { make_palette_diff(normal, shiny).await },
+                            JsonResponse::<PaletteDiff>,
+                        )
+                        .await,
+                    )
+                } else {
+                    Some(
+                        cached_asset(
+                            &sprite_collab,
+                            format!(
+                                "palette_diff|{:?}|{}/{:?}|{:?}|{:?}",
+                                asset_type, monster_idx, form_path, scale, background
+                            ),
+                            path,
+                            move || async move {
+                                make_palette_diff_sheet(normal, shiny, scale, background).await
+                            },
+                            |png: Vec<u8>| PngResponse(bytes_body(png)),
+                        )
+                        .await,
+                    )
+                }
+            }
+            AssetType::Preview => {
+                if group.portrait_files.is_empty() && group.sprite_files.is_empty() {
+                    return None;
+                }
+                let max_size = query.get("size").and_then(|s| s.parse::<u32>().ok());
+                Some(
+                    cached_asset(
+                        &sprite_collab,
+                        format!(
+                            "preview|{}/{:?}|{:?}|{:?}",
+                            monster_idx, form_path, max_size, background
+                        ),
+                        path,
+                        move || async move {
+                            make_preview(
+                                &group,
+                                &portrait_base_path,
+                                &sprite_base_path,
+                                max_size,
+                                background,
                             )
+                            .await
                         },
+                        |png: Vec<u8>| PngResponse(bytes_body(png)),
                     )
-                    .await
-                    .map(|r| {
-                        r.map(Bytes::from)
-                            .map(Full::new)
-                            .map(make_box_body)
-                            .map(PngResponse)
-                    }),
-                path,
-            )),
-            AssetType::SpriteZip => Some(process_nested_result(
-                sprite_collab
-                    .cached_may_fail(
-                        format!("sprite_zip|{}/{:?}", monster_idx, form_path),
-                        || make_sprite_zip(&sprite_base_path),
-                    )
-                    .await
-                    .map(|r| {
-                        r.map(Bytes::from)
-                            .map(Full::new)
-                            .map(make_box_body)
-                            .map(ZipResponse)
-                    }),
-                path,
-            )),
-            AssetType::SpriteRecolorSheet => Some(process_nested_result(
-                sprite_collab
-                    .cached_may_fail(
-                        format!("sprite_recolor_sheet|{}/{:?}", monster_idx, form_path),
-                        || make_sprite_recolor_sheet(&sprite_base_path),
+                    .await,
+                )
+            }
+            AssetType::SpriteAtlas(action, format) => {
+                let action = sprite_collab
+                    .data()
+                    .sprite_config
+                    .action(action)
+                    .to_string();
+                if !group.sprite_files.contains_key(action.as_str()) {
+                    return None;
+                }
+                Some(
+                    cached_asset(
+                        &sprite_collab,
+                        format!(
+                            "sprite_atlas|{}/{:?}|{}|{:?}",
+                            monster_idx, form_path, action, format
+                        ),
+                        path,
+                        move || async move {
+                            make_sprite_atlas(
+                                &sprite_base_path,
+                                full_form_path(monster_idx, &form_path),
+                                action,
+                                format,
+                            )
+                            .await
+                        },
+                        move |atlas: String| AtlasResponse(make_box_body(atlas), format),
                     )
-                    .await
-                    .map(|r| {
-                        r.map(Bytes::from)
-                            .map(Full::new)
-                            .map(make_box_body)
-                            .map(PngResponse)
-                    }),
-                path,
-            )),
+                    .await,
+                )
+            }
+            AssetType::SpriteAnim(action)
+            | AssetType::SpriteOffsets(action)
+            | AssetType::SpriteShadows(action) => {
+                // The action in the URL may be lowercase, like the rest of the file name.
+                let action = sprite_collab.data().sprite_config.action(action);
+                if !group.sprite_files.contains_key(action.as_str()) {
+                    return None;
+                }
+                let asset_type = match asset_type {
+                    AssetType::SpriteAnim(_) => AssetType::SpriteAnim(action.as_str()),
+                    AssetType::SpriteOffsets(_) => AssetType::SpriteOffsets(action.as_str()),
+                    _ => AssetType::SpriteShadows(action.as_str()),
+                };
+                // The sheets of single actions are served by the upstream repository.
+                Some(make_redirect_response(&get_url(
+                    asset_type,
+                    &AssetUrlBase::new(ServerConfig::get().this_server_url(), &sprite_collab),
+                    monster_idx,
+                    &form_path,
+                )))
+            }
             _ => None,
         }
     } else {
@@ -198,30 +745,267 @@ pub async fn match_and_process_assets_path(
     }
 }
 
+/// Looks up a generated asset in the cache, or generates it with `func`. If the cached asset
+/// was generated from an older commit, it is served right away with the [`STALE_HEADER`], and
+/// regenerated in the background.
+async fn cached_asset<T, R, Fn, Ft>(
+    sprite_collab: &Arc<SpriteCollab>,
+    cache_key: String,
+    request_path: &str,
+    func: Fn,
+    into_response: impl FnOnce(T) -> R,
+) -> Response<AssetBody>
+where
+    Fn: (FnOnce() -> Ft) + Send + 'static,
+    Ft: Future<Output = Result<CacheBehaviour<T>, anyhow::Error>> + Send + 'static,
+    T: DeserializeOwned + Serialize + Send + Sync + 'static,
+    R: TryInto<Response<AssetBody>>,
+    R::Error: Debug,
+{
+    cached_versioned_asset(sprite_collab, cache_key, request_path, func, |cached| {
+        into_response(cached.value)
+    })
+    .await
+}
+
+/// Like [`cached_asset`], but the response is made from the cached value together with the
+/// commit it was generated from.
+async fn cached_versioned_asset<T, R, Fn, Ft>(
+    sprite_collab: &Arc<SpriteCollab>,
+    cache_key: String,
+    request_path: &str,
+    func: Fn,
+    into_response: impl FnOnce(CachedVersion<T>) -> R,
+) -> Response<AssetBody>
+where
+    Fn: (FnOnce() -> Ft) + Send + 'static,
+    Ft: Future<Output = Result<CacheBehaviour<T>, anyhow::Error>> + Send + 'static,
+    T: DeserializeOwned + Serialize + Send + Sync + 'static,
+    R: TryInto<Response<AssetBody>>,
+    R::Error: Debug,
+{
+    let result = sprite_collab
+        .cached_stale_while_revalidate(cache_key, func)
+        .await;
+    if let Ok(Err(e)) = &result {
+        if let Some(response) = make_too_large_response(e) {
+            return response;
+        }
+    }
+    let stale = matches!(&result, Ok(Ok(cached)) if cached.stale);
+    let mut response = process_nested_result(result.map(|r| r.map(into_response)), request_path);
+    if stale {
+        response
+            .headers_mut()
+            .insert(STALE_HEADER, HeaderValue::from_static("true"));
+    }
+    response
+}
+
+/// Like [`cached_asset`], for ZIPs. They are the largest assets, so they are not cached while the
+/// disk is low on space, see [`crate::disk_monitor`]. A `Range` of the request is served, see
+/// [`crate::assets::range`], unless the cached ZIP is stale: Its bytes could not be combined
+/// with those of the ZIP of the current commit.
+async fn cached_zip<Fn, Ft>(
+    sprite_collab: &Arc<SpriteCollab>,
+    cache_key: String,
+    request_path: &str,
+    request_headers: &HeaderMap,
+    func: Fn,
+    file_name: &'static str,
+) -> Response<AssetBody>
+where
+    Fn: (FnOnce() -> Ft) + Send + 'static,
+    Ft: Future<Output = Result<CacheBehaviour<Vec<u8>>, anyhow::Error>> + Send + 'static,
+{
+    let low_disk_space = sprite_collab.disk_monitor().is_low();
+    let response = cached_versioned_asset(
+        sprite_collab,
+        cache_key,
+        request_path,
+        move || async move {
+            let zip = func().await?;
+            if low_disk_space {
+                Ok::<_, anyhow::Error>(CacheBehaviour::NoCache(zip.into_inner()))
+            } else {
+                Ok(zip)
+            }
+        },
+        |cached: CachedVersion<Vec<u8>>| RangedZipResponse {
+            range: if cached.stale {
+                None
+            } else {
+                requested_range(request_headers, &cached.version, sprite_collab)
+            },
+            zip: cached.value,
+            file_name,
+            version: cached.version,
+        },
+    )
+    .await;
+    if response.status() == StatusCode::INTERNAL_SERVER_ERROR
+        && sprite_collab.disk_monitor().is_low()
+    {
+        return make_insufficient_storage_response().map(make_box_body);
+    }
+    response
+}
+
+fn make_insufficient_storage_response() -> Response<String> {
+    Response::builder()
+        .status(StatusCode::INSUFFICIENT_STORAGE)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(String::from(
+            "<html><body><h1>Insufficient Storage</h1><p>The server is low on disk space, try again later.</p><img src=\"https://http.cat/507\"></body></html>",
+        ))
+        .unwrap()
+}
+
+fn make_bad_request_response(message: &str) -> Response<AssetBody> {
+    let mut response = Response::new(make_box_body(Full::new(Bytes::from(message.to_string()))));
+    *response.status_mut() = StatusCode::BAD_REQUEST;
+    response.headers_mut().insert(
+        "Content-Type",
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+fn bytes_body(bytes: Vec<u8>) -> AssetBody {
+    make_box_body(Full::new(Bytes::from(bytes)))
+}
+
+/// Redirects to another URL, eg. the file in the upstream repository.
+fn make_redirect_response(location: &str) -> Response<AssetBody> {
+    Response::builder()
+        .status(StatusCode::TEMPORARY_REDIRECT)
+        .header("Location", location)
+        .body(make_box_body(Full::new(Bytes::new())))
+        .unwrap_or_else(|e| make_err_response(e, location).map(make_box_body))
+}
+
+/// Zips all sprite files and a `manifest.json` describing the actions in the AnimData.xml.
+/// If `resolve_copies` is set, actions that are a copy of another action are added to the zip
+/// as well, with the files of the action they copy.
 pub async fn make_sprite_zip(
     sprite_base_path: &Path,
+    resolve_copies: bool,
 ) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
-    let buf = Vec::with_capacity(50000000);
-    let mut zip = ZipWriter::new(Cursor::new(buf));
+    let mut extra_files = Vec::new();
+    let anim_data_path = sprite_base_path.join("AnimData.xml");
+    if anim_data_path.is_file() {
+        let anim_data = AnimDataXml::open(anim_data_path)?;
+        if resolve_copies {
+            for (file_name, path) in copied_sprite_files(sprite_base_path, &anim_data) {
+                extra_files.push((file_name, fs::read(&path).await?));
+            }
+        }
+        let manifest = SpriteManifest::new(sprite_base_path, &anim_data);
+        extra_files.push((
+            SPRITE_MANIFEST_FILE_NAME.to_string(),
+            serde_json::to_vec_pretty(&manifest)?,
+        ));
+    }
+    make_dir_zip(sprite_base_path, extra_files).await
+}
 
-    let options = zip::write::SimpleFileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated);
+pub async fn make_portrait_zip(
+    portrait_base_path: &Path,
+) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
+    make_dir_zip(portrait_base_path, Vec::new()).await
+}
+
+/// Returns the files of all copied actions as (name in the zip, path of the original file).
+fn copied_sprite_files(sprite_base_path: &Path, anim_data: &AnimDataXml) -> Vec<(String, PathBuf)> {
+    let copies = anim_data.get_action_copies();
+    let mut files = Vec::new();
+    for (action, copy_of) in &copies {
+        // Copies may themselves point to other copies.
+        let mut source = copy_of;
+        for _ in 0..MAX_COPY_OF_DEPTH {
+            match copies.get(source) {
+                Some(next) => source = next,
+                None => break,
+            }
+        }
+        for suffix in SPRITE_FILE_SUFFIXES {
+            let file_name = format!("{}-{}.png", action, suffix);
+            let path = sprite_base_path.join(format!("{}-{}.png", source, suffix));
+            if path.is_file() && !sprite_base_path.join(&file_name).exists() {
+                files.push((file_name, path));
+            }
+        }
+    }
+    files.sort();
+    files
+}
 
-    let mut paths = fs::read_dir(sprite_base_path).await?;
+/// Zips all files directly in `base_path` (sorted by name), except for the credits.txt, and
+/// `extra_files` (name in the zip, content). The files are read asynchronously, compressing
+/// them is done on the blocking thread pool.
+async fn make_dir_zip(
+    base_path: &Path,
+    extra_files: Vec<(String, Vec<u8>)>,
+) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
+    let mut paths = fs::read_dir(base_path).await?;
+    let mut files = Vec::new();
 
     while let Some(path) = paths.next_entry().await? {
         if path.file_type().await?.is_file() {
-            let rfile_name = path.file_name();
-            let file_name = rfile_name.to_string_lossy();
+            let file_name = path.file_name().to_string_lossy().to_string();
             if file_name != "credits.txt" {
-                zip.start_file(file_name, options)?;
-                zip.write_all(&fs::read(&path.path()).await?)?;
+                files.push((file_name, path.path()));
             }
         }
     }
+    files.sort();
+
+    // Checked before the files are read, so large forms don't fill the memory.
+    let mut size = extra_files
+        .iter()
+        .map(|(_, content)| content.len() as u64)
+        .sum::<u64>();
+    for (_, path) in &files {
+        size += fs::metadata(path).await?.len();
+    }
+    check_asset_size(size)?;
+
+    let mut contents = Vec::with_capacity(files.len() + extra_files.len());
+    for (file_name, path) in files {
+        contents.push((file_name, fs::read(&path).await?));
+    }
+    contents.extend(extra_files);
+
+    run_blocking(move |cancellation| {
+        Ok(CacheBehaviour::Cache(zip_contents(contents, cancellation)?))
+    })
+    .await
+}
+
+/// Blocking, zips the files (name in the zip, content) in this order.
+fn zip_contents(
+    contents: Vec<(String, Vec<u8>)>,
+    cancellation: &Cancellation,
+) -> Result<Vec<u8>, anyhow::Error> {
+    // The files are mostly PNGs, which barely compress further.
+    let size = contents
+        .iter()
+        .map(|(_, content)| content.len() as u64)
+        .sum::<u64>();
+    check_asset_size(size)?;
+    let buf = Vec::with_capacity(size as usize);
+    let mut zip = ZipWriter::new(Cursor::new(buf));
+
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    for (file_name, content) in contents {
+        cancellation.check()?;
+        zip.start_file(file_name, options)?;
+        zip.write_all(&content)?;
+    }
 
-    let buf = zip.finish()?.into_inner();
-    Ok(CacheBehaviour::Cache(buf))
+    Ok(zip.finish()?.into_inner())
 }
 
 pub async fn make_credits_txt(base_path: &Path) -> Result<CacheBehaviour<String>, anyhow::Error> {
@@ -269,7 +1053,8 @@ pub fn make_err_response<E: Debug>(err: E, request_path: &str) -> Response<Strin
         )))
 }
 
-struct ZipResponse(AssetBody);
+/// A ZIP archive, and the file name it should be downloaded as.
+struct ZipResponse(AssetBody, &'static str);
 
 impl TryInto<Response<AssetBody>> for ZipResponse {
     type Error = anyhow::Error;
@@ -280,14 +1065,60 @@ impl TryInto<Response<AssetBody>> for ZipResponse {
         headers.insert("Content-Type", HeaderValue::from_str("application/zip")?);
         headers.insert(
             "Content-Disposition",
-            HeaderValue::from_str("attachment; filename=sprite.zip")?,
+            HeaderValue::from_str(&format!("attachment; filename={}", self.1))?,
         );
         Ok(resp)
     }
 }
 
+/// A cached ZIP, of which a byte range may have been requested, see [`range`].
+struct RangedZipResponse {
+    zip: Vec<u8>,
+    file_name: &'static str,
+    /// The commit the ZIP was generated from, sent as its `ETag`.
+    version: String,
+    range: Option<String>,
+}
+
+impl TryInto<Response<AssetBody>> for RangedZipResponse {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Response<AssetBody>, Self::Error> {
+        let len = self.zip.len() as u64;
+        let range = ByteRange::parse(self.range.as_deref(), len);
+        let (status, content_range, body) = match range {
+            ByteRange::Full => (StatusCode::OK, None, self.zip),
+            ByteRange::Partial(start, end) => (
+                StatusCode::PARTIAL_CONTENT,
+                Some(format!("bytes {}-{}/{}", start, end, len)),
+                self.zip[start as usize..=end as usize].to_vec(),
+            ),
+            ByteRange::Unsatisfiable => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                Some(format!("bytes */{}", len)),
+                Vec::new(),
+            ),
+        };
+        let mut resp: Response<AssetBody> =
+            ZipResponse(bytes_body(body), self.file_name).try_into()?;
+        *resp.status_mut() = status;
+        let headers = resp.headers_mut();
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        if let Some(etag) = entity_tag(&self.version) {
+            headers.insert(ETAG, HeaderValue::from_str(&etag)?);
+        }
+        if let Some(content_range) = content_range {
+            headers.insert(CONTENT_RANGE, HeaderValue::from_str(&content_range)?);
+        }
+        Ok(resp)
+    }
+}
+
 struct PngResponse(AssetBody);
 
+/// A recolor sheet, with the number of colors in its palette in the `X-Palette-Size` header.
+struct RecolorSheetResponse(RecolorSheet);
+
 impl TryInto<Response<AssetBody>> for PngResponse {
     type Error = anyhow::Error;
 
@@ -298,3 +1129,63 @@ impl TryInto<Response<AssetBody>> for PngResponse {
         Ok(resp)
     }
 }
+
+impl TryInto<Response<AssetBody>> for RecolorSheetResponse {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Response<AssetBody>, Self::Error> {
+        let palette_size = self.0.palette_size;
+        let mut resp = Response::new(make_box_body(Full::new(Bytes::from(self.0.png))));
+        let headers = resp.headers_mut();
+        headers.insert("Content-Type", HeaderValue::from_str("image/png")?);
+        headers.insert("X-Palette-Size", HeaderValue::from(palette_size));
+        Ok(resp)
+    }
+}
+
+/// A value that is serialized as the JSON body of the response.
+struct JsonResponse<T: Serialize>(T);
+
+impl<T: Serialize> TryInto<Response<AssetBody>> for JsonResponse<T> {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Response<AssetBody>, Self::Error> {
+        let mut resp = Response::new(bytes_body(serde_json::to_vec(&self.0)?));
+        let headers = resp.headers_mut();
+        headers.insert("Content-Type", HeaderValue::from_str("application/json")?);
+        Ok(resp)
+    }
+}
+
+struct TxtResponse(AssetBody);
+
+impl TryInto<Response<AssetBody>> for TxtResponse {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Response<AssetBody>, Self::Error> {
+        let mut resp = Response::new(self.0);
+        let headers = resp.headers_mut();
+        headers.insert(
+            "Content-Type",
+            HeaderValue::from_str("text/plain; charset=utf-8")?,
+        );
+        Ok(resp)
+    }
+}
+
+/// A frame atlas, with the content type of its format.
+struct AtlasResponse(AssetBody, AtlasFormat);
+
+impl TryInto<Response<AssetBody>> for AtlasResponse {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Response<AssetBody>, Self::Error> {
+        let mut resp = Response::new(self.0);
+        let headers = resp.headers_mut();
+        headers.insert(
+            "Content-Type",
+            HeaderValue::from_static(self.1.content_type()),
+        );
+        Ok(resp)
+    }
+}
@@ -0,0 +1,158 @@
+//! The 404 of asset URLs whose monster or form doesn't exist. It tells which of the two was
+//! invalid and lists the forms of the monster, as JSON if the request accepts it (like the
+//! API) and as HTML otherwise.
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{ACCEPT, CONTENT_TYPE, VARY};
+use hyper::{HeaderMap, Response, StatusCode};
+use serde::Serialize;
+
+use crate::assets::{make_box_body, AssetBody};
+use crate::datafiles::tracker::MonsterFormCollector;
+use crate::service::full_form_path;
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AssetNotFound {
+    /// `monster` or `form`, whichever of the two doesn't exist.
+    invalid: &'static str,
+    monster_id: i32,
+    /// The requested form, eg. `0001/0005`.
+    form_path: String,
+    /// The forms of the monster, if it exists.
+    valid_forms: Vec<ValidForm>,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+struct ValidForm {
+    /// eg. `0001/0001`
+    path: String,
+    name: String,
+}
+
+impl AssetNotFound {
+    fn new(monster_idx: i32, form_path: &[i32], collector: Option<&MonsterFormCollector>) -> Self {
+        Self {
+            invalid: if collector.is_some() {
+                "form"
+            } else {
+                "monster"
+            },
+            monster_id: monster_idx,
+            form_path: full_form_path(monster_idx, form_path),
+            valid_forms: collector
+                .map(|collector| {
+                    collector
+                        .map(|(path, names, _)| ValidForm {
+                            path: full_form_path(monster_idx, &path),
+                            name: names.join(" "),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = format!(
+            "<html><body><h1>Not Found</h1><p>{} <code>{}</code> does not exist.</p>",
+            if self.invalid == "monster" {
+                "The monster of"
+            } else {
+                "The form"
+            },
+            escape_html(&self.form_path)
+        );
+        if !self.valid_forms.is_empty() {
+            html.push_str("<p>Valid forms:</p><ul>");
+            for form in &self.valid_forms {
+                html.push_str(&format!(
+                    "<li><code>{}</code> {}</li>",
+                    escape_html(&form.path),
+                    escape_html(&form.name)
+                ));
+            }
+            html.push_str("</ul>");
+        }
+        html.push_str("<img src=\"https://http.cat/404\"></body></html>");
+        html
+    }
+}
+
+/// The 404 of an asset of the form `form_path` of the monster `monster_idx`. `collector` is
+/// `None` if the monster doesn't exist.
+pub(crate) fn make_form_not_found_response(
+    request_headers: &HeaderMap,
+    monster_idx: i32,
+    form_path: &[i32],
+    collector: Option<&MonsterFormCollector>,
+) -> Response<AssetBody> {
+    let not_found = AssetNotFound::new(monster_idx, form_path, collector);
+    let (content_type, body) = if accepts_json(request_headers) {
+        (
+            "application/json",
+            serde_json::to_string(&not_found).unwrap_or_default(),
+        )
+    } else {
+        ("text/html; charset=utf-8", not_found.to_html())
+    };
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(CONTENT_TYPE, content_type)
+        .header(VARY, "Accept")
+        .body(make_box_body(Full::new(Bytes::from(body))))
+        .unwrap()
+}
+
+fn accepts_json(request_headers: &HeaderMap) -> bool {
+    request_headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("application/json"))
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::snapshots::fixtures_dir;
+    use crate::datafiles::tracker::read_tracker;
+    use hyper::header::HeaderValue;
+
+    #[tokio::test]
+    async fn lists_valid_forms() {
+        let tracker = read_tracker(fixtures_dir().join("spritecollab/tracker.json"))
+            .await
+            .unwrap();
+        let collector = MonsterFormCollector::collect(&tracker, 1).unwrap();
+
+        let form = AssetNotFound::new(1, &[5], Some(&collector));
+        assert_eq!(form.invalid, "form");
+        assert_eq!(form.form_path, "0001/0005");
+        assert_eq!(form.valid_forms[0].path, "0001");
+        assert!(form.valid_forms.iter().all(|f| f.path.starts_with("0001")));
+        assert!(form.to_html().contains("<li><code>0001</code>"));
+
+        let monster = AssetNotFound::new(9999, &[], None);
+        assert_eq!(monster.invalid, "monster");
+        assert!(monster.valid_forms.is_empty());
+
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_json(&headers));
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/json, text/plain"),
+        );
+        let response = make_form_not_found_response(&headers, 1, &[5], Some(&collector));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+    }
+}
@@ -0,0 +1,293 @@
+//! Compares the palettes of the normal and the shiny version of a form: Which color of the
+//! normal form became which color of the shiny form. Both forms are composed like their recolor
+//! sheets, the portrait sheet for portraits and all unique frames for sprites, and compared
+//! pixel by pixel.
+
+use std::cmp::max;
+use std::path::{Path, PathBuf};
+
+use image::{GenericImage, Rgba, RgbaImage};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::img_util::{run_blocking, to_png, Background, Cancellation, SheetScale};
+use crate::assets::portrait_sheets::{do_make_portrait_sheet, PortraitSheetEmotions};
+use crate::assets::sprite_sheets::make_sprite_frames_image;
+use crate::cache::CacheBehaviour;
+use crate::datafiles::tracker::Group;
+
+/// Size of the color swatches below the sheets of the comparison image.
+const SWATCH_SIZE: u32 = 8;
+/// Space between the two sheets and above each row of swatches.
+const GAP: u32 = 4;
+
+/// The assets of one form to compare.
+pub enum PaletteDiffSource {
+    Portraits {
+        group_emotions: Vec<String>,
+        emotions: PortraitSheetEmotions,
+        portrait_base_path: PathBuf,
+        portrait_size: i32,
+    },
+    Sprites(PathBuf),
+}
+
+impl PaletteDiffSource {
+    pub fn portraits(
+        group: &Group,
+        emotions: PortraitSheetEmotions,
+        portrait_base_path: &Path,
+        portrait_size: i32,
+    ) -> Self {
+        Self::Portraits {
+            group_emotions: group.portrait_files.keys().cloned().collect(),
+            emotions,
+            portrait_base_path: portrait_base_path.to_path_buf(),
+            portrait_size,
+        }
+    }
+
+    pub fn sprites(sprite_base_path: &Path) -> Self {
+        Self::Sprites(sprite_base_path.to_path_buf())
+    }
+
+    /// Blocking, composes the image the recolor sheet is made of.
+    fn render(self, cancellation: &Cancellation) -> Result<RgbaImage, anyhow::Error> {
+        match self {
+            Self::Portraits {
+                group_emotions,
+                emotions,
+                portrait_base_path,
+                portrait_size,
+            } => do_make_portrait_sheet(
+                &group_emotions,
+                emotions,
+                &portrait_base_path,
+                portrait_size,
+                cancellation,
+            ),
+            Self::Sprites(sprite_base_path) => {
+                make_sprite_frames_image(&sprite_base_path, cancellation)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct PaletteDiff {
+    /// All colors of the normal form, in the order of the palette of its recolor sheet.
+    pub colors: Vec<PaletteDiffColor>,
+    /// Pixels that are transparent in one of the forms, but not in the other.
+    pub mismatched_pixels: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct PaletteDiffColor {
+    /// As `#rrggbb`, or `#rrggbbaa` if it is not fully opaque.
+    pub normal: String,
+    pub pixels: u64,
+    /// The colors of the shiny form at the pixels of the normal color, the most common first.
+    /// More than one means the recolor is not a pure palette swap.
+    pub shiny: Vec<PaletteDiffShinyColor>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct PaletteDiffShinyColor {
+    pub color: String,
+    pub pixels: u64,
+}
+
+type ColorCounts = IndexMap<Rgba<u8>, u64>;
+
+/// A color of the normal form and the colors of the shiny form at its pixels, with their
+/// number of pixels, the most common first.
+struct ColorMapping {
+    normal: Rgba<u8>,
+    pixels: u64,
+    shiny: Vec<(Rgba<u8>, u64)>,
+}
+
+pub async fn make_palette_diff(
+    normal: PaletteDiffSource,
+    shiny: PaletteDiffSource,
+) -> Result<CacheBehaviour<PaletteDiff>, anyhow::Error> {
+    run_blocking(move |cancellation| {
+        let normal_img = normal.render(cancellation)?;
+        let shiny_img = shiny.render(cancellation)?;
+        let (mappings, mismatched_pixels) = map_colors(&normal_img, &shiny_img);
+        Ok(CacheBehaviour::Cache(PaletteDiff {
+            colors: mappings
+                .into_iter()
+                .map(|mapping| PaletteDiffColor {
+                    normal: hex_color(mapping.normal),
+                    pixels: mapping.pixels,
+                    shiny: mapping
+                        .shiny
+                        .into_iter()
+                        .map(|(color, pixels)| PaletteDiffShinyColor {
+                            color: hex_color(color),
+                            pixels,
+                        })
+                        .collect(),
+                })
+                .collect(),
+            mismatched_pixels,
+        }))
+    })
+    .await
+}
+
+/// Makes an image with the sheets of the normal and the shiny form side by side. Below them,
+/// each color of the normal palette is shown above the shiny color it most commonly became.
+pub async fn make_palette_diff_sheet(
+    normal: PaletteDiffSource,
+    shiny: PaletteDiffSource,
+    scale: SheetScale,
+    background: Background,
+) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
+    run_blocking(move |cancellation| {
+        let normal_img = normal.render(cancellation)?;
+        let shiny_img = shiny.render(cancellation)?;
+        let (mappings, _) = map_colors(&normal_img, &shiny_img);
+        let img = make_comparison(&normal_img, &shiny_img, &mappings)?;
+        Ok(CacheBehaviour::Cache(to_png(
+            background.apply(scale.apply(img)),
+        )?))
+    })
+    .await
+}
+
+/// Maps each non-transparent color of `normal` to the colors of `shiny` at the same pixels.
+/// Also returns the number of pixels that are only transparent in one of the images.
+fn map_colors(normal: &RgbaImage, shiny: &RgbaImage) -> (Vec<ColorMapping>, u64) {
+    // Per normal color: Its number of pixels and the number of pixels of each shiny color.
+    let mut counts: IndexMap<Rgba<u8>, (u64, ColorCounts)> = IndexMap::new();
+    let mut mismatched_pixels = 0;
+    for (x, y, px) in normal.enumerate_pixels() {
+        let shiny_px = shiny.get_pixel_checked(x, y).filter(|px| px.0[3] != 0);
+        match (px.0[3] != 0, shiny_px) {
+            (true, shiny_px) => {
+                let (pixels, shiny_counts) = counts.entry(*px).or_default();
+                *pixels += 1;
+                match shiny_px {
+                    Some(shiny_px) => *shiny_counts.entry(*shiny_px).or_default() += 1,
+                    None => mismatched_pixels += 1,
+                }
+            }
+            (false, Some(_)) => mismatched_pixels += 1,
+            (false, None) => {}
+        }
+    }
+    // Pixels of the shiny form outside of the normal form.
+    for (x, y, px) in shiny.enumerate_pixels() {
+        if (x >= normal.width() || y >= normal.height()) && px.0[3] != 0 {
+            mismatched_pixels += 1;
+        }
+    }
+    let mappings = counts
+        .into_iter()
+        .map(|(normal, (pixels, shiny))| {
+            let mut shiny = shiny.into_iter().collect::<Vec<_>>();
+            // Stable, so equally common colors stay in the order they first appear.
+            shiny.sort_by(|(_, a), (_, b)| b.cmp(a));
+            ColorMapping {
+                normal,
+                pixels,
+                shiny,
+            }
+        })
+        .collect();
+    (mappings, mismatched_pixels)
+}
+
+fn make_comparison(
+    normal: &RgbaImage,
+    shiny: &RgbaImage,
+    mappings: &[ColorMapping],
+) -> Result<RgbaImage, anyhow::Error> {
+    let width = normal.width() + GAP + shiny.width();
+    let swatches_per_row = max(1, width / SWATCH_SIZE);
+    let swatch_rows = (mappings.len() as u32).div_ceil(swatches_per_row);
+    let sheets_height = max(normal.height(), shiny.height());
+    let mut img = RgbaImage::new(width, sheets_height + swatch_rows * (GAP + 2 * SWATCH_SIZE));
+    img.copy_from(normal, 0, 0)?;
+    img.copy_from(shiny, normal.width() + GAP, 0)?;
+    for (idx, mapping) in mappings.iter().enumerate() {
+        let idx = idx as u32;
+        let x = (idx % swatches_per_row) * SWATCH_SIZE;
+        let y = sheets_height + (idx / swatches_per_row) * (GAP + 2 * SWATCH_SIZE) + GAP;
+        fill_swatch(&mut img, x, y, mapping.normal);
+        if let Some((shiny_color, _)) = mapping.shiny.first() {
+            fill_swatch(&mut img, x, y + SWATCH_SIZE, *shiny_color);
+        }
+    }
+    Ok(img)
+}
+
+fn fill_swatch(img: &mut RgbaImage, x: u32, y: u32, color: Rgba<u8>) {
+    for yy in y..y + SWATCH_SIZE {
+        for xx in x..x + SWATCH_SIZE {
+            img.put_pixel(xx, yy, color);
+        }
+    }
+}
+
+fn hex_color(color: Rgba<u8>) -> String {
+    let [r, g, b, a] = color.0;
+    if a == 255 {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const GREEN: Rgba<u8> = Rgba([0, 255, 0, 255]);
+
+    fn image(pixels: &[Rgba<u8>]) -> RgbaImage {
+        let mut img = RgbaImage::new(pixels.len() as u32, 1);
+        for (x, px) in pixels.iter().enumerate() {
+            img.put_pixel(x as u32, 0, *px);
+        }
+        img
+    }
+
+    #[test]
+    fn maps_normal_to_shiny_colors() {
+        let clear = Rgba([0, 0, 0, 0]);
+        let normal = image(&[RED, RED, BLUE, RED, clear, BLUE]);
+        let shiny = image(&[GREEN, BLUE, RED, GREEN, GREEN]);
+        let (mappings, mismatched_pixels) = map_colors(&normal, &shiny);
+
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].normal, RED);
+        assert_eq!(mappings[0].pixels, 3);
+        assert_eq!(mappings[0].shiny, vec![(GREEN, 2), (BLUE, 1)]);
+        assert_eq!(mappings[1].normal, BLUE);
+        assert_eq!(mappings[1].pixels, 2);
+        assert_eq!(mappings[1].shiny, vec![(RED, 1)]);
+        // The transparent pixel of the normal form, and the pixel outside of the shiny form.
+        assert_eq!(mismatched_pixels, 2);
+        assert_eq!(hex_color(mappings[1].shiny[0].0), "#ff0000");
+    }
+
+    #[test]
+    fn comparison_shows_swatches_below_sheets() {
+        let normal = image(&[RED, BLUE]);
+        let shiny = image(&[GREEN, RED]);
+        let (mappings, _) = map_colors(&normal, &shiny);
+        let img = make_comparison(&normal, &shiny, &mappings).unwrap();
+
+        // Only one swatch fits into a row.
+        assert_eq!(img.dimensions(), (8, 1 + 2 * (GAP + 2 * SWATCH_SIZE)));
+        assert_eq!(img.get_pixel(2 + GAP, 0), &GREEN);
+        assert_eq!(img.get_pixel(0, 1 + GAP), &RED);
+        assert_eq!(img.get_pixel(0, 1 + GAP + SWATCH_SIZE), &GREEN);
+        assert_eq!(img.get_pixel(0, 1 + 2 * GAP + 2 * SWATCH_SIZE), &BLUE);
+    }
+}
@@ -1,38 +1,228 @@
-use crate::assets::img_util::{add_palette_to, to_png};
+use crate::assets::bitmap_font::{draw_text, text_width, GLYPH_HEIGHT};
+use crate::assets::img_util::{
+    make_recolor_sheet, run_blocking, to_png, Background, Cancellation, RecolorSheet, SheetScale,
+};
 use crate::cache::CacheBehaviour;
+use crate::datafiles::sprite_config::SpriteConfig;
 use crate::datafiles::tracker::Group;
-use image::{GenericImage, RgbaImage};
+use crate::ServerConfig;
+use image::{GenericImage, Rgba, RgbaImage};
+use log::warn;
 use std::cmp::max;
 use std::collections::HashMap;
 use std::path::Path;
 
+/// Space around the labels of the annotated sheets.
+const LABEL_PADDING: u32 = 2;
+const LABEL_BACKGROUND: Rgba<u8> = Rgba([32, 32, 32, 255]);
+const LABEL_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// The emotions requested for a portrait sheet: `?emotions=Normal,Happy` includes only these
+/// emotions, `?exclude=Angry,^` leaves out emotions, `^` all flipped emotions.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EmotionSelection {
+    /// The emotions to include, in this order. If set, `SCSRV_PORTRAIT_SHEET_EXCLUDE` is
+    /// ignored.
+    pub only: Option<Vec<String>>,
+    pub exclude: Vec<String>,
+}
+
+impl EmotionSelection {
+    /// Reads the selection from the query of a request. The names are matched with the
+    /// emotions of the sprite config ignoring case, unknown emotions are an error.
+    pub fn from_query(
+        query: &HashMap<String, String>,
+        sprite_config: &SpriteConfig,
+    ) -> Result<Self, String> {
+        let known = sprite_config.emotions_incl_flipped();
+        let parse = |param: &str| -> Result<Option<Vec<String>>, String> {
+            query
+                .get(param)
+                .map(|names| {
+                    names
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .map(|name| match name {
+                            "^" if param == "exclude" => Ok(name.to_string()),
+                            _ => known
+                                .iter()
+                                .find(|emotion| emotion.eq_ignore_ascii_case(name))
+                                .cloned()
+                                .ok_or_else(|| format!("Unknown emotion in {}: {}", param, name)),
+                        })
+                        .collect()
+                })
+                .transpose()
+        };
+        Ok(Self {
+            only: parse("emotions")?,
+            exclude: parse("exclude")?.unwrap_or_default(),
+        })
+    }
+
+    /// Whether an exclusion list leaves out `emotion`.
+    pub fn is_excluded_by(exclude: &[String], emotion: &str) -> bool {
+        exclude
+            .iter()
+            .any(|excluded| excluded == emotion || (excluded == "^" && emotion.ends_with('^')))
+    }
+
+    /// Appended to the cache keys of the sheets, empty for the default selection.
+    pub fn cache_key(&self) -> String {
+        if *self == Self::default() {
+            String::new()
+        } else {
+            format!("|{:?}|{:?}", self.only, self.exclude)
+        }
+    }
+}
+
 /// Maps known emotions from the sprite config to positions in the sheets.
 /// All positions, widths and heights here use the portraits as units, so they must
 /// be multiplied by the dimensions of a portrait for the actual coordinates / sizes.
+/// Portraits are `padding` pixels apart.
+#[derive(Clone, Debug)]
 pub struct PortraitSheetEmotions {
     emotion_positions: HashMap<String, (i32, i32)>,
     max_width: i32,
     max_height: i32,
+    padding: u32,
 }
 
 impl PortraitSheetEmotions {
+    /// The layout of SpriteBot: The emotions in the order of the sprite config, `width_sheet`
+    /// per row.
     pub fn new(emotion_cfg: Vec<String>, width_sheet: i32) -> PortraitSheetEmotions {
-        let mut current_row = 0;
-        let mut max_width = 0;
-        let mut emotion_positions = HashMap::with_capacity(emotion_cfg.len());
-        for (idx, emotion) in emotion_cfg.into_iter().enumerate() {
-            let current_col = (idx as i32) % width_sheet;
-            emotion_positions.insert(emotion, (current_col, current_row));
-            if current_col == width_sheet - 1 {
-                current_row += 1;
+        let rows = emotion_cfg
+            .chunks(width_sheet.max(1) as usize)
+            .map(<[String]>::to_vec)
+            .collect::<Vec<_>>();
+        Self::from_layout(&rows)
+    }
+
+    /// An explicit layout, row by row. Empty names leave their cell empty, and if an emotion
+    /// is in the layout more than once, its first cell is used.
+    pub fn from_layout(rows: &[Vec<String>]) -> PortraitSheetEmotions {
+        let mut emotion_positions = HashMap::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, emotion) in row.iter().enumerate() {
+                if !emotion.is_empty() {
+                    emotion_positions
+                        .entry(emotion.clone())
+                        .or_insert((x as i32, y as i32));
+                }
             }
-            max_width = max(max_width, current_col + 1);
         }
         Self {
             emotion_positions,
-            max_height: current_row,
-            max_width,
+            max_width: rows.iter().map(Vec::len).max().unwrap_or_default() as i32,
+            max_height: rows.len() as i32,
+            padding: 0,
+        }
+    }
+
+    /// The layout of the sheets of this server: `SCSRV_PORTRAIT_SHEET_LAYOUT` and
+    /// `SCSRV_PORTRAIT_SHEET_PADDING` if set, or the layout of SpriteBot. The emotions in
+    /// `SCSRV_PORTRAIT_SHEET_EXCLUDE` are left out.
+    pub fn configured(sprite_config: &SpriteConfig) -> PortraitSheetEmotions {
+        Self::selected(sprite_config, &EmotionSelection::default())
+    }
+
+    /// The configured layout with the emotions of `selection`. If the selection lists the
+    /// emotions to include, they are laid out like SpriteBot does, in the order of the list.
+    /// Otherwise the excluded emotions are left out of the SpriteBot layout, or leave their
+    /// cells empty in an explicit `SCSRV_PORTRAIT_SHEET_LAYOUT`.
+    pub fn selected(
+        sprite_config: &SpriteConfig,
+        selection: &EmotionSelection,
+    ) -> PortraitSheetEmotions {
+        let config = ServerConfig::get();
+        let excluded = |emotion: &str| {
+            EmotionSelection::is_excluded_by(&selection.exclude, emotion)
+                || (selection.only.is_none()
+                    && EmotionSelection::is_excluded_by(&config.portrait_sheet_exclude, emotion))
+        };
+        let included = |emotions: &[String]| {
+            emotions
+                .iter()
+                .filter(|emotion| !excluded(emotion))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        match (&selection.only, &config.portrait_sheet_layout) {
+            (Some(only), _) => Self::new(included(only), sprite_config.portrait_tile_x),
+            (None, Some(layout)) => Self::from_layout(
+                &layout
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|emotion| match excluded(emotion) {
+                                true => String::new(),
+                                false => emotion.clone(),
+                            })
+                            .collect()
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            (None, None) => Self::new(
+                included(&sprite_config.emotions_incl_flipped()),
+                sprite_config.portrait_tile_x,
+            ),
         }
+        .with_padding(config.portrait_sheet_padding)
+    }
+
+    /// Leaves `padding` pixels between the portraits.
+    pub fn with_padding(self, padding: u32) -> PortraitSheetEmotions {
+        Self { padding, ..self }
+    }
+
+    /// Width of the sheet, in portraits.
+    pub fn width(&self) -> i32 {
+        self.max_width
+    }
+
+    /// Height of the sheet, in portraits.
+    pub fn height(&self) -> i32 {
+        self.max_height
+    }
+
+    /// The emotions with their (x, y) positions in the sheet, row by row.
+    pub fn positions(&self) -> Vec<(&str, i32, i32)> {
+        let mut positions = self
+            .emotion_positions
+            .iter()
+            .map(|(emotion, (x, y))| (emotion.as_str(), *x, *y))
+            .collect::<Vec<_>>();
+        positions.sort_by_key(|(_, x, y)| (*y, *x));
+        positions
+    }
+
+    /// Pixels between the portraits.
+    pub fn padding(&self) -> u32 {
+        self.padding
+    }
+
+    /// Size of the sheet in pixels, for cells of `cell_width` x `cell_height` pixels.
+    pub fn pixel_size(&self, cell_width: u32, cell_height: u32) -> (u32, u32) {
+        let size = |cells: i32, cell_size: u32| match cells as u32 {
+            0 => 0,
+            cells => cells * cell_size + (cells - 1) * self.padding,
+        };
+        (
+            size(self.max_width, cell_width),
+            size(self.max_height, cell_height),
+        )
+    }
+
+    /// Pixel coordinates of the cell at `(x, y)`, for cells of `cell_width` x `cell_height`
+    /// pixels.
+    fn pixel_position(&self, x: i32, y: i32, cell_width: u32, cell_height: u32) -> (u32, u32) {
+        (
+            x as u32 * (cell_width + self.padding),
+            y as u32 * (cell_height + self.padding),
+        )
     }
 }
 
@@ -41,10 +231,24 @@ pub async fn make_portrait_sheet(
     emotions: PortraitSheetEmotions,
     portrait_base_path: &Path,
     portrait_size: i32,
+    scale: SheetScale,
+    background: Background,
 ) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
-    Ok(CacheBehaviour::Cache(to_png(
-        do_make_portrait_sheet(0, group, emotions, portrait_base_path, portrait_size).await?,
-    )?))
+    let group_emotions = group.portrait_files.keys().cloned().collect::<Vec<_>>();
+    let portrait_base_path = portrait_base_path.to_path_buf();
+    run_blocking(move |cancellation| {
+        let img = do_make_portrait_sheet(
+            &group_emotions,
+            emotions,
+            &portrait_base_path,
+            portrait_size,
+            cancellation,
+        )?;
+        Ok(CacheBehaviour::Cache(to_png(
+            background.apply(scale.apply(img)),
+        )?))
+    })
+    .await
 }
 
 pub async fn make_portrait_recolor_sheet(
@@ -52,36 +256,211 @@ pub async fn make_portrait_recolor_sheet(
     emotions: PortraitSheetEmotions,
     portrait_base_path: &Path,
     portrait_size: i32,
-) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
-    let mut img =
-        do_make_portrait_sheet(1, group, emotions, portrait_base_path, portrait_size).await?;
-    add_palette_to(&mut img);
-    Ok(CacheBehaviour::Cache(to_png(img)?))
+    scale: SheetScale,
+) -> Result<CacheBehaviour<RecolorSheet>, anyhow::Error> {
+    let group_emotions = group.portrait_files.keys().cloned().collect::<Vec<_>>();
+    let portrait_base_path = portrait_base_path.to_path_buf();
+    run_blocking(move |cancellation| {
+        let img = do_make_portrait_sheet(
+            &group_emotions,
+            emotions,
+            &portrait_base_path,
+            portrait_size,
+            cancellation,
+        )?;
+        let sheet = make_recolor_sheet(&img, scale)?;
+        if sheet.exceeds_color_limit() {
+            warn!(
+                "Portraits at {:?} have {} colors, more than allowed.",
+                portrait_base_path, sheet.palette_size
+            );
+        }
+        Ok(CacheBehaviour::Cache(sheet))
+    })
+    .await
 }
 
-async fn do_make_portrait_sheet(
-    padding_top: i32,
+/// Makes an overview of the portraits, like the one SpriteBot posts: All emotions of the sheet
+/// in the same grid, each labeled with its name below it. Missing portraits are left empty.
+pub async fn make_portrait_annotated_sheet(
     group: &Group,
     emotions: PortraitSheetEmotions,
     portrait_base_path: &Path,
     portrait_size: i32,
+    scale: SheetScale,
+    background: Background,
+) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
+    let group_emotions = group.portrait_files.keys().cloned().collect::<Vec<_>>();
+    let portrait_base_path = portrait_base_path.to_path_buf();
+    run_blocking(move |cancellation| {
+        let portrait_size = portrait_size as u32;
+        let positions = emotions.positions();
+        let label_height = GLYPH_HEIGHT + 2 * LABEL_PADDING;
+        let cell_width = positions
+            .iter()
+            .map(|(emotion, _, _)| text_width(emotion) + 2 * LABEL_PADDING)
+            .fold(portrait_size, max);
+        let cell_height = portrait_size + label_height;
+        let (width, height) = emotions.pixel_size(cell_width, cell_height);
+        let mut img = RgbaImage::new(width, height);
+        for (emotion, x, y) in positions {
+            cancellation.check()?;
+            let (cell_x, cell_y) = emotions.pixel_position(x, y, cell_width, cell_height);
+            for label_y in 0..label_height {
+                for label_x in 0..cell_width {
+                    img.put_pixel(
+                        cell_x + label_x,
+                        cell_y + portrait_size + label_y,
+                        LABEL_BACKGROUND,
+                    );
+                }
+            }
+            draw_text(
+                &mut img,
+                cell_x + (cell_width - text_width(emotion)) / 2,
+                cell_y + portrait_size + LABEL_PADDING,
+                emotion,
+                LABEL_COLOR,
+            );
+            if group_emotions.iter().any(|e| e == emotion) {
+                let portrait_path = portrait_base_path.join(format!("{}.png", emotion));
+                if let Ok(portrait_img) = image::open(&portrait_path) {
+                    img.copy_from(
+                        &portrait_img,
+                        cell_x + (cell_width - portrait_size) / 2,
+                        cell_y,
+                    )?;
+                }
+            }
+        }
+        Ok(CacheBehaviour::Cache(to_png(
+            background.apply(scale.apply(img)),
+        )?))
+    })
+    .await
+}
+
+/// Blocking, decodes the portraits of `group_emotions` and places them on the sheet.
+pub(crate) fn do_make_portrait_sheet(
+    group_emotions: &[String],
+    emotions: PortraitSheetEmotions,
+    portrait_base_path: &Path,
+    portrait_size: i32,
+    cancellation: &Cancellation,
 ) -> Result<RgbaImage, anyhow::Error> {
-    let mut img = RgbaImage::new(
-        (emotions.max_width * portrait_size) as u32,
-        (emotions.max_height * portrait_size + padding_top) as u32,
-    );
-    for grp_emotion in group.portrait_files.keys() {
-        if emotions.emotion_positions.contains_key(grp_emotion) {
-            let (x, y) = emotions.emotion_positions.get(grp_emotion).unwrap();
-            let portrait_path = portrait_base_path.join(&format!("{}.png", grp_emotion));
+    let portrait_size = portrait_size as u32;
+    let (width, height) = emotions.pixel_size(portrait_size, portrait_size);
+    let mut img = RgbaImage::new(width, height);
+    for grp_emotion in group_emotions {
+        cancellation.check()?;
+        if let Some(&(x, y)) = emotions.emotion_positions.get(grp_emotion) {
+            let portrait_path = portrait_base_path.join(format!("{}.png", grp_emotion));
             if let Ok(portrait_img) = image::open(&portrait_path) {
-                img.copy_from(
-                    &portrait_img,
-                    (x * portrait_size) as u32,
-                    ((y * portrait_size) + padding_top) as u32,
-                )?;
+                let (portrait_x, portrait_y) =
+                    emotions.pixel_position(x, y, portrait_size, portrait_size);
+                img.copy_from(&portrait_img, portrait_x, portrait_y)?;
             }
         }
     }
     Ok(img)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::snapshots::fixtures_dir;
+    use crate::datafiles::sprite_config::read_sprite_config;
+
+    fn emotions(count: usize) -> Vec<String> {
+        (0..count).map(|idx| format!("E{}", idx)).collect()
+    }
+
+    #[test]
+    fn lays_out_emotions_in_rows() {
+        for (count, width, height) in [
+            (0, 0, 0),
+            (1, 1, 1),
+            (4, 4, 1),
+            (5, 5, 1),
+            (6, 5, 2),
+            (10, 5, 2),
+            (11, 5, 3),
+        ] {
+            let sheet = PortraitSheetEmotions::new(emotions(count), 5);
+            assert_eq!(
+                (sheet.width(), sheet.height()),
+                (width, height),
+                "{} emotions",
+                count
+            );
+            assert_eq!(sheet.positions().len(), count);
+        }
+        let sheet = PortraitSheetEmotions::new(emotions(10), 5);
+        assert_eq!(sheet.positions()[4], ("E4", 4, 0));
+        assert_eq!(sheet.positions()[5], ("E5", 0, 1));
+        assert_eq!(sheet.positions()[9], ("E9", 4, 1));
+        // A width of zero doesn't divide by zero.
+        assert_eq!(PortraitSheetEmotions::new(emotions(2), 0).height(), 2);
+    }
+
+    #[test]
+    fn lays_out_explicit_rows() {
+        let rows = vec![
+            vec!["Normal".to_string(), "".to_string(), "Happy".to_string()],
+            vec!["Angry".to_string(), "Normal".to_string()],
+        ];
+        let sheet = PortraitSheetEmotions::from_layout(&rows);
+        assert_eq!((sheet.width(), sheet.height()), (3, 2));
+        assert_eq!(
+            sheet.positions(),
+            vec![("Normal", 0, 0), ("Happy", 2, 0), ("Angry", 0, 1)]
+        );
+    }
+
+    #[test]
+    fn pads_portraits() {
+        let sheet = PortraitSheetEmotions::new(emotions(6), 5).with_padding(2);
+        assert_eq!(sheet.pixel_size(40, 40), (5 * 40 + 4 * 2, 2 * 40 + 2));
+        assert_eq!(sheet.pixel_position(1, 1, 40, 40), (42, 42));
+        let empty = PortraitSheetEmotions::new(Vec::new(), 5).with_padding(2);
+        assert_eq!(empty.pixel_size(40, 40), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn selects_emotions_from_the_query() {
+        crate::testing::config();
+        let sprite_config =
+            read_sprite_config(fixtures_dir().join("spritecollab/sprite_config.json"))
+                .await
+                .unwrap();
+        let query = |pairs: &[(&str, &str)]| {
+            let query = pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            EmotionSelection::from_query(&query, &sprite_config)
+        };
+
+        let selection = query(&[("emotions", "normal, Happy^"), ("exclude", "^,sad")]).unwrap();
+        assert_eq!(
+            selection.only,
+            Some(vec!["Normal".to_string(), "Happy^".to_string()])
+        );
+        assert_eq!(selection.exclude, vec!["^".to_string(), "Sad".to_string()]);
+        let sheet = PortraitSheetEmotions::selected(&sprite_config, &selection);
+        assert_eq!(sheet.positions(), vec![("Normal", 0, 0)]);
+
+        let selection = query(&[("exclude", "^")]).unwrap();
+        let sheet = PortraitSheetEmotions::selected(&sprite_config, &selection);
+        assert_eq!(sheet.positions().len(), sprite_config.emotions.len());
+        assert!(sheet
+            .positions()
+            .iter()
+            .all(|(emotion, _, _)| !emotion.ends_with('^')));
+        assert_ne!(selection.cache_key(), "");
+        assert_eq!(query(&[]).unwrap().cache_key(), "");
+
+        assert!(query(&[("emotions", "Normal,Sleepy")]).is_err());
+        assert!(query(&[("emotions", "^")]).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use image::imageops::{resize, FilterType};
+use image::{DynamicImage, RgbaImage};
+
+use crate::assets::img_util::{run_blocking, to_png, Background};
+use crate::cache::CacheBehaviour;
+use crate::datafiles::anim_data_xml::AnimDataXml;
+use crate::datafiles::tracker::Group;
+
+/// The largest size (width or height) a preview can be requested in.
+pub const MAX_PREVIEW_SIZE: u32 = 512;
+
+pub(crate) const PREVIEW_EMOTION: &str = "Normal";
+pub(crate) const PREVIEW_ACTION: &str = "Idle";
+
+/// Makes a small preview of a form: The Normal portrait or, if it doesn't exist, the first
+/// frame of the Idle animation. If `max_size` is set, the preview is scaled (nearest neighbour)
+/// so that its larger side has that size.
+pub async fn make_preview(
+    group: &Group,
+    portrait_base_path: &Path,
+    sprite_base_path: &Path,
+    max_size: Option<u32>,
+    background: Background,
+) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
+    let has_portrait = group.portrait_files.contains_key(PREVIEW_EMOTION);
+    let has_sprite = group.sprite_files.contains_key(PREVIEW_ACTION);
+    let portrait_base_path = portrait_base_path.to_path_buf();
+    let sprite_base_path = sprite_base_path.to_path_buf();
+    run_blocking(move |_| {
+        let img = if has_portrait {
+            image::open(portrait_base_path.join(format!("{}.png", PREVIEW_EMOTION)))?.into_rgba8()
+        } else if has_sprite {
+            get_first_frame(&sprite_base_path, PREVIEW_ACTION)?
+        } else {
+            return Err(anyhow!("This form has no portrait or sprite to preview."));
+        };
+        let img = match max_size {
+            Some(max_size) => scale_to_max_size(&img, max_size),
+            None => img,
+        };
+        Ok(CacheBehaviour::Cache(to_png(background.apply(img))?))
+    })
+    .await
+}
+
+fn get_first_frame(sprite_base_path: &Path, action: &str) -> Result<RgbaImage, anyhow::Error> {
+    let xml = AnimDataXml::open(sprite_base_path.join("AnimData.xml"))?;
+    let (name, frame_width, frame_height) = first_frame_of(&xml, action)?;
+    let sheet: DynamicImage = image::open(sprite_base_path.join(format!("{}-Anim.png", name)))?;
+    Ok(sheet.crop_imm(0, 0, frame_width, frame_height).into_rgba8())
+}
+
+/// The animation whose sheet has the frames of `action` (it may be a copy of another one), and
+/// the size of its frames.
+pub(crate) fn first_frame_of(
+    xml: &AnimDataXml,
+    action: &str,
+) -> Result<(String, u32, u32), anyhow::Error> {
+    let find_anim = |name: &str| xml.anims.anim.iter().find(|anim| anim.name == name);
+    let mut anim = find_anim(action).ok_or_else(|| anim_missing(action))?;
+    if let Some(copy_of) = &anim.copy_of {
+        anim = find_anim(copy_of).ok_or_else(|| anim_missing(copy_of))?;
+    }
+    match (anim.frame_width, anim.frame_height) {
+        (Some(w), Some(h)) => Ok((anim.name.clone(), w as u32, h as u32)),
+        _ => Err(anyhow!(
+            "The AnimData.xml for this sprite is invalid: FrameWidth or FrameHeight missing for {}",
+            anim.name
+        )),
+    }
+}
+
+fn anim_missing(name: &str) -> anyhow::Error {
+    anyhow!("The AnimData.xml for this sprite has no animation {}", name)
+}
+
+fn scale_to_max_size(img: &RgbaImage, max_size: u32) -> RgbaImage {
+    let max_size = max_size.clamp(1, MAX_PREVIEW_SIZE);
+    let larger_side = img.width().max(img.height()).max(1);
+    let width = (img.width() * max_size / larger_side).max(1);
+    let height = (img.height() * max_size / larger_side).max(1);
+    resize(img, width, height, FilterType::Nearest)
+}
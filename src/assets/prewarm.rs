@@ -0,0 +1,97 @@
+//! Pre-generates the assets of recently modified forms after a refresh, so that the first
+//! requests after the cache was flushed don't have to wait for them to be generated. Assets
+//! that are cached from an older commit are regenerated in the background.
+
+use std::cmp::max;
+use std::sync::Arc;
+
+use hyper::{HeaderMap, Method};
+use log::info;
+
+use crate::assets::match_and_process_assets_path;
+use crate::assets::url::{get_url, AssetType, AssetUrlBase};
+use crate::datafiles::tracker::{MonsterFormCollector, Tracker};
+use crate::SpriteCollab;
+
+/// Generates (and caches) the sheets and zips of the `count` most recently modified forms.
+pub async fn prewarm_assets(sprite_collab: Arc<SpriteCollab>, count: usize) {
+    if count == 0 {
+        return;
+    }
+    let tracker = sprite_collab.data().tracker.clone();
+    // Paths only, so the current versions are generated without following redirects.
+    let url_base = AssetUrlBase::new("", &sprite_collab);
+    let paths = recently_modified_asset_paths(&tracker, &url_base, count);
+    info!("Pre-warming {} assets...", paths.len());
+    let mut failed = 0;
+    let headers = HeaderMap::new();
+    for path in &paths {
+        match match_and_process_assets_path(
+            &Method::GET,
+            path,
+            None,
+            &headers,
+            sprite_collab.clone(),
+        )
+        .await
+        {
+            Some(response) if response.status().is_success() => {}
+            _ => failed += 1,
+        }
+    }
+    info!("Pre-warming assets done. {} failed.", failed);
+}
+
+fn recently_modified_asset_paths(
+    tracker: &Tracker,
+    url_base: &AssetUrlBase,
+    count: usize,
+) -> Vec<String> {
+    let mut forms = tracker
+        .keys()
+        .flat_map(|group_id| {
+            let monster_idx = **group_id as i32;
+            MonsterFormCollector::collect(tracker, monster_idx)
+                .unwrap()
+                .map(move |(path, _, group)| {
+                    (
+                        max(group.portrait_modified, group.sprite_modified),
+                        monster_idx,
+                        path,
+                        !group.portrait_files.is_empty(),
+                        !group.sprite_files.is_empty(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    forms.sort_by(|(date_a, ..), (date_b, ..)| date_b.cmp(date_a));
+
+    let mut paths = Vec::with_capacity(count * 4);
+    for (_, monster_idx, path, has_portraits, has_sprites) in forms.into_iter().take(count) {
+        if has_portraits {
+            paths.push(get_url(
+                AssetType::PortraitSheet,
+                url_base,
+                monster_idx,
+                &path,
+            ));
+            paths.push(get_url(
+                AssetType::PortraitRecolorSheet,
+                url_base,
+                monster_idx,
+                &path,
+            ));
+        }
+        if has_sprites {
+            paths.push(get_url(AssetType::SpriteZip, url_base, monster_idx, &path));
+            paths.push(get_url(
+                AssetType::SpriteRecolorSheet,
+                url_base,
+                monster_idx,
+                &path,
+            ));
+        }
+    }
+    paths
+}
@@ -0,0 +1,114 @@
+//! Byte ranges of ZIPs, so interrupted downloads can be resumed with `Range: bytes=<start>-`.
+//! Only single ranges are supported, requests for multiple ranges get the whole ZIP. With
+//! `If-Range`, the range is only served if the ZIP is still the one the client has: ZIPs have
+//! the commit they were generated from as `ETag`, and the `Last-Modified` date of the current
+//! commit, see [`crate::assets::cache_headers`].
+
+use hyper::header::{IF_RANGE, RANGE};
+use hyper::HeaderMap;
+
+use crate::assets::cache_headers::HTTP_DATE_FORMAT;
+use crate::SpriteCollab;
+
+/// The part of an asset of `len` bytes that was requested.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ByteRange {
+    Full,
+    /// The first and the last byte, inclusive.
+    Partial(u64, u64),
+    /// The range starts after the end of the asset.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parses the value of a `Range` header. Invalid values and multiple ranges are ignored.
+    pub fn parse(range: Option<&str>, len: u64) -> Self {
+        let spec = match range.and_then(|range| range.trim().strip_prefix("bytes=")) {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return ByteRange::Full,
+        };
+        let (start, end) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return ByteRange::Full,
+        };
+        match (start.parse::<u64>(), end.parse::<u64>()) {
+            // `bytes=-<n>`: The last n bytes.
+            (Err(_), Ok(suffix)) if start.is_empty() => {
+                if suffix == 0 || len == 0 {
+                    ByteRange::Unsatisfiable
+                } else {
+                    ByteRange::Partial(len - suffix.min(len), len - 1)
+                }
+            }
+            (Ok(start), Err(_)) if end.is_empty() => Self::from_start(start, u64::MAX, len),
+            (Ok(start), Ok(end)) if start <= end => Self::from_start(start, end, len),
+            _ => ByteRange::Full,
+        }
+    }
+
+    fn from_start(start: u64, end: u64, len: u64) -> Self {
+        if start >= len {
+            ByteRange::Unsatisfiable
+        } else {
+            ByteRange::Partial(start, end.min(len - 1))
+        }
+    }
+}
+
+/// The `ETag` of an asset generated from the commit `version`, `None` for data without a
+/// commit.
+pub fn entity_tag(version: &str) -> Option<String> {
+    (!version.is_empty()).then(|| format!("\"{}\"", version))
+}
+
+/// The `Range` header of the request for an asset generated from the commit `version`, `None`
+/// if there is none or if the `If-Range` condition doesn't match the asset.
+pub fn requested_range(
+    request_headers: &HeaderMap,
+    version: &str,
+    sprite_collab: &SpriteCollab,
+) -> Option<String> {
+    let range = request_headers.get(RANGE)?.to_str().ok()?.to_string();
+    let if_range = match request_headers.get(IF_RANGE) {
+        Some(if_range) => if_range.to_str().ok()?.trim(),
+        None => return Some(range),
+    };
+    if if_range.starts_with('"') {
+        return (entity_tag(version).as_deref() == Some(if_range)).then_some(range);
+    }
+    // The date is the one of the current commit, so it only validates assets of it.
+    if sprite_collab.data().assets_commit != version {
+        return None;
+    }
+    let modified = sprite_collab
+        .with_meta(|meta| meta.assets_update_date.format(HTTP_DATE_FORMAT).to_string());
+    (modified == if_range).then_some(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() {
+        let parse = |range: &str| ByteRange::parse(Some(range), 100);
+        assert_eq!(ByteRange::parse(None, 100), ByteRange::Full);
+        assert_eq!(parse("bytes=0-9"), ByteRange::Partial(0, 9));
+        assert_eq!(parse("bytes=50-"), ByteRange::Partial(50, 99));
+        assert_eq!(parse("bytes=90-200"), ByteRange::Partial(90, 99));
+        assert_eq!(parse("bytes=-10"), ByteRange::Partial(90, 99));
+        assert_eq!(parse("bytes=-200"), ByteRange::Partial(0, 99));
+        assert_eq!(parse("bytes=100-"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-0"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=0-9,20-29"), ByteRange::Full);
+        assert_eq!(parse("bytes=9-0"), ByteRange::Full);
+        assert_eq!(parse("items=0-9"), ByteRange::Full);
+        assert_eq!(parse("bytes=abc"), ByteRange::Full);
+        assert_eq!(
+            ByteRange::parse(Some("bytes=0-"), 0),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(entity_tag("abc").as_deref(), Some("\"abc\""));
+        assert_eq!(entity_tag(""), None);
+    }
+}
@@ -0,0 +1,287 @@
+//! Signed, expiring URLs for assets that are expensive to generate (ZIPs and recolor sheets).
+//!
+//! If `SCSRV_SIGNED_URL_SECRET` is set, the URLs of these assets returned by the API get an
+//! `expires` (Unix timestamp) and a `signature` query parameter. The signature is an
+//! HMAC-SHA256 of the asset path without the commit, the other query parameters and the expiry,
+//! so a link handed out by the API can't be changed to generate something else. With
+//! `SCSRV_REQUIRE_SIGNED_URLS`, these assets are only generated for signed URLs.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use hyper::http::HeaderValue;
+use hyper::{Response, StatusCode};
+use itertools::Itertools;
+use sha2::digest::Output;
+use sha2::Sha256;
+
+use crate::assets::url::{match_url, split_assets_commit, AssetType};
+use crate::assets::util::parse_query;
+use crate::ServerConfig;
+
+pub const EXPIRES_PARAM: &str = "expires";
+pub const SIGNATURE_PARAM: &str = "signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Whether the asset type is expensive enough to only be served for signed URLs, if they are
+/// required.
+pub fn is_signed_asset_type(asset_type: &AssetType) -> bool {
+    matches!(
+        asset_type,
+        AssetType::PortraitZip
+            | AssetType::SpriteZip
+            | AssetType::SpriteSplitZip
+            | AssetType::PortraitRecolorSheet
+            | AssetType::SpriteRecolorSheet
+    )
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum SignatureCheck {
+    Valid,
+    Missing,
+    Invalid,
+    Expired,
+}
+
+/// Appends the `expires` and `signature` parameters to `url`, which has no query yet.
+/// `asset_path` is the path of the asset without the commit, eg. `/assets/0025/sprites.zip`.
+///
+/// The expiry is rounded up to a multiple of the TTL, so all URLs of an asset handed out in the
+/// same interval are the same and can be cached. They are valid for one to two TTLs.
+pub fn sign_url(url: &str, asset_path: &str, secret: &str, ttl_secs: u64, now: u64) -> String {
+    let ttl_secs = ttl_secs.max(1);
+    let expires = (now / ttl_secs + 2) * ttl_secs;
+    let signature = signature(secret, asset_path, &[], expires);
+    format!(
+        "{}?{}={}&{}={}",
+        url, EXPIRES_PARAM, expires, SIGNATURE_PARAM, signature
+    )
+}
+
+/// Checks the `expires` and `signature` parameters of a request for the asset at `asset_path`.
+pub fn check_signature(
+    asset_path: &str,
+    query: Option<&str>,
+    secret: &str,
+    now: u64,
+) -> SignatureCheck {
+    let query = parse_query(query);
+    let (expires, signature) = match (query.get(EXPIRES_PARAM), query.get(SIGNATURE_PARAM)) {
+        (Some(expires), Some(signature)) => (expires, signature),
+        (None, None) => return SignatureCheck::Missing,
+        _ => return SignatureCheck::Invalid,
+    };
+    let expires = match expires.parse::<u64>() {
+        Ok(expires) => expires,
+        Err(_) => return SignatureCheck::Invalid,
+    };
+    let other_params = query
+        .iter()
+        .filter(|(key, _)| *key != EXPIRES_PARAM && *key != SIGNATURE_PARAM)
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect::<Vec<_>>();
+    let valid = decode_hex(signature).is_some_and(|signature| {
+        signature_mac(secret, asset_path, &other_params, expires)
+            .verify_slice(&signature)
+            .is_ok()
+    });
+    if !valid {
+        SignatureCheck::Invalid
+    } else if expires < now {
+        SignatureCheck::Expired
+    } else {
+        SignatureCheck::Valid
+    }
+}
+
+/// Checks the signature of a request for an asset, if the asset type needs one. Returns the
+/// response to send instead of the asset if the check failed.
+pub fn verify_asset_request(
+    asset_type: &AssetType,
+    asset_path: &str,
+    query: Option<&str>,
+) -> Option<Response<String>> {
+    let config = ServerConfig::get();
+    let secret = config.signed_url_secret.as_deref()?;
+    if !is_signed_asset_type(asset_type) {
+        return None;
+    }
+    match check_signature(asset_path, query, secret, unix_now()) {
+        SignatureCheck::Valid => None,
+        SignatureCheck::Missing if !config.require_signed_urls => None,
+        SignatureCheck::Missing => Some(make_forbidden_response(
+            "This asset is only available with a signed URL, as returned by the API.",
+        )),
+        SignatureCheck::Invalid => Some(make_forbidden_response("The signature is invalid.")),
+        SignatureCheck::Expired => Some(make_forbidden_response(
+            "The URL has expired. Request a new one from the API.",
+        )),
+    }
+}
+
+/// Whether the request is for an asset with a valid signed URL. `path` may contain the commit.
+pub fn has_valid_signature(path: &str, query: Option<&str>) -> bool {
+    let secret = match &ServerConfig::get().signed_url_secret {
+        Some(secret) => secret,
+        None => return false,
+    };
+    let asset_path = match split_assets_commit(path) {
+        Some((_, asset_path)) => asset_path,
+        None => path.to_string(),
+    };
+    match match_url(&asset_path) {
+        Some(route_match) if is_signed_asset_type(&route_match.asset_type()) => {
+            check_signature(&asset_path, query, secret, unix_now()) == SignatureCheck::Valid
+        }
+        _ => false,
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn make_forbidden_response(message: &str) -> Response<String> {
+    let mut response = Response::new(format!(
+        "<html><body><h1>{}</h1><img src=\"https://http.cat/403\"></body></html>",
+        message
+    ));
+    *response.status_mut() = StatusCode::FORBIDDEN;
+    response.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_static("text/html; charset=UTF-8"),
+    );
+    response
+}
+
+/// Hex encoded HMAC-SHA256 of the asset path, the query parameters (sorted) and the expiry.
+fn signature(secret: &str, asset_path: &str, params: &[(&str, &str)], expires: u64) -> String {
+    format!(
+        "{:x}",
+        signature_mac(secret, asset_path, params, expires)
+            .finalize()
+            .into_bytes()
+    )
+}
+
+/// The HMAC of a [`signature`], to finalize or verify. `-` in the path is treated as `/`, like
+/// when matching the routes.
+fn signature_mac(
+    secret: &str,
+    asset_path: &str,
+    params: &[(&str, &str)],
+    expires: u64,
+) -> HmacSha256 {
+    let params = params
+        .iter()
+        .sorted()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .join("&");
+    let message = format!("{}\n{}\n{}", asset_path.replace('-', "/"), params, expires);
+    let mut mac = new_hmac_sha256(secret.as_bytes());
+    mac.update(message.as_bytes());
+    mac
+}
+
+pub(crate) fn hmac_sha256(key: &[u8], message: &[u8]) -> Output<Sha256> {
+    let mut mac = new_hmac_sha256(key);
+    mac.update(message);
+    mac.finalize().into_bytes()
+}
+
+fn new_hmac_sha256(key: &[u8]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length.")
+}
+
+/// The bytes of a hex string, `None` if it isn't one.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "secret";
+    const PATH: &str = "/assets/0025/sprites.zip";
+
+    fn query_of(url: &str) -> &str {
+        url.split_once('?').unwrap().1
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        // Test case 2 of RFC 4231.
+        assert_eq!(
+            format!(
+                "{:x}",
+                hmac_sha256(b"Jefe", b"what do ya want for nothing?")
+            ),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn signed_urls_are_valid_until_they_expire() {
+        let url = sign_url(
+            "https://example.com/assets/abc/0025/sprites.zip",
+            PATH,
+            SECRET,
+            100,
+            1050,
+        );
+        assert!(url.ends_with(&format!(
+            "?expires=1200&signature={}",
+            signature(SECRET, PATH, &[], 1200)
+        )));
+        let query = Some(query_of(&url));
+
+        assert_eq!(
+            check_signature(PATH, query, SECRET, 1200),
+            SignatureCheck::Valid
+        );
+        assert_eq!(
+            check_signature(PATH, query, SECRET, 1201),
+            SignatureCheck::Expired
+        );
+        assert_eq!(
+            check_signature(PATH, query, "other", 1100),
+            SignatureCheck::Invalid
+        );
+        assert_eq!(
+            check_signature("/assets/0026/sprites.zip", query, SECRET, 1100),
+            SignatureCheck::Invalid
+        );
+        let with_param = format!("{}&resolve_copies=true", query_of(&url));
+        assert_eq!(
+            check_signature(PATH, Some(&with_param), SECRET, 1100),
+            SignatureCheck::Invalid
+        );
+        assert_eq!(
+            check_signature(PATH, None, SECRET, 1100),
+            SignatureCheck::Missing
+        );
+        for signature in ["abc", "zz", "é0"] {
+            assert_eq!(
+                check_signature(
+                    PATH,
+                    Some(&format!("expires=1200&signature={}", signature)),
+                    SECRET,
+                    1100
+                ),
+                SignatureCheck::Invalid
+            );
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! The limit of the size of generated assets, `SCSRV_MAX_ASSET_SIZE`. Assets are checked
+//! before they are assembled in memory: ZIPs by the total size of their files, images by their
+//! uncompressed size (4 bytes per pixel), which is larger than the PNG. Assets over the limit
+//! are answered with `413 Payload Too Large` instead.
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Response, StatusCode};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::assets::{make_box_body, AssetBody};
+use crate::ServerConfig;
+
+#[derive(Error, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[error("the asset would be {size} bytes, more than the limit of {max_size} bytes")]
+pub struct AssetTooLarge {
+    pub size: u64,
+    pub max_size: u64,
+}
+
+/// Fails if an asset of `size` bytes is over the limit. Without a configuration (in tests and
+/// the `render` example), there is no limit.
+pub fn check_asset_size(size: u64) -> Result<(), AssetTooLarge> {
+    let max_size = ServerConfig::try_get().map_or(0, |config| config.max_asset_size);
+    check_size(size, max_size)
+}
+
+/// Like [`check_asset_size`], for an image of `width` × `height` RGBA pixels.
+pub fn check_image_size(width: u32, height: u32) -> Result<(), AssetTooLarge> {
+    check_asset_size(width as u64 * height as u64 * 4)
+}
+
+fn check_size(size: u64, max_size: u64) -> Result<(), AssetTooLarge> {
+    if max_size > 0 && size > max_size {
+        Err(AssetTooLarge { size, max_size })
+    } else {
+        Ok(())
+    }
+}
+
+/// The 413 of an asset that was not generated because of `error`, if the error is an
+/// [`AssetTooLarge`].
+pub(crate) fn make_too_large_response(error: &anyhow::Error) -> Option<Response<AssetBody>> {
+    let too_large = error.downcast_ref::<AssetTooLarge>()?;
+    #[derive(Serialize)]
+    struct Body<'a> {
+        message: String,
+        #[serde(flatten)]
+        too_large: &'a AssetTooLarge,
+    }
+    let body = serde_json::to_string(&Body {
+        message: format!("The asset can't be generated: {}.", too_large),
+        too_large,
+    })
+    .ok()?;
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .header(CONTENT_TYPE, "application/json")
+        .body(make_box_body(Full::new(Bytes::from(body))))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_assets_over_the_limit() {
+        assert!(check_size(100, 100).is_ok());
+        assert!(check_size(u64::MAX, 0).is_ok());
+        let error = anyhow::Error::from(check_size(101, 100).unwrap_err());
+        let response = make_too_large_response(&error).unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(make_too_large_response(&anyhow::anyhow!("other")).is_none());
+    }
+}
@@ -0,0 +1,110 @@
+//! Regression snapshots of the generated sheets, rendered from the sample SpriteCollab data in
+//! `tests/fixtures/spritecollab`.
+//!
+//! The snapshots in `tests/fixtures/snapshots` were rendered by this implementation, not by
+//! SpriteBot, so they only catch unintended changes of the output, not differences to the
+//! sheets of SpriteBot. If a change to the sheet generation is intended, regenerate them with
+//! `cargo run --example render --features render` and review the difference.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use crate::assets::img_util::{Background, SheetScale};
+use crate::assets::portrait_sheets::{
+    make_portrait_annotated_sheet, make_portrait_sheet, PortraitSheetEmotions,
+};
+use crate::assets::sprite_sheets::make_sprite_recolor_sheet;
+use crate::datafiles::group_id::GroupId;
+use crate::datafiles::sprite_config::read_sprite_config;
+use crate::datafiles::tracker::read_tracker;
+
+pub const SPRITE_RECOLOR_SHEET: &str = "sprite_recolor_sheet.png";
+pub const PORTRAIT_SHEET: &str = "portrait_sheet.png";
+pub const PORTRAIT_ANNOTATED_SHEET: &str = "portrait_annotated_sheet.png";
+
+pub fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+}
+
+pub fn snapshot_dir() -> PathBuf {
+    fixtures_dir().join("snapshots")
+}
+
+/// Renders all snapshots, as file names and PNG data.
+pub async fn render_snapshots() -> Result<Vec<(&'static str, Vec<u8>)>, anyhow::Error> {
+    let repo = fixtures_dir().join("spritecollab");
+    let sprite_config = read_sprite_config(repo.join("sprite_config.json")).await?;
+    let tracker = read_tracker(repo.join("tracker.json")).await?;
+    let group = tracker
+        .get(&GroupId(1))
+        .ok_or_else(|| anyhow!("The fixture tracker.json has no monster 0001."))?;
+
+    let sprite_recolor_sheet = make_sprite_recolor_sheet(
+        &repo.join("sprite").join("0001"),
+        None,
+        SheetScale::Original,
+    )
+    .await?;
+    let portrait_sheet = make_portrait_sheet(
+        group,
+        PortraitSheetEmotions::new(
+            sprite_config.emotions_incl_flipped(),
+            sprite_config.portrait_tile_x,
+        ),
+        &repo.join("portrait").join("0001"),
+        sprite_config.portrait_size,
+        SheetScale::Original,
+        Background::Transparent,
+    )
+    .await?;
+
+    let portrait_annotated_sheet = make_portrait_annotated_sheet(
+        group,
+        PortraitSheetEmotions::new(
+            sprite_config.emotions_incl_flipped(),
+            sprite_config.portrait_tile_x,
+        ),
+        &repo.join("portrait").join("0001"),
+        sprite_config.portrait_size,
+        SheetScale::Original,
+        Background::Transparent,
+    )
+    .await?;
+
+    Ok(vec![
+        (SPRITE_RECOLOR_SHEET, sprite_recolor_sheet.into_inner().png),
+        (PORTRAIT_SHEET, portrait_sheet.into_inner()),
+        (
+            PORTRAIT_ANNOTATED_SHEET,
+            portrait_annotated_sheet.into_inner(),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sheets_match_snapshots() {
+        for (name, png) in render_snapshots().await.unwrap() {
+            let expected = image::open(snapshot_dir().join(name)).unwrap().to_rgba8();
+            let actual = image::load_from_memory(&png).unwrap().to_rgba8();
+            assert_eq!(
+                expected.dimensions(),
+                actual.dimensions(),
+                "{} has the wrong size",
+                name
+            );
+            assert!(
+                expected == actual,
+                "{} does not match the snapshot. If this is intended, regenerate it with \
+                `cargo run --example render --features render`.",
+                name
+            );
+        }
+    }
+}
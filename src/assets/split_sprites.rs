@@ -0,0 +1,114 @@
+//! ZIPs of the sprites of a form split per direction, for tools that import animations one
+//! direction at a time instead of slicing the SpriteBot sheets. The layout is this server's own,
+//! it is not the import format of any particular tool: Each action of the AnimData.xml gets a
+//! directory with a strip per direction and sheet, eg. `Walk/DownRight-Anim.png`, with the
+//! frames of that row. Copied actions get the strips of the action they copy. The frame
+//! durations of all actions are in `durations.csv`.
+
+use std::path::Path;
+
+use image::GenericImageView;
+use log::warn;
+
+use crate::assets::atlas::{direction_name, source_anim};
+use crate::assets::img_util::{run_blocking, to_png};
+use crate::assets::{zip_contents, SPRITE_FILE_SUFFIXES};
+use crate::cache::CacheBehaviour;
+use crate::datafiles::anim_data_xml::AnimDataXml;
+
+const DURATIONS_FILE_NAME: &str = "durations.csv";
+
+pub async fn make_split_sprite_zip(
+    sprite_base_path: &Path,
+) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
+    let sprite_base_path = sprite_base_path.to_path_buf();
+    run_blocking(move |cancellation| {
+        let anim_data_xml = std::fs::read(sprite_base_path.join("AnimData.xml"))?;
+        let anim_data = AnimDataXml::from_reader(anim_data_xml.as_slice())?;
+        let mut contents = vec![("AnimData.xml".to_string(), anim_data_xml)];
+        let mut durations = csv::Writer::from_writer(Vec::new());
+        durations.write_record(["action", "frame", "duration"])?;
+        for anim in &anim_data.anims.anim {
+            let source = match source_anim(&anim_data, &anim.name) {
+                Ok(source) => source,
+                Err(e) => {
+                    warn!("Skipping {} of {:?}: {}", anim.name, sprite_base_path, e);
+                    continue;
+                }
+            };
+            let (frame_width, frame_height) = match (source.frame_width, source.frame_height) {
+                (Some(width), Some(height)) if width > 0 && height > 0 => {
+                    (width as u32, height as u32)
+                }
+                _ => continue,
+            };
+            let frame_durations = source
+                .durations
+                .as_ref()
+                .and_then(|d| d.duration.clone())
+                .unwrap_or_default();
+            for (frame, duration) in frame_durations.iter().enumerate() {
+                durations.write_record([
+                    anim.name.clone(),
+                    frame.to_string(),
+                    duration.to_string(),
+                ])?;
+            }
+            for suffix in SPRITE_FILE_SUFFIXES {
+                cancellation.check()?;
+                let sheet_path = sprite_base_path.join(format!("{}-{}.png", source.name, suffix));
+                if !sheet_path.is_file() {
+                    continue;
+                }
+                let sheet = image::open(&sheet_path)?;
+                let rows = sheet.height() / frame_height;
+                let width = (frame_width * frame_durations.len() as u32).min(sheet.width());
+                for row in 0..rows {
+                    let strip = sheet.view(0, row * frame_height, width, frame_height);
+                    contents.push((
+                        format!("{}/{}-{}.png", anim.name, direction_name(row, rows), suffix),
+                        to_png(strip.to_image())?,
+                    ));
+                }
+            }
+        }
+        contents.push((DURATIONS_FILE_NAME.to_string(), durations.into_inner()?));
+        Ok(CacheBehaviour::Cache(zip_contents(contents, cancellation)?))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::assets::snapshots::fixtures_dir;
+
+    #[tokio::test]
+    async fn splits_sprites_per_direction() {
+        let sprites = fixtures_dir().join("spritecollab/sprite/0001");
+        let zip = make_split_sprite_zip(&sprites).await.unwrap().into_inner();
+        let mut zip = ZipArchive::new(Cursor::new(zip)).unwrap();
+
+        // 8 directions of 3 sheets, for Walk, Idle and Sleep (a copy of Idle).
+        assert_eq!(zip.len(), 2 + 3 * 8 * 3);
+        let mut strip = Vec::new();
+        zip.by_name("Sleep/UpLeft-Offsets.png")
+            .unwrap()
+            .read_to_end(&mut strip)
+            .unwrap();
+        let strip = image::load_from_memory(&strip).unwrap();
+        assert_eq!((strip.width(), strip.height()), (32, 16));
+
+        let mut durations = String::new();
+        zip.by_name(DURATIONS_FILE_NAME)
+            .unwrap()
+            .read_to_string(&mut durations)
+            .unwrap();
+        assert!(durations.starts_with("action,frame,duration\nWalk,0,8\n"));
+        assert!(durations.ends_with("Sleep,0,30\nSleep,1,10\n"));
+    }
+}
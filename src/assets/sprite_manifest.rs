@@ -0,0 +1,79 @@
+//! The `manifest.json` included in sprite zips, generated from the AnimData.xml.
+
+use std::path::Path;
+
+use log::warn;
+use serde::Serialize;
+
+use crate::assets::SPRITE_FILE_SUFFIXES;
+use crate::datafiles::anim_data_xml::AnimDataXml;
+
+#[derive(Serialize, Debug)]
+pub struct SpriteManifest {
+    shadow_size: i64,
+    actions: Vec<SpriteManifestAction>,
+    /// Files that are required by the AnimData.xml but do not exist.
+    missing_files: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SpriteManifestAction {
+    name: String,
+    index: Option<i64>,
+    copy_of: Option<String>,
+    frame_width: Option<i64>,
+    frame_height: Option<i64>,
+    durations: Vec<i64>,
+    rush_frame: Option<i64>,
+    hit_frame: Option<i64>,
+    return_frame: Option<i64>,
+}
+
+impl SpriteManifest {
+    /// Builds the manifest and checks that all files for the actions in the AnimData.xml exist.
+    pub fn new(sprite_base_path: &Path, anim_data: &AnimDataXml) -> Self {
+        let mut missing_files = Vec::new();
+        let actions = anim_data
+            .anims
+            .anim
+            .iter()
+            .map(|anim| {
+                // Copies don't have their own files.
+                if anim.copy_of.is_none() {
+                    for suffix in SPRITE_FILE_SUFFIXES {
+                        let file_name = format!("{}-{}.png", anim.name, suffix);
+                        if !sprite_base_path.join(&file_name).is_file() {
+                            missing_files.push(file_name);
+                        }
+                    }
+                }
+                SpriteManifestAction {
+                    name: anim.name.clone(),
+                    index: anim.index,
+                    copy_of: anim.copy_of.clone(),
+                    frame_width: anim.frame_width,
+                    frame_height: anim.frame_height,
+                    durations: anim
+                        .durations
+                        .as_ref()
+                        .and_then(|d| d.duration.clone())
+                        .unwrap_or_default(),
+                    rush_frame: anim.rush_frame,
+                    hit_frame: anim.hit_frame,
+                    return_frame: anim.return_frame,
+                }
+            })
+            .collect();
+        if !missing_files.is_empty() {
+            warn!(
+                "Sprites at {:?} are missing files required by the AnimData.xml: {:?}",
+                sprite_base_path, missing_files
+            );
+        }
+        Self {
+            shadow_size: anim_data.shadow_size,
+            actions,
+            missing_files,
+        }
+    }
+}
@@ -1,9 +1,12 @@
-use crate::assets::img_util::{add_palette_to, to_png};
+use crate::assets::img_util::{
+    make_recolor_sheet, run_blocking, Cancellation, RecolorSheet, SheetScale,
+};
 use crate::cache::CacheBehaviour;
 use crate::datafiles::anim_data_xml::AnimDataXml;
 use anyhow::anyhow;
 use image::{DynamicImage, GenericImage, GenericImageView, RgbaImage};
 use indexmap::IndexMap;
+use log::warn;
 use std::cmp::{max, min};
 use std::path::{Path, PathBuf};
 
@@ -88,14 +91,64 @@ impl SpriteOffsets {
     }
 }
 
+/// Makes a recolor sheet of all unique frames, with the palette in an extra row above them. If
+/// `debug_dump_dir` is set, every frame is also saved to that directory.
 pub async fn make_sprite_recolor_sheet(
     sprite_base_path: &Path,
-) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
-    let frames = get_sprite_frames(sprite_base_path).await?;
-    for (idx, (frame, _)) in frames.iter().enumerate() {
-        frame.save(format!("/workdir/{}.png", idx)).unwrap();
+    debug_dump_dir: Option<&Path>,
+    scale: SheetScale,
+) -> Result<CacheBehaviour<RecolorSheet>, anyhow::Error> {
+    let sprite_base_path = sprite_base_path.to_path_buf();
+    let debug_dump_dir = debug_dump_dir.map(Path::to_path_buf);
+    run_blocking(move |cancellation| {
+        do_make_sprite_recolor_sheet(
+            &sprite_base_path,
+            debug_dump_dir.as_deref(),
+            scale,
+            cancellation,
+        )
+    })
+    .await
+}
+
+fn do_make_sprite_recolor_sheet(
+    sprite_base_path: &Path,
+    debug_dump_dir: Option<&Path>,
+    scale: SheetScale,
+    cancellation: &Cancellation,
+) -> Result<CacheBehaviour<RecolorSheet>, anyhow::Error> {
+    let frames = get_sprite_frames(sprite_base_path, cancellation)?;
+    if let Some(debug_dump_dir) = debug_dump_dir {
+        for (idx, (frame, _)) in frames.iter().enumerate() {
+            let path = debug_dump_dir.join(format!("{}.png", idx));
+            if let Err(e) = frame.save(&path) {
+                warn!("Failed to write debug frame to {:?}: {:?}", path, e);
+            }
+        }
+    }
+    let combined_img = combine_frames(&frames)?;
+    let sheet = make_recolor_sheet(&combined_img, scale)?;
+    if sheet.exceeds_color_limit() {
+        warn!(
+            "Sprites at {:?} have {} colors, more than allowed.",
+            sprite_base_path, sheet.palette_size
+        );
     }
-    let (frame_size_x, frame_size_y) = get_sprite_frame_size_from_frames(&frames);
+    Ok(CacheBehaviour::Cache(sheet))
+}
+
+/// Blocking, the image of all unique frames that the recolor sheet is made of, without the
+/// palette.
+pub(crate) fn make_sprite_frames_image(
+    sprite_base_path: &Path,
+    cancellation: &Cancellation,
+) -> Result<RgbaImage, anyhow::Error> {
+    combine_frames(&get_sprite_frames(sprite_base_path, cancellation)?)
+}
+
+/// Places the frames in a square grid, each centered in a tile of the size of the largest frame.
+fn combine_frames(frames: &[(DynamicImage, SpriteOffsets)]) -> Result<RgbaImage, anyhow::Error> {
+    let (frame_size_x, frame_size_y) = get_sprite_frame_size_from_frames(frames);
 
     let max_size = (frames.len() as f64).sqrt().ceil() as u32;
     let mut combined_img = RgbaImage::new(max_size * frame_size_x, max_size * frame_size_y);
@@ -110,12 +163,12 @@ pub async fn make_sprite_recolor_sheet(
         let tile_pos_y = yy * frame_size_y;
         combined_img.copy_from(frame, tile_pos_x + diff_pos_x, tile_pos_y + diff_pos_y)?;
     }
-    add_palette_to(&mut combined_img);
-    Ok(CacheBehaviour::Cache(to_png(combined_img)?))
+    Ok(combined_img)
 }
 
-async fn get_sprite_frames(
+fn get_sprite_frames(
     sprite_base_path: &Path,
+    cancellation: &Cancellation,
 ) -> Result<Vec<(DynamicImage, SpriteOffsets)>, anyhow::Error> {
     let mut anim_dims = IndexMap::new();
 
@@ -140,6 +193,7 @@ async fn get_sprite_frames(
     let mut frames: Vec<(DynamicImage, SpriteOffsets)> = Vec::new();
 
     for (anim_name, (frame_size_x, frame_size_y)) in anim_dims {
+        cancellation.check()?;
         let img_path = sprite_base_path.join(format!("{}-Anim.png", anim_name));
         let c_img = image::open(img_path);
         let offset_img_path = sprite_base_path.join(format!("{}-Offsets.png", anim_name));
@@ -435,3 +489,54 @@ fn get_sprite_frame_size_from_frames(frames: &[(DynamicImage, SpriteOffsets)]) -
 fn round_up_to_mult(num: u32, mult: u32) -> u32 {
     (((num - 1) / mult) + 1) * mult
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sprite_collab::GIT_REPO_DIR;
+    use image::Rgba;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn list_dir(path: &Path) -> Vec<String> {
+        let mut files = fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn recolor_sheet_works_with_read_only_workdir() {
+        // Permissions don't apply to root, the workdir would still be writable.
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let config = crate::testing::config();
+        let workdir = &config.workdir;
+        let sprite_dir = workdir.join(GIT_REPO_DIR).join("sprite").join("0001");
+
+        for dir in [workdir, &sprite_dir] {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o555)).unwrap();
+        }
+        let files_before = (list_dir(workdir), list_dir(&sprite_dir));
+        let result = make_sprite_recolor_sheet(
+            &sprite_dir,
+            config.debug_dump_dir.as_deref(),
+            SheetScale::Original,
+        )
+        .await;
+        let files_after = (list_dir(workdir), list_dir(&sprite_dir));
+        for dir in [workdir, &sprite_dir] {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let sheet = result.unwrap().into_inner();
+        let img = image::load_from_memory(&sheet.png).unwrap().to_rgba8();
+        assert!(sheet.palette_size > 0);
+        // The palette strip is an extra row above the frames.
+        assert_ne!(img.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+        assert_eq!(files_before, files_after);
+    }
+}
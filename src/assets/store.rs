@@ -0,0 +1,148 @@
+//! Access to the portrait and sprite files of the forms. The server reads them from the
+//! checkout of the SpriteCollab repository in the workdir, see [`LocalAssetStore`]. The store is
+//! owned by [`crate::SpriteCollab`], so the GraphQL resolvers and the asset generation don't
+//! depend on where the files are, and tests can use a repository of their own.
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::assets::fs_check::AssetCategory;
+use crate::assets::util::join_monster_and_form;
+
+pub trait AssetStore: Send + Sync {
+    /// Directory with the portraits or sprites of a form. The asset generation reads the files
+    /// of the form from it.
+    fn form_dir(&self, category: AssetCategory, monster_id: i32, form_path: &[i32]) -> PathBuf;
+
+    /// Whether the portrait of an emotion exists. Flipped emotions end with `^`.
+    fn has_portrait(&self, monster_id: i32, form_path: &[i32], emotion: &str) -> bool {
+        self.form_dir(AssetCategory::Portrait, monster_id, form_path)
+            .join(format!("{}.png", emotion))
+            .exists()
+    }
+
+    /// Whether the sprite sheet of an action exists.
+    fn has_sprite(&self, monster_id: i32, form_path: &[i32], action: &str) -> bool {
+        self.form_dir(AssetCategory::Sprite, monster_id, form_path)
+            .join(format!("{}-Anim.png", action))
+            .exists()
+    }
+
+    /// Opens the portrait of an emotion. Flipped emotions end with `^`.
+    fn open_portrait(&self, monster_id: i32, form_path: &[i32], emotion: &str) -> io::Result<File> {
+        File::open(
+            self.form_dir(AssetCategory::Portrait, monster_id, form_path)
+                .join(format!("{}.png", emotion)),
+        )
+    }
+
+    /// Opens the `AnimData.xml` of the sprites of a form.
+    fn open_anim_data_xml(&self, monster_id: i32, form_path: &[i32]) -> io::Result<File> {
+        File::open(
+            self.form_dir(AssetCategory::Sprite, monster_id, form_path)
+                .join("AnimData.xml"),
+        )
+    }
+
+    /// Reads the `credits.txt` of the portraits or sprites of a form. `None` if the form has
+    /// none.
+    fn read_credits(
+        &self,
+        category: AssetCategory,
+        monster_id: i32,
+        form_path: &[i32],
+    ) -> io::Result<Option<Vec<u8>>> {
+        match std::fs::read(
+            self.form_dir(category, monster_id, form_path)
+                .join("credits.txt"),
+        ) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Names of the files of the sprites of a form, without the directories of its subforms.
+    /// Empty if the form has no sprites.
+    fn list_sprite_files(&self, monster_id: i32, form_path: &[i32]) -> io::Result<Vec<String>> {
+        let dir = self.form_dir(AssetCategory::Sprite, monster_id, form_path);
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut files = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                if let Some(name) = entry.file_name().to_str() {
+                    files.push(name.to_string());
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// The files of a checkout of the SpriteCollab repository.
+pub struct LocalAssetStore {
+    root: PathBuf,
+}
+
+impl LocalAssetStore {
+    /// `root` is the directory of the repository.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl AssetStore for LocalAssetStore {
+    fn form_dir(&self, category: AssetCategory, monster_id: i32, form_path: &[i32]) -> PathBuf {
+        let category = match category {
+            AssetCategory::Portrait => "portrait",
+            AssetCategory::Sprite => "sprite",
+        };
+        self.root
+            .join(category)
+            .join(join_monster_and_form(monster_id, form_path, '/'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SyntheticRepo;
+
+    #[test]
+    fn reads_files_of_the_repository() {
+        let repo = SyntheticRepo::create("scsrv-asset-store-test");
+        let store = LocalAssetStore::new(repo.path());
+
+        assert!(store.has_portrait(1, &[], "Normal"));
+        assert!(store.has_portrait(1, &[], "Normal^"));
+        assert!(!store.has_portrait(1, &[], "Sad"));
+        assert!(store.has_sprite(1, &[], "Idle"));
+        // Sleep is in the tracker, but has no file.
+        assert!(!store.has_sprite(1, &[], "Sleep"));
+        assert!(store.open_portrait(1, &[], "Happy").is_ok());
+        assert!(store.open_anim_data_xml(1, &[]).is_ok());
+
+        let files = store.list_sprite_files(1, &[]).unwrap();
+        assert_eq!(files.len(), 8);
+        assert!(files.contains(&"AnimData.xml".to_string()));
+        assert!(store.list_sprite_files(1, &[1]).unwrap().is_empty());
+
+        assert!(store
+            .read_credits(AssetCategory::Portrait, 1, &[])
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            store.read_credits(AssetCategory::Portrait, 2, &[]).unwrap(),
+            None
+        );
+    }
+}
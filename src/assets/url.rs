@@ -1,5 +1,11 @@
+use crate::assets::atlas::AtlasFormat;
+use crate::assets::files::FILES_PATH;
+use crate::assets::signed_urls::{is_signed_asset_type, sign_url, unix_now};
 use crate::assets::util::{force_shiny_group, join_monster_and_form};
-use crate::Config;
+use crate::openapi::RouteParam;
+use crate::sprite_collab::SpriteCollab;
+use crate::ServerConfig;
+use once_cell::sync::OnceCell;
 use route_recognizer::Router;
 use std::collections::VecDeque;
 
@@ -9,172 +15,490 @@ pub enum AssetType<'a> {
     SpriteCreditsTxt,
     PortraitSheet,
     PortraitRecolorSheet,
+    PortraitAnnotatedSheet,
+    /// A portrait, by the name of the emotion, see [`crate::datafiles::sprite_config::Emotion`].
     Portrait(&'a str),
+    /// A flipped portrait, by the name of the emotion without the `^`.
     PortraitFlipped(&'a str),
     SpriteAnimDataXml,
     SpriteZip,
+    /// The sprites split per direction, see [`crate::assets::split_sprites`].
+    SpriteSplitZip,
+    PortraitZip,
     SpriteRecolorSheet,
+    PortraitPaletteDiff,
+    PortraitPaletteDiffSheet,
+    SpritePaletteDiff,
+    SpritePaletteDiffSheet,
+    /// The sheets of a sprite action, by its name, see
+    /// [`crate::datafiles::sprite_config::ActionName`].
     SpriteAnim(&'a str),
     SpriteOffsets(&'a str),
     SpriteShadows(&'a str),
+    /// The frame atlas of a sprite action for a game engine.
+    SpriteAtlas(&'a str, AtlasFormat),
+    Preview,
+}
+
+/// The base of all URLs returned by [`get_url`].
+#[derive(Clone, Debug)]
+pub struct AssetUrlBase {
+    pub this_server_url: String,
+    /// The commit the served data is read from, see
+    /// [`crate::sprite_collab::SpriteCollabData::assets_commit`].
+    pub assets_commit: String,
+}
+
+impl AssetUrlBase {
+    pub fn new(this_server_url: &str, sprite_collab: &SpriteCollab) -> Self {
+        Self {
+            this_server_url: this_server_url.to_string(),
+            assets_commit: sprite_collab.data().assets_commit.clone(),
+        }
+    }
+
+    /// Base URL of the assets generated by this server. They are served under the commit they
+    /// are generated from, eg. `/assets/<commit>/portrait-0025.png`, so their URLs change
+    /// whenever their content could change and clients can cache them forever.
+    pub fn generated_assets_url(&self) -> String {
+        if self.assets_commit.is_empty() {
+            format!("{}/assets", self.this_server_url)
+        } else {
+            format!("{}/assets/{}", self.this_server_url, self.assets_commit)
+        }
+    }
 }
 
 pub fn get_url(
     asset_type: AssetType,
-    this_srv_url: &str,
+    url_base: &AssetUrlBase,
     monster_id: i32,
     path_to_form: &[i32],
 ) -> String {
-    let assets_srv_url = Config::GitAssetsUrl.get();
+    let config = ServerConfig::get();
+    let assets_srv_url = if config.local_asset_urls {
+        format!("{}{}", url_base.this_server_url, FILES_PATH)
+    } else {
+        config.git_assets_url()
+    };
+    let generated_srv_url = url_base.generated_assets_url();
 
-    match asset_type {
+    let url = match asset_type {
         AssetType::PortraitCreditsTxt => {
             let joined_f_dash = join_monster_and_form(monster_id, path_to_form, '-');
             format!(
-                "{}/assets/portrait-credits-{}.txt",
-                this_srv_url, joined_f_dash
+                "{}/portrait-credits-{}.txt",
+                generated_srv_url, joined_f_dash
             )
         }
         AssetType::SpriteCreditsTxt => {
             let joined_f_dash = join_monster_and_form(monster_id, path_to_form, '-');
-            format!(
-                "{}/assets/sprite-credits-{}.txt",
-                this_srv_url, joined_f_dash
-            )
+            format!("{}/sprite-credits-{}.txt", generated_srv_url, joined_f_dash)
         }
         AssetType::PortraitSheet => {
             let joined_f_dash = join_monster_and_form(monster_id, path_to_form, '-');
-            format!("{}/assets/portrait-{}.png", this_srv_url, joined_f_dash)
+            format!("{}/portrait-{}.png", generated_srv_url, joined_f_dash)
         }
         AssetType::PortraitRecolorSheet => {
             let joined_f_dash =
                 join_monster_and_form(monster_id, &force_shiny_group(path_to_form), '-');
             format!(
-                "{}/assets/portrait_recolor-{}.png",
-                this_srv_url, joined_f_dash
+                "{}/portrait_recolor-{}.png",
+                generated_srv_url, joined_f_dash
             )
         }
-        AssetType::Portrait(emotion) => {
-            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
+        AssetType::PortraitAnnotatedSheet => {
+            let joined_f_dash = join_monster_and_form(monster_id, path_to_form, '-');
             format!(
-                "{}/portrait/{}/{}.png",
-                assets_srv_url,
-                joined_f,
-                up(emotion)
+                "{}/portrait_annotated-{}.png",
+                generated_srv_url, joined_f_dash
             )
         }
+        AssetType::Portrait(emotion) => {
+            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
+            format!("{}/portrait/{}/{}.png", assets_srv_url, joined_f, emotion)
+        }
         AssetType::PortraitFlipped(emotion) => {
             let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
-            format!(
-                "{}/portrait/{}/{}.png",
-                assets_srv_url,
-                joined_f,
-                up(emotion)
-            )
+            format!("{}/portrait/{}/{}^.png", assets_srv_url, joined_f, emotion)
         }
         AssetType::SpriteAnimDataXml => {
             let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
             format!("{}/sprite/{}/AnimData.xml", assets_srv_url, joined_f)
         }
+        AssetType::PortraitZip => {
+            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
+            format!("{}/{}/portraits.zip", generated_srv_url, joined_f)
+        }
         AssetType::SpriteZip => {
             let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
-            format!("{}/assets/{}/sprites.zip", this_srv_url, joined_f)
+            format!("{}/{}/sprites.zip", generated_srv_url, joined_f)
+        }
+        AssetType::SpriteSplitZip => {
+            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
+            format!("{}/{}/sprites_split.zip", generated_srv_url, joined_f)
         }
         AssetType::SpriteRecolorSheet => {
+            let joined_f_dash =
+                join_monster_and_form(monster_id, &force_shiny_group(path_to_form), '-');
+            format!("{}/sprite_recolor-{}.png", generated_srv_url, joined_f_dash)
+        }
+        AssetType::PortraitPaletteDiff => {
             let joined_f_dash =
                 join_monster_and_form(monster_id, &force_shiny_group(path_to_form), '-');
             format!(
-                "{}/assets/sprite_recolor-{}.png",
-                this_srv_url, joined_f_dash
+                "{}/portrait_palette_diff-{}.json",
+                generated_srv_url, joined_f_dash
             )
         }
-        AssetType::SpriteAnim(action) => {
-            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
+        AssetType::PortraitPaletteDiffSheet => {
+            let joined_f_dash =
+                join_monster_and_form(monster_id, &force_shiny_group(path_to_form), '-');
+            format!(
+                "{}/portrait_palette_diff-{}.png",
+                generated_srv_url, joined_f_dash
+            )
+        }
+        AssetType::SpritePaletteDiff => {
+            let joined_f_dash =
+                join_monster_and_form(monster_id, &force_shiny_group(path_to_form), '-');
             format!(
-                "{}/sprite/{}/{}-Anim.png",
-                assets_srv_url,
-                joined_f,
-                up(action)
+                "{}/sprite_palette_diff-{}.json",
+                generated_srv_url, joined_f_dash
             )
         }
+        AssetType::SpritePaletteDiffSheet => {
+            let joined_f_dash =
+                join_monster_and_form(monster_id, &force_shiny_group(path_to_form), '-');
+            format!(
+                "{}/sprite_palette_diff-{}.png",
+                generated_srv_url, joined_f_dash
+            )
+        }
+        AssetType::SpriteAnim(action) => {
+            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
+            format!("{}/sprite/{}/{}-Anim.png", assets_srv_url, joined_f, action)
+        }
         AssetType::SpriteOffsets(action) => {
             let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
             format!(
                 "{}/sprite/{}/{}-Offsets.png",
-                assets_srv_url,
-                joined_f,
-                up(action)
+                assets_srv_url, joined_f, action
             )
         }
         AssetType::SpriteShadows(action) => {
             let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
             format!(
                 "{}/sprite/{}/{}-Shadow.png",
-                assets_srv_url,
-                joined_f,
-                up(action)
+                assets_srv_url, joined_f, action
             )
         }
+        AssetType::SpriteAtlas(action, format) => {
+            let joined_f_dash = join_monster_and_form(monster_id, path_to_form, '-');
+            format!(
+                "{}/sprite_atlas/{}/{}.{}",
+                generated_srv_url,
+                action,
+                joined_f_dash,
+                format.extension()
+            )
+        }
+        AssetType::Preview => {
+            let joined_f_dash = join_monster_and_form(monster_id, path_to_form, '-');
+            format!("{}/preview-{}.png", generated_srv_url, joined_f_dash)
+        }
+    };
+    match &config.signed_url_secret {
+        Some(secret) if is_signed_asset_type(&asset_type) => {
+            let asset_path = format!("/assets{}", &url[generated_srv_url.len()..]);
+            sign_url(
+                &url,
+                &asset_path,
+                secret,
+                config.signed_url_ttl.as_secs(),
+                unix_now(),
+            )
+        }
+        _ => url,
     }
 }
 
-/// Matches a URL, if it matches returns a tuple of (monster id, form path, asset type)
-pub fn match_url(path: &str) -> Option<(i32, VecDeque<i32>, AssetType)> {
-    let mut router = Router::new();
+/// A route serving assets of a single form. Used to match request paths and to generate the
+/// OpenAPI document.
+#[derive(Debug)]
+pub struct AssetRoute {
+    /// Path pattern in `route_recognizer` syntax. `*formpath` is the monster ID followed by the
+    /// form path.
+    pub pattern: &'static str,
+    pub asset_type: AssetType<'static>,
+    pub content_type: &'static str,
+    pub summary: &'static str,
+    /// Path parameters besides `formpath`.
+    pub path_params: &'static [RouteParam],
+    pub query_params: &'static [RouteParam],
+}
+
+/// Description of the `formpath` parameter of all asset routes.
+pub const FORMPATH_PARAM: RouteParam = RouteParam {
+    name: "formpath",
+    description:
+        "The monster ID, followed by the form path, seperated by - or /, eg. 0025-0000-0001.",
+};
+
+/// Query parameters of the routes of sheets that can be scaled.
+const SCALE_PARAMS: &[RouteParam] = &[
+    RouteParam {
+        name: "scale",
+        description: "Scale the sheet up by this integer factor (nearest neighbour), up to 8.",
+    },
+    RouteParam {
+        name: "max_width",
+        description: "Scale the sheet up by the largest integer factor that fits into this width, or down to this width if the sheet is wider. Ignored if scale is set.",
+    },
+];
+
+const ACTION_PARAM: RouteParam = RouteParam {
+    name: "action",
+    description: "Name of the sprite action, eg. Walk.",
+};
+
+/// All asset routes. `-` in request paths is treated as `/` before matching, to support
+/// SpriteBot-formatted file names. For the routes of single sprite actions, the action in
+/// `asset_type` is a placeholder, it is filled in from the `:action` parameter.
+pub const ASSET_ROUTES: &[AssetRoute] = &[
+    AssetRoute {
+        pattern: "/assets/portrait/credits/*formpath.txt",
+        asset_type: AssetType::PortraitCreditsTxt,
+        content_type: "text/plain",
+        summary: "The credits of the portraits of a form, in the format of SpriteBot's credits.txt.",
+        path_params: &[],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/sprite/credits/*formpath.txt",
+        asset_type: AssetType::SpriteCreditsTxt,
+        content_type: "text/plain",
+        summary: "The credits of the sprites of a form, in the format of SpriteBot's credits.txt.",
+        path_params: &[],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/portrait/*formpath.png",
+        asset_type: AssetType::PortraitSheet,
+        content_type: "image/png",
+        summary: "A SpriteBot format sheet of all portraits of a form.",
+        path_params: &[],
+        query_params: SCALE_PARAMS,
+    },
+    AssetRoute {
+        pattern: "/assets/portrait_recolor/*formpath.png",
+        asset_type: AssetType::PortraitRecolorSheet,
+        content_type: "image/png",
+        summary: "A SpriteBot format recolor sheet of the portraits of a form. The number of colors in the palette is returned in the X-Palette-Size header.",
+        path_params: &[],
+        query_params: SCALE_PARAMS,
+    },
+    AssetRoute {
+        pattern: "/assets/portrait_annotated/*formpath.png",
+        asset_type: AssetType::PortraitAnnotatedSheet,
+        content_type: "image/png",
+        summary: "An overview of the portraits of a form like the one SpriteBot posts: All emotions in the layout of the portrait sheet, labeled with their names.",
+        path_params: &[],
+        query_params: SCALE_PARAMS,
+    },
+    AssetRoute {
+        pattern: "/assets/*formpath/sprites.zip",
+        asset_type: AssetType::SpriteZip,
+        content_type: "application/zip",
+        summary: "A SpriteBot format ZIP archive of all sprites of a form, with a manifest.json.",
+        path_params: &[],
+        query_params: &[RouteParam {
+            name: "resolve_copies",
+            description: "If true, actions that are a copy of another action are included with the files of the action they copy.",
+        }],
+    },
+    AssetRoute {
+        pattern: "/assets/*formpath/sprites_split.zip",
+        asset_type: AssetType::SpriteSplitZip,
+        content_type: "application/zip",
+        summary: "A ZIP archive of the sprites of a form split per direction, in a layout specific to this server: A directory per action with a strip per direction and sheet, and the frame durations in durations.csv.",
+        path_params: &[],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/*formpath/portraits.zip",
+        asset_type: AssetType::PortraitZip,
+        content_type: "application/zip",
+        summary: "A ZIP archive of all portraits of a form.",
+        path_params: &[],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/sprite_recolor/*formpath.png",
+        asset_type: AssetType::SpriteRecolorSheet,
+        content_type: "image/png",
+        summary: "A SpriteBot format recolor sheet of the sprites of a form. The number of colors in the palette is returned in the X-Palette-Size header.",
+        path_params: &[],
+        query_params: SCALE_PARAMS,
+    },
+    AssetRoute {
+        pattern: "/assets/portrait_palette_diff/*formpath.json",
+        asset_type: AssetType::PortraitPaletteDiff,
+        content_type: "application/json",
+        summary: "Compares the portraits of the normal and the shiny version of a form: Each color of the normal palette, with the shiny colors at its pixels.",
+        path_params: &[],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/portrait_palette_diff/*formpath.png",
+        asset_type: AssetType::PortraitPaletteDiffSheet,
+        content_type: "image/png",
+        summary: "The portrait sheets of the normal and the shiny version of a form side by side, with each color of the normal palette above the shiny color it became.",
+        path_params: &[],
+        query_params: SCALE_PARAMS,
+    },
+    AssetRoute {
+        pattern: "/assets/sprite_palette_diff/*formpath.json",
+        asset_type: AssetType::SpritePaletteDiff,
+        content_type: "application/json",
+        summary: "Compares the sprites of the normal and the shiny version of a form: Each color of the normal palette, with the shiny colors at its pixels.",
+        path_params: &[],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/sprite_palette_diff/*formpath.png",
+        asset_type: AssetType::SpritePaletteDiffSheet,
+        content_type: "image/png",
+        summary: "The unique sprite frames of the normal and the shiny version of a form side by side, with each color of the normal palette above the shiny color it became.",
+        path_params: &[],
+        query_params: SCALE_PARAMS,
+    },
+    AssetRoute {
+        pattern: "/assets/sprite/:action/anim/*formpath.png",
+        asset_type: AssetType::SpriteAnim(""),
+        content_type: "image/png",
+        summary: "The sheet with the frames of a single sprite action.",
+        path_params: &[ACTION_PARAM],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/sprite/:action/offsets/*formpath.png",
+        asset_type: AssetType::SpriteOffsets(""),
+        content_type: "image/png",
+        summary: "The sheet with the offset pixels of each frame of a single sprite action.",
+        path_params: &[ACTION_PARAM],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/sprite/:action/shadow/*formpath.png",
+        asset_type: AssetType::SpriteShadows(""),
+        content_type: "image/png",
+        summary: "The sheet with the shadow placeholders of each frame of a single sprite action.",
+        path_params: &[ACTION_PARAM],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/sprite_atlas/:action/*formpath.json",
+        asset_type: AssetType::SpriteAtlas("", AtlasFormat::TexturePacker),
+        content_type: "application/json",
+        summary: "Where each frame of a sprite action is on its Anim sheet, in the JSON array format of TexturePacker, with the durations of the frames in milliseconds and a frame tag per direction.",
+        path_params: &[ACTION_PARAM],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/sprite_atlas/:action/*formpath.tres",
+        asset_type: AssetType::SpriteAtlas("", AtlasFormat::Godot),
+        content_type: "text/plain",
+        summary: "A Godot SpriteFrames resource with an animation per direction of a sprite action, cut from its Anim sheet.",
+        path_params: &[ACTION_PARAM],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/sprite_atlas/:action/*formpath.meta",
+        asset_type: AssetType::SpriteAtlas("", AtlasFormat::Unity),
+        content_type: "text/plain",
+        summary: "A Unity .meta file for the Anim sheet of a sprite action, which imports each frame as a sprite.",
+        path_params: &[ACTION_PARAM],
+        query_params: &[],
+    },
+    AssetRoute {
+        pattern: "/assets/preview/*formpath.png",
+        asset_type: AssetType::Preview,
+        content_type: "image/png",
+        summary: "A small preview image of a form: The Normal portrait, or the first frame of the Idle sprite if there are no portraits.",
+        path_params: &[],
+        query_params: &[RouteParam {
+            name: "size",
+            description: "Maximum width or height of the image in pixels, up to 512.",
+        }],
+    },
+];
+
+static ASSET_ROUTER: OnceCell<Router<&'static AssetRoute>> = OnceCell::new();
+
+/// The parameters of a request path that matched an asset route.
+#[derive(Debug)]
+pub struct AssetRouteMatch {
+    pub route: &'static AssetRoute,
+    pub monster_id: i32,
+    pub form_path: VecDeque<i32>,
+    /// The action, for the routes of single sprite actions.
+    pub action: Option<String>,
+}
+
+impl AssetRouteMatch {
+    /// The type of the asset, with the action filled in.
+    pub fn asset_type(&self) -> AssetType<'_> {
+        let action = self.action.as_deref().unwrap_or_default();
+        match self.route.asset_type {
+            AssetType::SpriteAnim(_) => AssetType::SpriteAnim(action),
+            AssetType::SpriteOffsets(_) => AssetType::SpriteOffsets(action),
+            AssetType::SpriteShadows(_) => AssetType::SpriteShadows(action),
+            AssetType::SpriteAtlas(_, format) => AssetType::SpriteAtlas(action, format),
+            ref asset_type => asset_type.clone(),
+        }
+    }
+}
+
+/// Matches a request path against the asset routes.
+pub fn match_url(path: &str) -> Option<AssetRouteMatch> {
+    let router = ASSET_ROUTER.get_or_init(|| {
+        let mut router = Router::new();
+        for route in ASSET_ROUTES {
+            router.add(route.pattern, route);
+        }
+        router
+    });
 
     // This is a bit of a hack, but we treat - as / to easily support
     // SpriteBot-formatted file names.
     let path = path.replace('-', "/");
 
-    router.add(
-        "/assets/portrait/credits/*formpath.txt",
-        AssetType::PortraitCreditsTxt,
-    );
-    router.add(
-        "/assets/sprite/credits/*formpath.txt",
-        AssetType::SpriteCreditsTxt,
-    );
-    router.add("/assets/portrait/*formpath.png", AssetType::PortraitSheet);
-    router.add(
-        "/assets/portrait_recolor/*formpath.png",
-        AssetType::PortraitRecolorSheet,
-    );
-    router.add("/assets/*formpath/sprites.zip", AssetType::SpriteZip);
-    router.add(
-        "/assets/sprite_recolor/*formpath.png",
-        AssetType::SpriteRecolorSheet,
-    );
-    router.add("/assets/portrait/*formpath.png", AssetType::PortraitSheet);
-    router.add(
-        "/assets/portrait_recolor/*formpath.png",
-        AssetType::PortraitRecolorSheet,
-    );
-    router.add("/assets/sprites.zip", AssetType::SpriteZip);
-
     let m = router.recognize(&path).ok()?;
+    let params = m.params();
 
-    let form_path = m.params().find("formpath").map(|s| {
-        s.split('/')
-            .map(|x| x.parse::<i32>())
-            .collect::<Result<VecDeque<i32>, _>>()
-    });
-
-    let (monster_id, form_path) = match form_path {
-        Some(Ok(mut x)) => (x.pop_front()?, x),
-        Some(Err(_)) => return None,
-        None => return None,
-    };
-    Some((monster_id, form_path, (*m.handler()).clone()))
+    let mut form_path = params
+        .find("formpath")?
+        .split('/')
+        .map(|x| x.parse::<i32>())
+        .collect::<Result<VecDeque<i32>, _>>()
+        .ok()?;
+    Some(AssetRouteMatch {
+        route: m.handler(),
+        monster_id: form_path.pop_front()?,
+        form_path,
+        action: params.find("action").map(str::to_string),
+    })
 }
 
-fn up(s: &str) -> String {
-    // a bit ugly, but it works for now
-    if s == "teary-eyed" {
-        return "Teary-Eyed".to_string();
-    }
-    let mut c = s.chars();
-    match c.next() {
-        None => String::new(),
-        Some(f) => f.to_uppercase().collect::<String>() + c.as_str(),
+/// Splits the commit off the path of a generated asset, eg.
+/// `/assets/<commit>/portrait-0025.png` into `<commit>` and `/assets/portrait-0025.png`.
+/// Returns `None` if the path does not contain a commit.
+pub fn split_assets_commit(path: &str) -> Option<(&str, String)> {
+    let rest = path.strip_prefix("/assets/")?;
+    let (commit, rest) = rest.split_once('/')?;
+    if !matches!(commit.len(), 40 | 64) || !commit.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
     }
+    Some((commit, format!("/assets/{}", rest)))
 }
@@ -1,4 +1,5 @@
 use itertools::Itertools;
+use std::collections::HashMap;
 
 pub fn join_form(form_path: &[i32], with_leading_slash: bool, character: char) -> String {
     let mut form_joined = form_path
@@ -48,3 +49,10 @@ pub fn force_shiny_group<'a, I: IntoIterator<Item = &'a i32>>(group: I) -> Vec<i
     }
     collected
 }
+
+/// Parses the query string of a request URI into a map of parameters.
+pub fn parse_query(query: Option<&str>) -> HashMap<String, String> {
+    query
+        .map(|q| form_urlencoded::parse(q.as_bytes()).into_owned().collect())
+        .unwrap_or_default()
+}
@@ -13,6 +13,16 @@ pub enum CacheBehaviour<T> {
     NoCache(T),
 }
 
+impl<T> CacheBehaviour<T> {
+    /// Returns the value, regardless of whether it should be cached.
+    pub fn into_inner(self) -> T {
+        match self {
+            CacheBehaviour::Cache(v) => v,
+            CacheBehaviour::NoCache(v) => v,
+        }
+    }
+}
+
 #[async_trait]
 /// Trait for caching data in Redis, and calculating it if it's not in the cache yet.
 pub trait ScCache: Send + Sync {
@@ -73,10 +83,17 @@ pub trait ScCache: Send + Sync {
         Ft: Future<Output = Result<CacheBehaviour<T>, E>> + Send,
         T: DeserializeOwned + Serialize + Send + Sync,
         E: Send;
+
+    /// Returns all keys in the cache that start with `prefix`, sorted.
+    async fn cache_keys(&self, prefix: &str) -> Result<Vec<String>, Self::Error>;
+
+    /// Removes the entry with the key `cache_key` from the cache, so it is calculated again on
+    /// the next lookup. Returns whether it existed.
+    async fn evict(&self, cache_key: &str) -> Result<bool, Self::Error>;
 }
 
 #[async_trait]
-impl<'a, B: ScCache> ScCache for &'a B {
+impl<B: ScCache> ScCache for &B {
     type Error = B::Error;
 
     async fn cached_may_fail<S, Fn, Ft, T, E>(
@@ -93,4 +110,12 @@ impl<'a, B: ScCache> ScCache for &'a B {
     {
         <B as ScCache>::cached_may_fail(self, cache_key, func).await
     }
+
+    async fn cache_keys(&self, prefix: &str) -> Result<Vec<String>, Self::Error> {
+        <B as ScCache>::cache_keys(self, prefix).await
+    }
+
+    async fn evict(&self, cache_key: &str) -> Result<bool, Self::Error> {
+        <B as ScCache>::evict(self, cache_key).await
+    }
 }
@@ -0,0 +1,143 @@
+//! Content negotiation and compression for text and JSON responses.
+
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use hyper::http::HeaderValue;
+use hyper::{HeaderMap, Response};
+use log::warn;
+
+use crate::assets::{make_box_body, make_err_response, AssetBody};
+
+/// Bodies smaller than this are not worth compressing.
+const MIN_COMPRESS_SIZE: usize = 1024;
+/// Content types (prefixes) that are compressed.
+const COMPRESSIBLE_CONTENT_TYPES: &[&str] = &["application/json", "text/"];
+/// Streamed content types, the body must not be collected.
+const STREAMED_CONTENT_TYPES: &[&str] = &["text/event-stream"];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// Picks the preferred supported encoding from the `Accept-Encoding` header of a request.
+    pub fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        let accepted = headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+        let mut gzip = false;
+        for entry in accepted.split(',') {
+            let mut parts = entry.split(';');
+            let name = parts.next().unwrap_or_default().trim();
+            // A quality of 0 means the encoding is explicitly not acceptable.
+            let rejected = parts.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    == Some(0.0)
+            });
+            if rejected {
+                continue;
+            }
+            match name {
+                "br" => return Some(Self::Brotli),
+                "gzip" => gzip = true,
+                _ => {}
+            }
+        }
+        gzip.then_some(Self::Gzip)
+    }
+
+    fn header_value(&self) -> HeaderValue {
+        match self {
+            Encoding::Brotli => HeaderValue::from_static("br"),
+            Encoding::Gzip => HeaderValue::from_static("gzip"),
+        }
+    }
+
+    fn compress(&self, input: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                writer.write_all(input)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(input)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+fn is_compressible(headers: &HeaderMap) -> bool {
+    if headers.contains_key(CONTENT_ENCODING) {
+        return false;
+    }
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|content_type| {
+            COMPRESSIBLE_CONTENT_TYPES
+                .iter()
+                .any(|prefix| content_type.starts_with(prefix))
+                && !STREAMED_CONTENT_TYPES
+                    .iter()
+                    .any(|prefix| content_type.starts_with(prefix))
+        })
+        .unwrap_or_default()
+}
+
+/// Compresses the body of a text or JSON response with the given encoding.
+/// Other responses are returned unchanged.
+pub async fn compress_response(
+    encoding: Option<Encoding>,
+    request_path: &str,
+    response: Response<AssetBody>,
+) -> Response<AssetBody> {
+    if !is_compressible(response.headers()) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // Generated assets already vary by the encoding, see `crate::assets::cache_headers`.
+    let varies_by_encoding = parts.headers.get_all(VARY).iter().any(|value| {
+        value
+            .to_str()
+            .is_ok_and(|value| value.eq_ignore_ascii_case("Accept-Encoding"))
+    });
+    if !varies_by_encoding {
+        parts
+            .headers
+            .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+    let bytes = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => return make_err_response(e, request_path).map(make_box_body),
+    };
+    let encoding = match encoding {
+        Some(encoding) if bytes.len() >= MIN_COMPRESS_SIZE => encoding,
+        _ => return Response::from_parts(parts, make_box_body(Full::new(bytes))),
+    };
+    match encoding.compress(&bytes) {
+        Ok(compressed) => {
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, encoding.header_value());
+            parts.headers.remove(CONTENT_LENGTH);
+            Response::from_parts(parts, make_box_body(Full::new(Bytes::from(compressed))))
+        }
+        Err(e) => {
+            warn!(
+                "Failed compressing response for '{}': {:?}",
+                request_path, e
+            );
+            Response::from_parts(parts, make_box_body(Full::new(bytes)))
+        }
+    }
+}
@@ -1,72 +1,692 @@
+//! Configuration of the server.
+//!
+//! The configuration is read once at startup from an optional TOML file (passed with
+//! `--config <path>` or `SCSRV_CONFIG_FILE`) and the `SCSRV_*` environment variables, which take
+//! precedence. Keys in the TOML file are the names of the environment variables without the
+//! `SCSRV_` prefix in lowercase, eg. `git_repo = "..."`.
+
+use std::collections::HashMap;
+use std::env::{args, vars};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread::available_parallelism;
+use std::time::Duration;
+
 use dotenv::dotenv;
-use std::env::var;
-
-#[allow(dead_code)] // discord feature
-pub enum Config {
-    Address,
-    GitRepo,
-    GitAssetsUrl,
-    Workdir,
-    RedisHost,
-    RedisPort,
-    DiscordToken,
-    DiscordChannels,
+use once_cell::sync::OnceCell;
+use url::Url;
+
+use crate::activity_exceptions::{read_credit_exceptions, CreditException};
+use crate::api_keys::ApiKey;
+use crate::assets::cache_headers::is_asset_type_name;
+use crate::assets::object_storage::ObjectStorageConfig;
+use crate::graphql_ide::GraphqlIde;
+use crate::webhooks::WebhookMode;
+
+static CONFIG: OnceCell<ServerConfig> = OnceCell::new();
+
+const ENV_PREFIX: &str = "SCSRV_";
+const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0:3000";
+const DEFAULT_GIT_REF: &str = "master";
+const DEFAULT_REFRESH_INTERVAL_SECS: u64 = 15 * 60;
+const DEFAULT_MIN_FREE_DISK_SPACE_MIB: u64 = 1024;
+const DEFAULT_HTTP1_HEADER_READ_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_HTTP2_KEEP_ALIVE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_ASSET_SIZE_MIB: u64 = 256;
+const DEFAULT_SIGNED_URL_TTL_SECS: u64 = 60 * 60;
+const DEFAULT_S3_REGION: &str = "us-east-1";
+
+#[derive(Debug)]
+pub struct ServerConfig {
+    /// Public URL of this server.
+    pub address: Url,
+    /// Address to listen on.
+    pub listen_address: SocketAddr,
+    /// Maximum number of open connections. `None` if unlimited.
+    pub max_connections: Option<usize>,
+    /// How long an HTTP/1 client may take to send the headers of a request. The time starts
+    /// when the connection waits for the next request, so idle keep-alive connections are
+    /// closed after it too.
+    pub http1_header_read_timeout: Duration,
+    /// How long to wait for an HTTP/2 keep-alive ping to be acknowledged.
+    pub http2_keep_alive_timeout: Duration,
+    /// Interval in which HTTP/2 keep-alive pings are sent. `None` if disabled.
+    pub http2_keep_alive_interval: Option<Duration>,
+    /// Maximum number of concurrent streams per HTTP/2 connection. `None` for hyper's default.
+    pub http2_max_concurrent_streams: Option<u32>,
+    /// How long a request may take before it is aborted. `None` if unlimited.
+    pub request_timeout: Option<Duration>,
+    /// URL of the SpriteCollab Git repository. Not used by mirrors.
+    pub git_repo: String,
+    /// The branch or tag of the SpriteCollab repository to serve.
+    pub git_ref: String,
+    /// URL of the raw files in the SpriteCollab repository, may contain a `{ref}` placeholder.
+    git_assets_url: String,
+    /// Whether the URLs of single portraits, sprite sheets and AnimData.xml files point to
+    /// the files served by this server instead of the upstream repository.
+    pub local_asset_urls: bool,
+    /// The depth of the clone (`None` for a full clone). Always a full clone with the activity
+    /// store, which needs the history of the repository.
+    pub git_clone_depth: Option<i32>,
+    /// Whether only the asset directories and data files should be checked out.
+    pub git_sparse_checkout: bool,
+    pub workdir: PathBuf,
+    /// URL of the primary instance, if this instance is a read-only mirror of it. Mirrors
+    /// download the data from the primary instead of cloning the repository, and serve the
+    /// assets of the primary.
+    pub mirror_of: Option<Url>,
+    /// The public URL of the primary, if the mirror reaches it under another URL. Its redirects
+    /// to this URL are followed on `mirror_of` instead, see [`crate::mirror`].
+    pub mirror_public_url: Option<Url>,
+    pub redis_host: String,
+    pub redis_port: u16,
+    /// How often to check the repository for updates.
+    pub refresh_interval: Duration,
+    /// Number of the most recently modified forms to pre-generate assets for after a refresh.
+    pub prewarm_count: usize,
+    /// How often unreachable objects are removed from the repository. `None` if disabled.
+    pub git_gc_interval: Option<Duration>,
+    /// Free space in bytes on the disk of the workdir, below which the server degrades, see
+    /// [`crate::disk_monitor`]. 0 if not monitored.
+    pub min_free_disk_space: u64,
+    /// Maximum number of assets that are generated at the same time. Further requests wait for
+    /// a free slot.
+    pub generation_workers: usize,
+    /// Size in bytes that generated assets can have at most, see
+    /// [`crate::assets::size_limit`]. 0 if not limited.
+    pub max_asset_size: u64,
+    /// The GraphQL IDE served at `/`, see [`crate::graphql_ide`]. Not served if `None`.
+    pub graphql_ide: Option<GraphqlIde>,
+    /// `user:password` the GraphQL IDE is protected with, if set.
+    pub graphql_ide_basic_auth: Option<String>,
+    /// Origins that are allowed to make cross-origin requests. `None` if all origins are allowed.
+    pub cors_origins: Option<Vec<String>>,
+    /// Max ages of generated assets by asset type, instead of the defaults of
+    /// [`crate::assets::cache_headers`].
+    pub asset_max_age: HashMap<String, Duration>,
+    /// Localized names of monsters and forms, instead of `translations.json` in the
+    /// repository, see [`crate::datafiles::translations`].
+    pub translations_file: Option<PathBuf>,
+    /// Community nicknames of monsters and forms that are found by the searches, see
+    /// [`crate::datafiles::aliases`].
+    pub aliases_file: Option<PathBuf>,
+    /// Rows of emotions of the portrait sheets, instead of the emotions of the sprite config in
+    /// rows of `portrait_tile_x`. Empty names leave a cell empty.
+    pub portrait_sheet_layout: Option<Vec<Vec<String>>>,
+    /// Pixels between the portraits of the portrait sheets.
+    pub portrait_sheet_padding: u32,
+    /// Emotions left out of the portrait sheets, unless a request lists the emotions to include.
+    /// `^` leaves out all flipped emotions.
+    pub portrait_sheet_exclude: Vec<String>,
+    /// If set, intermediate images of asset generation are written to this directory for
+    /// debugging.
+    pub debug_dump_dir: Option<PathBuf>,
+    /// Token for the admin API, sent as `Authorization: Bearer <token>`. The admin API is
+    /// disabled if not set.
+    pub admin_token: Option<String>,
+    /// Secret to sign the URLs of expensive assets (ZIPs and recolor sheets) with. URLs are not
+    /// signed if not set.
+    pub signed_url_secret: Option<String>,
+    /// How long signed URLs are valid at least. They are valid for up to twice as long.
+    pub signed_url_ttl: Duration,
+    /// Whether expensive assets are only served for signed URLs.
+    pub require_signed_urls: bool,
+    /// API keys of clients. API keys are not checked if empty.
+    pub api_keys: Vec<ApiKey>,
+    /// Whether requests without an API key are rejected.
+    pub require_api_key: bool,
+    /// Corrections of the credits of historical activities, see
+    /// [`crate::activity_exceptions`].
+    pub activity_credit_exceptions: Vec<CreditException>,
+    /// URLs the activities of new commits are sent to, see [`crate::webhooks`].
+    pub webhook_urls: Vec<Url>,
+    /// Secret to sign the webhook payloads with. Payloads are not signed if not set.
+    pub webhook_secret: Option<String>,
+    pub webhook_mode: WebhookMode,
+    /// Bucket generated sheets and ZIPs are stored in, see [`crate::assets::object_storage`].
+    pub object_storage: Option<ObjectStorageConfig>,
+    /// Database the activities are stored in, see [`crate::activity_store`].
+    #[allow(dead_code)] // activity-store feature
+    pub activity_database_url: Option<String>,
+    #[allow(dead_code)] // discord feature
+    pub discord_token: Option<String>,
+    #[allow(dead_code)] // discord feature
+    pub discord_channels: Vec<String>,
 }
 
-impl Config {
-    pub fn init() {
+impl ServerConfig {
+    /// Loads and validates the configuration. Must be called once at startup, before
+    /// [`ServerConfig::get`] is used.
+    pub fn init() -> Result<&'static Self, ConfigErrors> {
         dotenv().ok();
+        let config = Self::load(config_file_path().as_deref())?;
+        Ok(CONFIG.get_or_init(|| config))
+    }
+
+    /// Returns the configuration loaded by [`ServerConfig::init`].
+    pub fn get() -> &'static Self {
+        CONFIG
+            .get()
+            .expect("The configuration was not initialized.")
     }
 
-    /// Makes sure all required config values are set and panics otherwise.
-    pub fn check() {
-        Self::Address.get();
-        Self::GitRepo.get();
-        Self::GitAssetsUrl.get();
-        Self::Workdir.get();
-        Self::RedisHost.get();
-        Self::RedisPort.get();
+    /// Like [`ServerConfig::get`], `None` if the configuration was not initialized.
+    pub fn try_get() -> Option<&'static Self> {
+        CONFIG.get()
     }
 
-    pub fn get(&self) -> String {
-        match self {
-            Config::Address => var("SCSRV_ADDRESS").expect("SCSRV_ADDRESS not set"),
-            Config::GitRepo => var("SCSRV_GIT_REPO").expect("SCSRV_GIT_REPO not set"),
-            Config::GitAssetsUrl => {
-                var("SCSRV_GIT_ASSETS_URL").expect("SCSRV_GIT_ASSETS_URL not set")
+    /// Initializes the configuration with `values` (keys without the `SCSRV_` prefix) instead
+    /// of the environment, for tests. Only the first call has an effect.
+    #[cfg(test)]
+    pub(crate) fn init_for_tests(values: &[(&str, &str)]) -> &'static Self {
+        CONFIG.get_or_init(|| {
+            let raw = RawConfig {
+                values: values
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                errors: Vec::new(),
+            };
+            match Self::from_raw(raw) {
+                Ok(config) => config,
+                Err(e) => panic!("Invalid test configuration:\n{}", e),
             }
-            Config::Workdir => var("SCSRV_WORKDIR").expect("SCSRV_WORKDIR is not set"),
-            Config::RedisHost => var("SCSRV_REDIS_HOST").expect("SCSRV_REDIS_HOST is not set"),
-            Config::RedisPort => var("SCSRV_REDIS_PORT").expect("SCSRV_REDIS_PORT is not set"),
-            Config::DiscordToken => {
-                var("SCSRV_DISCORD_TOKEN").expect("SCSRV_DISCORD_TOKEN is not set")
+        })
+    }
+
+    fn load(file: Option<&Path>) -> Result<Self, ConfigErrors> {
+        Self::from_raw(RawConfig::load(file))
+    }
+
+    fn from_raw(mut raw: RawConfig) -> Result<Self, ConfigErrors> {
+        let address = raw.required::<Url>("address");
+        let listen_address = raw
+            .optional::<SocketAddr>("listen_address")
+            .unwrap_or_else(|| DEFAULT_LISTEN_ADDRESS.parse().unwrap());
+        let max_connections = raw.optional::<usize>("max_connections");
+        let http1_header_read_timeout = Duration::from_secs(
+            raw.optional::<u64>("http1_header_read_timeout")
+                .unwrap_or(DEFAULT_HTTP1_HEADER_READ_TIMEOUT_SECS),
+        );
+        let http2_keep_alive_timeout = Duration::from_secs(
+            raw.optional::<u64>("http2_keep_alive_timeout")
+                .unwrap_or(DEFAULT_HTTP2_KEEP_ALIVE_TIMEOUT_SECS),
+        );
+        let http2_keep_alive_interval = raw
+            .optional::<u64>("http2_keep_alive_interval")
+            .map(Duration::from_secs);
+        let min_free_disk_space = raw
+            .optional::<u64>("min_free_disk_space")
+            .unwrap_or(DEFAULT_MIN_FREE_DISK_SPACE_MIB)
+            .saturating_mul(1024 * 1024);
+        let http2_max_concurrent_streams = raw.optional::<u32>("http2_max_concurrent_streams");
+        let request_timeout = raw
+            .optional::<u64>("request_timeout")
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        let mirror_of = raw.optional_with("mirror_of", parse_http_url);
+        let mirror_public_url = raw.optional_with("mirror_public_url", parse_http_url);
+        let git_repo = if mirror_of.is_some() {
+            Some(raw.optional::<String>("git_repo").unwrap_or_default())
+        } else {
+            raw.required::<String>("git_repo")
+        };
+        let git_ref = raw
+            .optional::<String>("git_ref")
+            .unwrap_or_else(|| DEFAULT_GIT_REF.to_string());
+        let git_assets_url = raw.required_with("git_assets_url", |v| {
+            Url::parse(&v.replace("{ref}", &git_ref))
+                .map(|_| v.to_string())
+                .map_err(|e| e.to_string())
+        });
+        let local_asset_urls = raw
+            .optional_with("local_asset_urls", parse_bool)
+            .unwrap_or_default();
+        let git_clone_depth = raw.optional::<i32>("git_clone_depth");
+        let git_sparse_checkout = raw
+            .optional_with("git_sparse_checkout", parse_bool)
+            .unwrap_or_default();
+        let workdir = raw.required::<PathBuf>("workdir");
+        let redis_host = raw.required::<String>("redis_host");
+        let redis_port = raw.required::<u16>("redis_port");
+        let refresh_interval = Duration::from_secs(
+            raw.optional::<u64>("refresh_interval")
+                .unwrap_or(DEFAULT_REFRESH_INTERVAL_SECS),
+        );
+        let prewarm_count = raw.optional::<usize>("prewarm_count").unwrap_or_default();
+        let git_gc_interval = raw
+            .optional::<u64>("git_gc_interval")
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        let generation_workers = raw
+            .optional::<usize>("generation_workers")
+            .filter(|workers| *workers > 0)
+            .unwrap_or_else(|| available_parallelism().map_or(1, NonZeroUsize::get));
+        let max_asset_size = raw
+            .optional::<u64>("max_asset_size")
+            .unwrap_or(DEFAULT_MAX_ASSET_SIZE_MIB)
+            .saturating_mul(1024 * 1024);
+        let graphql_ide = raw
+            .optional_with("graphql_ide", |ide| match ide {
+                "none" => Ok(None),
+                ide => ide.parse::<GraphqlIde>().map(Some),
+            })
+            .flatten();
+        let graphql_ide_basic_auth = raw
+            .optional::<String>("graphql_ide_basic_auth")
+            .filter(|credentials| !credentials.is_empty());
+        if let Some(credentials) = &graphql_ide_basic_auth {
+            if !credentials.contains(':') {
+                raw.errors.push(format!(
+                    "{} must be user:password",
+                    display_key("graphql_ide_basic_auth")
+                ));
             }
-            Config::DiscordChannels => {
-                var("SCSRV_DISCORD_CHANNELS").expect("SCSRV_DISCORD_CHANNELS is not set")
+        }
+        let cors_origins = raw
+            .optional::<String>("cors_origins")
+            .filter(|origins| origins.trim() != "*")
+            .map(|origins| parse_list(&origins));
+        let asset_max_age = raw
+            .optional_with("asset_max_age", |max_ages| {
+                parse_list(max_ages)
+                    .iter()
+                    .map(|max_age| {
+                        let (name, secs) = max_age.split_once('=').ok_or_else(|| {
+                            format!("expected <asset type>=<seconds>, got '{}'", max_age)
+                        })?;
+                        let (name, secs) = (name.trim(), secs.trim());
+                        if !is_asset_type_name(name) {
+                            return Err(format!("unknown asset type '{}'", name));
+                        }
+                        let secs = secs
+                            .parse::<u64>()
+                            .map_err(|e| format!("{}: {}", name, e))?;
+                        Ok((name.to_string(), Duration::from_secs(secs)))
+                    })
+                    .collect::<Result<HashMap<_, _>, _>>()
+            })
+            .unwrap_or_default();
+        let translations_file = raw.optional::<PathBuf>("translations_file");
+        let aliases_file = raw.optional::<PathBuf>("aliases_file");
+        let portrait_sheet_layout = raw.optional_with("portrait_sheet_layout", |layout| {
+            let rows = layout
+                .split(';')
+                .map(|row| row.split(',').map(|v| v.trim().to_string()).collect())
+                .collect::<Vec<Vec<String>>>();
+            if rows.iter().flatten().all(String::is_empty) {
+                return Err("contains no emotions".to_string());
             }
+            Ok(rows)
+        });
+        let portrait_sheet_padding = raw
+            .optional::<u32>("portrait_sheet_padding")
+            .unwrap_or_default();
+        let portrait_sheet_exclude = raw
+            .optional::<String>("portrait_sheet_exclude")
+            .map(|exclude| parse_list(&exclude))
+            .unwrap_or_default();
+        let debug_dump_dir = raw.optional::<PathBuf>("debug_dump_dir");
+        let admin_token = raw
+            .optional::<String>("admin_token")
+            .filter(|token| !token.is_empty());
+        let signed_url_secret = raw
+            .optional::<String>("signed_url_secret")
+            .filter(|secret| !secret.is_empty());
+        let signed_url_ttl = Duration::from_secs(
+            raw.optional::<u64>("signed_url_ttl")
+                .filter(|secs| *secs > 0)
+                .unwrap_or(DEFAULT_SIGNED_URL_TTL_SECS),
+        );
+        let require_signed_urls = raw
+            .optional_with("require_signed_urls", parse_bool)
+            .unwrap_or_default();
+        if require_signed_urls && signed_url_secret.is_none() {
+            raw.errors.push(format!(
+                "{} requires {} to be set.",
+                display_key("require_signed_urls"),
+                display_key("signed_url_secret")
+            ));
         }
+        let api_keys = raw
+            .optional_with("api_keys", |keys| {
+                parse_list(keys)
+                    .iter()
+                    .map(|key| key.parse::<ApiKey>())
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .unwrap_or_default();
+        let require_api_key = raw
+            .optional_with("require_api_key", parse_bool)
+            .unwrap_or_default();
+        if require_api_key && api_keys.is_empty() {
+            raw.errors.push(format!(
+                "{} requires {} to be set.",
+                display_key("require_api_key"),
+                display_key("api_keys")
+            ));
+        }
+        let activity_database_url = raw
+            .optional::<String>("activity_database_url")
+            .filter(|url| !url.is_empty());
+        if activity_database_url.is_some() && !cfg!(feature = "activity-store") {
+            raw.errors.push(format!(
+                "{} requires the server to be built with the activity-store feature.",
+                display_key("activity_database_url")
+            ));
+        }
+        let activity_credit_exceptions = raw
+            .optional_with("activity_credit_exceptions", |path| {
+                read_credit_exceptions(Path::new(path))
+            })
+            .unwrap_or_default();
+        let webhook_urls = raw
+            .optional_with("webhook_urls", |urls| {
+                parse_list(urls)
+                    .iter()
+                    .map(|url| parse_http_url(url).map_err(|e| format!("{}: {}", url, e)))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .unwrap_or_default();
+        let webhook_secret = raw
+            .optional::<String>("webhook_secret")
+            .filter(|secret| !secret.is_empty());
+        let webhook_mode = raw
+            .optional::<WebhookMode>("webhook_mode")
+            .unwrap_or_default();
+        let s3_endpoint = raw.optional_with("s3_endpoint", parse_http_url);
+        let object_storage = s3_endpoint.and_then(|endpoint| {
+            let bucket = raw.required::<String>("s3_bucket");
+            let access_key = raw.required::<String>("s3_access_key");
+            let secret_key = raw.required::<String>("s3_secret_key");
+            let (bucket, access_key, secret_key) = match (bucket, access_key, secret_key) {
+                (Some(bucket), Some(access_key), Some(secret_key)) => {
+                    (bucket, access_key, secret_key)
+                }
+                _ => return None,
+            };
+            let region = raw
+                .optional::<String>("s3_region")
+                .unwrap_or_else(|| DEFAULT_S3_REGION.to_string());
+            let public_url = raw
+                .optional_with("s3_public_url", |v| {
+                    Url::parse(v)
+                        .map(|url| url.as_str().trim_end_matches('/').to_string())
+                        .map_err(|e| e.to_string())
+                })
+                .unwrap_or_else(|| {
+                    format!("{}/{}", endpoint.as_str().trim_end_matches('/'), bucket)
+                });
+            Some(ObjectStorageConfig {
+                endpoint,
+                bucket,
+                region,
+                access_key,
+                secret_key,
+                public_url,
+            })
+        });
+        if object_storage.is_some() && mirror_of.is_some() {
+            raw.errors.push(format!(
+                "{} can't be used on mirrors, they serve the assets of the primary instance.",
+                display_key("s3_endpoint")
+            ));
+        }
+        let discord_token = raw.optional::<String>("discord_token");
+        let discord_channels = raw
+            .optional::<String>("discord_channels")
+            .map(|channels| parse_list(&channels))
+            .unwrap_or_default();
+
+        match (
+            address,
+            git_repo,
+            git_assets_url,
+            workdir,
+            redis_host,
+            redis_port,
+        ) {
+            (
+                Some(address),
+                Some(git_repo),
+                Some(git_assets_url),
+                Some(workdir),
+                Some(redis_host),
+                Some(redis_port),
+            ) if raw.errors.is_empty() => Ok(Self {
+                address,
+                listen_address,
+                max_connections,
+                http1_header_read_timeout,
+                http2_keep_alive_timeout,
+                http2_keep_alive_interval,
+                http2_max_concurrent_streams,
+                request_timeout,
+                git_repo,
+                git_ref,
+                git_assets_url,
+                local_asset_urls,
+                git_clone_depth: git_clone_depth.filter(|_| activity_database_url.is_none()),
+                git_sparse_checkout,
+                workdir,
+                mirror_of,
+                mirror_public_url,
+                redis_host,
+                redis_port,
+                refresh_interval,
+                prewarm_count,
+                git_gc_interval,
+                min_free_disk_space,
+                generation_workers,
+                max_asset_size,
+                graphql_ide,
+                graphql_ide_basic_auth,
+                cors_origins,
+                asset_max_age,
+                translations_file,
+                aliases_file,
+                portrait_sheet_layout,
+                portrait_sheet_padding,
+                portrait_sheet_exclude,
+                debug_dump_dir,
+                admin_token,
+                signed_url_secret,
+                signed_url_ttl,
+                require_signed_urls,
+                api_keys,
+                require_api_key,
+                activity_credit_exceptions,
+                webhook_urls,
+                webhook_secret,
+                webhook_mode,
+                object_storage,
+                activity_database_url,
+                discord_token,
+                discord_channels,
+            }),
+            _ => Err(ConfigErrors(raw.errors)),
+        }
+    }
+
+    /// The URL of the primary instance without a trailing slash, if this is a mirror.
+    pub fn mirror_url(&self) -> Option<&str> {
+        self.mirror_of
+            .as_ref()
+            .map(|url| url.as_str().trim_end_matches('/'))
+    }
+
+    /// The public URL of this server, without a trailing slash.
+    pub fn this_server_url(&self) -> &str {
+        self.address.as_str().trim_end_matches('/')
+    }
+
+    /// URL of the raw files in the SpriteCollab repository for the served ref.
+    pub fn git_assets_url(&self) -> String {
+        self.git_assets_url.replace("{ref}", &self.git_ref)
+    }
+
+    pub fn redis_config(&self) -> (String, u16) {
+        (self.redis_host.clone(), self.redis_port)
     }
+}
 
-    pub fn get_or_none(&self) -> Option<String> {
-        match self {
-            Config::Address => var("SCSRV_ADDRESS").ok(),
-            Config::GitRepo => var("SCSRV_GIT_REPO").ok(),
-            Config::GitAssetsUrl => var("SCSRV_GIT_ASSETS_URL").ok(),
-            Config::Workdir => var("SCSRV_WORKDIR").ok(),
-            Config::RedisHost => var("SCSRV_REDIS_HOST").ok(),
-            Config::RedisPort => var("SCSRV_REDIS_PORT").ok(),
-            Config::DiscordToken => var("SCSRV_DISCORD_TOKEN").ok(),
-            Config::DiscordChannels => var("SCSRV_DISCORD_CHANNELS").ok(),
+/// All problems that were found while loading the configuration.
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl Display for ConfigErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for error in &self.0 {
+            writeln!(f, "- {}", error)?;
         }
+        Ok(())
     }
+}
 
-    pub fn redis_config() -> (String, u16) {
-        (
-            Self::RedisHost.get(),
-            Self::RedisPort
-                .get()
-                .parse::<u16>()
-                .expect("Invalid Redis port"),
-        )
+impl std::error::Error for ConfigErrors {}
+
+/// The unparsed configuration values, by key (lowercase, without the `SCSRV_` prefix).
+struct RawConfig {
+    values: HashMap<String, String>,
+    errors: Vec<String>,
+}
+
+impl RawConfig {
+    fn load(file: Option<&Path>) -> Self {
+        let mut raw = Self {
+            values: HashMap::new(),
+            errors: Vec::new(),
+        };
+        if let Some(file) = file {
+            match fs::read_to_string(file).map(|content| content.parse::<toml::Table>()) {
+                Ok(Ok(table)) => {
+                    for (key, value) in table {
+                        raw.values
+                            .insert(key.to_lowercase(), toml_value_to_string(value));
+                    }
+                }
+                Ok(Err(e)) => raw.errors.push(format!(
+                    "Could not parse config file {}: {}",
+                    file.display(),
+                    e
+                )),
+                Err(e) => raw.errors.push(format!(
+                    "Could not read config file {}: {}",
+                    file.display(),
+                    e
+                )),
+            }
+        }
+        for (key, value) in vars() {
+            if let Some(key) = key.strip_prefix(ENV_PREFIX) {
+                raw.values.insert(key.to_lowercase(), value);
+            }
+        }
+        raw
+    }
+
+    fn required<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.required_with(key, |v| v.parse::<T>().map_err(|e| e.to_string()))
+    }
+
+    fn required_with<T, F>(&mut self, key: &str, parse: F) -> Option<T>
+    where
+        F: FnOnce(&str) -> Result<T, String>,
+    {
+        if !self.values.contains_key(key) {
+            self.errors
+                .push(format!("{} is not set.", display_key(key)));
+        }
+        self.optional_with(key, parse)
+    }
+
+    fn optional<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.optional_with(key, |v| v.parse::<T>().map_err(|e| e.to_string()))
+    }
+
+    fn optional_with<T, F>(&mut self, key: &str, parse: F) -> Option<T>
+    where
+        F: FnOnce(&str) -> Result<T, String>,
+    {
+        let value = self.values.get(key)?;
+        match parse(value) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.errors
+                    .push(format!("{} is invalid: {}", display_key(key), e));
+                None
+            }
+        }
+    }
+}
+
+/// Returns the config file passed with `--config <path>` or `SCSRV_CONFIG_FILE`.
+fn config_file_path() -> Option<PathBuf> {
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var("SCSRV_CONFIG_FILE").ok().map(PathBuf::from)
+}
+
+fn display_key(key: &str) -> String {
+    format!("{}{} ({})", ENV_PREFIX, key.to_uppercase(), key)
+}
+
+fn toml_value_to_string(value: toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s,
+        toml::Value::Array(values) => values
+            .into_iter()
+            .map(toml_value_to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
     }
 }
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" | "" => Ok(false),
+        _ => Err(format!("expected true or false, got '{}'", value)),
+    }
+}
+
+/// Parses a URL the server sends requests to with [`crate::http_client`].
+fn parse_http_url(value: &str) -> Result<Url, String> {
+    let url = Url::parse(value).map_err(|e| e.to_string())?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(format!(
+            "only http and https URLs are supported, got {}",
+            scheme
+        )),
+    }
+}
+
+/// Parses a list of values separated by commas.
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
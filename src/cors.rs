@@ -0,0 +1,67 @@
+//! CORS headers for all responses and answers to preflight requests.
+
+use http_body_util::Empty;
+use hyper::body::Bytes;
+use hyper::header::{
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ORIGIN,
+    VARY,
+};
+use hyper::http::HeaderValue;
+use hyper::{HeaderMap, Response, StatusCode};
+
+use crate::ServerConfig;
+
+const ALLOWED_METHODS: &str = "GET, POST, OPTIONS";
+const DEFAULT_ALLOWED_HEADERS: &str = "Content-Type, Authorization, Accept";
+/// Custom response headers that scripts on other origins may read.
+const EXPOSED_HEADERS: &str = "X-Palette-Size, X-SC-Stale, Content-Range, Accept-Ranges";
+
+/// Adds the CORS headers for the origin of a request (from its headers) to the response headers.
+pub fn apply_cors_headers(request_headers: &HeaderMap, response_headers: &mut HeaderMap) {
+    response_headers.insert(
+        ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static(EXPOSED_HEADERS),
+    );
+    match &ServerConfig::get().cors_origins {
+        None => {
+            response_headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        }
+        Some(allowed) => {
+            response_headers.append(VARY, HeaderValue::from_static("Origin"));
+            if let Some(origin) = request_headers.get(ORIGIN) {
+                let is_allowed = origin
+                    .to_str()
+                    .map(|origin| allowed.iter().any(|a| a == origin))
+                    .unwrap_or_default();
+                if is_allowed {
+                    response_headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Make a HTTP OPTIONS response to a (preflight) request.
+pub fn make_http_options_response(request_headers: &HeaderMap) -> Response<Empty<Bytes>> {
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS)
+        .header(
+            ACCESS_CONTROL_ALLOW_HEADERS,
+            request_headers
+                .get(ACCESS_CONTROL_REQUEST_HEADERS)
+                .cloned()
+                .unwrap_or_else(|| HeaderValue::from_static(DEFAULT_ALLOWED_HEADERS)),
+        )
+        .header(ACCESS_CONTROL_MAX_AGE, "86400")
+        .body(Empty::new())
+        .unwrap();
+    let response_headers = response.headers_mut();
+    response_headers.append(
+        VARY,
+        HeaderValue::from_static("Access-Control-Request-Headers"),
+    );
+    apply_cors_headers(request_headers, response_headers);
+    response
+}
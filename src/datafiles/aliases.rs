@@ -0,0 +1,99 @@
+use crate::datafiles::DataReadResult;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+pub async fn read_aliases<P: AsRef<Path>>(path: P) -> DataReadResult<Aliases> {
+    let input = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(input))?)
+}
+
+/// Community nicknames of monsters and forms, that are found by the searches. Maps the aliases
+/// to full form paths:
+///
+/// ```json
+/// { "Mewtwo X": "0150/0001", "Pika": "0025" }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct Aliases(BTreeMap<String, AliasTarget>);
+
+impl Aliases {
+    /// All aliases, with the monster ID and form path they point to.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, i32, &[i32])> {
+        self.0
+            .iter()
+            .map(|(alias, target)| (alias.as_str(), target.monster_id, &*target.form_path))
+    }
+
+    /// The aliases of exactly this form.
+    pub fn of_form(&self, monster_id: i32, form_path: &[i32]) -> Vec<&str> {
+        self.iter()
+            .filter(|(_, alias_monster_id, alias_form_path)| {
+                *alias_monster_id == monster_id && *alias_form_path == form_path
+            })
+            .map(|(alias, _, _)| alias)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub struct AliasTarget {
+    monster_id: i32,
+    /// Without trailing `0000`, like the paths of the forms in the tracker.
+    form_path: Vec<i32>,
+}
+
+impl TryFrom<String> for AliasTarget {
+    type Error = String;
+
+    fn try_from(full_path: String) -> Result<Self, Self::Error> {
+        let mut ids = full_path
+            .split('/')
+            .filter(|id| !id.is_empty())
+            .map(|id| id.parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid form path '{}': {}", full_path, e))?;
+        if ids.is_empty() {
+            return Err("empty form path".to_string());
+        }
+        let monster_id = ids.remove(0);
+        while ids.last() == Some(&0) {
+            ids.pop();
+        }
+        Ok(Self {
+            monster_id,
+            form_path: ids,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_aliases() {
+        let aliases: Aliases = serde_json::from_value(json!({
+            "Mewtwo X": "0150/0001",
+            "Pika": "0025/0000",
+            "Sparky": "0025"
+        }))
+        .unwrap();
+        assert_eq!(
+            aliases.iter().collect::<Vec<_>>(),
+            vec![
+                ("Mewtwo X", 150, &[1][..]),
+                ("Pika", 25, &[][..]),
+                ("Sparky", 25, &[][..])
+            ]
+        );
+        assert_eq!(aliases.of_form(25, &[]), vec!["Pika", "Sparky"]);
+        assert!(aliases.of_form(150, &[]).is_empty());
+        assert!(serde_json::from_value::<Aliases>(json!({ "Bad": "0025/abc" })).is_err());
+    }
+}
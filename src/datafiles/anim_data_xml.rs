@@ -1,10 +1,9 @@
-use crate::assets::util::join_monster_and_form;
-use crate::Config;
+use crate::assets::store::AssetStore;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, Read};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use thiserror::Error;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -58,13 +57,12 @@ impl AnimDataXml {
     }
 
     pub fn open_for_form(
+        store: &dyn AssetStore,
         monster_idx: i32,
         path_to_form: &[i32],
     ) -> Result<Self, AnimDataXmlOpenError> {
-        let joined_f = join_monster_and_form(monster_idx, path_to_form, '/');
-        let path = PathBuf::from(Config::Workdir.get())
-            .join(format!("spritecollab/sprite/{}/AnimData.xml", joined_f));
-        Self::open(path)
+        let file = store.open_anim_data_xml(monster_idx, path_to_form)?;
+        Ok(Self::from_reader(BufReader::new(file))?)
     }
 
     pub fn from_reader<R: Read>(r: R) -> Result<Self, serde_xml_rs::Error> {
@@ -1,5 +1,5 @@
 use crate::datafiles::{cleanup_discord_id, DataReadError, DataReadResult};
-use crate::search::fuzzy_find;
+use crate::search::{fuzzy_find, fuzzy_find_matches, SearchMatch};
 use csv::ReaderBuilder;
 use serde::Deserialize;
 use std::borrow::Cow;
@@ -44,7 +44,7 @@ pub async fn read_credit_names<P: AsRef<Path>>(path: P) -> DataReadResult<Credit
     })
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct CreditNames {
     /// Vector that contains all rows.
     data: Vec<CreditNamesRow>,
@@ -59,14 +59,20 @@ impl CreditNames {
         self.data.iter()
     }
     pub fn fuzzy_find<S: AsRef<str>>(&self, query: S) -> impl Iterator<Item = &CreditNamesRow> {
-        fuzzy_find(
-            self.keys_credit_ids
-                .iter()
-                .map(|(key, val)| (key, Cow::from(vec![*val])))
-                .chain(self.keys_names.iter().map(|(kn, kv)| (kn, Cow::from(kv)))),
-            query,
-        )
-        .map(|val| &self.data[val])
+        fuzzy_find(self.search_keys(), query).map(|val| &self.data[val])
+    }
+    /// Like [`CreditNames::fuzzy_find`], but also returns the scores and matched keys.
+    pub fn fuzzy_find_matches<S: AsRef<str>>(&self, query: S) -> Vec<SearchMatch<&CreditNamesRow>> {
+        fuzzy_find_matches(self.search_keys(), query)
+            .into_iter()
+            .map(|search_match| search_match.map(|val| &self.data[val]))
+            .collect()
+    }
+    fn search_keys(&self) -> impl Iterator<Item = (&String, Cow<'_, [usize]>)> {
+        self.keys_credit_ids
+            .iter()
+            .map(|(key, val)| (key, Cow::from(vec![*val])))
+            .chain(self.keys_names.iter().map(|(kn, kv)| (kn, Cow::from(kv))))
     }
     pub fn get(&self, credit_id: &str) -> Option<&CreditNamesRow> {
         self.keys_credit_ids
@@ -2,7 +2,7 @@ use std::fmt::Formatter;
 use std::ops::Deref;
 
 use serde::de::{Error, Unexpected, Visitor};
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[repr(transparent)]
 #[derive(Hash, PartialOrd, Ord, PartialEq, Eq, Debug, Copy, Clone)]
@@ -16,6 +16,16 @@ impl Deref for GroupId {
     }
 }
 
+/// Serialized like in the tracker of SpriteCollab, as a string padded to 4 digits.
+impl Serialize for GroupId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&format!("{:04}", self.0))
+    }
+}
+
 impl<'de> Deserialize<'de> for GroupId {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
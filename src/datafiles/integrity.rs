@@ -0,0 +1,340 @@
+//! Consistency checks of the tracker against the files in the repository and the credit names.
+//! They are run on every refresh. Problems are logged and returned by the `dataIntegrity`
+//! query, but don't prevent the data from being served.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::iter::once;
+use std::path::Path;
+
+use crate::assets::util::join_monster_and_form;
+use crate::assets::SPRITE_FILE_SUFFIXES;
+use crate::datafiles::anim_data_xml::AnimDataXml;
+use crate::datafiles::credit_names::CreditNames;
+use crate::datafiles::parse_credit_id;
+use crate::datafiles::sprite_config::SpriteConfig;
+use crate::datafiles::tracker::{Group, MonsterFormCollector, Tracker};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IntegrityIssueKind {
+    /// A file listed in the tracker does not exist.
+    MissingFile,
+    /// A file exists, but is not listed in the tracker.
+    UntrackedFile,
+    /// A credit ID of the tracker is not in the credit names.
+    UnknownCreditId,
+    /// The completion phase in the tracker is higher than the existing files allow.
+    InconsistentPhase,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IntegrityIssue {
+    pub kind: IntegrityIssueKind,
+    /// Full path of the form, eg. `0025/0000/0001`.
+    pub form_path: String,
+    pub details: String,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct IntegrityReport {
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    /// Checks all forms of the tracker against the files of the repository at `repo_path`.
+    pub fn check(
+        repo_path: &Path,
+        sprite_config: &SpriteConfig,
+        tracker: &Tracker,
+        credit_names: &CreditNames,
+    ) -> Self {
+        let mut report = Self::default();
+        for group_id in tracker.keys() {
+            let monster_idx = **group_id as i32;
+            let collector = match MonsterFormCollector::collect(tracker, monster_idx) {
+                Some(collector) => collector,
+                None => continue,
+            };
+            for (path, group) in collector.map(|(path, _, group)| (path, group)) {
+                let joined_p = join_monster_and_form(monster_idx, &path, '/');
+                let mut form = FormCheck {
+                    report: &mut report,
+                    form_path: &joined_p,
+                };
+                form.check_portraits(
+                    &repo_path.join("portrait").join(&joined_p),
+                    sprite_config,
+                    group,
+                );
+                form.check_sprites(
+                    &repo_path.join("sprite").join(&joined_p),
+                    sprite_config,
+                    group,
+                );
+                form.check_credits(group, credit_names);
+            }
+        }
+        report
+    }
+
+    pub fn count(&self, kind: IntegrityIssueKind) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.kind == kind)
+            .count()
+    }
+
+    /// The credit IDs of the tracker that are not in the credit names, sorted and without
+    /// duplicates.
+    pub fn unknown_credit_ids(&self) -> Vec<String> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.kind == IntegrityIssueKind::UnknownCreditId)
+            .map(|issue| issue.details.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+struct FormCheck<'a> {
+    report: &'a mut IntegrityReport,
+    form_path: &'a str,
+}
+
+impl FormCheck<'_> {
+    fn add(&mut self, kind: IntegrityIssueKind, details: String) {
+        self.report.issues.push(IntegrityIssue {
+            kind,
+            form_path: self.form_path.to_string(),
+            details,
+        });
+    }
+
+    fn check_portraits(&mut self, dir: &Path, sprite_config: &SpriteConfig, group: &Group) {
+        let mut existing = Vec::with_capacity(group.portrait_files.len());
+        for emotion in group.portrait_files.keys() {
+            if dir.join(format!("{}.png", emotion)).is_file() {
+                existing.push(emotion.clone());
+            } else {
+                self.add(
+                    IntegrityIssueKind::MissingFile,
+                    format!("portrait/{}/{}.png", self.form_path, emotion),
+                );
+            }
+        }
+        for file_name in list_files(dir) {
+            let tracked = file_name
+                .strip_suffix(".png")
+                .map(|emotion| group.portrait_files.contains_key(emotion));
+            if tracked == Some(false) {
+                self.add(
+                    IntegrityIssueKind::UntrackedFile,
+                    format!("portrait/{}/{}", self.form_path, file_name),
+                );
+            }
+        }
+        self.check_phase(
+            "portrait",
+            group.portrait_complete,
+            &sprite_config.completion_emotions,
+            &sprite_config.emotions,
+            &existing,
+        );
+    }
+
+    fn check_sprites(&mut self, dir: &Path, sprite_config: &SpriteConfig, group: &Group) {
+        if group.sprite_files.is_empty() && list_files(dir).is_empty() {
+            self.check_phase(
+                "sprite",
+                group.sprite_complete,
+                &sprite_config.completion_actions,
+                &sprite_config.actions,
+                &[],
+            );
+            return;
+        }
+        // Actions that are copies of other actions don't have files.
+        let copies = match AnimDataXml::open(dir.join("AnimData.xml")) {
+            Ok(xml) => xml.get_action_copies(),
+            Err(_) => {
+                self.add(
+                    IntegrityIssueKind::MissingFile,
+                    format!("sprite/{}/AnimData.xml", self.form_path),
+                );
+                Default::default()
+            }
+        };
+        let mut existing = Vec::with_capacity(group.sprite_files.len());
+        for action in group.sprite_files.keys() {
+            if copies.contains_key(action) {
+                existing.push(action.clone());
+                continue;
+            }
+            let mut complete = true;
+            for suffix in SPRITE_FILE_SUFFIXES {
+                let file_name = format!("{}-{}.png", action, suffix);
+                if !dir.join(&file_name).is_file() {
+                    complete = false;
+                    self.add(
+                        IntegrityIssueKind::MissingFile,
+                        format!("sprite/{}/{}", self.form_path, file_name),
+                    );
+                }
+            }
+            if complete {
+                existing.push(action.clone());
+            }
+        }
+        for file_name in list_files(dir) {
+            let tracked = file_name
+                .strip_suffix(".png")
+                .map(|stem| match stem.rsplit_once('-') {
+                    Some((action, suffix)) => {
+                        SPRITE_FILE_SUFFIXES.contains(&suffix)
+                            && group.sprite_files.contains_key(action)
+                    }
+                    None => false,
+                });
+            if tracked == Some(false) {
+                self.add(
+                    IntegrityIssueKind::UntrackedFile,
+                    format!("sprite/{}/{}", self.form_path, file_name),
+                );
+            }
+        }
+        self.check_phase(
+            "sprite",
+            group.sprite_complete,
+            &sprite_config.completion_actions,
+            &sprite_config.actions,
+            &existing,
+        );
+    }
+
+    /// The phase of the tracker must not be higher than the number of phases whose
+    /// requirements (a list of indices into `names` per phase) are met by the existing files.
+    fn check_phase(
+        &mut self,
+        category: &str,
+        phase: i64,
+        completion: &[Vec<i32>],
+        names: &[String],
+        existing: &[String],
+    ) {
+        let files_phase = completion
+            .iter()
+            .take_while(|required| {
+                required.iter().all(|idx| {
+                    names
+                        .get(*idx as usize)
+                        .is_some_and(|name| existing.contains(name))
+                })
+            })
+            .count() as i64;
+        if phase > files_phase {
+            self.add(
+                IntegrityIssueKind::InconsistentPhase,
+                format!(
+                    "The {} phase is {}, but the existing files only meet the requirements of phase {}.",
+                    category, phase, files_phase
+                ),
+            );
+        }
+    }
+
+    fn check_credits(&mut self, group: &Group, credit_names: &CreditNames) {
+        let mut unknown = HashSet::new();
+        for credit in [&group.portrait_credit, &group.sprite_credit] {
+            for credit_id in once(&credit.primary).chain(credit.secondary.iter()) {
+                let credit_id = parse_credit_id(credit_id);
+                if !credit_id.is_empty()
+                    && credit_names.get(&credit_id).is_none()
+                    && unknown.insert(credit_id.clone())
+                {
+                    self.add(IntegrityIssueKind::UnknownCreditId, credit_id);
+                }
+            }
+        }
+    }
+}
+
+/// Names of all files (not directories) in `dir`, sorted. Empty if it does not exist.
+fn list_files(dir: &Path) -> Vec<String> {
+    let mut files = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::snapshots::fixtures_dir;
+    use crate::datafiles::credit_names::read_credit_names;
+    use crate::datafiles::group_id::GroupId;
+    use crate::datafiles::sprite_config::read_sprite_config;
+    use crate::datafiles::tracker::read_tracker;
+
+    async fn read_fixtures() -> (SpriteConfig, Tracker, CreditNames) {
+        let repo = fixtures_dir().join("spritecollab");
+        (
+            read_sprite_config(repo.join("sprite_config.json"))
+                .await
+                .unwrap(),
+            read_tracker(repo.join("tracker.json")).await.unwrap(),
+            read_credit_names(repo.join("credit_names.txt"))
+                .await
+                .unwrap(),
+        )
+    }
+
+    fn issue(kind: IntegrityIssueKind, details: &str) -> IntegrityIssue {
+        IntegrityIssue {
+            kind,
+            form_path: "0001".to_string(),
+            details: details.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn fixtures_are_consistent() {
+        let (sprite_config, tracker, credit_names) = read_fixtures().await;
+        let repo = fixtures_dir().join("spritecollab");
+        let report = IntegrityReport::check(&repo, &sprite_config, &tracker, &credit_names);
+        assert_eq!(report.issues, vec![]);
+    }
+
+    #[tokio::test]
+    async fn reports_inconsistencies() {
+        let (sprite_config, mut tracker, credit_names) = read_fixtures().await;
+        let group = tracker.get_mut(&GroupId(1)).unwrap();
+        group.portrait_files.shift_remove("Happy");
+        group.portrait_files.insert("Sad".to_string(), false);
+        group.portrait_complete = 2;
+        group.sprite_credit.primary = "Nobody".to_string();
+
+        let repo = fixtures_dir().join("spritecollab");
+        let report = IntegrityReport::check(&repo, &sprite_config, &tracker, &credit_names);
+        assert_eq!(
+            report.issues,
+            vec![
+                issue(IntegrityIssueKind::MissingFile, "portrait/0001/Sad.png"),
+                issue(IntegrityIssueKind::UntrackedFile, "portrait/0001/Happy.png"),
+                issue(
+                    IntegrityIssueKind::InconsistentPhase,
+                    "The portrait phase is 2, but the existing files only meet the requirements of phase 1."
+                ),
+                issue(IntegrityIssueKind::UnknownCreditId, "Nobody"),
+            ]
+        );
+        assert_eq!(report.unknown_credit_ids(), vec!["Nobody".to_string()]);
+    }
+}
@@ -8,15 +8,22 @@ use regex::Regex;
 use serde::{Deserialize, Deserializer};
 use thiserror::Error;
 
+use crate::assets::store::AssetStore;
 use crate::datafiles::anim_data_xml::{AnimDataXml, AnimDataXmlOpenError};
+use crate::datafiles::refresh_report::DataFileError;
 use crate::datafiles::tracker::{MonsterFormCollector, Tracker};
 
+pub mod aliases;
 pub mod anim_data_xml;
 pub mod credit_names;
 pub mod group_id;
+pub mod integrity;
 pub mod local_credits_file;
+pub mod project_stats;
+pub mod refresh_report;
 pub mod sprite_config;
 pub mod tracker;
+pub mod translations;
 
 pub type DataReadResult<T> = Result<T, DataReadError>;
 
@@ -55,24 +62,30 @@ impl From<std::io::Error> for DataReadError {
 }
 
 /// Reads the given file and returns the result of `generate_fn`.
-/// If there was an error, it tries to process and log it.
-pub async fn read_and_report_error<P, FN, FT, T>(path: P, generate_fn: FN) -> DataReadResult<T>
+/// If there was an error, it is logged and returned together with the path of the file.
+pub async fn read_and_report_error<P, FN, FT, T>(
+    path: P,
+    generate_fn: FN,
+) -> Result<T, DataFileError>
 where
     P: AsRef<Path> + Copy,
     FN: FnOnce(P) -> FT,
     FT: Future<Output = DataReadResult<T>>,
 {
-    let out = generate_fn(path).await;
-    match &out {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Failed reading {}: {}", path.as_ref().display(), e);
-        }
-    }
-    out
+    generate_fn(path).await.map_err(|source| {
+        let e = DataFileError {
+            path: path.as_ref().to_path_buf(),
+            source,
+        };
+        error!("{}", e);
+        e
+    })
 }
 
-pub async fn try_read_in_anim_data_xml(tracker: &Tracker) -> Result<(), DataReadError> {
+pub async fn try_read_in_anim_data_xml(
+    store: &dyn AssetStore,
+    tracker: &Tracker,
+) -> Result<(), DataReadError> {
     let errs = tracker
         .keys()
         .flat_map(|group_id| {
@@ -84,7 +97,7 @@ pub async fn try_read_in_anim_data_xml(tracker: &Tracker) -> Result<(), DataRead
                     if group.sprite_complete == 0 {
                         return None;
                     }
-                    if let Err(e) = AnimDataXml::open_for_form(group_id, &path) {
+                    if let Err(e) = AnimDataXml::open_for_form(store, group_id, &path) {
                         Some((group_id, path, Arc::new(e)))
                     } else {
                         None
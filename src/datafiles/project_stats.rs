@@ -0,0 +1,112 @@
+//! Statistics of the whole project, computed on every refresh and returned by the
+//! `projectStats` query.
+
+use std::collections::{BTreeMap, HashSet};
+use std::iter::once;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::datafiles::parse_credit_id;
+use crate::datafiles::tracker::{MonsterFormCollector, Tracker};
+
+/// Modifications in this many days before the refresh are counted as recent.
+pub const RECENT_DAYS: i64 = 30;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProjectStats {
+    pub monsters: usize,
+    /// All forms, including the base form of each monster.
+    pub forms: usize,
+    /// Number of forms per raw portrait phase of the tracker.
+    pub portrait_phases: BTreeMap<i64, usize>,
+    /// Number of forms per raw sprite phase of the tracker.
+    pub sprite_phases: BTreeMap<i64, usize>,
+    /// Distinct credit IDs credited for any portraits or sprites.
+    pub contributors: usize,
+    /// Forms whose portraits were modified in the [`RECENT_DAYS`] before `computed_at`.
+    pub recent_portraits: usize,
+    /// Forms whose sprites were modified in the [`RECENT_DAYS`] before `computed_at`.
+    pub recent_sprites: usize,
+    pub computed_at: DateTime<Utc>,
+}
+
+impl ProjectStats {
+    pub fn compute(tracker: &Tracker, now: DateTime<Utc>) -> Self {
+        let recent_since = now - Duration::days(RECENT_DAYS);
+        let is_recent = |date: Option<DateTime<Utc>>| date.is_some_and(|d| d >= recent_since);
+        let mut stats = Self {
+            monsters: tracker.len(),
+            forms: 0,
+            portrait_phases: BTreeMap::new(),
+            sprite_phases: BTreeMap::new(),
+            contributors: 0,
+            recent_portraits: 0,
+            recent_sprites: 0,
+            computed_at: now,
+        };
+        let mut contributors = HashSet::new();
+        for group_id in tracker.keys() {
+            let collector = match MonsterFormCollector::collect(tracker, **group_id as i32) {
+                Some(collector) => collector,
+                None => continue,
+            };
+            for group in collector.map(|(_, _, group)| group) {
+                stats.forms += 1;
+                *stats
+                    .portrait_phases
+                    .entry(group.portrait_complete)
+                    .or_default() += 1;
+                *stats
+                    .sprite_phases
+                    .entry(group.sprite_complete)
+                    .or_default() += 1;
+                if is_recent(group.portrait_modified) {
+                    stats.recent_portraits += 1;
+                }
+                if is_recent(group.sprite_modified) {
+                    stats.recent_sprites += 1;
+                }
+                for credit in [&group.portrait_credit, &group.sprite_credit] {
+                    for credit_id in once(&credit.primary).chain(credit.secondary.iter()) {
+                        let credit_id = parse_credit_id(credit_id);
+                        if !credit_id.is_empty() {
+                            contributors.insert(credit_id);
+                        }
+                    }
+                }
+            }
+        }
+        stats.contributors = contributors.len();
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::snapshots::fixtures_dir;
+    use crate::datafiles::group_id::GroupId;
+    use crate::datafiles::tracker::read_tracker;
+
+    #[tokio::test]
+    async fn counts_forms_phases_and_contributors() {
+        let mut tracker = read_tracker(fixtures_dir().join("spritecollab/tracker.json"))
+            .await
+            .unwrap();
+        let now = Utc::now();
+        let group = tracker.get_mut(&GroupId(1)).unwrap();
+        group.portrait_modified = Some(now - Duration::days(2));
+        group.sprite_modified = Some(now - Duration::days(RECENT_DAYS + 1));
+        group.portrait_credit.primary = "Alice".to_string();
+        group.sprite_credit.secondary = vec!["Alice".to_string(), "Bob".to_string()];
+
+        let stats = ProjectStats::compute(&tracker, now);
+        assert_eq!(stats.monsters, 1);
+        assert_eq!(stats.forms, 1);
+        assert_eq!(stats.portrait_phases, BTreeMap::from([(1, 1)]));
+        assert_eq!(stats.sprite_phases, BTreeMap::from([(1, 1)]));
+        assert_eq!(stats.contributors, 2);
+        assert_eq!(stats.recent_portraits, 1);
+        assert_eq!(stats.recent_sprites, 0);
+    }
+}
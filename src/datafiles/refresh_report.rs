@@ -0,0 +1,182 @@
+//! The outcome of the last refresh of the data, with diagnostics for each data file that could
+//! not be read. It is returned by the `meta { lastRefreshReport }` query.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+use crate::assets::util::join_monster_and_form;
+use crate::datafiles::anim_data_xml::AnimDataXmlOpenError;
+use crate::datafiles::DataReadError;
+
+/// A data file that could not be read, see [`crate::datafiles::read_and_report_error`].
+#[derive(Error, Debug, Clone)]
+#[error("Failed reading {}: {source}", path.display())]
+pub struct DataFileError {
+    pub path: PathBuf,
+    pub source: DataReadError,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileErrorKind {
+    /// The file is not valid JSON, or doesn't match the expected structure.
+    Json,
+    /// The file is not valid CSV, or doesn't match the expected structure.
+    Csv,
+    /// An XML file could not be parsed.
+    Xml,
+    /// The file could not be opened or read.
+    Io,
+    /// A credit ID appears more than once in the credit names.
+    DuplicateCreditId,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileDiagnostic {
+    /// Path of the file, relative to the repository.
+    pub path: String,
+    pub kind: FileErrorKind,
+    pub message: String,
+    /// Line of the error (1-based), if known.
+    pub line: Option<usize>,
+    /// Column of the error (1-based), if known.
+    pub column: Option<usize>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RefreshReport {
+    pub date: DateTime<Utc>,
+    /// The commit that is served after the refresh.
+    pub commit: String,
+    pub success: bool,
+    /// The error the refresh failed with, if it failed.
+    pub error: Option<String>,
+    pub files: Vec<FileDiagnostic>,
+    /// Credit IDs of the tracker that are missing in the credit names. The API returns them as
+    /// unresolved credits.
+    pub unknown_credit_ids: Vec<String>,
+}
+
+impl RefreshReport {
+    pub fn succeeded(commit: String, unknown_credit_ids: Vec<String>) -> Self {
+        Self {
+            date: Utc::now(),
+            commit,
+            success: true,
+            error: None,
+            files: vec![],
+            unknown_credit_ids,
+        }
+    }
+
+    /// The report of a failed refresh. The previous data and `commit` stay served. `repo_path`
+    /// is the directory the paths of the diagnostics are relative to.
+    pub fn failed(commit: String, error: &anyhow::Error, repo_path: &Path) -> Self {
+        let files = if let Some(e) = error.downcast_ref::<DataFileError>() {
+            let path = e.path.strip_prefix(repo_path).unwrap_or(&e.path);
+            FileDiagnostic::from_error(&path.to_string_lossy(), &e.source)
+        } else if let Some(e) = error.downcast_ref::<DataReadError>() {
+            FileDiagnostic::from_error("", e)
+        } else {
+            vec![]
+        };
+        Self {
+            date: Utc::now(),
+            commit,
+            success: false,
+            error: Some(error.to_string()),
+            files,
+            unknown_credit_ids: vec![],
+        }
+    }
+}
+
+impl FileDiagnostic {
+    /// The diagnostics of an error reading the file at `path`. Errors of AnimData.xml files
+    /// contain their own paths.
+    pub fn from_error(path: &str, error: &DataReadError) -> Vec<Self> {
+        let diagnostic = |kind, message: String, position: Option<(usize, usize)>| Self {
+            path: path.to_string(),
+            kind,
+            message,
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+        };
+        match error {
+            DataReadError::SerdeJson(e) => vec![diagnostic(
+                FileErrorKind::Json,
+                e.to_string(),
+                // serde_json reports line 0 for errors without a position, eg. I/O errors.
+                Some((e.line(), e.column())).filter(|(line, _)| *line > 0),
+            )],
+            DataReadError::SerdeCsv(e) => vec![diagnostic(
+                FileErrorKind::Csv,
+                e.to_string(),
+                // CSV positions have no column, only the byte offset of the record.
+                e.position().map(|pos| (pos.line() as usize, 1)),
+            )],
+            DataReadError::Io(e) => vec![diagnostic(FileErrorKind::Io, e.to_string(), None)],
+            DataReadError::CreditsDuplicateCreditId(credit_id) => vec![diagnostic(
+                FileErrorKind::DuplicateCreditId,
+                format!("Duplicate credit ID: {}", credit_id),
+                None,
+            )],
+            DataReadError::AnimDataXmlErrors(errs) => errs
+                .iter()
+                .map(|(monster_idx, path_to_form, e)| Self {
+                    path: format!(
+                        "sprite/{}/AnimData.xml",
+                        join_monster_and_form(*monster_idx, path_to_form, '/')
+                    ),
+                    kind: match **e {
+                        AnimDataXmlOpenError::IoError(_) => FileErrorKind::Io,
+                        AnimDataXmlOpenError::SerdeXmlError(_) => FileErrorKind::Xml,
+                    },
+                    message: e.to_string(),
+                    line: None,
+                    column: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datafiles::credit_names::read_credit_names;
+    use crate::testing::unique_temp_path;
+
+    #[tokio::test]
+    async fn reports_positions_of_file_errors() {
+        let dir = unique_temp_path("scsrv-refresh-report-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let credit_names = dir.join("credit_names.txt");
+        std::fs::write(&credit_names, "Name\tDiscord\tContact\nA\t1\t\nB\t1\t\n").unwrap();
+
+        let e = read_credit_names(&credit_names).await.err().unwrap();
+        let error = anyhow::Error::new(DataFileError {
+            path: credit_names,
+            source: e,
+        });
+        let report = RefreshReport::failed("abc".to_string(), &error, &dir);
+        assert!(!report.success);
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.files[0].path, "credit_names.txt");
+        assert_eq!(report.files[0].kind, FileErrorKind::DuplicateCreditId);
+
+        let e = serde_json::from_str::<Vec<i32>>("[\n  1,\n  x\n]").unwrap_err();
+        assert_eq!(
+            FileDiagnostic::from_error("tracker.json", &e.into()),
+            vec![FileDiagnostic {
+                path: "tracker.json".to_string(),
+                kind: FileErrorKind::Json,
+                message: "expected value at line 3 column 3".to_string(),
+                line: Some(3),
+                column: Some(3),
+            }]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,7 @@
 use crate::datafiles::DataReadResult;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
@@ -10,7 +11,7 @@ pub async fn read_sprite_config<P: AsRef<Path>>(path: P) -> DataReadResult<Sprit
     Ok(serde_json::from_reader(BufReader::new(input))?)
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Default, Deserialize, Eq, PartialEq)]
 pub struct SpriteConfig {
     pub portrait_size: i32,
     pub portrait_tile_x: i32,
@@ -21,3 +22,124 @@ pub struct SpriteConfig {
     pub actions: Vec<String>,
     pub action_map: HashMap<i32, String>,
 }
+
+impl SpriteConfig {
+    /// The emotion of a name from a request or the tracker, eg. `happy` or `Happy^`. The name is
+    /// matched with the emotions of the sprite config ignoring case, names that are not in it are
+    /// kept as they are.
+    pub fn emotion(&self, name: &str) -> Emotion {
+        let (name, flipped) = match name.strip_suffix('^') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        Emotion {
+            name: canonical_name(&self.emotions, name),
+            flipped,
+        }
+    }
+
+    /// The action of a name from a request or the tracker, eg. `idle`. The name is matched with
+    /// the actions of the sprite config ignoring case, names that are not in it are kept as
+    /// they are.
+    pub fn action(&self, name: &str) -> ActionName {
+        ActionName(canonical_name(&self.actions, name))
+    }
+
+    /// All emotions, followed by their flipped variants (with a `^` suffix).
+    pub fn emotions_incl_flipped(&self) -> Vec<String> {
+        self.emotions
+            .iter()
+            .cloned()
+            .chain(self.emotions.iter().map(|e| format!("{}^", e)))
+            .collect()
+    }
+}
+
+fn canonical_name(names: &[String], name: &str) -> String {
+    names
+        .iter()
+        .find(|known| known.eq_ignore_ascii_case(name))
+        .map_or_else(|| name.to_string(), Clone::clone)
+}
+
+/// An emotion of the portraits, named like in the sprite config, eg. `Teary-Eyed`. See
+/// [`SpriteConfig::emotion`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Emotion {
+    name: String,
+    flipped: bool,
+}
+
+impl Emotion {
+    /// The name without the `^` of flipped portraits.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_flipped(&self) -> bool {
+        self.flipped
+    }
+
+    /// The flipped portrait of the emotion.
+    pub fn flipped(self) -> Self {
+        Self {
+            flipped: true,
+            ..self
+        }
+    }
+}
+
+/// The key of the portrait in the tracker and the name of its file, with a `^` suffix if it is
+/// flipped.
+impl Display for Emotion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.flipped {
+            write!(f, "{}^", self.name)
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+/// An action of the sprites, named like in the sprite config, eg. `Idle`. See
+/// [`SpriteConfig::action`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ActionName(String);
+
+impl ActionName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for ActionName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::snapshots::fixtures_dir;
+
+    #[tokio::test]
+    async fn canonicalizes_names() {
+        let config = read_sprite_config(fixtures_dir().join("spritecollab/sprite_config.json"))
+            .await
+            .unwrap();
+
+        let happy = config.emotion("happy");
+        assert_eq!(happy.name(), "Happy");
+        assert!(!happy.is_flipped());
+        let flipped = config.emotion("HAPPY^");
+        assert_eq!(flipped.name(), "Happy");
+        assert!(flipped.is_flipped());
+        assert_eq!(flipped.to_string(), "Happy^");
+        // Unknown names are kept.
+        assert_eq!(config.emotion("Unknown^").to_string(), "Unknown^");
+
+        assert_eq!(config.action("idle").as_str(), "Idle");
+        assert_eq!(config.action("Unknown").as_str(), "Unknown");
+    }
+}
@@ -1,3 +1,4 @@
+use std::cmp::max;
 use std::collections::VecDeque;
 use std::fs::File;
 use std::io::BufReader;
@@ -6,14 +7,14 @@ use std::path::Path;
 
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use indexmap::IndexMap;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 
-use crate::cache::CacheBehaviour;
-use crate::cache::ScCache;
+use crate::datafiles::aliases::Aliases;
 use crate::datafiles::group_id::GroupId;
+use crate::datafiles::translations::Translations;
 use crate::datafiles::DataReadResult;
-use crate::search::fuzzy_find;
+use crate::search::{did_you_mean, fuzzy_find, fuzzy_find_matches, SearchMatch};
 
 pub async fn read_tracker<P: AsRef<Path>>(path: P) -> DataReadResult<Tracker> {
     let input = File::open(path)?;
@@ -23,14 +24,14 @@ pub async fn read_tracker<P: AsRef<Path>>(path: P) -> DataReadResult<Tracker> {
 pub type MapImpl<K, V> = IndexMap<K, V>;
 pub type Tracker = MapImpl<GroupId, Group>;
 
-#[derive(Debug, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
 pub struct Credit {
     pub primary: String,
     pub secondary: Vec<String>,
     pub total: i64,
 }
 
-#[derive(Debug, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
 pub struct Group {
     pub canon: bool,
     pub modreward: bool,
@@ -40,7 +41,10 @@ pub struct Group {
     pub portrait_credit: Credit,
     pub portrait_files: MapImpl<String, bool>,
     pub portrait_link: String,
-    #[serde(deserialize_with = "parse_datetime")]
+    #[serde(
+        deserialize_with = "parse_datetime",
+        serialize_with = "serialize_datetime"
+    )]
     pub portrait_modified: Option<DateTime<Utc>>,
     pub portrait_pending: Value,
     pub portrait_recolor_link: String,
@@ -50,7 +54,10 @@ pub struct Group {
     pub sprite_credit: Credit,
     pub sprite_files: MapImpl<String, bool>,
     pub sprite_link: String,
-    #[serde(deserialize_with = "parse_datetime")]
+    #[serde(
+        deserialize_with = "parse_datetime",
+        serialize_with = "serialize_datetime"
+    )]
     pub sprite_modified: Option<DateTime<Utc>>,
     pub sprite_pending: Value,
     pub sprite_recolor_link: String,
@@ -58,6 +65,17 @@ pub struct Group {
     pub subgroups: MapImpl<GroupId, Group>,
 }
 
+impl Group {
+    /// The most recent modification of the portraits or sprites of this group or any of its
+    /// subgroups.
+    pub fn last_modified(&self) -> Option<DateTime<Utc>> {
+        self.subgroups
+            .values()
+            .map(Group::last_modified)
+            .fold(max(self.portrait_modified, self.sprite_modified), max)
+    }
+}
+
 fn parse_datetime<'de, D>(deser: D) -> Result<Option<DateTime<Utc>>, D::Error>
 where
     D: Deserializer<'de>,
@@ -72,37 +90,171 @@ where
     }
 }
 
-pub async fn fuzzy_find_tracker<S, C, E, T, F>(
-    tracker: &Tracker,
-    monster_name: S,
-    cache: &C,
-    consume: F,
-) -> Result<Vec<T>, E>
+/// The inverse of [`parse_datetime`], in the format SpriteBot writes.
+fn serialize_datetime<S>(datetime: &Option<DateTime<Utc>>, ser: S) -> Result<S::Ok, S::Error>
 where
-    S: AsRef<str>,
-    C: ScCache<Error = E>,
-    F: Fn(i64) -> T,
+    S: Serializer,
 {
-    let index: MapImpl<String, Vec<i64>> = cache
-        .cached("fuzzy_find_tracker", || async {
-            let mut names: MapImpl<String, Vec<i64>> = MapImpl::with_capacity(tracker.len() * 10);
-            for (monster_idx, monster) in tracker.iter() {
-                fft_insert(&mut names, **monster_idx, &monster.name);
-                fft_recurse(&mut names, **monster_idx, &monster.subgroups);
-            }
-            CacheBehaviour::Cache(names)
+    match datetime {
+        None => ser.serialize_str(""),
+        Some(datetime) if datetime.timestamp_subsec_nanos() == 0 => {
+            ser.serialize_str(&datetime.format("%Y-%m-%d %H:%M:%S").to_string())
+        }
+        Some(datetime) => ser.serialize_str(&datetime.format("%Y-%m-%d %H:%M:%S%.6f").to_string()),
+    }
+}
+
+/// The names the monster and form searches match against. It is built when the data is read,
+/// so searching needs no cache lookups.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TrackerSearchIndex {
+    /// Names of the monsters and their forms, localized names and aliases, with the IDs of the
+    /// monsters they belong to.
+    monster_names: MapImpl<String, Vec<i64>>,
+    /// Synthesized full names of all forms (eg. "Shiny Female Sneasel Hisui"), localized names
+    /// and aliases, with the monster IDs and paths of the forms.
+    form_names: Vec<(String, i32, Vec<i32>)>,
+}
+
+impl TrackerSearchIndex {
+    pub fn build(tracker: &Tracker, translations: &Translations, aliases: &Aliases) -> Self {
+        Self {
+            monster_names: Self::build_monster_names(tracker, translations, aliases),
+            form_names: Self::build_form_names(tracker, translations, aliases),
+        }
+    }
+
+    /// Fuzzy-finds monsters by their names, the names of their forms, localized names and
+    /// aliases. Results are sorted by best match.
+    pub fn find_monsters<S: AsRef<str>>(&self, monster_name: S) -> Vec<SearchMatch<i64>> {
+        fuzzy_find_matches(self.monster_names.iter(), monster_name)
+    }
+
+    /// Like [`TrackerSearchIndex::find_monsters`], but searches over the full names of all forms
+    /// and returns the monster IDs and paths of the matching forms.
+    pub fn find_forms<S: AsRef<str>>(&self, query: S) -> impl Iterator<Item = (i32, &[i32])> {
+        fuzzy_find(
+            self.form_names
+                .iter()
+                .enumerate()
+                .map(|(idx, (name, _, _))| (name, vec![idx])),
+            query,
+        )
+        .map(|idx| {
+            let (_, monster_idx, path) = &self.form_names[idx];
+            (*monster_idx, path.as_slice())
         })
-        .await?;
-    Ok(fuzzy_find(index.iter(), monster_name)
-        .map(consume)
-        .collect())
+    }
+
+    /// A suggestion for a query with typos: the closest full name of a form, see
+    /// [`did_you_mean`].
+    pub fn did_you_mean<S: AsRef<str>>(&self, query: S) -> Option<&str> {
+        did_you_mean(self.form_names.iter().map(|(name, _, _)| name), query).map(String::as_str)
+    }
+
+    fn build_monster_names(
+        tracker: &Tracker,
+        translations: &Translations,
+        aliases: &Aliases,
+    ) -> MapImpl<String, Vec<i64>> {
+        let mut names: MapImpl<String, Vec<i64>> = MapImpl::with_capacity(tracker.len() * 10);
+        for (monster_idx, monster) in tracker.iter() {
+            fft_insert(&mut names, **monster_idx, &monster.name);
+            fft_recurse(&mut names, **monster_idx, &monster.subgroups);
+        }
+        for (monster_idx, name) in translations.names() {
+            fft_insert(&mut names, monster_idx, name);
+        }
+        for (alias, monster_idx, _) in aliases.iter() {
+            if tracker.contains_key(&GroupId(monster_idx as i64)) {
+                fft_insert(&mut names, monster_idx as i64, alias);
+            }
+        }
+        names
+    }
+
+    fn build_form_names(
+        tracker: &Tracker,
+        translations: &Translations,
+        aliases: &Aliases,
+    ) -> Vec<(String, i32, Vec<i32>)> {
+        let mut forms = Vec::with_capacity(tracker.len() * 10);
+        for monster_idx in tracker.keys() {
+            let monster_idx = **monster_idx as i32;
+            if let Some(collector) = MonsterFormCollector::collect(tracker, monster_idx) {
+                let monster = collector.0;
+                forms.extend(collector.map(move |(path, name_path, _)| {
+                    (
+                        synthesize_form_name(&path, &name_path, &monster.name),
+                        monster_idx,
+                        path,
+                    )
+                }));
+                // The same names with the localized names of the monster and the form.
+                for lang in translations.languages() {
+                    let monster_name = translations.name(lang, monster_idx, &[]);
+                    #[allow(clippy::map_flatten)] // See comment at MonsterFormCollector::map
+                    forms.extend(
+                        collector
+                            .map(move |(path, name_path, _)| {
+                                let form_name = if path.is_empty() {
+                                    None
+                                } else {
+                                    translations.name(lang, monster_idx, &path)
+                                };
+                                if monster_name.is_none() && form_name.is_none() {
+                                    return None;
+                                }
+                                let name_path =
+                                    form_name.map_or(name_path, |name| vec![name.to_string()]);
+                                Some((
+                                    synthesize_form_name(
+                                        &path,
+                                        &name_path,
+                                        monster_name.unwrap_or(&monster.name),
+                                    ),
+                                    monster_idx,
+                                    path,
+                                ))
+                            })
+                            .flatten(),
+                    );
+                }
+            }
+        }
+        forms.extend(
+            aliases
+                .iter()
+                .map(|(alias, monster_idx, path)| (alias.to_string(), monster_idx, path.to_vec())),
+        );
+        forms
+    }
+}
+
+/// Builds a name like "Shiny Female Sneasel Hisui" for a form.
+fn synthesize_form_name(path: &[i32], name_path: &[String], monster_name: &str) -> String {
+    let mut parts: Vec<&str> = Vec::with_capacity(name_path.len() + 3);
+    if MonsterFormCollector::is_shiny(path) {
+        parts.push("Shiny");
+    }
+    if MonsterFormCollector::is_female(path) {
+        parts.push("Female");
+    }
+    parts.push(monster_name);
+    if !path.is_empty() {
+        parts.extend(
+            name_path
+                .iter()
+                .map(String::as_str)
+                .filter(|name| !name.eq_ignore_ascii_case("shiny"))
+                .filter(|name| !name.eq_ignore_ascii_case("female")),
+        );
+    }
+    parts.join(" ")
 }
 
 fn fft_insert(names: &mut MapImpl<String, Vec<i64>>, monster_idx: i64, name: &str) {
-    names
-        .entry(name.to_lowercase())
-        .or_default()
-        .push(monster_idx);
+    names.entry(name.to_string()).or_default().push(monster_idx);
 }
 
 fn fft_recurse(
@@ -162,7 +314,7 @@ where
 pub struct MonsterFormCollector<'a>(&'a Group);
 
 impl<'a> MonsterFormCollector<'a> {
-    pub fn collect(tracker: &'a Tracker, monster_idx: i32) -> Option<MonsterFormCollector> {
+    pub fn collect(tracker: &'a Tracker, monster_idx: i32) -> Option<MonsterFormCollector<'a>> {
         tracker
             .get(&GroupId(monster_idx as i64))
             .map(MonsterFormCollector)
@@ -357,3 +509,57 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::snapshots::fixtures_dir;
+
+    #[tokio::test]
+    async fn serializes_like_spritebot() {
+        let mut tracker = read_tracker(fixtures_dir().join("spritecollab/tracker.json"))
+            .await
+            .unwrap();
+        let group = tracker.get_mut(&GroupId(1)).unwrap();
+        group.portrait_modified = Some(Utc.with_ymd_and_hms(2023, 4, 5, 6, 7, 8).unwrap());
+        group.sprite_modified = None;
+
+        let json = serde_json::to_value(&tracker).unwrap();
+        assert_eq!(json["0001"]["portrait_modified"], "2023-04-05 06:07:08");
+        assert_eq!(json["0001"]["sprite_modified"], "");
+        let read_back: Tracker = serde_json::from_value(json).unwrap();
+        assert_eq!(read_back, tracker);
+    }
+
+    #[tokio::test]
+    async fn finds_localized_names() {
+        let tracker = read_tracker(fixtures_dir().join("spritecollab/tracker.json"))
+            .await
+            .unwrap();
+        let translations: Translations =
+            serde_json::from_value(serde_json::json!({ "de": { "0001": "Testmon" } })).unwrap();
+        let index = TrackerSearchIndex::build(&tracker, &translations, &Aliases::default());
+        let monsters = index.find_monsters("testmon");
+        assert_eq!(monsters.len(), 1);
+        assert_eq!(monsters[0].value, 1);
+        assert_eq!(monsters[0].key, "Testmon");
+        assert_eq!(index.find_forms("testmon").next(), Some((1, &[][..])));
+    }
+
+    #[tokio::test]
+    async fn finds_aliases() {
+        let tracker = read_tracker(fixtures_dir().join("spritecollab/tracker.json"))
+            .await
+            .unwrap();
+        let aliases: Aliases =
+            serde_json::from_value(serde_json::json!({ "Fixy": "0001", "Ghost": "0999" })).unwrap();
+        let index = TrackerSearchIndex::build(&tracker, &Translations::default(), &aliases);
+        let monsters = index.find_monsters("fixy");
+        assert_eq!(monsters.len(), 1);
+        assert_eq!(monsters[0].value, 1);
+        assert!(!index
+            .find_monsters("ghost")
+            .iter()
+            .any(|search_match| search_match.value == 999));
+    }
+}
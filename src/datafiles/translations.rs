@@ -0,0 +1,89 @@
+use crate::datafiles::DataReadResult;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind};
+use std::path::Path;
+
+/// Name of the translations file in the root of the SpriteCollab repository.
+pub const TRANSLATIONS_FILE_NAME: &str = "translations.json";
+
+/// Reads the translations, if the file exists.
+pub async fn read_translations<P: AsRef<Path>>(path: P) -> DataReadResult<Translations> {
+    let input = match File::open(path) {
+        Ok(input) => input,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Translations::default()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(serde_json::from_reader(BufReader::new(input))?)
+}
+
+/// Localized names of monsters and forms. Maps languages (eg. `de` or `pt-BR`) to a map of
+/// full form paths (eg. `0025` for the monster, `0025/0001` for a form) to their names:
+///
+/// ```json
+/// { "de": { "0025": "Pikachu", "0025/0001": "Kappe" } }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct Translations(HashMap<String, HashMap<String, String>>);
+
+impl Translations {
+    /// The name of the form `form_path` of a monster in `lang`, the name of the monster itself
+    /// if `form_path` is empty. Languages are matched ignoring case, and a regional language
+    /// (eg. `de-CH`) falls back to the general one (`de`).
+    pub fn name(&self, lang: &str, monster_id: i32, form_path: &[i32]) -> Option<&str> {
+        let full_path = form_path
+            .iter()
+            .fold(format!("{:04}", monster_id), |path, form_id| {
+                format!("{}/{:04}", path, form_id)
+            });
+        let general_lang = lang.split(['-', '_']).next().unwrap_or(lang);
+        [lang, general_lang].into_iter().find_map(|lang| {
+            self.0
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(lang))
+                .and_then(|(_, names)| names.get(&full_path))
+                .map(String::as_str)
+        })
+    }
+
+    /// All languages with translations.
+    pub fn languages(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    /// All localized names, with the ID of the monster they belong to.
+    pub fn names(&self) -> impl Iterator<Item = (i64, &str)> {
+        self.0.values().flatten().filter_map(|(full_path, name)| {
+            let monster_id = full_path.split('/').next()?.parse().ok()?;
+            Some((monster_id, name.as_str()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn finds_localized_names() {
+        let translations: Translations = serde_json::from_value(json!({
+            "de": { "0001": "Fixturemon-DE", "0001/0001": "Kappe" },
+            "pt-BR": { "0001": "Fixturemon-BR" }
+        }))
+        .unwrap();
+        assert_eq!(translations.name("de", 1, &[]), Some("Fixturemon-DE"));
+        assert_eq!(translations.name("DE-ch", 1, &[1]), Some("Kappe"));
+        assert_eq!(translations.name("pt-br", 1, &[]), Some("Fixturemon-BR"));
+        assert_eq!(translations.name("pt", 1, &[]), None);
+        assert_eq!(translations.name("de", 2, &[]), None);
+        let mut names = translations.names().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![(1, "Fixturemon-BR"), (1, "Fixturemon-DE"), (1, "Kappe")]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Monitors the free space on the disk of the workdir. While it is below
+//! `SCSRV_MIN_FREE_DISK_SPACE`, ZIPs are no longer cached, since they are the largest assets,
+//! and failing to generate them is answered with `507 Insufficient Storage`. The state is
+//! reported at `/healthz`, together with whether data is loaded.
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use hyper::{Response, StatusCode};
+use log::{info, warn};
+use serde_json::json;
+
+use crate::graphql::make_json_response;
+use crate::{ServerConfig, SpriteCollab};
+
+pub const HEALTHZ_PATH: &str = "/healthz";
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Stored as the free space until it was checked for the first time.
+const UNKNOWN: u64 = u64::MAX;
+
+pub struct DiskMonitor {
+    free_bytes: AtomicU64,
+    low: AtomicBool,
+}
+
+impl Default for DiskMonitor {
+    fn default() -> Self {
+        Self {
+            free_bytes: AtomicU64::new(UNKNOWN),
+            low: AtomicBool::new(false),
+        }
+    }
+}
+
+impl DiskMonitor {
+    /// Whether the free space was below the threshold at the last check.
+    pub fn is_low(&self) -> bool {
+        self.low.load(Ordering::Relaxed)
+    }
+
+    /// The free space at the last check, `None` if it could not be checked yet.
+    pub fn free_bytes(&self) -> Option<u64> {
+        Some(self.free_bytes.load(Ordering::Relaxed)).filter(|free| *free != UNKNOWN)
+    }
+
+    /// Records the free space and logs if it fell below or recovered above `min_free_bytes`.
+    /// Returns whether it is below.
+    pub fn update(&self, free_bytes: u64, min_free_bytes: u64) -> bool {
+        self.free_bytes.store(free_bytes, Ordering::Relaxed);
+        let low = free_bytes < min_free_bytes;
+        if self.low.swap(low, Ordering::Relaxed) != low {
+            if low {
+                warn!(
+                    "Low disk space: {} MiB free in the workdir, the minimum is {} MiB. ZIPs are no longer cached.",
+                    free_bytes / 1024 / 1024,
+                    min_free_bytes / 1024 / 1024
+                );
+            } else {
+                info!(
+                    "Disk space recovered: {} MiB free in the workdir.",
+                    free_bytes / 1024 / 1024
+                );
+            }
+        }
+        low
+    }
+
+    /// Checks the free space of the workdir periodically. Does nothing if the monitoring is
+    /// disabled.
+    pub async fn run(&self) {
+        let config = ServerConfig::get();
+        if config.min_free_disk_space == 0 {
+            return;
+        }
+        loop {
+            match free_space(&config.workdir) {
+                Ok(free_bytes) => {
+                    self.update(free_bytes, config.min_free_disk_space);
+                }
+                Err(e) => warn!("Failed checking the free disk space: {}", e),
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    }
+}
+
+/// The space available to unprivileged users on the file system of `path`, in bytes.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a valid C string and `stat` is only read if the call succeeded.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)] // The types differ between platforms.
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Checking the free disk space is only supported on Unix.",
+    ))
+}
+
+/// The health of the server. Also returns `200 OK` if it is degraded, since restarting it
+/// doesn't free up space. Returns `503 Service Unavailable` while no data is loaded, see
+/// [`SpriteCollab::is_ready`], with the error of the last refresh.
+pub fn make_healthz_response(sprite_collab: &SpriteCollab) -> Response<String> {
+    let monitor = sprite_collab.disk_monitor();
+    let low = monitor.is_low();
+    let ready = sprite_collab.is_ready();
+    let (status_code, status) = match (ready, low) {
+        (false, _) => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+        (true, true) => (StatusCode::OK, "degraded"),
+        (true, false) => (StatusCode::OK, "ok"),
+    };
+    make_json_response(
+        status_code,
+        json!({
+            "status": status,
+            "ready": ready,
+            "lastError": sprite_collab.with_meta(|meta| meta.last_error.clone()),
+            "commit": sprite_collab.data().assets_commit,
+            "disk": {
+                "freeBytes": monitor.free_bytes(),
+                "minFreeBytes": ServerConfig::get().min_free_disk_space,
+                "low": low,
+            },
+        })
+        .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_low_disk_space() {
+        let monitor = DiskMonitor::default();
+        assert_eq!(monitor.free_bytes(), None);
+        assert!(!monitor.is_low());
+        assert!(monitor.update(100, 1000));
+        assert!(monitor.is_low());
+        assert_eq!(monitor.free_bytes(), Some(100));
+        assert!(!monitor.update(1000, 1000));
+        assert!(!monitor.is_low());
+        assert!(free_space(&std::env::temp_dir()).unwrap() > 0);
+    }
+}
@@ -0,0 +1,154 @@
+//! Server-Sent Events at `/events`: Refreshes of the data, new commits and new contributions are
+//! sent to all connected clients as JSON, so bots can react to them without polling.
+
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::{stream, Stream};
+use hyper::body::{Body, Bytes, Frame};
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::http::HeaderValue;
+use hyper::Response;
+use log::warn;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::timeout;
+
+use crate::activity::find_activities;
+use crate::assets::fs_check::AssetCategory;
+use crate::assets::{make_box_body, AssetBody};
+use crate::datafiles::tracker::Tracker;
+
+pub const EVENTS_PATH: &str = "/events";
+/// Number of events kept for clients that are slow to read them.
+pub const EVENTS_CAPACITY: usize = 256;
+/// Interval of the comments sent to keep idle connections open.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ServerEvent {
+    /// The data was refreshed. `changed` is false if nothing changed since the last refresh.
+    Refresh {
+        commit: String,
+        changed: bool,
+        date: DateTime<Utc>,
+    },
+    /// A new commit of the SpriteCollab repository is served.
+    #[serde(rename_all = "camelCase")]
+    Commit {
+        commit: String,
+        previous_commit: String,
+    },
+    /// The portraits or sprites of a form were modified in the new commit.
+    #[serde(rename_all = "camelCase")]
+    Contribution {
+        monster_id: i32,
+        form_path: String,
+        category: AssetCategory,
+        /// The primary credit ID of the portraits or sprites.
+        credit: String,
+        modified_date: DateTime<Utc>,
+    },
+    /// Credit IDs of the tracker of the new commit are missing in the credit names.
+    #[serde(rename_all = "camelCase")]
+    UnknownCreditIds {
+        commit: String,
+        credit_ids: Vec<String>,
+    },
+}
+
+/// Contributions in `new`, see [`find_activities`].
+pub fn find_contributions(old: &Tracker, new: &Tracker) -> Vec<ServerEvent> {
+    find_activities(old, new)
+        .into_iter()
+        .map(|activity| ServerEvent::Contribution {
+            monster_id: activity.monster_id,
+            form_path: activity.form_path,
+            category: activity.category,
+            credit: activity.credit,
+            modified_date: activity.modified_date,
+        })
+        .collect()
+}
+
+/// Streams the events of `receiver` to the client, until it disconnects.
+pub fn make_events_response(receiver: broadcast::Receiver<ServerEvent>) -> Response<AssetBody> {
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        let chunk = match timeout(KEEP_ALIVE_INTERVAL, receiver.recv()).await {
+            Ok(Ok(event)) => match serde_json::to_string(&event) {
+                Ok(json) => format!("data: {}\n\n", json),
+                Err(e) => {
+                    warn!("Failed serializing event {:?}: {:?}", event, e);
+                    return Some((Bytes::new(), receiver));
+                }
+            },
+            Ok(Err(RecvError::Lagged(missed))) => format!(": missed {} events\n\n", missed),
+            Ok(Err(RecvError::Closed)) => return None,
+            Err(_) => ": keep-alive\n\n".to_string(),
+        };
+        Some((Bytes::from(chunk), receiver))
+    });
+    let mut response = Response::new(make_box_body(EventsBody(Mutex::new(Box::pin(events)))));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    response
+}
+
+/// The body of the event stream. The stream is only polled through `&mut`, the mutex just makes
+/// the body `Sync`.
+struct EventsBody(Mutex<Pin<Box<dyn Stream<Item = Bytes> + Send>>>);
+
+impl Body for EventsBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let events = self.get_mut().0.get_mut().unwrap();
+        events
+            .as_mut()
+            .poll_next(cx)
+            .map(|chunk| chunk.map(|chunk| Ok(Frame::data(chunk))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::snapshots::fixtures_dir;
+    use crate::datafiles::group_id::GroupId;
+    use crate::datafiles::tracker::read_tracker;
+
+    #[tokio::test]
+    async fn finds_newly_modified_forms() {
+        let old = read_tracker(fixtures_dir().join("spritecollab/tracker.json"))
+            .await
+            .unwrap();
+        let mut new = old.clone();
+        let group = new.get_mut(&GroupId(1)).unwrap();
+        let modified = group.portrait_modified.unwrap_or_default() + chrono::Duration::days(1);
+        group.portrait_modified = Some(modified);
+        group.portrait_credit.primary = "<@!1234>".to_string();
+
+        assert_eq!(find_contributions(&old, &old), vec![]);
+        assert_eq!(
+            find_contributions(&old, &new),
+            vec![ServerEvent::Contribution {
+                monster_id: 1,
+                form_path: "0001".to_string(),
+                category: AssetCategory::Portrait,
+                credit: "1234".to_string(),
+                modified_date: modified,
+            }]
+        );
+    }
+}
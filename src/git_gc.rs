@@ -0,0 +1,284 @@
+//! Garbage collection of the repository, configured with `SCSRV_GIT_GC_INTERVAL`.
+//!
+//! Every fetch adds a pack file, and the objects of commits that are no longer checked out are
+//! never removed. libgit2 has no `git gc`, so all objects reachable from `HEAD` and the
+//! references (including annotated tags) are written to a single new pack. Once the new pack
+//! was verified to contain all of them, all other packs and loose objects are superseded.
+//!
+//! Readers open the repository without a lock, eg. to serve blobs or the history of a form, and
+//! may still be reading from the superseded files. They are therefore only listed in
+//! `gc-superseded` in the git directory, and deleted (along with the reflogs) by the next
+//! garbage collection, an interval later.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Error};
+use git2::{Odb, Oid, Repository, Sort};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct GcStats {
+    /// Objects in the new pack.
+    pub objects: usize,
+    /// Size of the object database before the garbage collection, in bytes.
+    pub size_before: u64,
+    /// Size of the object database after the garbage collection, without the superseded files,
+    /// in bytes.
+    pub size_after: u64,
+}
+
+impl GcStats {
+    pub fn reclaimed(&self) -> u64 {
+        self.size_before.saturating_sub(self.size_after)
+    }
+}
+
+/// Lists the files the last garbage collection superseded, relative to the objects directory.
+const SUPERSEDED_FILE: &str = "gc-superseded";
+
+/// Deletes the files the last garbage collection superseded, repacks the objects of the
+/// repository at `repo_path` and marks everything else as superseded. Must not run at the same
+/// time as a fetch.
+pub fn collect_garbage(repo_path: &Path) -> Result<GcStats, Error> {
+    let repo = Repository::open(repo_path)?;
+    let objects_dir = repo.path().join("objects");
+    let pack_dir = objects_dir.join("pack");
+    delete_superseded(repo.path(), &objects_dir)?;
+    let size_before = dir_size(&objects_dir)?;
+    let packs_before = list_dir(&pack_dir)?;
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::NONE)?;
+    walk.push_head()?;
+    walk.push_glob("*")?;
+    let mut builder = repo.packbuilder()?;
+    builder.insert_walk(&mut walk)?;
+    // The walk only has the commits, not eg. the objects of annotated tags.
+    let targets = ref_targets(&repo)?;
+    for target in &targets {
+        builder.insert_recursive(*target, None)?;
+    }
+    let objects = builder.object_count();
+
+    let odb = repo.odb()?;
+    let mut writer = odb.packwriter()?;
+    let mut write_error = None;
+    let result = builder.foreach(|chunk| match writer.write_all(chunk) {
+        Ok(()) => true,
+        Err(e) => {
+            write_error = Some(e);
+            false
+        }
+    });
+    if let Some(e) = write_error {
+        return Err(e.into());
+    }
+    result?;
+    writer.commit()?;
+
+    // The name of a pack is the hash of its content. If no new pack was written, the objects
+    // are already packed exactly like this, and it's not known which of the packs it is.
+    let new_packs = list_dir(&pack_dir)?
+        .difference(&packs_before)
+        .cloned()
+        .collect::<HashSet<_>>();
+    if !new_packs.iter().any(|name| name.ends_with(".pack")) {
+        return Ok(GcStats {
+            objects,
+            size_before,
+            size_after: size_before,
+        });
+    }
+    verify_pack(repo.path(), &pack_dir, &new_packs, objects, &targets)?;
+    let mut superseded = packs_before
+        .iter()
+        .filter(|name| name.starts_with("pack-"))
+        .map(|name| format!("pack/{}", name))
+        .collect::<Vec<_>>();
+    for dir in loose_object_dirs(&objects_dir)? {
+        for name in list_dir(&objects_dir.join(&dir))? {
+            superseded.push(format!("{}/{}", dir, name));
+        }
+    }
+    let mut superseded_size = 0;
+    for path in &superseded {
+        superseded_size += fs::metadata(objects_dir.join(path))?.len();
+    }
+    fs::write(repo.path().join(SUPERSEDED_FILE), superseded.join("\n"))?;
+    odb.refresh()?;
+
+    Ok(GcStats {
+        objects,
+        size_before,
+        size_after: dir_size(&objects_dir)?.saturating_sub(superseded_size),
+    })
+}
+
+/// Deletes the files listed in the `gc-superseded` file of the last garbage collection, the
+/// loose object directories that are empty afterwards, and the reflogs, which point to commits
+/// whose objects were just deleted.
+fn delete_superseded(git_dir: &Path, objects_dir: &Path) -> Result<(), Error> {
+    let list_path = git_dir.join(SUPERSEDED_FILE);
+    let list = match fs::read_to_string(&list_path) {
+        Ok(list) => list,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for path in list.lines().filter(|path| !path.is_empty()) {
+        match fs::remove_file(objects_dir.join(path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    for dir in loose_object_dirs(objects_dir)? {
+        // Objects written since the last garbage collection are kept.
+        if list_dir(&objects_dir.join(&dir))?.is_empty() {
+            fs::remove_dir(objects_dir.join(dir))?;
+        }
+    }
+    let logs_dir = git_dir.join("logs");
+    if logs_dir.exists() {
+        fs::remove_dir_all(logs_dir)?;
+    }
+    fs::remove_file(list_path)?;
+    Ok(())
+}
+
+/// The directories of the loose objects in `objects_dir`, named after the first two hex digits
+/// of their objects.
+fn loose_object_dirs(objects_dir: &Path) -> io::Result<Vec<String>> {
+    Ok(list_dir(objects_dir)?
+        .into_iter()
+        .filter(|name| name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit()))
+        .collect())
+}
+
+/// The objects `HEAD` and all references point to.
+fn ref_targets(repo: &Repository) -> Result<Vec<Oid>, Error> {
+    let mut targets = vec![repo.head()?.peel_to_commit()?.id()];
+    for reference in repo.references()? {
+        // Symbolic references point to one of the other references.
+        if let Some(target) = reference?.target() {
+            targets.push(target);
+        }
+    }
+    Ok(targets)
+}
+
+/// Fails unless the files `new_packs` in `pack_dir` are a pack with `objects` objects, that has
+/// all of `targets`. The pack is opened on its own, in a temporary directory in `git_dir`.
+fn verify_pack(
+    git_dir: &Path,
+    pack_dir: &Path,
+    new_packs: &HashSet<String>,
+    objects: usize,
+    targets: &[Oid],
+) -> Result<(), Error> {
+    let verify_dir = git_dir.join("gc-verify");
+    if verify_dir.exists() {
+        fs::remove_dir_all(&verify_dir)?;
+    }
+    let verify_pack_dir = verify_dir.join("pack");
+    fs::create_dir_all(&verify_pack_dir)?;
+    let result = (|| {
+        for name in new_packs {
+            if name.ends_with(".pack") || name.ends_with(".idx") {
+                fs::copy(pack_dir.join(name), verify_pack_dir.join(name))?;
+            }
+        }
+        let odb = Odb::new()?;
+        odb.add_disk_alternate(&verify_dir.to_string_lossy())?;
+        let mut count = 0;
+        odb.foreach(|_| {
+            count += 1;
+            true
+        })?;
+        if count != objects {
+            return Err(anyhow!(
+                "The new pack has {} objects instead of {}.",
+                count,
+                objects
+            ));
+        }
+        if let Some(missing) = targets.iter().find(|target| !odb.exists(**target)) {
+            return Err(anyhow!("The new pack is missing {}.", missing));
+        }
+        Ok(())
+    })();
+    fs::remove_dir_all(&verify_dir)?;
+    result
+}
+
+fn list_dir(path: &Path) -> io::Result<HashSet<String>> {
+    fs::read_dir(path)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect()
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::unique_temp_path;
+    use git2::{ObjectType, Signature};
+
+    #[test]
+    fn removes_unreachable_objects() {
+        let dir = unique_temp_path("scsrv-git-gc-test");
+        let repo = Repository::init(&dir).unwrap();
+        let odb = repo.odb().unwrap();
+        let blob = odb.write(ObjectType::Blob, b"tracked").unwrap();
+        let unreachable = odb.write(ObjectType::Blob, b"unreachable").unwrap();
+        let mut tree = repo.treebuilder(None).unwrap();
+        tree.insert("tracker.json", blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &signature, &signature, "Test", &tree, &[])
+            .unwrap();
+        let tag = repo
+            .tag(
+                "v1",
+                &repo.find_object(commit, None).unwrap(),
+                &signature,
+                "Release",
+                false,
+            )
+            .unwrap();
+
+        let stats = collect_garbage(&dir).unwrap();
+        assert_eq!(stats.objects, 4);
+        // Readers may still use the superseded loose objects until the next run.
+        let loose_unreachable = dir
+            .join(".git/objects")
+            .join(&unreachable.to_string()[..2])
+            .join(&unreachable.to_string()[2..]);
+        assert!(loose_unreachable.exists());
+        assert!(dir.join(".git").join(SUPERSEDED_FILE).exists());
+
+        collect_garbage(&dir).unwrap();
+        assert!(!loose_unreachable.exists());
+        assert!(!dir.join(".git").join(SUPERSEDED_FILE).exists());
+        let odb = repo.odb().unwrap();
+        assert!(odb.exists(commit));
+        assert!(odb.exists(tag));
+        assert!(odb.exists(blob));
+        assert!(!odb.exists(unreachable));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,654 @@
+//! Handling of GraphQL requests, including support for Automatic Persisted Queries (APQ).
+//!
+//! Clients may send the SHA-256 hash of a query in `extensions.persistedQuery.sha256Hash`
+//! instead of the query itself. If the hash is not known yet, a `PersistedQueryNotFound`
+//! error is returned and the client is expected to send the query and hash together, which
+//! registers it. Registered queries are kept when the data is refreshed.
+//!
+//! The schema is also available in the GraphQL schema language at `/graphql/schema.sdl`. A
+//! snapshot of it is kept in `schema.graphql`, which is checked by the tests.
+
+use std::sync::Arc;
+
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::header::CONTENT_TYPE;
+use hyper::http::HeaderValue;
+use hyper::{Method, Request, Response, StatusCode};
+use juniper::http::GraphQLBatchRequest;
+use juniper::{EmptyMutation, EmptySubscription, RootNode};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::assets::util::parse_query;
+use crate::cache::{CacheBehaviour, ScCache};
+use crate::schema::{Context, Query, API_VERSION};
+use crate::sprite_collab::VERSIONED_KEY_PREFIX;
+use crate::SpriteCollab;
+
+pub type Schema = RootNode<'static, Query, EmptyMutation<Context>, EmptySubscription<Context>>;
+
+/// Path of the schema snapshot, relative to the crate root.
+pub const SCHEMA_SNAPSHOT_FILE: &str = "schema.graphql";
+
+pub fn make_schema() -> Schema {
+    RootNode::new(
+        Query,
+        EmptyMutation::<Context>::new(),
+        EmptySubscription::<Context>::new(),
+    )
+}
+
+/// The schema in the GraphQL schema language, with the API version in a comment on the first
+/// line.
+pub fn schema_sdl(root_node: &Schema) -> String {
+    format!("# API version: {}\n{}", API_VERSION, root_node.as_sdl())
+}
+
+/// Responds with the schema in the GraphQL schema language.
+pub fn make_schema_sdl_response(root_node: &Schema) -> Response<String> {
+    let mut response = Response::new(schema_sdl(root_node));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+/// Handles a GraphQL GET or POST request.
+pub async fn graphql(
+    root_node: Arc<Schema>,
+    sprite_collab: Arc<SpriteCollab>,
+    req: Request<Incoming>,
+) -> Response<String> {
+    let mut request = match read_request(req).await {
+        Ok(request) => request,
+        Err(e) => return make_error_response(StatusCode::BAD_REQUEST, &e, None),
+    };
+
+    let operations: Vec<&mut Value> = match &mut request {
+        Value::Array(operations) => operations.iter_mut().collect(),
+        operation => vec![operation],
+    };
+    for operation in operations {
+        match resolve_persisted_query(&sprite_collab, operation).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return make_error_response(
+                    StatusCode::OK,
+                    "PersistedQueryNotFound",
+                    Some("PERSISTED_QUERY_NOT_FOUND"),
+                )
+            }
+            Err(e) => return make_error_response(StatusCode::BAD_REQUEST, &e, None),
+        }
+    }
+
+    let batch: GraphQLBatchRequest = match serde_json::from_value(request) {
+        Ok(batch) => batch,
+        Err(e) => {
+            return make_error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid GraphQL request: {e}"),
+                None,
+            )
+        }
+    };
+    let ctx = Context::new(sprite_collab);
+    let response = batch.execute(&*root_node, &ctx).await;
+    let status = if response.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    match serde_json::to_string(&response) {
+        Ok(body) => make_json_response(status, body),
+        Err(e) => make_error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string(), None),
+    }
+}
+
+/// Reads the GraphQL request (or batch of requests) as JSON from the query parameters
+/// (GET) or the body (POST).
+async fn read_request(req: Request<Incoming>) -> Result<Value, String> {
+    if req.method() == Method::GET {
+        let mut request = Map::new();
+        for (key, value) in parse_query(req.uri().query()) {
+            match key.as_str() {
+                "query" | "operationName" => {
+                    request.insert(key, Value::String(value));
+                }
+                "variables" | "extensions" => {
+                    let parsed = serde_json::from_str(&value)
+                        .map_err(|e| format!("Invalid {key} parameter: {e}"))?;
+                    request.insert(key, parsed);
+                }
+                _ => {}
+            }
+        }
+        return Ok(Value::Object(request));
+    }
+
+    let is_raw_query = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|content_type| content_type.starts_with("application/graphql"))
+        .unwrap_or_default();
+    let body = req
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| format!("Failed reading request body: {e}"))?
+        .to_bytes();
+    if is_raw_query {
+        let query =
+            String::from_utf8(body.to_vec()).map_err(|e| format!("Invalid request body: {e}"))?;
+        Ok(json!({ "query": query }))
+    } else {
+        serde_json::from_slice(&body).map_err(|e| format!("Invalid request body: {e}"))
+    }
+}
+
+/// If the operation references a persisted query, either registers the query (if sent along)
+/// or fills in the query from the cache. Returns false if the persisted query is not known.
+async fn resolve_persisted_query(
+    sprite_collab: &SpriteCollab,
+    operation: &mut Value,
+) -> Result<bool, String> {
+    let hash = match operation
+        .pointer("/extensions/persistedQuery/sha256Hash")
+        .and_then(Value::as_str)
+    {
+        Some(hash) => hash.to_string(),
+        None => return Ok(true),
+    };
+    // The query does not depend on the data, so it must survive the flush of refreshes.
+    let cache_key = format!("{}apq|{}", VERSIONED_KEY_PREFIX, hash);
+    match operation.get("query").and_then(Value::as_str) {
+        Some(query) => {
+            if format!("{:x}", Sha256::digest(query.as_bytes())) != hash {
+                return Err("provided sha does not match query".to_string());
+            }
+            let query = query.to_string();
+            sprite_collab
+                .cached(cache_key, || async move { CacheBehaviour::Cache(query) })
+                .await
+                .map_err(|e| format!("Failed storing persisted query: {e}"))?;
+            Ok(true)
+        }
+        None => {
+            let lookup: Result<String, ()> = sprite_collab
+                .cached_may_fail(cache_key, || async { Err(()) })
+                .await
+                .map_err(|e| format!("Failed looking up persisted query: {e}"))?;
+            match (lookup, operation.as_object_mut()) {
+                (Ok(query), Some(operation)) => {
+                    operation.insert("query".to_string(), Value::String(query));
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        }
+    }
+}
+
+pub(crate) fn make_json_response(status: StatusCode, body: String) -> Response<String> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn make_error_response(status: StatusCode, message: &str, code: Option<&str>) -> Response<String> {
+    let error = match code {
+        Some(code) => json!({ "message": message, "extensions": { "code": code } }),
+        None => json!({ "message": message }),
+    };
+    make_json_response(status, json!({ "errors": [error] }).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::{fs, io};
+
+    use juniper::http::GraphQLRequest;
+
+    use super::*;
+    use crate::testing::context;
+
+    /// Set to rewrite the schema snapshot instead of failing.
+    const UPDATE_SNAPSHOTS: &str = "UPDATE_SNAPSHOTS";
+
+    /// Executes a query and returns the response as JSON.
+    async fn execute(context: &Context, query: &str) -> Value {
+        let request = GraphQLRequest::new(query.to_string(), None, None);
+        serde_json::to_value(request.execute(&make_schema(), context).await).unwrap()
+    }
+
+    /// Adds the forms `0001/0000` and `0001/0000/0001` (shiny) and the monster `0002` to the
+    /// sample tracker.
+    fn add_forms(tracker: &mut Value) {
+        let mut shiny = tracker["0001"].clone();
+        shiny["name"] = json!("Shiny");
+        let mut normal = tracker["0001"].clone();
+        normal["name"] = json!("");
+        normal["subgroups"] = json!({ "0001": shiny });
+        let mut second = tracker["0001"].clone();
+        second["name"] = json!("Secondmon");
+        tracker["0001"]["subgroups"] = json!({ "0000": normal });
+        tracker["0002"] = second;
+    }
+
+    fn error_code(response: &Value) -> &Value {
+        &response["errors"][0]["extensions"]["code"]
+    }
+
+    /// Fails if the schema differs from the snapshot. With `UPDATE_SNAPSHOTS=1`, the snapshot is
+    /// rewritten instead, but only if [`API_VERSION`] was bumped.
+    #[test]
+    fn schema_snapshot_matches_api_version() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SCHEMA_SNAPSHOT_FILE);
+        let sdl = schema_sdl(&make_schema());
+        let snapshot = match fs::read_to_string(&path) {
+            Ok(snapshot) => snapshot,
+            Err(e) if std::env::var_os(UPDATE_SNAPSHOTS).is_some() => {
+                assert_eq!(e.kind(), io::ErrorKind::NotFound, "{}", e);
+                String::new()
+            }
+            Err(e) => panic!(
+                "Failed reading {}: {}. Run the tests with {}=1 to create it.",
+                SCHEMA_SNAPSHOT_FILE, e, UPDATE_SNAPSHOTS
+            ),
+        };
+        if snapshot == sdl {
+            return;
+        }
+        let snapshot_version = snapshot
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("# API version: "));
+        assert_ne!(
+            snapshot_version,
+            Some(API_VERSION),
+            "The schema changed, but API_VERSION was not bumped. Bump it to update {}.",
+            SCHEMA_SNAPSHOT_FILE
+        );
+        assert!(
+            std::env::var_os(UPDATE_SNAPSHOTS).is_some(),
+            "The schema changed. Run the tests with {}=1 to update {}.",
+            UPDATE_SNAPSHOTS,
+            SCHEMA_SNAPSHOT_FILE
+        );
+        fs::write(&path, sdl).unwrap();
+    }
+
+    #[tokio::test]
+    async fn canonicalizes_emotions_and_actions() {
+        let (context, _) = context(|_| {}).await;
+        let response = execute(
+            &context,
+            r#"{ monster(filter: [1]) { forms {
+                portraits {
+                    emotion(emotion: "happy") { emotion url }
+                    emotionFlipped(emotion: "NORMAL") { emotion url }
+                }
+                sprites { action(action: "idle") { ... on Sprite { action animUrl } } }
+            } } }"#,
+        )
+        .await;
+        let form = &response["data"]["monster"][0]["forms"][0];
+        let happy = &form["portraits"]["emotion"];
+        assert_eq!(happy["emotion"], "Happy");
+        assert!(happy["url"]
+            .as_str()
+            .unwrap()
+            .ends_with("/portrait/0001/Happy.png"));
+        let flipped = &form["portraits"]["emotionFlipped"];
+        assert_eq!(flipped["emotion"], "Normal^");
+        assert!(flipped["url"]
+            .as_str()
+            .unwrap()
+            .ends_with("/portrait/0001/Normal^.png"));
+        let idle = &form["sprites"]["action"];
+        assert_eq!(idle["action"], "Idle");
+        assert!(idle["animUrl"]
+            .as_str()
+            .unwrap()
+            .ends_with("/sprite/0001/Idle-Anim.png"));
+    }
+
+    #[tokio::test]
+    async fn resolves_monsters() {
+        let (context, _) = context(add_forms).await;
+        // `0001/0000` is the base form again, it is not listed separately.
+        let response = execute(
+            &context,
+            "{ monster(filter: [2, 1]) { monsterId rawId name forms { fullPath isShiny } } }",
+        )
+        .await;
+        assert_eq!(
+            response["data"]["monster"],
+            json!([
+                {
+                    "monsterId": 1,
+                    "rawId": "0001",
+                    "name": "Fixturemon",
+                    "forms": [
+                        { "fullPath": "0001", "isShiny": false },
+                        { "fullPath": "0001/0000/0001", "isShiny": true }
+                    ]
+                },
+                { "monsterId": 2, "rawId": "0002", "name": "Secondmon", "forms": [{ "fullPath": "0002", "isShiny": false }] }
+            ])
+        );
+
+        let response = execute(&context, "{ monster(filter: [3]) { monsterId } }").await;
+        assert_eq!(response["data"]["monster"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn searches_monsters() {
+        let (context, cache) = context(add_forms).await;
+        let lookups = cache.hits() + cache.misses();
+        let query = r#"{ searchMonster(monsterName: "mon") { monsterId } }"#;
+        let response = execute(&context, query).await;
+        assert_eq!(
+            response["data"]["searchMonster"],
+            json!([{ "monsterId": 1 }, { "monsterId": 2 }])
+        );
+        // The search index is built with the data, searching needs no cache.
+        assert_eq!(cache.hits() + cache.misses(), lookups);
+
+        let response = execute(
+            &context,
+            r#"{ searchMonster(monsterName: "mon", first: 1, offset: 1) { monsterId } }"#,
+        )
+        .await;
+        assert_eq!(
+            response["data"]["searchMonster"],
+            json!([{ "monsterId": 2 }])
+        );
+        let response = execute(
+            &context,
+            r#"{ searchMonster(monsterName: "mon", first: 101) { monsterId } }"#,
+        )
+        .await;
+        assert_eq!(error_code(&response), "INVALID_ARGUMENT");
+    }
+
+    #[tokio::test]
+    async fn collapses_form_paths() {
+        let (context, _) = context(add_forms).await;
+        for (path, expected) in [
+            ("0001", "0001"),
+            ("0001/0000", "0001"),
+            ("0001/0000/0000", "0001"),
+            ("1/0/1", "0001/0000/0001"),
+            ("0001/0000/0001/0000", "0001/0000/0001"),
+        ] {
+            let response = execute(
+                &context,
+                &format!(r#"{{ monsterForm(fullPath: "{}") {{ fullPath }} }}"#, path),
+            )
+            .await;
+            assert_eq!(
+                response["data"]["monsterForm"]["fullPath"], expected,
+                "{} was not collapsed to {}",
+                path, expected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn returns_error_codes() {
+        let (context, _) = context(add_forms).await;
+        for (query, code) in [
+            (
+                r#"{ monsterForm(fullPath: "0001/0002") { fullPath } }"#,
+                "NOT_FOUND",
+            ),
+            (
+                r#"{ monsterForm(fullPath: "0999") { fullPath } }"#,
+                "NOT_FOUND",
+            ),
+            (
+                r#"{ monsterForm(fullPath: "0001/abc") { fullPath } }"#,
+                "INVALID_PATH",
+            ),
+            (
+                r#"{ monsterForm(fullPath: "/") { fullPath } }"#,
+                "INVALID_PATH",
+            ),
+        ] {
+            let response = execute(&context, query).await;
+            assert_eq!(error_code(&response), code, "{}", query);
+        }
+        let response = execute(
+            &context,
+            &format!(
+                r#"{{ searchMonster(monsterName: "{}") {{ monsterId }} }}"#,
+                "a".repeat(76)
+            ),
+        )
+        .await;
+        assert_eq!(error_code(&response), "INVALID_ARGUMENT");
+    }
+
+    #[tokio::test]
+    async fn persisted_queries_survive_refreshes() {
+        let (context, _) = context(|_| {}).await;
+        let query = "{ monster(filter: [1]) { name } }";
+        let hash = format!("{:x}", Sha256::digest(query.as_bytes()));
+        let mut operation = json!({
+            "query": query,
+            "extensions": { "persistedQuery": { "version": 1, "sha256Hash": hash } }
+        });
+        assert_eq!(
+            resolve_persisted_query(&context.collab, &mut operation).await,
+            Ok(true)
+        );
+
+        context.collab.flush_except_versioned().await;
+        let mut operation = json!({
+            "extensions": { "persistedQuery": { "version": 1, "sha256Hash": hash } }
+        });
+        assert_eq!(
+            resolve_persisted_query(&context.collab, &mut operation).await,
+            Ok(true)
+        );
+        assert_eq!(operation["query"], query);
+    }
+
+    #[tokio::test]
+    async fn lists_bounties() {
+        let (context, _) = context(|tracker| {
+            add_forms(tracker);
+            tracker["0001"]["portrait_bounty"] = json!({ "3": 5, "1": 10 });
+            tracker["0002"]["sprite_bounty"] = json!({ "2": 30 });
+        })
+        .await;
+        let response = execute(
+            &context,
+            "{ bounties { form { fullPath } category bounty { total map { phase bounty } } } }",
+        )
+        .await;
+        assert_eq!(
+            response["data"]["bounties"],
+            json!([
+                {
+                    "form": { "fullPath": "0002" },
+                    "category": "SPRITE",
+                    "bounty": { "total": 30, "map": [{ "phase": 2, "bounty": 30 }] }
+                },
+                {
+                    "form": { "fullPath": "0001" },
+                    "category": "PORTRAIT",
+                    "bounty": {
+                        "total": 15,
+                        "map": [{ "phase": 1, "bounty": 10 }, { "phase": 3, "bounty": 5 }]
+                    }
+                }
+            ])
+        );
+
+        let response = execute(
+            &context,
+            "{ bounties(minAmount: 20) { form { fullPath } } portraits: bounties(category: PORTRAIT) { form { fullPath } } }",
+        )
+        .await;
+        assert_eq!(
+            response["data"]["bounties"],
+            json!([{ "form": { "fullPath": "0002" } }])
+        );
+        assert_eq!(
+            response["data"]["portraits"],
+            json!([{ "form": { "fullPath": "0001" } }])
+        );
+    }
+
+    #[tokio::test]
+    async fn tolerates_typos_in_searches() {
+        let (context, _) = context(add_forms).await;
+        let response = execute(
+            &context,
+            r#"{
+                searchMonster(monsterName: "Secnodmon") { monsterId }
+                typo: didYouMean(query: "Secnodmon")
+                known: didYouMean(query: "secondmon")
+            }"#,
+        )
+        .await;
+        assert_eq!(
+            response["data"]["searchMonster"],
+            json!([{ "monsterId": 2 }])
+        );
+        assert_eq!(response["data"]["typo"], json!("Secondmon"));
+        assert_eq!(response["data"]["known"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn returns_search_matches() {
+        let (context, _) = context(add_forms).await;
+        let response = execute(
+            &context,
+            r#"{ searchMonsterMatches(monsterName: "scndmn") {
+                monster { monsterId } matchedName matchedIndices
+            } }"#,
+        )
+        .await;
+        assert_eq!(
+            response["data"]["searchMonsterMatches"],
+            json!([{
+                "monster": { "monsterId": 2 },
+                "matchedName": "Secondmon",
+                "matchedIndices": [0, 2, 4, 5, 6, 8]
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_modified_assets_of_history() {
+        let (context, _) = context(|_| {}).await;
+        let response = execute(
+            &context,
+            r#"{ monster(filter: [1]) { forms {
+                portraits { history { modifiedAssets { name portrait { emotion } sprite { __typename } } } }
+                sprites { history { modifiedAssets { name portrait { emotion } } } }
+            } } }"#,
+        )
+        .await;
+        let form = &response["data"]["monster"][0]["forms"][0];
+        assert_eq!(
+            form["portraits"]["history"][0]["modifiedAssets"],
+            json!([
+                { "name": "Normal", "portrait": { "emotion": "Normal" }, "sprite": null },
+                { "name": "Happy", "portrait": { "emotion": "Happy" }, "sprite": null }
+            ])
+        );
+        // The sprites have no actions named like the emotions.
+        assert_eq!(
+            form["sprites"]["history"][0]["modifiedAssets"],
+            json!([
+                { "name": "Normal", "portrait": null },
+                { "name": "Happy", "portrait": null }
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn aggregates_relevant_credits() {
+        let (context, _) = context(|_| {}).await;
+        let response = execute(
+            &context,
+            r#"{ monster(filter: [1]) { forms {
+                portraits { relevantCredits { credit { creditId } items } }
+            } } }"#,
+        )
+        .await;
+        assert_eq!(
+            response["data"]["monster"][0]["forms"][0]["portraits"]["relevantCredits"],
+            json!([{ "credit": { "creditId": "FixtureAuthor" }, "items": ["Normal", "Happy"] }])
+        );
+    }
+
+    #[tokio::test]
+    async fn refetches_nodes() {
+        let (context, _) = context(add_forms).await;
+        let response = execute(
+            &context,
+            r#"{ monster(filter: [2]) { id forms { id } } credit { id } }"#,
+        )
+        .await;
+        assert_eq!(response["data"]["monster"][0]["id"], "Monster:2");
+        assert_eq!(
+            response["data"]["monster"][0]["forms"][0]["id"],
+            "MonsterForm:0002"
+        );
+        assert_eq!(response["data"]["credit"][0]["id"], "Credit:FixtureAuthor");
+
+        let response = execute(
+            &context,
+            r#"{
+                monster: node(id: "Monster:2") { __typename ... on Monster { name } }
+                form: node(id: "MonsterForm:0002") { ... on MonsterForm { name } }
+                credit: node(id: "Credit:FixtureAuthor") { id ... on Credit { unresolved } }
+                missing: node(id: "Monster:9999") { id }
+                missingCredit: node(id: "Credit:NoSuchAuthor") { id }
+            }"#,
+        )
+        .await;
+        assert_eq!(
+            response["data"],
+            json!({
+                "monster": { "__typename": "Monster", "name": "Secondmon" },
+                "form": { "name": "Secondmon" },
+                "credit": { "id": "Credit:FixtureAuthor", "unresolved": false },
+                "missing": null,
+                "missingCredit": null
+            })
+        );
+
+        let response = execute(&context, r#"{ node(id: "Portrait:0002") { id } }"#).await;
+        assert_eq!(error_code(&response), "INVALID_ARGUMENT");
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_commits_of_form_changes() {
+        let (context, _) = context(|_| {}).await;
+        let query = |from: &str| {
+            format!(
+                r#"{{ formChanges(monsterId: 1, formPath: "", fromCommit: "{}", toCommit: "abcdef12") {{
+                    changedEmotions
+                }} }}"#,
+                from
+            )
+        };
+        let response = execute(&context, &query("HEAD~1")).await;
+        assert_eq!(error_code(&response), "INVALID_ARGUMENT");
+        let response = execute(&context, &query("0123456789")).await;
+        assert_eq!(error_code(&response), "NOT_FOUND");
+    }
+}
@@ -0,0 +1,161 @@
+//! The interactive GraphQL IDE served at `/`, if enabled with `SCSRV_GRAPHQL_IDE`: GraphiQL,
+//! GraphQL Playground or Apollo Sandbox. The IDE can be protected with basic auth
+//! (`SCSRV_GRAPHQL_IDE_BASIC_AUTH`), for deployments that must not expose it publicly.
+//!
+//! The page is served with a `Content-Security-Policy` that only allows the scripts of the
+//! page itself, which carry a new nonce on every request.
+
+use std::str::FromStr;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use hyper::header::{AUTHORIZATION, CONTENT_SECURITY_POLICY, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::{HeaderMap, Response, StatusCode};
+use juniper::http::graphiql::graphiql_source;
+use juniper::http::playground::playground_source;
+
+use crate::ServerConfig;
+
+const GRAPHQL_PATH: &str = "/graphql";
+const SANDBOX_SCRIPT_URL: &str =
+    "https://embeddable-sandbox.cdn.apollographql.com/_latest/embeddable-sandbox.umd.production.min.js";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GraphqlIde {
+    Graphiql,
+    Playground,
+    /// Apollo Sandbox, loaded from the CDN of Apollo.
+    Sandbox,
+}
+
+impl FromStr for GraphqlIde {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "graphiql" => Ok(GraphqlIde::Graphiql),
+            "playground" => Ok(GraphqlIde::Playground),
+            "sandbox" => Ok(GraphqlIde::Sandbox),
+            _ => Err(format!(
+                "expected graphiql, playground or sandbox, got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+impl GraphqlIde {
+    fn source(&self) -> String {
+        match self {
+            GraphqlIde::Graphiql => graphiql_source(GRAPHQL_PATH, None),
+            GraphqlIde::Playground => playground_source(GRAPHQL_PATH, None),
+            GraphqlIde::Sandbox => format!(
+                r##"<!DOCTYPE html>
+<html>
+<head><title>SpriteCollab GraphQL</title></head>
+<body style="margin: 0">
+<div id="sandbox" style="width: 100vw; height: 100vh"></div>
+<script src="{}"></script>
+<script>
+new window.EmbeddedSandbox({{ target: "#sandbox", initialEndpoint: "{}{}" }});
+</script>
+</body>
+</html>"##,
+                SANDBOX_SCRIPT_URL,
+                ServerConfig::get().this_server_url(),
+                GRAPHQL_PATH
+            ),
+        }
+    }
+}
+
+/// Serves the configured IDE, `None` if it is disabled.
+pub fn make_graphql_ide_response(request_headers: &HeaderMap) -> Option<Response<String>> {
+    let config = ServerConfig::get();
+    let ide = config.graphql_ide?;
+    if let Some(credentials) = &config.graphql_ide_basic_auth {
+        if !is_authorized(request_headers, credentials) {
+            return Some(
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(WWW_AUTHENTICATE, "Basic realm=\"GraphQL IDE\"")
+                    .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                    .body(String::from(
+                        "<html><body><h1>Unauthorized</h1><img src=\"https://http.cat/401\"></body></html>",
+                    ))
+                    .unwrap(),
+            );
+        }
+    }
+    let nonce = make_nonce();
+    Some(
+        Response::builder()
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(
+                CONTENT_SECURITY_POLICY,
+                format!(
+                    "script-src 'nonce-{}' 'strict-dynamic'; object-src 'none'; base-uri 'none'",
+                    nonce
+                ),
+            )
+            .body(add_nonce(&ide.source(), &nonce))
+            .unwrap(),
+    )
+}
+
+/// Whether the request has the basic auth `credentials` (`user:password`).
+fn is_authorized(request_headers: &HeaderMap, credentials: &str) -> bool {
+    let expected = format!("Basic {}", BASE64_STANDARD.encode(credentials));
+    match request_headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(given) if given.len() == expected.len() => {
+            given
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+        }
+        _ => false,
+    }
+}
+
+/// Adds the nonce to all scripts of the page.
+fn add_nonce(html: &str, nonce: &str) -> String {
+    html.replace("<script", &format!("<script nonce=\"{}\"", nonce))
+}
+
+/// 128 random bits from the operating system, base64 encoded.
+fn make_nonce() -> String {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("The system random number generator failed.");
+    BASE64_STANDARD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn checks_basic_auth() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_authorized(&headers, "Aladdin:open sesame"));
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_static("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="),
+        );
+        assert!(is_authorized(&headers, "Aladdin:open sesame"));
+        assert!(!is_authorized(&headers, "Aladdin:close sesame"));
+    }
+
+    #[test]
+    fn adds_nonces_to_scripts() {
+        let nonce = make_nonce();
+        assert_eq!(nonce.len(), 24);
+        assert_ne!(nonce, make_nonce());
+        let html = add_nonce(&GraphqlIde::Graphiql.source(), &nonce);
+        assert!(html.contains(&format!("<script nonce=\"{}\"", nonce)));
+        assert!(!html.contains("<script>"));
+    }
+}
@@ -0,0 +1,27 @@
+//! The HTTP client of the outgoing requests of the server: the webhooks, the requests of a
+//! mirror to its primary and the uploads to the object storage. It supports `http` and `https`
+//! URLs, certificates are verified against the Mozilla root certificates.
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use once_cell::sync::OnceCell;
+
+pub type HttpClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+static CLIENT: OnceCell<HttpClient> = OnceCell::new();
+
+/// The client, shared so the requests reuse connections.
+pub fn http_client() -> &'static HttpClient {
+    CLIENT.get_or_init(|| {
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Client::builder(TokioExecutor::new()).build(connector)
+    })
+}
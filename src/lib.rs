@@ -0,0 +1,41 @@
+//! SpriteCollab Rust GraphQL Server.
+//!
+//! The server itself is in `main.rs`, this library contains everything else so it can also be
+//! used by the examples and tests.
+#![forbid(unused_must_use)]
+
+pub mod activity;
+#[cfg(feature = "activity-store")]
+pub mod activity_backfill;
+pub mod activity_exceptions;
+#[cfg(feature = "activity-store")]
+pub mod activity_store;
+pub mod admin;
+pub mod api;
+pub mod api_keys;
+pub mod assets;
+pub mod cache;
+pub mod compression;
+pub mod config;
+pub mod cors;
+pub mod datafiles;
+pub mod disk_monitor;
+pub mod events;
+pub mod git_gc;
+pub mod graphql;
+pub mod graphql_ide;
+pub mod http_client;
+pub mod mirror;
+pub mod node_id;
+pub mod openapi;
+pub mod scheduler;
+pub mod schema;
+pub mod search;
+pub mod service;
+pub mod sprite_collab;
+#[cfg(test)]
+pub mod testing;
+pub mod webhooks;
+
+pub use config::ServerConfig;
+pub use sprite_collab::SpriteCollab;
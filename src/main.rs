@@ -1,78 +1,89 @@
 //! SpriteCollab Rust GraphQL Server.
 //!
-//! Access `/ for GraphiQL.
+//! Access `/` for GraphiQL, if enabled with `SCSRV_GRAPHQL_IDE`.
 #![forbid(unused_must_use)]
 
+use std::env::args;
+use std::io;
 use std::net::SocketAddr;
 use std::pin::pin;
+use std::process::exit;
 use std::sync::Mutex;
 use std::time::Duration;
 use std::{convert::Infallible, sync::Arc};
 
-use http_body_util::Empty;
-use hyper::body::Bytes;
 use hyper::http::HeaderValue;
 use hyper::{service::service_fn, Method, Response, StatusCode};
-use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
 use hyper_util::server::conn::auto;
 use hyper_util::server::graceful::GracefulShutdown;
-use juniper::{EmptyMutation, EmptySubscription, RootNode};
 use log::{info, warn};
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
 
-use crate::assets::{make_box_body, match_and_process_assets_path};
-use crate::config::Config;
-use crate::scheduler::DataRefreshScheduler;
-use crate::schema::{Context, Query};
-use crate::sprite_collab::SpriteCollab;
-
-mod assets;
-mod cache;
-mod config;
-mod datafiles;
-mod scheduler;
-mod schema;
-mod search;
-mod sprite_collab;
-
-const PORT: u16 = 3000;
+use spritecollab_srv::admin::{make_admin_response, ADMIN_PREFIX};
+use spritecollab_srv::api::{
+    make_api_response, make_tracker_export_response, API_PREFIX, TRACKER_EXPORT_PATH,
+};
+use spritecollab_srv::api_keys::check_api_key;
+use spritecollab_srv::assets::bundle::make_bundle_response;
+use spritecollab_srv::assets::{make_box_body, match_and_process_assets_path, AssetBody};
+use spritecollab_srv::compression::{compress_response, Encoding};
+use spritecollab_srv::cors::{apply_cors_headers, make_http_options_response};
+use spritecollab_srv::disk_monitor::{make_healthz_response, HEALTHZ_PATH};
+use spritecollab_srv::events::{make_events_response, EVENTS_PATH};
+use spritecollab_srv::graphql::{graphql, make_schema, make_schema_sdl_response};
+use spritecollab_srv::graphql_ide::make_graphql_ide_response;
+use spritecollab_srv::openapi::{make_openapi_response, OPENAPI_PATH};
+use spritecollab_srv::scheduler::DataRefreshScheduler;
+use spritecollab_srv::{ServerConfig, SpriteCollab};
 
 #[tokio::main]
 async fn main() {
-    Config::init();
-    Config::check();
+    let config = match ServerConfig::init() {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("The configuration is invalid:\n{}", errors);
+            exit(1);
+        }
+    };
+    if args().any(|arg| arg == "--check-config") {
+        println!("The configuration is valid.");
+        return;
+    }
     pretty_env_logger::init_timed();
+    if args().nth(1).as_deref() == Some("backfill-activities") {
+        exit(backfill_activities(config).await);
+    }
 
-    let sprite_collab = SpriteCollab::new(Config::redis_config()).await;
+    let sprite_collab = SpriteCollab::new(config.redis_config()).await;
 
     let scheduler = Arc::new(Mutex::new(DataRefreshScheduler::new(sprite_collab.clone())));
 
-    let addr: SocketAddr = ([0, 0, 0, 0], PORT).into();
+    let addr = config.listen_address;
 
-    let ctx = Arc::new(Context::new(sprite_collab.clone()));
-    let root_node = Arc::new(RootNode::new(
-        Query,
-        EmptyMutation::<Context>::new(),
-        EmptySubscription::<Context>::new(),
-    ));
+    let root_node = Arc::new(make_schema());
 
     let listener = TcpListener::bind(addr)
         .await
         .expect("expected to listen on address");
     let graceful = GracefulShutdown::new();
-    let server = Arc::new(auto::Builder::new(TokioExecutor::new()));
+    let server = Arc::new(make_server_builder(config));
+    let connection_limit = Arc::new(Semaphore::new(
+        config.max_connections.unwrap_or(Semaphore::MAX_PERMITS),
+    ));
 
     let mut ctrl_c = pin!(tokio::signal::ctrl_c());
 
     info!("GraphQL server started.");
     loop {
         let root_node = root_node.clone();
-        let ctx = ctx.clone();
         let sprite_collab = sprite_collab.clone();
         let server = server.clone();
 
         tokio::select! {
-            conn = listener.accept() => {
+            (conn, permit) = accept_connection(&listener, connection_limit.clone()) => {
                 let (stream, _) = match conn {
                     Ok(v) => v,
                     Err(e) => {
@@ -84,23 +95,31 @@ async fn main() {
                 let io = TokioIo::new(stream);
 
                 tokio::spawn(async move {
+                    // Hold the connection slot until the connection is closed.
+                    let _permit = permit;
                     if let Err(e) = server
                         .serve_connection(
                             io,
                             service_fn(move |req| {
                                 let root_node = root_node.clone();
-                                let ctx = ctx.clone();
                                 let sprite_collab = sprite_collab.clone();
                                 async move {
-                                    Ok::<_, Infallible>(match (req.method(), req.uri().path()) {
-                                        (&Method::OPTIONS, _) => make_http_options_response().map(make_box_body),
-                                        (&Method::GET, "/") => juniper_hyper::graphiql("/graphql", None).await.map(make_box_body),
+                                    let encoding = Encoding::negotiate(req.headers());
+                                    let request_path = req.uri().path().to_string();
+                                    let request_headers = req.headers().clone();
+                                    let handle = async {
+                                    // Browsers send CORS preflights without the Authorization header.
+                                    if req.method() != Method::OPTIONS {
+                                        if let Some(response) = check_api_key(&sprite_collab, req.uri().path(), req.uri().query(), &request_headers) {
+                                            return response.map(make_box_body);
+                                        }
+                                    }
+                                    match (req.method(), req.uri().path()) {
+                                        (&Method::OPTIONS, _) => make_http_options_response(req.headers()).map(make_box_body),
+                                        (&Method::GET, "/") if ServerConfig::get().graphql_ide.is_some() => make_graphql_ide_response(&request_headers).unwrap_or_default().map(make_box_body),
+                                        (&Method::GET, "/graphql/schema.sdl") => make_schema_sdl_response(&root_node).map(make_box_body),
                                         (&Method::GET, "/graphql") | (&Method::POST, "/graphql") => {
-                                            let mut response = juniper_hyper::graphql(root_node, ctx, req).await;
-                                            response.headers_mut().insert(
-                                                "Access-Control-Allow-Origin",
-                                                HeaderValue::try_from("*").unwrap(),
-                                            );
+                                            let response = graphql(root_node, sprite_collab, req).await;
                                             if response.status() != StatusCode::OK {
                                                 let body = response.body();
                                                 warn!(
@@ -110,10 +129,19 @@ async fn main() {
                                             }
                                             response.map(make_box_body)
                                         }
+                                        (&Method::POST, "/assets/bundle") => make_bundle_response(req, sprite_collab).await,
+                                        (&Method::GET, OPENAPI_PATH) => make_openapi_response(ServerConfig::get().this_server_url()).map(make_box_body),
+                                        (&Method::GET, HEALTHZ_PATH) => make_healthz_response(&sprite_collab).map(make_box_body),
+                                        (&Method::GET, EVENTS_PATH) => make_events_response(sprite_collab.subscribe_events()),
+                                        (&Method::GET, TRACKER_EXPORT_PATH) => make_tracker_export_response(req.uri().query(), &sprite_collab).map(make_box_body),
+                                        (method, path) if path.starts_with(ADMIN_PREFIX) => make_admin_response(method, path, req.uri().query(), &request_headers, sprite_collab).await.map(make_box_body),
+                                        (&Method::GET, path) if path.starts_with(API_PREFIX) => make_api_response(path, req.uri().query(), sprite_collab).await.map(make_box_body),
                                         (method, path) =>
                                             match_and_process_assets_path(
                                                 method,
                                                 path,
+                                                req.uri().query(),
+                                                &request_headers,
                                                 sprite_collab.clone(),
                                             )
                                                 .await
@@ -128,7 +156,15 @@ async fn main() {
                                                     );
                                                     response.map(make_box_body)
                                             })
-                                    })
+                                    }};
+                                    // Dropping the handler on timeout also cancels the asset
+                                    // generation it is waiting for.
+                                    let mut response = match ServerConfig::get().request_timeout {
+                                        Some(request_timeout) => timeout(request_timeout, handle).await.unwrap_or_else(|_| make_timeout_response()),
+                                        None => handle.await,
+                                    };
+                                    apply_cors_headers(&request_headers, response.headers_mut());
+                                    Ok::<_, Infallible>(compress_response(encoding, &request_path, response).await)
                                 }
                             }),
                         )
@@ -158,17 +194,88 @@ async fn main() {
     }
 }
 
-/// Make a HTTP OPTIONS response.
-fn make_http_options_response() -> Response<Empty<Bytes>> {
-    Response::builder()
-        .status(StatusCode::OK)
-        .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
-        .header(
-            "Access-Control-Allow-Headers",
-            "Content-Type, Authorization, Accept",
-        )
-        .header("Access-Control-Allow-Origin", "*")
-        .header("Access-Control-Max-Age", "86400")
-        .body(Empty::new())
-        .unwrap()
+/// Runs the `backfill-activities` command, returns the exit code.
+#[cfg(feature = "activity-store")]
+async fn backfill_activities(config: &ServerConfig) -> i32 {
+    use spritecollab_srv::activity_backfill::{backfill_activities, parse_args};
+    use spritecollab_srv::activity_store::ActivityStore;
+    use spritecollab_srv::sprite_collab::GIT_REPO_DIR;
+
+    let backfill_args = match parse_args(args().skip(2)) {
+        Ok(backfill_args) => backfill_args,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let url = match &config.activity_database_url {
+        Some(url) => url,
+        None => {
+            eprintln!("backfill-activities requires SCSRV_ACTIVITY_DATABASE_URL.");
+            return 1;
+        }
+    };
+    let store = match ActivityStore::connect(url).await {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Failed connecting to the activity store: {}", e);
+            return 1;
+        }
+    };
+    match backfill_activities(&store, &config.workdir.join(GIT_REPO_DIR), backfill_args).await {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("The backfill failed: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(not(feature = "activity-store"))]
+async fn backfill_activities(_config: &ServerConfig) -> i32 {
+    eprintln!("backfill-activities requires the activity-store feature.");
+    1
+}
+
+fn make_timeout_response() -> Response<AssetBody> {
+    let mut response = Response::new(String::from(
+        "<html><body><h1>The request took too long.</h1><img src=\"https://http.cat/503\"></body></html>",
+    ));
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    response.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_static("text/html; charset=UTF-8"),
+    );
+    response.map(make_box_body)
+}
+
+/// Configures HTTP/1 and HTTP/2 (with prior knowledge, h2c) connections.
+fn make_server_builder(config: &ServerConfig) -> auto::Builder<TokioExecutor> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(true)
+        // Also runs while a kept-alive connection waits for the next request.
+        .header_read_timeout(config.http1_header_read_timeout);
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .keep_alive_interval(config.http2_keep_alive_interval)
+        .keep_alive_timeout(config.http2_keep_alive_timeout)
+        .max_concurrent_streams(config.http2_max_concurrent_streams);
+    builder
+}
+
+/// Waits until the number of open connections is below the configured limit, then accepts the
+/// next connection.
+async fn accept_connection(
+    listener: &TcpListener,
+    connection_limit: Arc<Semaphore>,
+) -> (io::Result<(TcpStream, SocketAddr)>, OwnedSemaphorePermit) {
+    let permit = connection_limit
+        .acquire_owned()
+        .await
+        .expect("the connection limit semaphore is never closed");
+    (listener.accept().await, permit)
 }
@@ -0,0 +1,194 @@
+//! Read-only mirrors of another instance, configured with `SCSRV_MIRROR_OF`. A mirror does not
+//! clone the repository: It downloads the data files from the primary instance on every refresh
+//! and serves the assets of the primary through its own cache.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Error};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::http::HeaderValue;
+use hyper::{Response, StatusCode, Uri};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use url::Url;
+
+use crate::api::{COMMIT_HEADER, TRACKER_EXPORT_PATH};
+use crate::assets::files::FILES_PATH;
+use crate::assets::{make_box_body, AssetBody, STALE_HEADER};
+use crate::cache::CacheBehaviour;
+use crate::datafiles::translations::TRANSLATIONS_FILE_NAME;
+use crate::http_client::http_client;
+use crate::ServerConfig;
+
+/// Redirects of the primary, eg. to the asset of its current commit, are followed this often.
+const MAX_REDIRECTS: usize = 5;
+/// Data files that are downloaded as they are, the tracker comes from [`TRACKER_EXPORT_PATH`].
+const MIRRORED_DATA_FILES: &[&str] = &["sprite_config.json", "credit_names.txt"];
+
+/// An asset of the primary, as it is cached by the mirror.
+#[derive(Serialize, Deserialize)]
+pub struct MirroredAsset {
+    status: u16,
+    /// The content type and the `X-...` headers of the response.
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl TryInto<Response<AssetBody>> for MirroredAsset {
+    type Error = hyper::http::Error;
+
+    fn try_into(self) -> Result<Response<AssetBody>, Self::Error> {
+        let mut builder = Response::builder().status(self.status);
+        for (name, value) in self.headers {
+            builder = builder.header(name, value);
+        }
+        builder.body(make_box_body(Full::new(Bytes::from(self.body))))
+    }
+}
+
+/// Downloads the data files of the primary into `repo_path`, where they are read like the files
+/// of a clone. Returns the commit the primary serves.
+pub async fn download_data(repo_path: &Path) -> Result<String, Error> {
+    fs::create_dir_all(repo_path).await?;
+    let tracker = fetch_ok(TRACKER_EXPORT_PATH).await?;
+    let commit = tracker
+        .headers()
+        .get(COMMIT_HEADER)
+        .and_then(|commit| commit.to_str().ok())
+        .ok_or_else(|| anyhow!("The primary did not return its commit."))?
+        .to_string();
+    fs::write(repo_path.join("tracker.json"), tracker.body()).await?;
+    for file_name in MIRRORED_DATA_FILES {
+        let file = fetch_ok(&format!("{}/{}", FILES_PATH, file_name)).await?;
+        fs::write(repo_path.join(file_name), file.body()).await?;
+    }
+    // The translations are optional.
+    let translations = fetch(&format!("{}/{}", FILES_PATH, TRANSLATIONS_FILE_NAME)).await?;
+    let translations_path = repo_path.join(TRANSLATIONS_FILE_NAME);
+    if translations.status() == StatusCode::OK {
+        fs::write(&translations_path, translations.body()).await?;
+    } else if translations_path.exists() {
+        fs::remove_file(&translations_path).await?;
+    }
+    Ok(commit)
+}
+
+/// Fetches an asset from the primary. Only successful responses are cached.
+pub async fn fetch_asset(
+    path_and_query: String,
+) -> Result<CacheBehaviour<MirroredAsset>, anyhow::Error> {
+    let response = fetch(&path_and_query).await?;
+    let headers = response
+        .headers()
+        .iter()
+        .filter(|(name, _)| {
+            *name == CONTENT_TYPE
+                || (name.as_str().starts_with("x-")
+                    && !name.as_str().eq_ignore_ascii_case(STALE_HEADER))
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let asset = MirroredAsset {
+        status: response.status().as_u16(),
+        headers,
+        body: response.into_body().to_vec(),
+    };
+    if asset.status == StatusCode::OK {
+        Ok(CacheBehaviour::Cache(asset))
+    } else {
+        Ok(CacheBehaviour::NoCache(asset))
+    }
+}
+
+async fn fetch_ok(path_and_query: &str) -> Result<Response<Bytes>, Error> {
+    let response = fetch(path_and_query).await?;
+    if response.status() != StatusCode::OK {
+        return Err(anyhow!(
+            "The primary returned {} for {}.",
+            response.status(),
+            path_and_query
+        ));
+    }
+    Ok(response)
+}
+
+/// Requests a path of the primary and follows its redirects.
+async fn fetch(path_and_query: &str) -> Result<Response<Bytes>, Error> {
+    let config = ServerConfig::get();
+    let primary = config
+        .mirror_of
+        .as_ref()
+        .ok_or_else(|| anyhow!("This server is not a mirror."))?;
+    let public_url = config.mirror_public_url.as_ref().unwrap_or(primary);
+    let mut url = primary.join(path_and_query)?;
+    for _ in 0..=MAX_REDIRECTS {
+        let response = http_client().get(url.as_str().parse::<Uri>()?).await?;
+        let location = match response.headers().get(LOCATION) {
+            Some(location) if response.status().is_redirection() => location,
+            _ => {
+                let (parts, body) = response.into_parts();
+                let body = body.collect().await?.to_bytes();
+                return Ok(Response::from_parts(parts, body));
+            }
+        };
+        url = redirect_url(primary, public_url, &url, location)?;
+    }
+    Err(anyhow!(
+        "Too many redirects requesting {} of the primary.",
+        url
+    ))
+}
+
+/// The primary redirects to its public URL, which may not be reachable from the mirror, so only
+/// the path and query of these locations are used. Locations on other hosts, eg. the bucket of
+/// the primary, are followed as they are.
+fn redirect_url(
+    primary: &Url,
+    public_url: &Url,
+    current: &Url,
+    location: &HeaderValue,
+) -> Result<Url, Error> {
+    let location = current.join(location.to_str()?)?;
+    if location.origin() != public_url.origin() {
+        return Ok(location);
+    }
+    let mut url = primary.join(location.path())?;
+    url.set_query(location.query());
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_stay_on_the_primary() {
+        let primary = Url::parse("http://primary:3000").unwrap();
+        let public_url = Url::parse("https://spriteserver.pmdcollab.org").unwrap();
+        let current = primary.join("/assets/portrait-0025.png").unwrap();
+        let redirect = |location| {
+            redirect_url(
+                &primary,
+                &public_url,
+                &current,
+                &HeaderValue::from_static(location),
+            )
+            .unwrap()
+            .to_string()
+        };
+        assert_eq!(
+            redirect("https://spriteserver.pmdcollab.org/assets/abc/portrait-0025.png?scale=2"),
+            "http://primary:3000/assets/abc/portrait-0025.png?scale=2"
+        );
+        assert_eq!(
+            redirect("/assets/abc/portrait-0025.png"),
+            "http://primary:3000/assets/abc/portrait-0025.png"
+        );
+        assert_eq!(
+            redirect("https://cdn.example.com/abc/portrait_sheet.png"),
+            "https://cdn.example.com/abc/portrait_sheet.png"
+        );
+    }
+}
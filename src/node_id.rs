@@ -0,0 +1,71 @@
+//! Global object IDs of the GraphQL `Node` interface. They are unique across all types of
+//! objects, eg. `Monster:25`, `MonsterForm:0025/0000/0001` or `Credit:<credit ID>`, so clients
+//! can use them to normalize their caches and to refetch single objects with the `node` query.
+
+use crate::service::full_form_path;
+
+/// The object a global ID points to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NodeId {
+    Monster(i32),
+    /// The monster ID and the form path.
+    MonsterForm(i32, Vec<i32>),
+    Credit(String),
+}
+
+impl NodeId {
+    const SEPARATOR: char = ':';
+
+    pub fn encode(&self) -> String {
+        let (kind, id) = match self {
+            NodeId::Monster(id) => ("Monster", id.to_string()),
+            NodeId::MonsterForm(id, form_id) => ("MonsterForm", full_form_path(*id, form_id)),
+            NodeId::Credit(id) => ("Credit", id.clone()),
+        };
+        format!("{}{}{}", kind, Self::SEPARATOR, id)
+    }
+
+    pub fn decode(node_id: &str) -> Option<Self> {
+        // Credit IDs may contain the separator themselves.
+        let (kind, id) = node_id.split_once(Self::SEPARATOR)?;
+        match kind {
+            "Monster" => id.parse().ok().map(NodeId::Monster),
+            "MonsterForm" => {
+                let mut path = id
+                    .split('/')
+                    .map(|id| id.parse::<i32>().ok())
+                    .collect::<Option<Vec<_>>>()?;
+                let monster_id = path.remove(0);
+                Some(NodeId::MonsterForm(monster_id, path))
+            }
+            "Credit" if !id.is_empty() => Some(NodeId::Credit(id.to_string())),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrips_node_ids() {
+        for node_id in [
+            NodeId::Monster(25),
+            NodeId::MonsterForm(25, vec![]),
+            NodeId::MonsterForm(25, vec![0, 1]),
+            NodeId::Credit("Fixture:Author".to_string()),
+        ] {
+            assert_eq!(NodeId::decode(&node_id.encode()), Some(node_id));
+        }
+        assert_eq!(
+            NodeId::MonsterForm(25, vec![0, 1]).encode(),
+            "MonsterForm:0025/0000/0001"
+        );
+        assert_eq!(NodeId::decode("Monster:abc"), None);
+        assert_eq!(NodeId::decode("MonsterForm:0025//0001"), None);
+        assert_eq!(NodeId::decode("Credit:"), None);
+        assert_eq!(NodeId::decode("Portrait:0025"), None);
+        assert_eq!(NodeId::decode("25"), None);
+    }
+}
@@ -0,0 +1,198 @@
+//! The OpenAPI 3 document describing the asset endpoints and the REST API, served at
+//! `/api/openapi.json`. It is generated from the route registries [`ASSET_ROUTES`] and
+//! [`API_ROUTES`], so it can't get out of sync with the routes that are actually matched.
+
+use hyper::{Response, StatusCode};
+use serde_json::{json, Map, Value};
+
+use crate::api::{ApiEndpoint, API_ROUTES, TRACKER_EXPORT_PATH};
+use crate::assets::signed_urls::{is_signed_asset_type, EXPIRES_PARAM, SIGNATURE_PARAM};
+use crate::assets::url::{ASSET_ROUTES, FORMPATH_PARAM};
+use crate::graphql::make_json_response;
+use crate::schema::API_VERSION;
+
+/// Path the OpenAPI document is served at.
+pub const OPENAPI_PATH: &str = "/api/openapi.json";
+
+/// A path or query parameter of a route.
+#[derive(Debug)]
+pub struct RouteParam {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+/// Makes the OpenAPI document, with `this_server_url` as the server.
+pub fn openapi_document(this_server_url: &str) -> Value {
+    let mut paths = Map::new();
+    for route in ASSET_ROUTES {
+        let mut parameters = vec![path_parameter(&FORMPATH_PARAM)];
+        parameters.extend(route.path_params.iter().map(path_parameter));
+        parameters.push(query_parameter(&RouteParam {
+            name: "ref",
+            description: "The branch or tag of the SpriteCollab repository. Only the one this server is configured for can be served.",
+        }));
+        parameters.extend(route.query_params.iter().map(query_parameter));
+        let mut responses = json!({
+            "200": {
+                "description": "The asset.",
+                "content": { route.content_type: {} }
+            },
+            "307": { "description": "Redirect to the asset of the current commit, under /assets/{commit}/." },
+            "404": { "description": "The form or asset does not exist." },
+            "500": { "description": "The asset could not be generated." }
+        });
+        if is_signed_asset_type(&route.asset_type) {
+            responses["403"] = json!({ "description": "The signature of the URL is invalid or expired, or a signed URL is required." });
+            parameters.push(query_parameter(&RouteParam {
+                name: EXPIRES_PARAM,
+                description: "Expiry of a signed URL, as a Unix timestamp. Part of the URLs returned by the API, if the server signs them.",
+            }));
+            parameters.push(query_parameter(&RouteParam {
+                name: SIGNATURE_PARAM,
+                description: "Signature of a signed URL. Part of the URLs returned by the API, if the server signs them.",
+            }));
+        }
+        paths.insert(
+            openapi_path(route.pattern),
+            json!({
+                "get": {
+                    "tags": ["assets"],
+                    "summary": route.summary,
+                    "parameters": parameters,
+                    "responses": responses
+                }
+            }),
+        );
+    }
+    for route in API_ROUTES {
+        let mut operation = json!({
+            "get": {
+                "tags": ["api"],
+                "summary": route.summary,
+                "parameters": route
+                    .path_params
+                    .iter()
+                    .map(path_parameter)
+                    .chain(route.query_params.iter().map(query_parameter))
+                    .collect::<Vec<_>>(),
+                "responses": {
+                    "200": { "$ref": "#/components/responses/Ok" },
+                    "400": { "$ref": "#/components/responses/Error" },
+                    "404": { "$ref": "#/components/responses/Error" },
+                    "500": { "$ref": "#/components/responses/Error" },
+                    "503": { "$ref": "#/components/responses/Error" }
+                }
+            }
+        });
+        if matches!(route.endpoint, ApiEndpoint::Activities) {
+            operation["get"]["responses"]["501"] =
+                json!({ "$ref": "#/components/responses/Error" });
+        }
+        paths.insert(openapi_path(route.pattern), operation);
+    }
+    paths.insert(
+        TRACKER_EXPORT_PATH.to_string(),
+        json!({
+            "get": {
+                "tags": ["api"],
+                "summary": "The tracker in the format of SpriteBot's tracker.json, from the commit in the X-SC-Commit header.",
+                "parameters": [query_parameter(&RouteParam {
+                    name: "monsters",
+                    description: "Comma-separated IDs of the monsters to limit the tracker to.",
+                })],
+                "responses": {
+                    "200": { "$ref": "#/components/responses/Ok" },
+                    "400": { "$ref": "#/components/responses/Error" }
+                }
+            }
+        }),
+    );
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "SpriteCollab Server",
+            "description": "Asset endpoints and REST API of the SpriteCollab server. The full API is available via GraphQL at /graphql.",
+            "version": API_VERSION
+        },
+        "servers": [{ "url": this_server_url }],
+        "paths": paths,
+        "components": {
+            "responses": {
+                "Ok": {
+                    "description": "The requested data, with the same fields as in the GraphQL schema.",
+                    "content": { "application/json": {} }
+                },
+                "Error": {
+                    "description": "An error, with a machine-readable code in the extensions.",
+                    "content": {
+                        "application/json": {
+                            "schema": {
+                                "type": "object",
+                                "properties": {
+                                    "message": { "type": "string" },
+                                    "extensions": {
+                                        "type": "object",
+                                        "properties": { "code": { "type": "string" } }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Responds with the OpenAPI document.
+pub fn make_openapi_response(this_server_url: &str) -> Response<String> {
+    make_json_response(
+        StatusCode::OK,
+        openapi_document(this_server_url).to_string(),
+    )
+}
+
+/// Converts a `route_recognizer` pattern to an OpenAPI path template, eg.
+/// `/assets/preview/*formpath.png` to `/assets/preview/{formpath}.png`.
+fn openapi_path(pattern: &str) -> String {
+    let mut path = String::with_capacity(pattern.len() + 2);
+    let mut in_param = false;
+    for c in pattern.chars() {
+        match c {
+            ':' | '*' => {
+                path.push('{');
+                in_param = true;
+            }
+            '/' | '.' if in_param => {
+                path.push('}');
+                path.push(c);
+                in_param = false;
+            }
+            _ => path.push(c),
+        }
+    }
+    if in_param {
+        path.push('}');
+    }
+    path
+}
+
+fn path_parameter(param: &RouteParam) -> Value {
+    json!({
+        "name": param.name,
+        "in": "path",
+        "required": true,
+        "description": param.description,
+        "schema": { "type": "string" }
+    })
+}
+
+fn query_parameter(param: &RouteParam) -> Value {
+    json!({
+        "name": param.name,
+        "in": "query",
+        "required": false,
+        "description": param.description,
+        "schema": { "type": "string" }
+    })
+}
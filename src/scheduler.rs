@@ -1,18 +1,30 @@
-use crate::SpriteCollab;
-use log::info;
+use crate::assets::prewarm::prewarm_assets;
+use crate::search::AssetSearchIndex;
+use crate::{ServerConfig, SpriteCollab};
+use log::{info, warn};
 use std::mem::take;
-use std::sync::mpsc::{channel, Sender};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-const REFRESH_INTERVAL: u64 = 15 * 60;
-pub struct DataRefreshScheduler(Option<JoinHandle<()>>, Sender<()>);
+/// Delay of the first refresh if the server started without data, see
+/// [`SpriteCollab::is_ready`]. It doubles after each failed refresh, up to the refresh interval.
+const NOT_READY_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+enum Command {
+    /// Refresh now, eg. because another instance serves a new commit.
+    Refresh,
+    Shutdown,
+}
+
+pub struct DataRefreshScheduler(Option<JoinHandle<()>>, Sender<Command>);
 
 impl DataRefreshScheduler {
     pub fn new(sprite_collab: Arc<SpriteCollab>) -> Self {
-        let (shutdown_sender, shutdown_receiver) = channel();
+        let (sender, receiver) = channel();
+        let refresh_sender = sender.clone();
 
         let handle = thread::spawn(move || {
             info!("Starting Job Scheduler.");
@@ -21,25 +33,64 @@ impl DataRefreshScheduler {
                 .build()
                 .unwrap();
             rt.block_on(async {
+                let listening_collab = sprite_collab.clone();
+                tokio::spawn(async move {
+                    listening_collab
+                        .listen_for_commits(|commit| {
+                            info!("Another instance serves commit {}, refreshing.", commit);
+                            refresh_sender.send(Command::Refresh).ok();
+                        })
+                        .await
+                });
+                let monitoring_collab = sprite_collab.clone();
+                tokio::spawn(async move { monitoring_collab.disk_monitor().run().await });
+                let mut last_gc = Instant::now();
+                let mut retry_delay = NOT_READY_RETRY_DELAY;
                 loop {
-                    if shutdown_receiver
-                        .recv_timeout(Duration::from_secs(REFRESH_INTERVAL))
-                        .is_ok()
-                    {
+                    let refresh_interval = ServerConfig::get().refresh_interval;
+                    let wait = if sprite_collab.is_ready() {
+                        refresh_interval
+                    } else {
+                        let wait = retry_delay.min(refresh_interval);
+                        info!("No data loaded yet, refreshing in {}s.", wait.as_secs());
+                        retry_delay *= 2;
+                        wait
+                    };
+                    match receiver.recv_timeout(wait) {
                         // Sleep was interrupted
-                        break;
+                        Ok(Command::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                        Ok(Command::Refresh) | Err(RecvTimeoutError::Timeout) => {}
+                    }
+                    if SpriteCollab::refresh(sprite_collab.clone()).await {
+                        if let Err(e) = AssetSearchIndex::get(&*sprite_collab, &sprite_collab).await
+                        {
+                            warn!("Failed to build the asset search index: {:?}", e);
+                        }
+                        // Mirrors have no files to generate assets from.
+                        if ServerConfig::get().mirror_of.is_none() {
+                            prewarm_assets(sprite_collab.clone(), ServerConfig::get().prewarm_count)
+                                .await
+                        }
+                    }
+                    if let Some(gc_interval) = ServerConfig::get().git_gc_interval {
+                        // Mirrors have no repository.
+                        if last_gc.elapsed() >= gc_interval
+                            && ServerConfig::get().mirror_of.is_none()
+                        {
+                            sprite_collab.collect_garbage().await;
+                            last_gc = Instant::now();
+                        }
                     }
-                    SpriteCollab::refresh(sprite_collab.clone()).await
                 }
             });
             info!("Stopped Job Scheduler.");
         });
 
-        Self(Some(handle), shutdown_sender)
+        Self(Some(handle), sender)
     }
 
     pub fn shutdown(&mut self) {
-        self.1.send(()).unwrap();
+        self.1.send(Command::Shutdown).unwrap();
         let jh = take(&mut self.0);
         jh.unwrap().join().ok();
     }
@@ -1,44 +1,113 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::fmt::Debug;
+use std::fmt::Display;
 use std::future::Future;
-use std::iter::once;
+use std::mem::take;
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use fred::types::RedisKey;
+use futures::future::join_all;
 use itertools::Itertools;
 use juniper::{
-    graphql_object, graphql_value, FieldError, FieldResult, GraphQLEnum, GraphQLObject,
-    GraphQLUnion,
+    graphql_interface, graphql_object, graphql_value, FieldError, FieldResult, GraphQLEnum,
+    GraphQLObject, GraphQLUnion, Object, Value, ID,
 };
 #[allow(unused_imports)]
 use log::warn;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio::task::{spawn_blocking, yield_now};
 
+use crate::activity::{ActivityCursor, ContributorStats, CreditActivity};
+use crate::assets::activity_diff::activity_diff_url;
+use crate::assets::form_changes;
 use crate::assets::fs_check::{
-    get_existing_portrait_file, get_existing_sprite_file, get_local_credits_file,
-    iter_existing_portrait_files, iter_existing_sprite_files, AssetCategory,
+    get_existing_portrait_file, get_existing_sprite_file, get_form_file_stats,
+    get_local_credits_file, iter_existing_portrait_files, iter_existing_sprite_files,
+    AssetCategory, FormFileStats,
 };
-use crate::assets::url::{get_url, AssetType};
+use crate::assets::portrait_sheets::PortraitSheetEmotions;
+use crate::assets::store::AssetStore;
+use crate::assets::url::{get_url, AssetType, AssetUrlBase};
 use crate::cache::{CacheBehaviour, ScCache};
-use crate::config::Config as SystemConfig;
-use crate::datafiles::anim_data_xml::AnimDataXml;
+use crate::config::ServerConfig;
 use crate::datafiles::credit_names::CreditNamesRow;
 use crate::datafiles::group_id::GroupId;
+use crate::datafiles::integrity::{IntegrityIssue, IntegrityIssueKind};
 use crate::datafiles::local_credits_file::LocalCreditRow;
 use crate::datafiles::parse_credit_id;
+use crate::datafiles::project_stats::{ProjectStats as ProjectStatsData, RECENT_DAYS};
+use crate::datafiles::refresh_report::{FileDiagnostic, FileErrorKind, RefreshReport};
 use crate::datafiles::sprite_config::SpriteConfig;
-use crate::datafiles::tracker::{
-    fuzzy_find_tracker, FormMatch, Group, MapImpl, MonsterFormCollector,
-};
-use crate::sprite_collab::SpriteCollab;
+use crate::datafiles::tracker::{FormMatch, Group, MapImpl, MonsterFormCollector, Tracker};
+use crate::node_id::NodeId;
+use crate::search::{AssetSearchEntry, AssetSearchIndex, SearchMatch};
+use crate::service::{self, failed_credits_read, parse_form_path, LicensedForms};
+use crate::sprite_collab::{SpriteCollab, GIT_REPO_DIR};
 
 /// Maximum length for search query strings
 const MAX_QUERY_LEN: usize = 75;
-const API_VERSION: &str = "1.6";
+/// Default and maximum number of entries returned by the queries of the activity store.
+const DEFAULT_ACTIVITY_PAGE_SIZE: i32 = 20;
+const MAX_ACTIVITY_PAGE_SIZE: i32 = 100;
+const MAX_SEARCH_PAGE_SIZE: i32 = 100;
+/// Version of the API. Must be bumped on every change to the schema, see `schema.graphql`.
+pub const API_VERSION: &str = "2.1";
+
+/// Machine-readable error codes. Every error returned by the API has one in the `code` field of
+/// its extensions, so clients don't have to match on the error messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorCode {
+    /// The requested monster, form or other entity does not exist.
+    NotFound,
+    /// A form path could not be parsed.
+    InvalidPath,
+    /// Another argument is invalid, eg. a search query is too long.
+    InvalidArgument,
+    /// An unexpected error on the server, eg. invalid data in the SpriteCollab repository.
+    Internal,
+    /// The cache could not be reached. The request can be retried.
+    CacheUnavailable,
+    /// The data is currently being updated and can not be read. The request can be retried.
+    DataStale,
+    /// An API key is required, or the given one is invalid.
+    Unauthorized,
+    /// The quota of the API key is exceeded. The request can be retried later.
+    QuotaExceeded,
+    /// The requested feature is not enabled on this server.
+    Unavailable,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::InvalidPath => "INVALID_PATH",
+            ErrorCode::InvalidArgument => "INVALID_ARGUMENT",
+            ErrorCode::Internal => "INTERNAL",
+            ErrorCode::CacheUnavailable => "CACHE_UNAVAILABLE",
+            ErrorCode::DataStale => "DATA_STALE",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::Unavailable => "UNAVAILABLE",
+        }
+    }
+
+    /// Makes an error with this code. Other fields of `extensions` (if it is an object) are
+    /// kept.
+    pub fn error<M: Display>(self, message: M, extensions: Value) -> FieldError {
+        let mut extensions = match extensions {
+            Value::Object(object) => object,
+            _ => Object::with_capacity(1),
+        };
+        extensions.add_field("code", Value::from(self.as_str()));
+        FieldError::new(message, Value::Object(extensions))
+    }
+}
 
 #[derive(GraphQLEnum)]
 #[graphql(description = "A known license from a common list of options.")]
@@ -80,6 +149,30 @@ pub enum License {
     Other(OtherLicense),
 }
 
+#[derive(GraphQLObject)]
+#[graphql(description = "The forms whose current portraits or sprites are under a license.")]
+pub struct LicenseUsage {
+    license: License,
+    #[graphql(
+        description = "Full paths of the forms (eg. 0025/0000/0001) with portraits under this license."
+    )]
+    portrait_forms: Vec<String>,
+    #[graphql(
+        description = "Full paths of the forms (eg. 0025/0000/0001) with sprites under this license."
+    )]
+    sprite_forms: Vec<String>,
+}
+
+impl From<LicensedForms> for LicenseUsage {
+    fn from(value: LicensedForms) -> Self {
+        Self {
+            license: value.license.into(),
+            portrait_forms: value.portrait_forms,
+            sprite_forms: value.sprite_forms,
+        }
+    }
+}
+
 impl From<String> for License {
     fn from(value: String) -> Self {
         match &*value {
@@ -104,7 +197,8 @@ impl From<String> for License {
 }
 
 #[repr(i64)]
-#[derive(GraphQLEnum)]
+#[derive(GraphQLEnum, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[graphql(description = "The current phase of the sprite or portrait.")]
 pub enum Phase {
     Incomplete = 0,
@@ -127,97 +221,161 @@ impl From<i64> for Phase {
     }
 }
 
-#[derive(GraphQLObject)]
+/// Returns the names of all entries of the first list of `completion` (a list of indices into
+/// `names` per phase) that are not in `existing`. This is what is still missing to reach the next
+/// phase.
+fn missing_for_next_phase(
+    completion: &[Vec<i32>],
+    names: &[String],
+    existing: &[String],
+) -> Vec<String> {
+    completion
+        .iter()
+        .map(|required| {
+            required
+                .iter()
+                .filter_map(|idx| names.get(*idx as usize))
+                .filter(|name| !existing.contains(name))
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .find(|missing| !missing.is_empty())
+        .unwrap_or_default()
+}
+
+#[derive(GraphQLObject, Serialize)]
+#[serde(rename_all = "camelCase")]
 #[graphql(description = "A single sprite for a single action.")]
 pub struct Sprite {
     #[graphql(description = "Action of this sprite.")]
-    action: String,
+    pub(crate) action: String,
     #[graphql(
         description = "Whether or not this sprite is locked and requires special permissions to be updated."
     )]
-    locked: bool,
+    pub(crate) locked: bool,
     #[graphql(
         description = "URL to the sprite sheet containing the actual frames for the animation."
     )]
-    anim_url: String,
+    pub(crate) anim_url: String,
     #[graphql(
         description = "URL to the sprite sheet containing the sprite offset pixels for each frame."
     )]
-    offsets_url: String,
+    pub(crate) offsets_url: String,
     #[graphql(
         description = "URL to the sprite sheet containing the shadow placeholders for each frame."
     )]
-    shadows_url: String,
+    pub(crate) shadows_url: String,
 }
 
-#[derive(GraphQLObject)]
+#[derive(GraphQLObject, Serialize)]
+#[serde(rename_all = "camelCase")]
 #[graphql(description = "A sprite, which is a copy of another sprite.")]
 pub struct CopyOf {
     #[graphql(description = "Action of this sprite.")]
-    action: String,
+    pub(crate) action: String,
     #[graphql(
         description = "Whether or not this sprite is locked and requires special permissions to be updated."
     )]
-    locked: bool,
+    pub(crate) locked: bool,
     #[graphql(description = "Which action this sprite is a copy of.")]
-    copy_of: String,
+    pub(crate) copy_of: String,
 }
 
-#[derive(GraphQLUnion)]
+#[derive(GraphQLUnion, Serialize)]
+#[serde(tag = "__typename")]
 #[graphql(
     description = "A single sprite for a single action that is either a copy of another sprite (as defined in the AnimData.xml) or has it's own sprite data."
 )]
-enum SpriteUnion {
+pub enum SpriteUnion {
     Sprite(Sprite),
     CopyOf(CopyOf),
 }
 
-#[derive(GraphQLObject)]
+#[derive(GraphQLObject, Serialize)]
 #[graphql(description = "A single portrait for a single emotion.")]
 pub struct Portrait {
     #[graphql(description = "Name of the emotion.")]
-    emotion: String,
+    pub(crate) emotion: String,
     #[graphql(
         description = "Whether or not this sprite is locked and requires special permissions to be updated."
     )]
-    locked: bool,
+    pub(crate) locked: bool,
     #[graphql(description = "URL to the portraits.")]
-    url: String,
+    pub(crate) url: String,
 }
 
-#[derive(GraphQLObject)]
+#[derive(GraphQLObject, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[graphql(
+    context = Context,
+    description = "A contributor to the portraits or sprites of a monster, with the forms they contributed to."
+)]
+pub struct MonsterContributor {
+    #[graphql(description = "The contributor.")]
+    pub(crate) credit: Credit,
+    #[graphql(
+        description = "Full paths of the forms (eg. 0025/0000/0001) with portraits by this contributor."
+    )]
+    pub(crate) portrait_forms: Vec<String>,
+    #[graphql(
+        description = "Full paths of the forms (eg. 0025/0000/0001) with sprites by this contributor."
+    )]
+    pub(crate) sprite_forms: Vec<String>,
+}
+
+#[derive(GraphQLObject, Serialize)]
 #[graphql(description = "A bounty for a non-standard phase.")]
 pub struct OtherBounty {
     phase: i32,
     bounty: i32,
 }
 
+#[derive(GraphQLObject, Serialize)]
+#[graphql(description = "A bounty for a phase, by the raw ID of the phase (see phaseRaw).")]
+pub struct PhaseBounty {
+    phase: i32,
+    bounty: i32,
+}
+
 pub struct MonsterHistory {
     credit: Option<Credit>,
     modified_date: DateTime<Utc>,
     modifications: Vec<String>,
     obsolete: bool,
     license: License,
+    /// Whether this is an entry of the portraits or sprites of the form, and the form.
+    category: AssetCategory,
+    group: Arc<Group>,
+    monster_id: i32,
+    form_id: Vec<i32>,
 }
 
 impl MonsterHistory {
-    fn try_from_credit_row(context: &Context, value: LocalCreditRow) -> Result<Self, FieldError> {
+    async fn from_credit_row(
+        context: &Context,
+        category: AssetCategory,
+        group: &Arc<Group>,
+        monster_id: i32,
+        form_id: &[i32],
+        value: LocalCreditRow,
+    ) -> Self {
         let credit_id = parse_credit_id(value.credit_id);
         let credit = if credit_id.is_empty() {
             None
         } else {
-            Some(Credit::new(
-                context.collab.data().credit_names.get(&credit_id),
-                &credit_id,
-            )?)
+            Some(context.credits.load(credit_id).await)
         };
-        Ok(Self {
+        Self {
             credit,
             modified_date: value.date,
             modifications: value.items,
             obsolete: value.obsolete,
             license: value.license.into(),
-        })
+            category,
+            group: group.clone(),
+            monster_id,
+            form_id: form_id.to_vec(),
+        }
     }
 }
 
@@ -241,6 +399,39 @@ impl MonsterHistory {
         &self.modifications
     }
 
+    #[graphql(
+        description = "The emotions or actions of modifications, with their current portraits or sprites."
+    )]
+    pub async fn modified_assets(&self, context: &Context) -> FieldResult<Vec<ModifiedAsset>> {
+        let mut assets = Vec::with_capacity(self.modifications.len());
+        for name in &self.modifications {
+            let (portrait, sprite) = match self.category {
+                AssetCategory::Portrait => {
+                    let portraits = MonsterFormPortraits(
+                        self.group.clone(),
+                        self.monster_id,
+                        self.form_id.clone(),
+                    );
+                    (portraits.emotion(context, name.clone()).await?, None)
+                }
+                AssetCategory::Sprite => {
+                    let sprites = MonsterFormSprites(
+                        self.group.clone(),
+                        self.monster_id,
+                        self.form_id.clone(),
+                    );
+                    (None, sprites.action(context, name.clone()).await?)
+                }
+            };
+            assets.push(ModifiedAsset {
+                name: name.clone(),
+                portrait,
+                sprite,
+            });
+        }
+        Ok(assets)
+    }
+
     #[graphql(
         description = "True if the credit for this history entry was marked as no longer relevant for the current portraits or sprites."
     )]
@@ -255,6 +446,37 @@ impl MonsterHistory {
 }
 
 #[derive(GraphQLObject)]
+#[graphql(
+    context = Context,
+    description = "A contributor to the current portraits or sprites of a form, with the emotions or actions they are responsible for."
+)]
+pub struct RelevantCredit {
+    #[graphql(description = "The contributor.")]
+    pub(crate) credit: Credit,
+    #[graphql(
+        description = "The emotions or actions whose newest contribution that is not obsolete is by this contributor."
+    )]
+    pub(crate) items: Vec<String>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    description = "An emotion or action changed in a history entry, with its current portrait or sprite."
+)]
+pub struct ModifiedAsset {
+    #[graphql(description = "The emotion or action, as listed in modifications.")]
+    name: String,
+    #[graphql(
+        description = "The current portrait of the emotion. Null in the history of sprites, or if the portrait no longer exists."
+    )]
+    portrait: Option<Portrait>,
+    #[graphql(
+        description = "The current sprite of the action. Null in the history of portraits, or if the sprite no longer exists."
+    )]
+    sprite: Option<SpriteUnion>,
+}
+
+#[derive(GraphQLObject, Serialize)]
 #[graphql(
     description = "A SkyTemple Discord Server Guild Point bounty that will be rewarded, if the portrait or sprite has transitioned into a phase."
 )]
@@ -270,6 +492,12 @@ pub struct MonsterBounty {
     #[graphql(description = "Amount of points to reward if the phase changes to Full.")]
     full: Option<i32>,
     other: Vec<OtherBounty>,
+    #[graphql(description = "Sum of the bounties of all phases.")]
+    total: i32,
+    #[graphql(
+        description = "The bounties of all phases as they are in the tracker, sorted by phase."
+    )]
+    map: Vec<PhaseBounty>,
 }
 
 impl MonsterBounty {
@@ -293,6 +521,135 @@ impl MonsterBounty {
                     bounty: *v as i32,
                 })
                 .collect(),
+            total: bounty_spec.values().sum::<i64>() as i32,
+            map: bounty_spec
+                .iter()
+                .sorted_by_key(|(&k, _)| k)
+                .map(|(k, v)| PhaseBounty {
+                    phase: *k as i32,
+                    bounty: *v as i32,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn total(&self) -> i64 {
+        self.total.into()
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    context = Context,
+    description = "The Guild Point bounty for the portraits or sprites of a form."
+)]
+pub struct FormBounty {
+    pub(crate) form: MonsterForm,
+    pub(crate) category: ActivityCategory,
+    pub(crate) bounty: MonsterBounty,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    context = Context,
+    description = "A monster found by searchMonsterMatches, with how well and by which name it matched."
+)]
+pub struct MonsterSearchMatch {
+    monster: Monster,
+    #[graphql(
+        description = "Score of the match, higher is better. Matches with typos have a negative score, the negated number of typos."
+    )]
+    score: i32,
+    #[graphql(
+        description = "The name that matched: of the monster, one of its forms, a localized name or an alias."
+    )]
+    matched_name: String,
+    #[graphql(
+        description = "Indices of the characters of matchedName that matched the query, for highlighting. Empty for matches with typos."
+    )]
+    matched_indices: Vec<i32>,
+}
+
+impl From<SearchMatch<i64>> for MonsterSearchMatch {
+    fn from(search_match: SearchMatch<i64>) -> Self {
+        Self {
+            monster: Monster {
+                id: search_match.value as i32,
+            },
+            score: search_match_score(search_match.score),
+            matched_name: search_match.key,
+            matched_indices: search_match_indices(search_match.indices),
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    context = Context,
+    description = "A credit entry found by searchCreditMatches, with how well and by which name it matched."
+)]
+pub struct CreditSearchMatch {
+    credit: Credit,
+    #[graphql(
+        description = "Score of the match, higher is better. Matches with typos have a negative score, the negated number of typos."
+    )]
+    score: i32,
+    #[graphql(description = "The ID or author name that matched.")]
+    matched_name: String,
+    #[graphql(
+        description = "Indices of the characters of matchedName that matched the query, for highlighting. Empty for matches with typos."
+    )]
+    matched_indices: Vec<i32>,
+}
+
+impl From<SearchMatch<&CreditNamesRow>> for CreditSearchMatch {
+    fn from(search_match: SearchMatch<&CreditNamesRow>) -> Self {
+        Self {
+            credit: search_match.value.into(),
+            score: search_match_score(search_match.score),
+            matched_name: search_match.key,
+            matched_indices: search_match_indices(search_match.indices),
+        }
+    }
+}
+
+fn search_match_score(score: i64) -> i32 {
+    score.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+fn search_match_indices(indices: Vec<usize>) -> Vec<i32> {
+    indices.into_iter().map(|idx| idx as i32).collect()
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "Statistics of the portrait or sprite files of a form in the repository.")]
+pub struct AssetStats {
+    #[graphql(
+        description = "Number of the emotions or actions listed for this form whose files exist."
+    )]
+    existing_count: i32,
+    #[graphql(description = "Number of files in the directory of this form.")]
+    file_count: i32,
+    #[graphql(description = "Total size of these files in bytes.")]
+    total_size: i32,
+    #[graphql(
+        description = "The last modification time of any of these files on the disk of the server."
+    )]
+    last_file_modified: Option<DateTime<Utc>>,
+    #[graphql(
+        description = "Number of sprite actions that are a copy of another action. Always 0 for portraits."
+    )]
+    copy_of_count: i32,
+}
+
+impl From<FormFileStats> for AssetStats {
+    fn from(stats: FormFileStats) -> Self {
+        Self {
+            existing_count: stats.existing as i32,
+            file_count: stats.files as i32,
+            total_size: i32::try_from(stats.total_size).unwrap_or(i32::MAX),
+            last_file_modified: stats.last_modified,
+            copy_of_count: stats.copy_of_actions as i32,
         }
     }
 }
@@ -325,146 +682,96 @@ impl MonsterFormPortraits {
     }
 
     #[graphql(description = "Primary artist credits.")]
-    fn credit_primary(&self, context: &Context) -> FieldResult<Option<Credit>> {
-        let credit_id = parse_credit_id(&self.0.portrait_credit.primary);
-        if credit_id.is_empty() {
-            Ok(None)
-        } else {
-            Credit::new(
-                context.collab.data().credit_names.get(&credit_id),
-                &credit_id,
-            )
-            .map(Some)
-        }
+    async fn credit_primary(&self, context: &Context) -> Option<Credit> {
+        service::credit(context, &self.0.portrait_credit.primary).await
     }
 
     #[graphql(description = "All other artists credited.")]
-    fn credit_secondary(&self, context: &Context) -> FieldResult<Vec<Credit>> {
-        let names = &context.collab.data().credit_names;
-        self.0
-            .portrait_credit
-            .secondary
-            .iter()
-            .map(parse_credit_id)
-            .map(|v| Credit::new(names.get(&v), &v))
-            .collect()
+    async fn credit_secondary(&self, context: &Context) -> Vec<Credit> {
+        service::credits(context, &self.0.portrait_credit.secondary).await
     }
 
     #[graphql(description = "URL to a SpriteBot format sheet of all portraits.")]
     fn sheet_url(&self, context: &Context) -> String {
+        get_url(AssetType::PortraitSheet, &context.url_base, self.1, &self.2)
+    }
+
+    #[graphql(description = "URL to a SpriteBot format recolor sheet.")]
+    fn recolor_sheet_url(&self, context: &Context) -> String {
         get_url(
-            AssetType::PortraitSheet,
-            &context.this_server_url,
+            AssetType::PortraitRecolorSheet,
+            &context.url_base,
             self.1,
             &self.2,
         )
     }
 
-    #[graphql(description = "URL to a SpriteBot format recolor sheet.")]
-    fn recolor_sheet_url(&self, context: &Context) -> String {
+    #[graphql(
+        description = "URL to an overview of all emotions in the layout of the portrait sheet, labeled with their names, like the one SpriteBot posts."
+    )]
+    fn annotated_sheet_url(&self, context: &Context) -> String {
         get_url(
-            AssetType::PortraitRecolorSheet,
-            &context.this_server_url,
+            AssetType::PortraitAnnotatedSheet,
+            &context.url_base,
             self.1,
             &self.2,
         )
     }
 
+    #[graphql(description = "URL to a ZIP archive of all portraits.")]
+    fn zip_url(&self, context: &Context) -> Option<String> {
+        if self.0.portrait_files.is_empty() {
+            None
+        } else {
+            Some(get_url(
+                AssetType::PortraitZip,
+                &context.url_base,
+                self.1,
+                &self.2,
+            ))
+        }
+    }
+
     #[graphql(description = "A list of all existing portraits for the emotions.")]
     async fn emotions(&self, context: &Context) -> FieldResult<Vec<Portrait>> {
-        Ok(
-            iter_existing_portrait_files(&context, &self.0.portrait_files, false, self.1, &self.2)
-                .await?
-                .into_iter()
-                .map(|(emotion, locked)| Portrait {
-                    emotion: emotion.clone(),
-                    locked,
-                    url: get_url(
-                        AssetType::Portrait(&emotion),
-                        &context.this_server_url,
-                        self.1,
-                        &self.2,
-                    ),
-                })
-                .collect(),
-        )
+        service::portraits(context, &self.0, self.1, &self.2, false).await
     }
 
     #[graphql(description = "A single portrait for a given emotion.")]
     async fn emotion(&self, context: &Context, emotion: String) -> FieldResult<Option<Portrait>> {
+        let emotion = context.collab.data().sprite_config.emotion(&emotion);
         Ok(get_existing_portrait_file(
             &context,
+            context.asset_store(),
             &self.0.portrait_files,
             &emotion,
-            false,
             self.1,
             &self.2,
         )
         .await?
-        .map(|locked| Portrait {
-            emotion: emotion.clone(),
-            locked,
-            url: get_url(
-                AssetType::Portrait(&emotion),
-                &context.this_server_url,
-                self.1,
-                &self.2,
-            ),
-        }))
+        .map(|locked| service::portrait(&context.url_base, self.1, &self.2, &emotion, locked)))
     }
 
     #[graphql(
         description = "A single portrait. Return the 'Normal' portrait if avalaible, but may return another one if not present."
     )]
     fn preview_emotion(&self, context: &Context) -> Option<Portrait> {
-        if let Some(locked) = self.0.portrait_files.get("Normal") {
-            Some(Portrait {
-                emotion: "Normal".to_string(),
-                locked: *locked,
-                url: get_url(
-                    AssetType::Portrait("Normal"),
-                    &context.this_server_url,
-                    self.1,
-                    &self.2,
-                ),
-            })
-        } else {
-            self.0
-                .portrait_files
-                .iter()
-                .sorted()
-                .next()
-                .map(|(emotion, locked)| Portrait {
-                    emotion: emotion.clone(),
-                    locked: *locked,
-                    url: get_url(
-                        AssetType::Portrait(emotion),
-                        &context.this_server_url,
-                        self.1,
-                        &self.2,
-                    ),
-                })
-        }
+        let (emotion, locked) = match self.0.portrait_files.get_key_value("Normal") {
+            Some(normal) => normal,
+            None => self.0.portrait_files.iter().sorted().next()?,
+        };
+        Some(service::portrait(
+            &context.url_base,
+            self.1,
+            &self.2,
+            &context.collab.data().sprite_config.emotion(emotion),
+            *locked,
+        ))
     }
 
     #[graphql(description = "A list of all existing flipped portraits for the emotions.")]
     async fn emotions_flipped(&self, context: &Context) -> FieldResult<Vec<Portrait>> {
-        Ok(
-            iter_existing_portrait_files(&context, &self.0.portrait_files, true, self.1, &self.2)
-                .await?
-                .into_iter()
-                .map(|(emotion, locked)| Portrait {
-                    emotion: emotion.clone(),
-                    locked,
-                    url: get_url(
-                        AssetType::PortraitFlipped(&emotion),
-                        &context.this_server_url,
-                        self.1,
-                        &self.2,
-                    ),
-                })
-                .collect(),
-        )
+        service::portraits(context, &self.0, self.1, &self.2, true).await
     }
 
     #[graphql(description = "A single flipped portrait for a given emotion.")]
@@ -473,25 +780,46 @@ impl MonsterFormPortraits {
         context: &Context,
         emotion: String,
     ) -> FieldResult<Option<Portrait>> {
+        let emotion = context
+            .collab
+            .data()
+            .sprite_config
+            .emotion(&emotion)
+            .flipped();
         Ok(get_existing_portrait_file(
             &context,
+            context.asset_store(),
             &self.0.portrait_files,
             &emotion,
-            true,
             self.1,
             &self.2,
         )
         .await?
-        .map(|locked| Portrait {
-            emotion: emotion.clone(),
-            locked,
-            url: get_url(
-                AssetType::PortraitFlipped(&emotion),
-                &context.this_server_url,
-                self.1,
-                &self.2,
-            ),
-        }))
+        .map(|locked| service::portrait(&context.url_base, self.1, &self.2, &emotion, locked)))
+    }
+
+    #[graphql(
+        description = "Emotions that are still missing for the portraits to reach the next completion phase, according to the completion requirements of the sprite config."
+    )]
+    async fn missing_emotions(&self, context: &Context) -> FieldResult<Vec<String>> {
+        let existing: Vec<String> = iter_existing_portrait_files(
+            &context,
+            context.asset_store(),
+            &self.0.portrait_files,
+            false,
+            self.1,
+            &self.2,
+        )
+        .await?
+        .into_iter()
+        .map(|(emotion, _)| emotion)
+        .collect();
+        let data = context.collab.data();
+        Ok(missing_for_next_phase(
+            &data.sprite_config.completion_emotions,
+            &data.sprite_config.emotions,
+            &existing,
+        ))
     }
 
     #[graphql(description = "The date and time this portrait set was last updated.")]
@@ -503,11 +831,58 @@ impl MonsterFormPortraits {
         description = "List of all modifications made to those portraits since its creation."
     )]
     async fn history(&self, context: &Context) -> FieldResult<Vec<MonsterHistory>> {
-        get_local_credits_file(&context, AssetCategory::Portrait, self.1, &self.2)
-            .await??
-            .into_iter()
-            .map(|i| MonsterHistory::try_from_credit_row(context, i))
-            .collect::<Result<Vec<_>, _>>()
+        let rows = get_local_credits_file(
+            &context,
+            context.asset_store(),
+            AssetCategory::Portrait,
+            self.1,
+            &self.2,
+        )
+        .await?
+        .map_err(failed_credits_read)?;
+        Ok(join_all(rows.into_iter().map(|row| {
+            MonsterHistory::from_credit_row(
+                context,
+                AssetCategory::Portrait,
+                &self.0,
+                self.1,
+                &self.2,
+                row,
+            )
+        }))
+        .await)
+    }
+
+    #[graphql(
+        description = "The contributors to the current portraits, with the emotions each of them is responsible for: Of every emotion, the author of its newest contribution in the history that is not obsolete."
+    )]
+    async fn relevant_credits(&self, context: &Context) -> FieldResult<Vec<RelevantCredit>> {
+        service::relevant_credits(context, AssetCategory::Portrait, self.1, &self.2).await
+    }
+
+    #[graphql(
+        description = "The license of the current portraits: The license of the newest contribution in the history that is not obsolete. Null if there is no history."
+    )]
+    async fn current_license(&self, context: &Context) -> FieldResult<Option<License>> {
+        Ok(
+            service::current_license(context, AssetCategory::Portrait, self.1, &self.2)
+                .await?
+                .map(License::from),
+        )
+    }
+
+    #[graphql(description = "Statistics of the portrait files of this form.")]
+    async fn stats(&self, context: &Context) -> FieldResult<AssetStats> {
+        Ok(get_form_file_stats(
+            &context,
+            context.asset_store(),
+            AssetCategory::Portrait,
+            &self.0.portrait_files,
+            self.1,
+            &self.2,
+        )
+        .await?
+        .into())
     }
 
     #[graphql(
@@ -516,7 +891,7 @@ impl MonsterFormPortraits {
     fn history_url(&self, context: &Context) -> Option<String> {
         Some(get_url(
             AssetType::PortraitCreditsTxt,
-            &context.this_server_url,
+            &context.url_base,
             self.1,
             &self.2,
         ))
@@ -528,64 +903,11 @@ impl MonsterFormPortraits {
 pub struct MonsterFormSprites(Arc<Group>, i32, Vec<i32>);
 
 impl MonsterFormSprites {
-    fn process_sprite_action(&self, action: &str, locked: bool, this_server_url: &str) -> Sprite {
-        Sprite {
-            anim_url: get_url(
-                AssetType::SpriteAnim(action),
-                this_server_url,
-                self.1,
-                &self.2,
-            ),
-            offsets_url: get_url(
-                AssetType::SpriteOffsets(action),
-                this_server_url,
-                self.1,
-                &self.2,
-            ),
-            shadows_url: get_url(
-                AssetType::SpriteShadows(action),
-                this_server_url,
-                self.1,
-                &self.2,
-            ),
-            action: action.to_string(),
-            locked,
-        }
+    #[inline]
+    fn sprites_available(&self) -> bool {
+        !self.0.sprite_files.is_empty()
     }
-
-    async fn fetch_xml_and_make_action_map(
-        monster_idx: i32,
-        path_to_form: &[i32],
-    ) -> FieldResult<CacheBehaviour<HashMap<String, String>>> {
-        let xml = AnimDataXml::open_for_form(monster_idx, path_to_form)
-            .map_err(Self::failed_xml_fetch)?;
-        Ok(CacheBehaviour::Cache(xml.get_action_copies()))
-    }
-
-    fn failed_xml_fetch<E: Debug>(e: E) -> FieldError {
-        let e_as_str = format!("{:?}", e);
-        FieldError::new(
-            "Internal Server Error: Failed processing the animation data from the AnimData.xml."
-                .to_string(),
-            graphql_value!({ "details": e_as_str }),
-        )
-    }
-
-    #[inline]
-    fn sprites_available(&self) -> bool {
-        !self.0.sprite_files.is_empty()
-    }
-
-    /// XXX: This isn't ideal, but Juniper is a bit silly about it's Sync requirements, so there's
-    /// currently no way to do this truly async as far as I can tell.
-    async fn get_action_map(&self, context: &Context) -> FieldResult<HashMap<String, String>> {
-        context
-            .cached_may_fail_chain(format!("/monster_actions|{}/{:?}", self.1, self.2), || {
-                Self::fetch_xml_and_make_action_map(self.1, &self.2)
-            })
-            .await
-    }
-}
+}
 
 #[graphql_object(Context = Context)]
 #[graphql(description = "Sprites for a single monster form.")]
@@ -611,29 +933,13 @@ impl MonsterFormSprites {
     }
 
     #[graphql(description = "Primary artist credits.")]
-    fn credit_primary(&self, context: &Context) -> FieldResult<Option<Credit>> {
-        let credit_id = parse_credit_id(&self.0.sprite_credit.primary);
-        if credit_id.is_empty() {
-            Ok(None)
-        } else {
-            Credit::new(
-                context.collab.data().credit_names.get(&credit_id),
-                &credit_id,
-            )
-            .map(Some)
-        }
+    async fn credit_primary(&self, context: &Context) -> Option<Credit> {
+        service::credit(context, &self.0.sprite_credit.primary).await
     }
 
     #[graphql(description = "All other artists credited.")]
-    fn credit_secondary(&self, context: &Context) -> FieldResult<Vec<Credit>> {
-        let names = &context.collab.data().credit_names;
-        self.0
-            .sprite_credit
-            .secondary
-            .iter()
-            .map(parse_credit_id)
-            .map(|v| Credit::new(names.get(&v), &v))
-            .collect()
+    async fn credit_secondary(&self, context: &Context) -> Vec<Credit> {
+        service::credits(context, &self.0.sprite_credit.secondary).await
     }
 
     #[graphql(description = "URL to the AnimData XML file for this sprite set.")]
@@ -641,7 +947,7 @@ impl MonsterFormSprites {
         if self.sprites_available() {
             Some(get_url(
                 AssetType::SpriteAnimDataXml,
-                &context.this_server_url,
+                &context.url_base,
                 self.1,
                 &self.2,
             ))
@@ -650,12 +956,14 @@ impl MonsterFormSprites {
         }
     }
 
-    #[graphql(description = "URL to a SpriteBot format ZIP archive of all sprites.")]
+    #[graphql(
+        description = "URL to a SpriteBot format ZIP archive of all sprites. The archive also contains a manifest.json listing all actions with their frame sizes and durations, and any files that are missing. Add the query parameter 'resolve_copies=true' to also include the files of actions that are a copy of another action, under their own name."
+    )]
     fn zip_url(&self, context: &Context) -> Option<String> {
         if self.sprites_available() {
             Some(get_url(
                 AssetType::SpriteZip,
-                &context.this_server_url,
+                &context.url_base,
                 self.1,
                 &self.2,
             ))
@@ -669,7 +977,7 @@ impl MonsterFormSprites {
         if self.sprites_available() {
             Some(get_url(
                 AssetType::SpriteRecolorSheet,
-                &context.this_server_url,
+                &context.url_base,
                 self.1,
                 &self.2,
             ))
@@ -680,87 +988,31 @@ impl MonsterFormSprites {
 
     #[graphql(description = "A list of all existing sprites for the actions.")]
     async fn actions(&self, context: &Context) -> FieldResult<Vec<SpriteUnion>> {
-        if self.sprites_available() {
-            let action_copy_map = self.get_action_map(context).await?;
-            // TODO: needed because of borrow in closure. can this be optimized?
-            let action_copy_map_clone = action_copy_map.clone();
-            let mut normal_sprites: HashMap<String, Sprite> =
-                iter_existing_sprite_files(&context, &self.0.sprite_files, self.1, &self.2)
-                    .await?
-                    .into_iter()
-                    .filter_map(|(action, locked)| {
-                        // Copy ofs shouldn't appear here since they shouldn't have any sheets, but
-                        // if they do, we filter them out, since we explicitly add them below.
-                        if action_copy_map_clone.contains_key(&action) {
-                            None
-                        } else {
-                            let action_clone = action.clone();
-                            Some((
-                                action,
-                                self.process_sprite_action(
-                                    &action_clone,
-                                    locked,
-                                    &context.this_server_url,
-                                ),
-                            ))
-                        }
-                    })
-                    .collect();
-
-            let mut copy_of_sprites: HashMap<String, CopyOf> = action_copy_map
-                .into_iter()
-                .map(|(action, copy_of)| {
-                    let action_clone = action.clone();
-                    (
-                        action,
-                        CopyOf {
-                            locked: self
-                                .0
-                                .sprite_files
-                                .get(&action_clone)
-                                .copied()
-                                .unwrap_or_default(),
-                            action: action_clone,
-                            copy_of: copy_of.to_string(),
-                        },
-                    )
-                })
-                .collect();
-
-            let sprites_iter = self.0.sprite_files.keys().filter_map(|k| {
-                if let Some(sprite) = normal_sprites.remove(k) {
-                    Some(SpriteUnion::Sprite(sprite))
-                } else {
-                    copy_of_sprites.remove(k).map(SpriteUnion::CopyOf)
-                }
-            });
-
-            Ok(sprites_iter.collect())
-        } else {
-            Ok(vec![])
-        }
+        service::sprite_actions(context, &self.0, self.1, &self.2).await
     }
 
     #[graphql(description = "A single sprite for a given action.")]
     async fn action(&self, context: &Context, action: String) -> FieldResult<Option<SpriteUnion>> {
         if self.sprites_available() {
-            let action_copy_map = self.get_action_map(context).await?;
-            if let Some(copy_of) = action_copy_map.get(&action) {
+            let action = context.collab.data().sprite_config.action(&action);
+            let action_copy_map = service::sprite_action_copies(context, self.1, &self.2).await?;
+            if let Some(copy_of) = action_copy_map.get(action.as_str()) {
                 // Copy of
                 Ok(Some(SpriteUnion::CopyOf(CopyOf {
                     locked: self
                         .0
                         .sprite_files
-                        .get(&action)
+                        .get(action.as_str())
                         .copied()
                         .unwrap_or_default(),
-                    action,
+                    action: action.to_string(),
                     copy_of: copy_of.to_string(),
                 })))
             } else {
                 // Regular sprite
                 Ok(get_existing_sprite_file(
                     &context,
+                    context.asset_store(),
                     &self.0.sprite_files,
                     &action,
                     self.1,
@@ -768,10 +1020,12 @@ impl MonsterFormSprites {
                 )
                 .await?
                 .map(|locked| {
-                    SpriteUnion::Sprite(self.process_sprite_action(
+                    SpriteUnion::Sprite(service::sprite(
+                        &context.url_base,
+                        self.1,
+                        &self.2,
                         &action,
                         locked,
-                        &context.this_server_url,
                     ))
                 }))
             }
@@ -780,6 +1034,37 @@ impl MonsterFormSprites {
         }
     }
 
+    #[graphql(
+        description = "Actions that are still missing for the sprites to reach the next completion phase, according to the completion requirements of the sprite config."
+    )]
+    async fn missing_actions(&self, context: &Context) -> FieldResult<Vec<String>> {
+        let mut existing: Vec<String> = iter_existing_sprite_files(
+            &context,
+            context.asset_store(),
+            &self.0.sprite_files,
+            self.1,
+            &self.2,
+        )
+        .await?
+        .into_iter()
+        .map(|(action, _)| action)
+        .collect();
+        if self.sprites_available() {
+            // Copies of other actions have no sheets of their own, but count as existing.
+            existing.extend(
+                service::sprite_action_copies(context, self.1, &self.2)
+                    .await?
+                    .into_keys(),
+            );
+        }
+        let data = context.collab.data();
+        Ok(missing_for_next_phase(
+            &data.sprite_config.completion_actions,
+            &data.sprite_config.actions,
+            &existing,
+        ))
+    }
+
     #[graphql(description = "The date and time this sprite set was last updated.")]
     fn modified_date(&self) -> Option<DateTime<Utc>> {
         self.0.sprite_modified
@@ -787,11 +1072,58 @@ impl MonsterFormSprites {
 
     #[graphql(description = "List of all modifications made to those sprites since its creation.")]
     async fn history(&self, context: &Context) -> FieldResult<Vec<MonsterHistory>> {
-        get_local_credits_file(&context, AssetCategory::Sprite, self.1, &self.2)
-            .await??
-            .into_iter()
-            .map(|i| MonsterHistory::try_from_credit_row(context, i))
-            .collect::<Result<Vec<_>, _>>()
+        let rows = get_local_credits_file(
+            &context,
+            context.asset_store(),
+            AssetCategory::Sprite,
+            self.1,
+            &self.2,
+        )
+        .await?
+        .map_err(failed_credits_read)?;
+        Ok(join_all(rows.into_iter().map(|row| {
+            MonsterHistory::from_credit_row(
+                context,
+                AssetCategory::Sprite,
+                &self.0,
+                self.1,
+                &self.2,
+                row,
+            )
+        }))
+        .await)
+    }
+
+    #[graphql(
+        description = "The contributors to the current sprites, with the actions each of them is responsible for: Of every action, the author of its newest contribution in the history that is not obsolete."
+    )]
+    async fn relevant_credits(&self, context: &Context) -> FieldResult<Vec<RelevantCredit>> {
+        service::relevant_credits(context, AssetCategory::Sprite, self.1, &self.2).await
+    }
+
+    #[graphql(
+        description = "The license of the current sprites: The license of the newest contribution in the history that is not obsolete. Null if there is no history."
+    )]
+    async fn current_license(&self, context: &Context) -> FieldResult<Option<License>> {
+        Ok(
+            service::current_license(context, AssetCategory::Sprite, self.1, &self.2)
+                .await?
+                .map(License::from),
+        )
+    }
+
+    #[graphql(description = "Statistics of the sprite files of this form.")]
+    async fn stats(&self, context: &Context) -> FieldResult<AssetStats> {
+        Ok(get_form_file_stats(
+            &context,
+            context.asset_store(),
+            AssetCategory::Sprite,
+            &self.0.sprite_files,
+            self.1,
+            &self.2,
+        )
+        .await?
+        .into())
     }
 
     #[graphql(
@@ -800,22 +1132,57 @@ impl MonsterFormSprites {
     fn history_url(&self, context: &Context) -> Option<String> {
         Some(get_url(
             AssetType::SpriteCreditsTxt,
-            &context.this_server_url,
+            &context.url_base,
             self.1,
             &self.2,
         ))
     }
 }
 
+/// Objects with a global ID, that can be refetched with the `node` query.
+#[graphql_interface(for = [Monster, MonsterForm, Credit], context = Context)]
+#[graphql(description = "An object with a global ID, that can be refetched with the node query.")]
+pub trait Node {
+    #[graphql(description = "Global ID of this object, unique across all types.")]
+    fn id(&self) -> ID;
+}
+
 pub struct MonsterForm {
-    id: i32,
-    form_id: Vec<i32>,
-    name_path: Vec<String>,
-    data: Arc<Group>,
+    pub(crate) id: i32,
+    pub(crate) form_id: Vec<i32>,
+    pub(crate) name_path: Vec<String>,
+    pub(crate) data: Arc<Group>,
 }
 
-#[graphql_object(Context = Context)]
 impl MonsterForm {
+    pub(crate) fn new(id: i32, form_id: Vec<i32>, name_path: Vec<String>, data: &Group) -> Self {
+        Self {
+            id,
+            form_id,
+            name_path,
+            data: Arc::new(data.clone()),
+        }
+    }
+
+    /// Looks up the form at exactly this path.
+    fn find_exact(tracker: &Tracker, monster_idx: i32, form_path: &[i32]) -> Option<Self> {
+        service::find_form(
+            tracker,
+            monster_idx,
+            form_path.iter().copied().map(FormMatch::Exact),
+        )
+        .ok()
+        .flatten()
+    }
+}
+
+#[graphql_object(Context = Context, impl = NodeValue)]
+impl MonsterForm {
+    #[graphql(description = "Global ID of this form, see the node query.")]
+    fn id(&self) -> ID {
+        ID::new(NodeId::MonsterForm(self.id, self.form_id.clone()).encode())
+    }
+
     #[graphql(description = "The ID of the monster, that this form belongs to.")]
     fn monster_id(&self) -> i32 {
         self.id
@@ -825,29 +1192,34 @@ impl MonsterForm {
         description = "The path to this form (without the monster ID) as it's specified in the SpriteCollab tracker.json file and repository file structure."
     )]
     fn path(&self) -> String {
-        let mut path = self.form_id.iter().map(|v| format!("{:04}", v)).join("/");
-        if path.ends_with('/') {
-            path.truncate(path.len() - 1);
-        }
-        path
+        service::form_path(&self.form_id)
     }
 
     #[graphql(
         description = "The path to this form (including the monster ID) as it's specified in the SpriteCollab tracker.json file and repository file structure."
     )]
     fn full_path(&self) -> String {
-        let mut path = once(format!("{:04}", self.id))
-            .chain(self.form_id.iter().map(|v| format!("{:04}", v)))
-            .join("/");
-        if path.ends_with('/') {
-            path.truncate(path.len() - 1);
-        }
-        path
+        service::full_form_path(self.id, &self.form_id)
     }
 
     #[graphql(description = "Human-readable name of this form.")]
-    fn name(&self) -> String {
-        self.data.name.clone()
+    fn name(
+        &self,
+        context: &Context,
+        #[graphql(
+            description = "Language to return the name in, eg. 'de'. Falls back to English if there is no translation."
+        )]
+        lang: Option<String>,
+    ) -> String {
+        lang.and_then(|lang| {
+            context
+                .collab
+                .data()
+                .translations
+                .name(&lang, self.id, &self.form_id)
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| self.data.name.clone())
     }
 
     #[graphql(
@@ -857,6 +1229,20 @@ impl MonsterForm {
         self.name_path.iter().cloned().join(" ")
     }
 
+    #[graphql(
+        description = "Community nicknames of exactly this form, that are also found by the searches."
+    )]
+    fn aliases(&self, context: &Context) -> Vec<String> {
+        context
+            .collab
+            .data()
+            .aliases
+            .of_form(self.id, &self.form_id)
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
     #[graphql(description = "Whether or not this form is considered for a shiny.")]
     fn is_shiny(&self) -> bool {
         MonsterFormCollector::is_shiny(&self.form_id)
@@ -872,6 +1258,13 @@ impl MonsterForm {
         self.data.canon
     }
 
+    #[graphql(
+        description = "URL to a small preview image of this form: The Normal portrait, or the first frame of the Idle sprite if there are no portraits. The image can be scaled to a maximum width or height (up to 512 pixels) with the 'size' query parameter."
+    )]
+    fn preview_url(&self, context: &Context) -> Option<String> {
+        service::preview_url(&context.url_base, self)
+    }
+
     #[graphql(description = "Portraits for this form.")]
     fn portraits(&self) -> MonsterFormPortraits {
         MonsterFormPortraits(self.data.clone(), self.id, self.form_id.clone())
@@ -888,14 +1281,52 @@ pub struct Monster {
     id: i32,
 }
 
-fn monster_not_found(id: i32) -> FieldError {
-    FieldError::new("Monster not found", graphql_value!({ "id": id }))
+#[derive(GraphQLEnum, Clone, Copy)]
+#[graphql(description = "Order of a list of monsters.")]
+pub enum MonsterSort {
+    #[graphql(description = "By ID.")]
+    Id,
+    #[graphql(description = "Alphabetically by name.")]
+    Name,
+    #[graphql(
+        description = "By the last modification of any portrait or sprite of any form, most recent first."
+    )]
+    LastModified,
 }
 
-#[graphql_object(Context = Context)]
+/// Lists the monsters in the tracker for which `include` returns true, in tracker order or
+/// sorted by `sort`.
+fn list_monsters<F>(tracker: &Tracker, sort: Option<MonsterSort>, include: F) -> Vec<Monster>
+where
+    F: Fn(&GroupId, &Group) -> bool,
+{
+    let mut monsters = tracker
+        .iter()
+        .filter(|(idx, group)| include(idx, group))
+        .collect::<Vec<_>>();
+    match sort {
+        None => {}
+        Some(MonsterSort::Id) => monsters.sort_by_key(|(idx, _)| **idx),
+        Some(MonsterSort::Name) => monsters.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name)),
+        Some(MonsterSort::LastModified) => {
+            monsters.sort_by_key(|(_, group)| Reverse(group.last_modified()))
+        }
+    }
+    monsters
+        .into_iter()
+        .map(|(idx, _)| Monster { id: **idx as i32 })
+        .collect()
+}
+
+#[graphql_object(Context = Context, impl = NodeValue)]
 impl Monster {
+    #[graphql(description = "Global ID of this monster, see the node query.")]
+    fn id(&self) -> ID {
+        ID::new(NodeId::Monster(self.id).encode())
+    }
+
     #[graphql(description = "ID of this monster.")]
-    async fn id(&self) -> FieldResult<i32> {
+    async fn monster_id(&self) -> FieldResult<i32> {
         Ok(self.id)
     }
 
@@ -907,32 +1338,39 @@ impl Monster {
     }
 
     #[graphql(description = "Human-readable name of this monster.")]
-    fn name(&self, context: &Context) -> FieldResult<String> {
-        context
-            .collab
-            .data()
-            .tracker
-            .get(&GroupId(self.id as i64))
-            .ok_or_else(|| monster_not_found(self.id))
-            .map(|monster| monster.name.clone())
+    fn name(
+        &self,
+        context: &Context,
+        #[graphql(
+            description = "Language to return the name in, eg. 'de'. Falls back to English if there is no translation."
+        )]
+        lang: Option<String>,
+    ) -> FieldResult<String> {
+        let data = context.collab.data();
+        let monster = service::monster_group(&data.tracker, self.id)?;
+        Ok(lang
+            .and_then(|lang| data.translations.name(&lang, self.id, &[]))
+            .unwrap_or(monster.name.as_str())
+            .to_string())
+    }
+
+    #[graphql(
+        description = "Date of the last modification of any portrait or sprite of any form of this monster."
+    )]
+    fn last_modified(&self, context: &Context) -> FieldResult<Option<DateTime<Utc>>> {
+        service::monster_group(&context.collab.data().tracker, self.id).map(Group::last_modified)
     }
 
     #[graphql(description = "All forms that exist for this monster.")]
     fn forms(&self, context: &Context) -> FieldResult<Vec<MonsterForm>> {
-        match MonsterFormCollector::collect(&context.collab.data().tracker, self.id) {
-            Some(collector) => Ok(collector
-                .map(|(k, name_path, v)| MonsterForm {
-                    id: self.id,
-                    form_id: k,
-                    name_path,
-                    data: Arc::new(v.clone()),
-                })
-                .collect()),
-            None => Err(FieldError::new(
-                "Monster not found",
-                graphql_value!({ "id": (self.id) }),
-            )),
-        }
+        service::monster_forms(&context.collab.data().tracker, self.id)
+    }
+
+    #[graphql(
+        description = "All contributors to the portraits and sprites of any form of this monster (including shiny and female forms), without obsolete contributions. Useful for attributing all assets of a monster at once."
+    )]
+    async fn contributors(&self, context: &Context) -> FieldResult<Vec<MonsterContributor>> {
+        service::monster_contributors(context, self.id).await
     }
 
     #[graphql(description = "Get a specific form for this monster.")]
@@ -944,205 +1382,714 @@ impl Monster {
         female: bool,
     ) -> FieldResult<Option<MonsterForm>> {
         // <poke id>/<form index>/<shiny? - yes: 0001, no: 0000>/<female? - yes: 0002, no: 0001>
-        match MonsterFormCollector::collect(&context.collab.data().tracker, self.id) {
-            Some(collector) => Ok(collector
-                .find_form([
-                    FormMatch::Exact(form_id),
-                    FormMatch::Exact(if shiny { 1 } else { 0 }),
-                    if female {
-                        FormMatch::Exact(2)
-                    } else {
-                        FormMatch::Fallback(1)
-                    },
-                ])
-                .map(|(path, name_path, v)| MonsterForm {
-                    id: self.id,
-                    form_id: path,
-                    name_path,
-                    data: Arc::new(v.clone()),
-                })),
-            None => Err(FieldError::new(
-                "Monster not found",
-                graphql_value!({ "id": (self.id) }),
-            )),
-        }
+        service::find_form(
+            &context.collab.data().tracker,
+            self.id,
+            [
+                FormMatch::Exact(form_id),
+                FormMatch::Exact(if shiny { 1 } else { 0 }),
+                if female {
+                    FormMatch::Exact(2)
+                } else {
+                    FormMatch::Fallback(1)
+                },
+            ],
+        )
     }
 
     #[graphql(
         description = "Manually enter the path to a monster, seperated by /. This should match the path as it is stored in SpriteCollab, however the path passed in might be collapsed until a unique form is found."
     )]
     fn manual(&self, context: &Context, path: String) -> FieldResult<Option<MonsterForm>> {
-        let form_needle: Result<Vec<i32>, _> = path
-            .split('/')
-            .filter(|v| !v.is_empty())
-            .map(|v| v.parse::<i32>())
-            .collect();
-        match form_needle {
-            Ok(form_needle) => {
-                match MonsterFormCollector::collect(&context.collab.data().tracker, self.id) {
-                    Some(collector) => Ok(collector
-                        .find_form(form_needle.into_iter().map(FormMatch::Exact))
-                        .map(|(path, name_path, v)| MonsterForm {
-                            id: self.id,
-                            form_id: path,
-                            name_path,
-                            data: Arc::new(v.clone()),
-                        })),
-                    None => Err(FieldError::new(
-                        "Monster not found",
-                        graphql_value!({ "id": (self.id) }),
-                    )),
-                }
-            }
-            Err(e) => {
-                let e_dbg = format!("{:?}", e);
-                Err(FieldError::new(
-                    "Invalid path.",
-                    graphql_value!({ "details": e_dbg }),
-                ))
-            }
-        }
+        let form_needle = parse_form_path(&path)?;
+        service::find_form(
+            &context.collab.data().tracker,
+            self.id,
+            form_needle.into_iter().map(FormMatch::Exact),
+        )
     }
 }
 
-#[derive(GraphQLObject)]
-#[graphql(description = "An action mapped uniquely to an ID.")]
-pub struct ActionId {
-    id: i32,
-    name: String,
-}
-
-#[derive(GraphQLObject)]
-#[graphql(description = "Configuration for this instance of SpriteCollab.")]
-pub struct Config {
-    #[graphql(description = "The portrait width and height in pixels.")]
-    portrait_size: i32,
-    #[graphql(description = "How many portraits per row a portrait sheet contains.")]
-    portrait_tile_x: i32,
-    #[graphql(description = "How many rows a portrait sheet contains.")]
-    portrait_tile_y: i32,
-    #[graphql(description = "A list of known emotions. The position is the ID of the emotion.")]
-    emotions: Vec<String>,
-    #[graphql(description = "A list of known action. The position is the ID of the action.")]
-    actions: Vec<String>,
-    #[graphql(
-        description = "Returns a list, that for each phase contains a list of emotions (by index) that need to exist for this phase to be considered completed."
-    )]
-    completion_emotions: Vec<Vec<i32>>,
-    #[graphql(
-        description = "Returns a list, that for each phase contains a list of actions (by index) that need to exist for this phase to be considered completed."
-    )]
-    completion_actions: Vec<Vec<i32>>,
-    #[graphql(description = "A mapping of actions to EoS action indices.")]
-    action_map: Vec<ActionId>,
+#[derive(GraphQLEnum)]
+#[graphql(description = "Whether an asset is a portrait emotion or a sprite action.")]
+pub enum AssetSearchCategory {
+    #[graphql(description = "A portrait emotion.")]
+    Portrait,
+    #[graphql(description = "A sprite action.")]
+    Sprite,
 }
 
-impl From<&SpriteConfig> for Config {
-    fn from(c: &SpriteConfig) -> Self {
-        Self {
-            portrait_size: c.portrait_size,
-            portrait_tile_x: c.portrait_tile_x,
-            portrait_tile_y: c.portrait_tile_y,
-            emotions: c.emotions.clone(),
-            actions: c.actions.clone(),
-            completion_emotions: c.completion_emotions.clone(),
-            completion_actions: c.completion_actions.clone(),
-            action_map: c
-                .action_map
-                .iter()
-                .map(|(idx, act)| ActionId {
-                    id: *idx,
-                    name: act.clone(),
-                })
-                .collect(),
+impl From<AssetCategory> for AssetSearchCategory {
+    fn from(category: AssetCategory) -> Self {
+        match category {
+            AssetCategory::Portrait => AssetSearchCategory::Portrait,
+            AssetCategory::Sprite => AssetSearchCategory::Sprite,
         }
     }
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct Credit {
-    id: String,
-    name: Option<String>,
-    contact: Option<String>,
-}
+pub struct AssetSearchResult(AssetSearchEntry);
 
 #[graphql_object(Context = Context)]
-impl Credit {
-    #[graphql(description = "Discord ID or absentee ID. Guaranteed to be an ASCII string.")]
-    fn id(&self) -> String {
-        self.id.clone()
+impl AssetSearchResult {
+    #[graphql(description = "Whether this is a portrait emotion or a sprite action.")]
+    fn category(&self) -> AssetSearchCategory {
+        self.0.category.into()
     }
 
-    #[graphql(
-        description = "The human-readable name of the author. Guaranteed to be an ASCII string."
-    )]
-    fn name(&self) -> Option<String> {
-        self.name.clone()
+    #[graphql(description = "Name of the emotion or action.")]
+    fn name(&self) -> &str {
+        &self.0.name
     }
 
-    #[graphql(description = "Contact information for this author.")]
-    fn contact(&self) -> Option<String> {
-        self.contact.clone()
+    #[graphql(description = "All monster forms that currently have this emotion or action.")]
+    fn forms(&self, context: &Context) -> Vec<MonsterForm> {
+        let tracker = context.collab.data().tracker.clone();
+        self.0
+            .forms
+            .iter()
+            .filter_map(|(monster_idx, form_path)| {
+                MonsterForm::find_exact(&tracker, *monster_idx, form_path)
+            })
+            .collect()
     }
+}
 
+#[derive(GraphQLEnum)]
+#[graphql(description = "The kind of a problem found by the data integrity checks.")]
+pub enum DataIntegrityIssueKind {
+    #[graphql(description = "A file listed in the tracker does not exist.")]
+    MissingFile,
+    #[graphql(description = "A file exists, but is not listed in the tracker.")]
+    UntrackedFile,
+    #[graphql(description = "A credit ID used in the tracker is not in the credit names.")]
+    UnknownCreditId,
     #[graphql(
-        description = "This used to return the Discord handle of this author, if applicable and possible. It will now always return null.",
-        deprecated = "This is no longer implemented and will always return null. It may or may not be re-introduced in future versions."
+        description = "The completion phase in the tracker is higher than the existing files allow, according to the completion requirements of the sprite config."
     )]
-    async fn discord_handle(&self) -> FieldResult<Option<String>> {
-        Ok(None)
-    }
+    InconsistentPhase,
 }
 
-impl Credit {
-    fn new(credit_entry: Option<&CreditNamesRow>, credit_id: &str) -> FieldResult<Credit> {
-        credit_entry
-            .map(|v| Self {
-                id: v.credit_id.clone(),
-                name: v.name.as_ref().cloned(),
-                contact: v.contact.as_ref().cloned(),
-            })
-            .ok_or_else(|| {
-                FieldError::new(
-                    "Internal error. Could not resolved credit ID.",
-                    graphql_value!({ "credit_id": (credit_id) }),
-                )
-            })
+impl From<IntegrityIssueKind> for DataIntegrityIssueKind {
+    fn from(kind: IntegrityIssueKind) -> Self {
+        match kind {
+            IntegrityIssueKind::MissingFile => DataIntegrityIssueKind::MissingFile,
+            IntegrityIssueKind::UntrackedFile => DataIntegrityIssueKind::UntrackedFile,
+            IntegrityIssueKind::UnknownCreditId => DataIntegrityIssueKind::UnknownCreditId,
+            IntegrityIssueKind::InconsistentPhase => DataIntegrityIssueKind::InconsistentPhase,
+        }
     }
 }
 
-impl From<&CreditNamesRow> for Credit {
-    fn from(c: &CreditNamesRow) -> Self {
+#[derive(GraphQLObject)]
+#[graphql(description = "A problem found by the data integrity checks.")]
+pub struct DataIntegrityIssue {
+    kind: DataIntegrityIssueKind,
+    #[graphql(description = "Full path of the affected form, eg. 0025/0000/0001.")]
+    form_path: String,
+    #[graphql(
+        description = "The affected file (relative to the repository), credit ID or a description of the problem."
+    )]
+    details: String,
+}
+
+impl From<&IntegrityIssue> for DataIntegrityIssue {
+    fn from(issue: &IntegrityIssue) -> Self {
         Self {
-            id: c.credit_id.clone(),
-            name: c.name.clone(),
-            contact: c.contact.clone(),
+            kind: issue.kind.into(),
+            form_path: issue.form_path.clone(),
+            details: issue.details.clone(),
         }
     }
 }
 
-pub struct Context {
-    this_server_url: String,
-    collab: Arc<SpriteCollab>,
+#[derive(GraphQLEnum)]
+#[graphql(description = "The kind of an error reading a data file.")]
+pub enum DataFileErrorKind {
+    #[graphql(
+        description = "The file is not valid JSON, or doesn't match the expected structure."
+    )]
+    Json,
+    #[graphql(description = "The file is not valid CSV, or doesn't match the expected structure.")]
+    Csv,
+    #[graphql(description = "An XML file (AnimData.xml) could not be parsed.")]
+    Xml,
+    #[graphql(description = "The file could not be opened or read.")]
+    Io,
+    #[graphql(description = "A credit ID appears more than once in the credit names.")]
+    DuplicateCreditId,
 }
 
-impl Context {
-    pub fn new(collab: Arc<SpriteCollab>) -> Self {
-        Context {
-            this_server_url: SystemConfig::Address.get_or_none().unwrap_or_default(),
-            collab,
+impl From<FileErrorKind> for DataFileErrorKind {
+    fn from(kind: FileErrorKind) -> Self {
+        match kind {
+            FileErrorKind::Json => DataFileErrorKind::Json,
+            FileErrorKind::Csv => DataFileErrorKind::Csv,
+            FileErrorKind::Xml => DataFileErrorKind::Xml,
+            FileErrorKind::Io => DataFileErrorKind::Io,
+            FileErrorKind::DuplicateCreditId => DataFileErrorKind::DuplicateCreditId,
         }
     }
 }
 
-#[async_trait]
-impl ScCache for Context {
-    type Error = FieldError;
+#[derive(GraphQLObject)]
+#[graphql(description = "An error reading a data file of the assets repository.")]
+pub struct DataFileDiagnostic {
+    #[graphql(description = "Path of the file, relative to the repository.")]
+    path: String,
+    kind: DataFileErrorKind,
+    message: String,
+    #[graphql(description = "Line of the error (1-based), if known.")]
+    line: Option<i32>,
+    #[graphql(description = "Column of the error (1-based), if known.")]
+    column: Option<i32>,
+}
 
-    async fn cached_may_fail<S, Fn, Ft, T, E>(
-        &self,
-        cache_key: S,
-        func: Fn,
+impl From<&FileDiagnostic> for DataFileDiagnostic {
+    fn from(diagnostic: &FileDiagnostic) -> Self {
+        Self {
+            path: diagnostic.path.clone(),
+            kind: diagnostic.kind.into(),
+            message: diagnostic.message.clone(),
+            line: diagnostic.line.map(|line| line as i32),
+            column: diagnostic.column.map(|column| column as i32),
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "The outcome of a refresh of the data.")]
+pub struct DataRefreshReport {
+    date: DateTime<Utc>,
+    #[graphql(
+        description = "The commit that is served after the refresh. If the refresh failed, the data of this commit stays served."
+    )]
+    commit: String,
+    success: bool,
+    #[graphql(description = "The error the refresh failed with, if it failed.")]
+    error: Option<String>,
+    #[graphql(description = "The data files that could not be read.")]
+    files: Vec<DataFileDiagnostic>,
+    #[graphql(
+        description = "Credit IDs used in the tracker, but missing in the credit names. They are returned as unresolved credits."
+    )]
+    unknown_credit_ids: Vec<String>,
+}
+
+impl From<&RefreshReport> for DataRefreshReport {
+    fn from(report: &RefreshReport) -> Self {
+        Self {
+            date: report.date,
+            commit: report.commit.clone(),
+            success: report.success,
+            error: report.error.clone(),
+            files: report.files.iter().map(Into::into).collect(),
+            unknown_credit_ids: report.unknown_credit_ids.clone(),
+        }
+    }
+}
+
+#[derive(GraphQLEnum)]
+#[graphql(description = "Whether an activity added or updated portraits or sprites.")]
+pub enum ActivityCategory {
+    Portrait,
+    Sprite,
+}
+
+impl From<AssetCategory> for ActivityCategory {
+    fn from(category: AssetCategory) -> Self {
+        match category {
+            AssetCategory::Portrait => ActivityCategory::Portrait,
+            AssetCategory::Sprite => ActivityCategory::Sprite,
+        }
+    }
+}
+
+impl From<ActivityCategory> for AssetCategory {
+    fn from(category: ActivityCategory) -> Self {
+        match category {
+            ActivityCategory::Portrait => AssetCategory::Portrait,
+            ActivityCategory::Sprite => AssetCategory::Sprite,
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    context = Context,
+    description = "How many activities (added or updated portraits or sprites of a form) an author is credited for."
+)]
+pub struct ContributorActivity {
+    credit: Credit,
+    #[graphql(description = "Activities that added or updated portraits.")]
+    portraits: i32,
+    #[graphql(description = "Activities that added or updated sprites.")]
+    sprites: i32,
+    total: i32,
+    #[graphql(description = "Modification date of the newest activity.")]
+    last_activity: DateTime<Utc>,
+}
+
+impl ContributorActivity {
+    fn new(stats: ContributorStats, context: &Context) -> Self {
+        let credit_names = &context.collab.data().credit_names;
+        Self {
+            credit: Credit::new(credit_names.get(&stats.credit_id), &stats.credit_id),
+            portraits: stats.portraits as i32,
+            sprites: stats.sprites as i32,
+            total: (stats.portraits + stats.sprites) as i32,
+            last_activity: stats.last_activity,
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    description = "An activity (added or updated portraits or sprites of a form) an author is credited for."
+)]
+pub struct CreditActivityEntry {
+    #[graphql(description = "The commit of the assets repository the activity is part of.")]
+    commit: String,
+    monster_id: i32,
+    #[graphql(description = "Full path of the form, eg. 0025/0000/0001.")]
+    form_path: String,
+    category: ActivityCategory,
+    modified_date: DateTime<Utc>,
+    #[graphql(
+        description = "Whether the author is the primary credit of the portraits or sprites."
+    )]
+    primary: bool,
+    #[graphql(
+        description = "URL of an image of the portraits (Normal emotion) or sprites (first frame of Idle) before and after the activity, side by side."
+    )]
+    diff_url: String,
+    #[graphql(description = "Pass this as `after` to get the activities after this one.")]
+    cursor: String,
+}
+
+impl From<CreditActivity> for CreditActivityEntry {
+    fn from(activity: CreditActivity) -> Self {
+        Self {
+            cursor: ActivityCursor::from(&activity).encode(),
+            diff_url: activity_diff_url(&activity.commit, activity.category, &activity.form_path),
+            commit: activity.commit,
+            monster_id: activity.monster_id,
+            form_path: activity.form_path,
+            category: activity.category.into(),
+            modified_date: activity.modified_date,
+            primary: activity.primary,
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "A page of the activities of an author, newest first.")]
+pub struct CreditActivityPage {
+    activities: Vec<CreditActivityEntry>,
+    #[graphql(description = "The cursor of the last activity of this page.")]
+    end_cursor: Option<String>,
+    has_next_page: bool,
+}
+
+#[derive(GraphQLEnum)]
+#[graphql(description = "How a file changed between two commits.")]
+pub enum FileChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "A portrait or sprite file of a form that changed between two commits.")]
+pub struct FormFileChange {
+    category: ActivityCategory,
+    #[graphql(description = "Path of the file in the assets repository.")]
+    path: String,
+    #[graphql(
+        description = "The emotion or action of the file, null for files of the whole form, like credits.txt or AnimData.xml."
+    )]
+    name: Option<String>,
+    change: FileChangeKind,
+    #[graphql(description = "ID of the blob of the file in the first commit.")]
+    from_oid: Option<String>,
+    #[graphql(description = "ID of the blob of the file in the second commit.")]
+    to_oid: Option<String>,
+}
+
+impl From<form_changes::FormFileChange> for FormFileChange {
+    fn from(change: form_changes::FormFileChange) -> Self {
+        Self {
+            category: change.info.category.into(),
+            path: change.path,
+            name: change.info.name,
+            change: match (change.from_oid, change.to_oid) {
+                (None, _) => FileChangeKind::Added,
+                (_, None) => FileChangeKind::Deleted,
+                _ => FileChangeKind::Modified,
+            },
+            from_oid: change.from_oid.map(|oid| oid.to_string()),
+            to_oid: change.to_oid.map(|oid| oid.to_string()),
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "The portraits and sprites of a form that changed between two commits.")]
+pub struct FormChanges {
+    #[graphql(description = "Emotions with added, modified or deleted portraits.")]
+    changed_emotions: Vec<String>,
+    #[graphql(description = "Actions with added, modified or deleted sprite files.")]
+    changed_actions: Vec<String>,
+    files: Vec<FormFileChange>,
+}
+
+impl From<Vec<form_changes::FormFileChange>> for FormChanges {
+    fn from(changes: Vec<form_changes::FormFileChange>) -> Self {
+        let changed_names = |category| {
+            changes
+                .iter()
+                .filter(|change| change.info.category == category)
+                .filter_map(|change| change.info.name.clone())
+                .unique()
+                .collect()
+        };
+        Self {
+            changed_emotions: changed_names(AssetCategory::Portrait),
+            changed_actions: changed_names(AssetCategory::Sprite),
+            files: changes.into_iter().map(FormFileChange::from).collect(),
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "The number of forms in a phase.")]
+pub struct PhaseCount {
+    phase: Phase,
+    #[graphql(description = "The raw phase value of the tracker.")]
+    phase_raw: i32,
+    count: i32,
+}
+
+fn phase_counts(phases: &BTreeMap<i64, usize>) -> Vec<PhaseCount> {
+    phases
+        .iter()
+        .map(|(phase, count)| PhaseCount {
+            phase: Phase::from(*phase),
+            phase_raw: *phase as i32,
+            count: *count as i32,
+        })
+        .collect()
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "Statistics of the whole project.")]
+pub struct ProjectStats {
+    #[graphql(description = "Number of monsters.")]
+    monster_count: i32,
+    #[graphql(description = "Number of forms, including the base form of each monster.")]
+    form_count: i32,
+    #[graphql(description = "Number of forms per portrait phase.")]
+    portrait_phases: Vec<PhaseCount>,
+    #[graphql(description = "Number of forms per sprite phase.")]
+    sprite_phases: Vec<PhaseCount>,
+    #[graphql(description = "Number of distinct contributors credited for any asset.")]
+    contributor_count: i32,
+    #[graphql(description = "The number of days counted by the recently modified fields.")]
+    recent_days: i32,
+    #[graphql(
+        description = "Number of forms whose portraits were modified in the recent days before computedDate."
+    )]
+    recently_modified_portraits: i32,
+    #[graphql(
+        description = "Number of forms whose sprites were modified in the recent days before computedDate."
+    )]
+    recently_modified_sprites: i32,
+    #[graphql(description = "When the statistics were computed, on the last data update.")]
+    computed_date: DateTime<Utc>,
+}
+
+impl From<&ProjectStatsData> for ProjectStats {
+    fn from(stats: &ProjectStatsData) -> Self {
+        Self {
+            monster_count: stats.monsters as i32,
+            form_count: stats.forms as i32,
+            portrait_phases: phase_counts(&stats.portrait_phases),
+            sprite_phases: phase_counts(&stats.sprite_phases),
+            contributor_count: stats.contributors as i32,
+            recent_days: RECENT_DAYS as i32,
+            recently_modified_portraits: stats.recent_portraits as i32,
+            recently_modified_sprites: stats.recent_sprites as i32,
+            computed_date: stats.computed_at,
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "An action mapped uniquely to an ID.")]
+pub struct ActionId {
+    id: i32,
+    name: String,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "Configuration for this instance of SpriteCollab.")]
+pub struct Config {
+    #[graphql(description = "The portrait width and height in pixels.")]
+    portrait_size: i32,
+    #[graphql(
+        description = "How many portraits per row a portrait sheet contains in the sprite config. The sheets of this server may use another layout, see portraitSheetLayout."
+    )]
+    portrait_tile_x: i32,
+    #[graphql(description = "How many rows a portrait sheet contains.")]
+    portrait_tile_y: i32,
+    #[graphql(description = "A list of known emotions. The position is the ID of the emotion.")]
+    emotions: Vec<String>,
+    #[graphql(description = "A list of known action. The position is the ID of the action.")]
+    actions: Vec<String>,
+    #[graphql(
+        description = "Returns a list, that for each phase contains a list of emotions (by index) that need to exist for this phase to be considered completed."
+    )]
+    completion_emotions: Vec<Vec<i32>>,
+    #[graphql(
+        description = "Returns a list, that for each phase contains a list of actions (by index) that need to exist for this phase to be considered completed."
+    )]
+    completion_actions: Vec<Vec<i32>>,
+    #[graphql(description = "A mapping of actions to EoS action indices.")]
+    action_map: Vec<ActionId>,
+    #[graphql(description = "The layout of the portrait sheets generated by this server.")]
+    portrait_sheet_layout: PortraitSheetLayout,
+}
+
+impl From<&SpriteConfig> for Config {
+    fn from(c: &SpriteConfig) -> Self {
+        Self {
+            portrait_size: c.portrait_size,
+            portrait_tile_x: c.portrait_tile_x,
+            portrait_tile_y: c.portrait_tile_y,
+            emotions: c.emotions.clone(),
+            actions: c.actions.clone(),
+            completion_emotions: c.completion_emotions.clone(),
+            completion_actions: c.completion_actions.clone(),
+            action_map: c
+                .action_map
+                .iter()
+                .map(|(idx, act)| ActionId {
+                    id: *idx,
+                    name: act.clone(),
+                })
+                .collect(),
+            portrait_sheet_layout: PortraitSheetLayout::new(c),
+        }
+    }
+}
+
+#[derive(GraphQLObject, Serialize)]
+#[graphql(description = "The position of an emotion in a portrait sheet.")]
+pub struct PortraitSheetTile {
+    #[graphql(description = "Name of the emotion. Flipped emotions end with ^.")]
+    emotion: String,
+    #[graphql(description = "Column of the tile, starting at 0.")]
+    x: i32,
+    #[graphql(description = "Row of the tile, starting at 0.")]
+    y: i32,
+}
+
+#[derive(GraphQLObject, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[graphql(
+    description = "The layout of the (unscaled) portrait sheets generated by this server. Tiles of emotions a form has no portrait for are empty. Recolor sheets have the same layout below an extra row of pixels with the palette."
+)]
+pub struct PortraitSheetLayout {
+    #[graphql(description = "The width and height of a tile in pixels.")]
+    tile_size: i32,
+    #[graphql(description = "Number of tiles per row.")]
+    columns: i32,
+    #[graphql(description = "Number of rows.")]
+    rows: i32,
+    #[graphql(
+        description = "Pixels between the tiles. The tile at (x, y) starts at x * (tileSize + padding), y * (tileSize + padding)."
+    )]
+    padding: i32,
+    #[graphql(description = "Width of the sheet in pixels.")]
+    width: i32,
+    #[graphql(description = "Height of the sheet in pixels.")]
+    height: i32,
+    #[graphql(description = "The position of every emotion, row by row.")]
+    emotions: Vec<PortraitSheetTile>,
+}
+
+impl PortraitSheetLayout {
+    pub(crate) fn new(sprite_config: &SpriteConfig) -> Self {
+        let emotions = PortraitSheetEmotions::configured(sprite_config);
+        let tile_size = sprite_config.portrait_size;
+        let (width, height) = emotions.pixel_size(tile_size as u32, tile_size as u32);
+        Self {
+            tile_size,
+            columns: emotions.width(),
+            rows: emotions.height(),
+            padding: emotions.padding() as i32,
+            width: width as i32,
+            height: height as i32,
+            emotions: emotions
+                .positions()
+                .into_iter()
+                .map(|(emotion, x, y)| PortraitSheetTile {
+                    emotion: emotion.to_string(),
+                    x,
+                    y,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Credit {
+    id: String,
+    name: Option<String>,
+    contact: Option<String>,
+    unresolved: bool,
+}
+
+#[graphql_object(Context = Context, impl = NodeValue)]
+impl Credit {
+    #[graphql(description = "Global ID of this credit, see the node query.")]
+    fn id(&self) -> ID {
+        ID::new(NodeId::Credit(self.id.clone()).encode())
+    }
+
+    #[graphql(description = "Discord ID or absentee ID. Guaranteed to be an ASCII string.")]
+    fn credit_id(&self) -> String {
+        self.id.clone()
+    }
+
+    #[graphql(
+        description = "The human-readable name of the author. Guaranteed to be an ASCII string."
+    )]
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    #[graphql(description = "Contact information for this author.")]
+    fn contact(&self) -> Option<String> {
+        self.contact.clone()
+    }
+
+    #[graphql(
+        description = "True if this credit ID is used in SpriteCollab, but not listed in the credit names. In that case only the ID is known."
+    )]
+    fn unresolved(&self) -> bool {
+        self.unresolved
+    }
+
+    #[graphql(
+        description = "This used to return the Discord handle of this author, if applicable and possible. It will now always return null.",
+        deprecated = "This is no longer implemented and will always return null. It may or may not be re-introduced in future versions."
+    )]
+    async fn discord_handle(&self) -> FieldResult<Option<String>> {
+        Ok(None)
+    }
+}
+
+impl Credit {
+    /// Makes the credit for an ID from its entry in the credit names. If the ID is unknown, a
+    /// credit with only the ID is returned.
+    fn new(credit_entry: Option<&CreditNamesRow>, credit_id: &str) -> Credit {
+        credit_entry.map(Credit::from).unwrap_or_else(|| Self {
+            id: credit_id.to_string(),
+            name: None,
+            contact: None,
+            unresolved: true,
+        })
+    }
+}
+
+impl From<&CreditNamesRow> for Credit {
+    fn from(c: &CreditNamesRow) -> Self {
+        Self {
+            id: c.credit_id.clone(),
+            name: c.name.clone(),
+            contact: c.contact.clone(),
+            unresolved: false,
+        }
+    }
+}
+
+/// The context of a single GraphQL request.
+pub struct Context {
+    pub(crate) url_base: AssetUrlBase,
+    pub(crate) collab: Arc<SpriteCollab>,
+    pub(crate) credits: CreditLoader,
+}
+
+impl Context {
+    pub fn new(collab: Arc<SpriteCollab>) -> Self {
+        Context {
+            url_base: AssetUrlBase::new(ServerConfig::get().this_server_url(), &collab),
+            credits: CreditLoader::new(collab.clone()),
+            collab,
+        }
+    }
+
+    /// The portrait and sprite files of the served data.
+    pub fn asset_store(&self) -> &dyn AssetStore {
+        self.collab.asset_store()
+    }
+}
+
+/// Resolves credit IDs to credits in batches: IDs requested by fields that are resolved
+/// concurrently are looked up together, and every ID is only looked up once per request.
+pub(crate) struct CreditLoader {
+    collab: Arc<SpriteCollab>,
+    state: Mutex<CreditLoaderState>,
+}
+
+#[derive(Default)]
+struct CreditLoaderState {
+    pending: HashSet<String>,
+    loaded: HashMap<String, Credit>,
+}
+
+impl CreditLoader {
+    fn new(collab: Arc<SpriteCollab>) -> Self {
+        Self {
+            collab,
+            state: Mutex::new(CreditLoaderState::default()),
+        }
+    }
+
+    pub(crate) async fn load(&self, credit_id: String) -> Credit {
+        {
+            let mut state = self.state.lock().await;
+            if let Some(credit) = state.loaded.get(&credit_id) {
+                return credit.clone();
+            }
+            state.pending.insert(credit_id.clone());
+        }
+        // Give the other fields that are resolved concurrently a chance to add their IDs to
+        // the batch.
+        yield_now().await;
+        let mut state = self.state.lock().await;
+        if !state.pending.is_empty() {
+            let pending = take(&mut state.pending);
+            let data = self.collab.data();
+            for id in pending {
+                let credit = Credit::new(data.credit_names.get(&id), &id);
+                state.loaded.insert(id, credit);
+            }
+        }
+        state.loaded[&credit_id].clone()
+    }
+}
+
+#[async_trait]
+impl ScCache for Context {
+    type Error = FieldError;
+
+    async fn cached_may_fail<S, Fn, Ft, T, E>(
+        &self,
+        cache_key: S,
+        func: Fn,
     ) -> FieldResult<Result<T, E>>
     where
         S: AsRef<str> + Into<RedisKey> + Send + Sync,
@@ -1155,12 +2102,30 @@ impl ScCache for Context {
             .cached_may_fail(cache_key, func)
             .await
             .map_err(|_e| {
-                FieldError::new(
+                ErrorCode::CacheUnavailable.error(
                     "Internal lookup error.",
                     graphql_value!({ "reason": "redis lookup failed. try again." }),
                 )
             })
     }
+
+    async fn cache_keys(&self, prefix: &str) -> FieldResult<Vec<String>> {
+        self.collab.cache_keys(prefix).await.map_err(|_e| {
+            ErrorCode::CacheUnavailable.error(
+                "Internal lookup error.",
+                graphql_value!({ "reason": "redis lookup failed. try again." }),
+            )
+        })
+    }
+
+    async fn evict(&self, cache_key: &str) -> FieldResult<bool> {
+        self.collab.evict(cache_key).await.map_err(|_e| {
+            ErrorCode::CacheUnavailable.error(
+                "Internal lookup error.",
+                graphql_value!({ "reason": "redis lookup failed. try again." }),
+            )
+        })
+    }
 }
 
 pub struct Meta;
@@ -1180,56 +2145,141 @@ impl Meta {
     #[graphql(
         description = "Git Commit (https://github.com/PMDCollab/SpriteCollab/) currently checked out to serve the assets."
     )]
-    async fn assets_commit(context: &Context) -> FieldResult<String> {
+    fn assets_commit(context: &Context) -> String {
+        context.collab.with_meta(|meta| meta.assets_commit.clone())
+    }
+
+    #[graphql(
+        description = "Git Commit that was served before `assetsCommit`. Null until the served commit changed since the server started."
+    )]
+    fn previous_commit(context: &Context) -> Option<String> {
         context
             .collab
-            .with_meta(|meta| {
-                meta.map_err(|_| {
-                    FieldError::new(
-                        "Internal error while trying to load meta data.",
-                        graphql_value!(None),
-                    )
-                })
-                .map(|v| v.assets_commit.clone())
-            })
-            .await
+            .with_meta(|meta| meta.previous_commit.clone())
+    }
+
+    #[graphql(
+        description = "Branch or tag of the assets repository (https://github.com/PMDCollab/SpriteCollab) that is currently served."
+    )]
+    fn branch(context: &Context) -> String {
+        context.collab.with_meta(|meta| meta.branch.clone())
     }
 
     #[graphql(
         description = "Date of the last commit in the assets repository (https://github.com/PMDCollab/SpriteCollab) that is currently checked out."
     )]
-    async fn assets_update_date(context: &Context) -> FieldResult<DateTime<Utc>> {
+    fn assets_update_date(context: &Context) -> DateTime<Utc> {
+        context.collab.with_meta(|meta| meta.assets_update_date)
+    }
+
+    #[graphql(
+        description = "The outcome of the last refresh of the data, with the data files that could not be read. Null until the first refresh finished."
+    )]
+    fn last_refresh_report(context: &Context) -> Option<DataRefreshReport> {
         context
             .collab
-            .with_meta(|meta| {
-                meta.map_err(|_| {
-                    FieldError::new(
-                        "Internal error while trying to load meta data.",
-                        graphql_value!(None),
-                    )
-                })
-                .map(|v| v.assets_update_date)
-            })
-            .await
+            .with_meta(|meta| meta.last_refresh_report.as_ref().map(Into::into))
+    }
+
+    #[graphql(
+        description = "How long the last refresh of the data took in milliseconds, whether it succeeded or not. Null until the first refresh finished."
+    )]
+    fn last_refresh_duration_ms(context: &Context) -> Option<i32> {
+        context.collab.with_meta(|meta| {
+            meta.last_refresh_duration
+                .map(|duration| duration.as_millis().min(i32::MAX as u128) as i32)
+        })
+    }
+
+    #[graphql(
+        description = "The error of the last refresh that failed. Unlike the error of `lastRefreshReport`, it is kept when later refreshes succeed. Null if no refresh failed since the server started."
+    )]
+    fn last_error(context: &Context) -> Option<String> {
+        context.collab.with_meta(|meta| meta.last_error.clone())
     }
 
     #[graphql(description = "Date that the server last checked for updates.")]
-    async fn update_checked_date(context: &Context) -> FieldResult<DateTime<Utc>> {
-        context
-            .collab
-            .with_meta(|meta| {
-                meta.map_err(|_| {
-                    FieldError::new(
-                        "Internal error while trying to load meta data.",
-                        graphql_value!(None),
-                    )
-                })
-                .map(|v| v.update_checked_date)
-            })
-            .await
+    fn update_checked_date(context: &Context) -> DateTime<Utc> {
+        context.collab.with_meta(|meta| meta.update_checked_date)
+    }
+}
+
+fn activity_page_size(first: Option<i32>) -> FieldResult<i32> {
+    match first.unwrap_or(DEFAULT_ACTIVITY_PAGE_SIZE) {
+        first @ 1..=MAX_ACTIVITY_PAGE_SIZE => Ok(first),
+        _ => Err(ErrorCode::InvalidArgument.error(
+            "first must be between 1 and 100",
+            graphql_value!({ "max": (MAX_ACTIVITY_PAGE_SIZE) }),
+        )),
     }
 }
 
+/// The `first` and `offset` arguments of the searches.
+struct SearchPage {
+    first: usize,
+    offset: usize,
+}
+
+impl SearchPage {
+    fn new(first: Option<i32>, offset: Option<i32>) -> FieldResult<Self> {
+        let first = match first.unwrap_or(MAX_SEARCH_PAGE_SIZE) {
+            first @ 1..=MAX_SEARCH_PAGE_SIZE => first,
+            _ => {
+                return Err(ErrorCode::InvalidArgument.error(
+                    "first must be between 1 and 100",
+                    graphql_value!({ "max": (MAX_SEARCH_PAGE_SIZE) }),
+                ))
+            }
+        };
+        let offset = match offset.unwrap_or(0) {
+            offset @ 0.. => offset,
+            _ => {
+                return Err(ErrorCode::InvalidArgument
+                    .error("offset must not be negative", graphql_value!(None)))
+            }
+        };
+        Ok(Self {
+            first: first as usize,
+            offset: offset as usize,
+        })
+    }
+
+    fn apply<T>(&self, results: impl IntoIterator<Item = T>) -> Vec<T> {
+        results
+            .into_iter()
+            .skip(self.offset)
+            .take(self.first)
+            .collect()
+    }
+}
+
+/// The activity store, if the server was built with it and it is configured.
+#[cfg(feature = "activity-store")]
+pub(crate) fn activity_store(
+    context: &Context,
+) -> FieldResult<&crate::activity_store::ActivityStore> {
+    context
+        .collab
+        .activity_store()
+        .ok_or_else(activity_store_unavailable)
+}
+
+pub(crate) fn activity_store_unavailable() -> FieldError {
+    ErrorCode::Unavailable.error(
+        "This server does not store activities.",
+        graphql_value!(None),
+    )
+}
+
+#[cfg(feature = "activity-store")]
+pub(crate) fn activity_store_failed(e: sqlx::Error) -> FieldError {
+    warn!("Failed querying the activity store: {:?}", e);
+    ErrorCode::Internal.error(
+        "Internal Server Error: Failed querying the activity store.",
+        graphql_value!(None),
+    )
+}
+
 // To make our context usable by Juniper, we have to implement a marker trait.
 impl juniper::Context for Context {}
 
@@ -1245,92 +2295,419 @@ impl Query {
     #[graphql(
         description = "Search for a monster by (parts) of its name. Results are sorted by best match."
     )]
-    async fn search_monster(context: &Context, monster_name: String) -> FieldResult<Vec<Monster>> {
+    fn search_monster(
+        context: &Context,
+        monster_name: String,
+        #[graphql(description = "Number of results to return (default and at most: 100).")]
+        first: Option<i32>,
+        #[graphql(description = "Number of results to skip.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<Monster>> {
+        let page = SearchPage::new(first, offset)?;
         if monster_name.len() > MAX_QUERY_LEN {
-            Err(FieldError::new(
+            Err(ErrorCode::InvalidArgument.error(
                 "Search query too long",
                 graphql_value!({ "max_length": (MAX_QUERY_LEN as i32) }),
             ))
         } else {
-            let tracker = context.collab.data().tracker.clone();
-            context
-                .cached_may_fail_chain(format!("/search_monster|{}", &monster_name), || async {
-                    let r: FieldResult<Vec<Monster>> =
-                        fuzzy_find_tracker(&tracker, &monster_name, context, |idx| Monster {
-                            id: idx as i32,
-                        })
-                        .await;
-                    match r {
-                        Ok(v) if !v.is_empty() => Ok(CacheBehaviour::Cache(v)),
-                        Ok(v) => Ok(CacheBehaviour::NoCache(v)),
-                        Err(e) => Err(e),
-                    }
-                })
-                .await
+            let data = context.collab.data();
+            let matches = data.search_index.find_monsters(&monster_name);
+            Ok(page.apply(matches.into_iter().map(|search_match| Monster {
+                id: search_match.value as i32,
+            })))
         }
     }
 
+    #[graphql(
+        description = "Like searchMonster, but also returns the score of the matches and which characters of which name matched, eg. for highlighting."
+    )]
+    fn search_monster_matches(
+        context: &Context,
+        monster_name: String,
+        #[graphql(description = "Number of results to return (default and at most: 100).")]
+        first: Option<i32>,
+        #[graphql(description = "Number of results to skip.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<MonsterSearchMatch>> {
+        let page = SearchPage::new(first, offset)?;
+        if monster_name.len() > MAX_QUERY_LEN {
+            Err(ErrorCode::InvalidArgument.error(
+                "Search query too long",
+                graphql_value!({ "max_length": (MAX_QUERY_LEN as i32) }),
+            ))
+        } else {
+            let data = context.collab.data();
+            let matches = data.search_index.find_monsters(&monster_name);
+            Ok(page.apply(matches.into_iter().map(MonsterSearchMatch::from)))
+        }
+    }
+
+    #[graphql(
+        description = "Search for a monster form by (parts) of its full name, eg. 'Shiny Female Sneasel Hisui'. Results are sorted by best match."
+    )]
+    fn search_monster_form(context: &Context, query: String) -> FieldResult<Vec<MonsterForm>> {
+        if query.len() > MAX_QUERY_LEN {
+            Err(ErrorCode::InvalidArgument.error(
+                "Search query too long",
+                graphql_value!({ "max_length": (MAX_QUERY_LEN as i32) }),
+            ))
+        } else {
+            let data = context.collab.data();
+            Ok(data
+                .search_index
+                .find_forms(&query)
+                .filter_map(|(idx, path)| MonsterForm::find_exact(&data.tracker, idx, path))
+                .collect())
+        }
+    }
+
+    #[graphql(
+        description = "A suggestion for a search query with typos: the closest full name of a monster form (including localized names and aliases), eg. 'Sneasel' for 'Snaesel'. Null if the query is a known name or no name is close enough."
+    )]
+    fn did_you_mean(context: &Context, query: String) -> FieldResult<Option<String>> {
+        if query.len() > MAX_QUERY_LEN {
+            Err(ErrorCode::InvalidArgument.error(
+                "Search query too long",
+                graphql_value!({ "max_length": (MAX_QUERY_LEN as i32) }),
+            ))
+        } else {
+            Ok(context
+                .collab
+                .data()
+                .search_index
+                .did_you_mean(&query)
+                .map(str::to_string))
+        }
+    }
+
+    #[graphql(
+        description = "Search for portrait emotions and sprite actions by (parts) of their name, and list which monster forms currently have them. Results are sorted by best match."
+    )]
+    async fn search_asset(context: &Context, query: String) -> FieldResult<Vec<AssetSearchResult>> {
+        if query.len() > MAX_QUERY_LEN {
+            Err(ErrorCode::InvalidArgument.error(
+                "Search query too long",
+                graphql_value!({ "max_length": (MAX_QUERY_LEN as i32) }),
+            ))
+        } else {
+            Ok(AssetSearchIndex::get(context, &context.collab)
+                .await?
+                .search(&query)
+                .cloned()
+                .map(AssetSearchResult)
+                .collect())
+        }
+    }
+
+    #[graphql(
+        description = "Refetch a monster, form or credit by its global ID (the id field). Returns null if the object no longer exists."
+    )]
+    fn node(context: &Context, id: ID) -> FieldResult<Option<NodeValue>> {
+        let node_id = NodeId::decode(&id).ok_or_else(|| {
+            ErrorCode::InvalidArgument.error(
+                "Invalid node ID",
+                graphql_value!({ "id": (id.to_string()) }),
+            )
+        })?;
+        let data = context.collab.data();
+        Ok(match node_id {
+            NodeId::Monster(id) => service::monster_group(&data.tracker, id)
+                .ok()
+                .map(|_| Monster { id }.into()),
+            NodeId::MonsterForm(id, form_id) => {
+                MonsterForm::find_exact(&data.tracker, id, &form_id).map(Into::into)
+            }
+            NodeId::Credit(id) => data
+                .credit_names
+                .get(&id)
+                .map(|entry| Credit::from(entry).into()),
+        })
+    }
+
     #[graphql(description = "Retrieve a list of monsters.")]
     fn monster(
         context: &Context,
         #[graphql(description = "Monster IDs to limit the request to.")] filter: Option<Vec<i32>>,
+        #[graphql(description = "Order of the monsters. Defaults to the order in the tracker.")]
+        sort: Option<MonsterSort>,
     ) -> FieldResult<Vec<Monster>> {
-        Ok(context
-            .collab
-            .data()
-            .tracker
-            .keys()
-            .filter(|v| {
+        Ok(list_monsters(
+            &context.collab.data().tracker,
+            sort,
+            |idx, _| {
                 if let Some(filter) = &filter {
-                    filter.contains(&(***v as i32))
+                    filter.contains(&(**idx as i32))
                 } else {
                     true
                 }
-            })
-            .map(|idx| Monster { id: **idx as i32 })
-            .collect())
+            },
+        ))
+    }
+
+    #[graphql(
+        description = "Retrieve a list of monsters that had any portrait or sprite of any form modified at or after the given date. Useful to only fetch changed data since the last poll."
+    )]
+    fn monsters_modified_since(
+        context: &Context,
+        since: DateTime<Utc>,
+        #[graphql(description = "Order of the monsters. Defaults to the order in the tracker.")]
+        sort: Option<MonsterSort>,
+    ) -> FieldResult<Vec<Monster>> {
+        Ok(list_monsters(
+            &context.collab.data().tracker,
+            sort,
+            |_, group| group.last_modified().is_some_and(|date| date >= since),
+        ))
+    }
+
+    #[graphql(
+        description = "Retrieve a single monster form by its full path (including the monster ID), seperated by /, eg. '0001/0001/0002'. This should match the path as it is stored in SpriteCollab, however the path passed in might be collapsed until a unique form is found."
+    )]
+    fn monster_form(context: &Context, full_path: String) -> FieldResult<MonsterForm> {
+        let mut form_needle = parse_form_path(&full_path)?;
+        if form_needle.is_empty() {
+            return Err(ErrorCode::InvalidPath.error(
+                "Invalid path.",
+                graphql_value!({ "details": "the path is empty" }),
+            ));
+        }
+        let monster_id = form_needle.remove(0);
+        service::find_form(
+            &context.collab.data().tracker,
+            monster_id,
+            form_needle.into_iter().map(FormMatch::Exact),
+        )?
+        .ok_or_else(|| {
+            ErrorCode::NotFound.error(
+                "Form not found",
+                graphql_value!({ "full_path": (full_path.as_str()) }),
+            )
+        })
     }
 
     #[graphql(
         description = "Search for a credit entry by (parts) of the ID, the author name or the contact info. Results are sorted by best match."
     )]
-    async fn search_credit(context: &Context, query: String) -> FieldResult<Vec<Credit>> {
+    fn search_credit(
+        context: &Context,
+        query: String,
+        #[graphql(description = "Number of results to return (default and at most: 100).")]
+        first: Option<i32>,
+        #[graphql(description = "Number of results to skip.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<Credit>> {
+        let page = SearchPage::new(first, offset)?;
         if query.len() > MAX_QUERY_LEN {
-            Err(FieldError::new(
+            Err(ErrorCode::InvalidArgument.error(
                 "Search query too long",
                 graphql_value!({ "max_length": (MAX_QUERY_LEN as i32) }),
             ))
         } else {
-            context
-                .cached(format!("/search_credit|{}", &query), || async {
-                    let r: Vec<Credit> = context
-                        .collab
-                        .data()
-                        .credit_names
-                        .fuzzy_find(&query)
-                        .map(Credit::from)
-                        .collect();
-                    if !r.is_empty() {
-                        CacheBehaviour::Cache(r)
-                    } else {
-                        CacheBehaviour::NoCache(r)
-                    }
-                })
-                .await
+            Ok(page.apply(
+                context
+                    .collab
+                    .data()
+                    .credit_names
+                    .fuzzy_find(&query)
+                    .map(Credit::from),
+            ))
+        }
+    }
+
+    #[graphql(
+        description = "Like searchCredit, but also returns the score of the matches and which characters of which ID or name matched, eg. for highlighting."
+    )]
+    fn search_credit_matches(
+        context: &Context,
+        query: String,
+        #[graphql(description = "Number of results to return (default and at most: 100).")]
+        first: Option<i32>,
+        #[graphql(description = "Number of results to skip.")] offset: Option<i32>,
+    ) -> FieldResult<Vec<CreditSearchMatch>> {
+        let page = SearchPage::new(first, offset)?;
+        if query.len() > MAX_QUERY_LEN {
+            Err(ErrorCode::InvalidArgument.error(
+                "Search query too long",
+                graphql_value!({ "max_length": (MAX_QUERY_LEN as i32) }),
+            ))
+        } else {
+            Ok(page.apply(
+                context
+                    .collab
+                    .data()
+                    .credit_names
+                    .fuzzy_find_matches(&query)
+                    .into_iter()
+                    .map(CreditSearchMatch::from),
+            ))
         }
     }
 
+    #[graphql(
+        description = "Lists which forms are under which license, by the license of their current portraits and sprites (see currentLicense)."
+    )]
+    async fn licenses(context: &Context) -> FieldResult<Vec<LicenseUsage>> {
+        Ok(service::licensed_forms(context)
+            .await?
+            .into_iter()
+            .map(LicenseUsage::from)
+            .collect())
+    }
+
     #[graphql(description = "Retrieve a list of credits.")]
     fn credit(context: &Context) -> FieldResult<Vec<Credit>> {
+        Ok(service::all_credits(&context.collab))
+    }
+
+    #[graphql(
+        description = "The portraits and sprites of a form that changed between two commits of the assets repository, eg. for reviews or changelogs. Commits can be abbreviated."
+    )]
+    async fn form_changes(
+        monster_id: i32,
+        #[graphql(description = "Path of the form without the monster ID, eg. 0000/0001.")]
+        form_path: String,
+        from_commit: String,
+        to_commit: String,
+    ) -> FieldResult<FormChanges> {
+        let form_id = parse_form_path(&form_path)?;
+        for commit in [&from_commit, &to_commit] {
+            if !form_changes::is_commit_id_prefix(commit) {
+                return Err(ErrorCode::InvalidArgument.error(
+                    "Invalid commit",
+                    graphql_value!({ "commit": (commit.as_str()) }),
+                ));
+            }
+        }
+        let repo_path = ServerConfig::get().workdir.join(GIT_REPO_DIR);
+        let (from, to) = (from_commit.clone(), to_commit.clone());
+        let result = spawn_blocking(move || {
+            form_changes::form_changes(&repo_path, &from, &to, monster_id, &form_id)
+        })
+        .await;
+        let changes = match result {
+            Ok(changes) => changes,
+            Err(e) => Err(e.into()),
+        }
+        .map_err(|e| {
+            warn!("Failed diffing {}..{}: {:?}", from_commit, to_commit, e);
+            ErrorCode::Internal.error(
+                "Internal Server Error: Failed diffing the commits.",
+                graphql_value!(None),
+            )
+        })?;
+        changes.map(FormChanges::from).ok_or_else(|| {
+            let commits = format!("{}..{}", from_commit, to_commit);
+            ErrorCode::NotFound.error(
+                "Commit not found",
+                graphql_value!({ "commits": (commits.as_str()) }),
+            )
+        })
+    }
+
+    #[graphql(
+        description = "Problems with the consistency of the tracker, the files in the repository and the credit names, found when the data was last updated."
+    )]
+    fn data_integrity(context: &Context) -> FieldResult<Vec<DataIntegrityIssue>> {
         Ok(context
             .collab
             .data()
-            .credit_names
+            .integrity
+            .issues
             .iter()
-            .map(Credit::from)
+            .map(DataIntegrityIssue::from)
             .collect())
     }
 
+    #[graphql(
+        description = "The authors credited for the most activities (added or updated portraits or sprites of a form) since the given date, or ever. Only available if the server stores activities."
+    )]
+    async fn top_contributors(
+        context: &Context,
+        since: Option<DateTime<Utc>>,
+        category: Option<ActivityCategory>,
+        #[graphql(description = "Number of authors to return (default: 20, at most 100).")]
+        first: Option<i32>,
+    ) -> FieldResult<Vec<ContributorActivity>> {
+        let first = activity_page_size(first)?;
+        #[cfg(feature = "activity-store")]
+        let stats = activity_store(context)?
+            .top_contributors(since, category.map(Into::into), first.into())
+            .await
+            .map_err(activity_store_failed)?;
+        #[cfg(not(feature = "activity-store"))]
+        let stats: Vec<ContributorStats> = {
+            let _ = (since, category, first);
+            Err(activity_store_unavailable())?
+        };
+        Ok(stats
+            .into_iter()
+            .map(|stats| ContributorActivity::new(stats, context))
+            .collect())
+    }
+
+    #[graphql(
+        description = "The activities (added or updated portraits or sprites of a form) an author is credited for, newest first. Only available if the server stores activities."
+    )]
+    async fn credit_activity(
+        context: &Context,
+        credit_id: String,
+        #[graphql(description = "Number of activities to return (default: 20, at most 100).")]
+        first: Option<i32>,
+        #[graphql(description = "Return the activities after the activity with this cursor.")]
+        after: Option<String>,
+    ) -> FieldResult<CreditActivityPage> {
+        let first = activity_page_size(first)?;
+        let after = match after {
+            Some(after) => Some(ActivityCursor::decode(&after).ok_or_else(|| {
+                ErrorCode::InvalidArgument
+                    .error("Invalid cursor", graphql_value!({ "after": after }))
+            })?),
+            None => None,
+        };
+        // One more than requested, to know whether there is a next page.
+        #[cfg(feature = "activity-store")]
+        let mut activities = activity_store(context)?
+            .credit_activity(&credit_id, i64::from(first) + 1, after.as_ref())
+            .await
+            .map_err(activity_store_failed)?;
+        #[cfg(not(feature = "activity-store"))]
+        let mut activities: Vec<CreditActivity> = {
+            let _ = (context, credit_id, after);
+            Err(activity_store_unavailable())?
+        };
+        let has_next_page = activities.len() > first as usize;
+        activities.truncate(first as usize);
+        let activities = activities
+            .into_iter()
+            .map(CreditActivityEntry::from)
+            .collect::<Vec<_>>();
+        Ok(CreditActivityPage {
+            end_cursor: activities.last().map(|activity| activity.cursor.clone()),
+            activities,
+            has_next_page,
+        })
+    }
+
+    #[graphql(
+        description = "Statistics of the whole project: Monsters, forms, phases, contributors and recent modifications. Computed when the data was last updated."
+    )]
+    fn project_stats(context: &Context) -> FieldResult<ProjectStats> {
+        Ok(ProjectStats::from(&context.collab.data().stats))
+    }
+
+    #[graphql(
+        description = "The forms with a Guild Point bounty for their portraits or sprites, largest bounty (in total) first."
+    )]
+    fn bounties(
+        context: &Context,
+        #[graphql(description = "Minimum total bounty (default: 1).")] min_amount: Option<i32>,
+        #[graphql(description = "Only return bounties for portraits or for sprites.")]
+        category: Option<ActivityCategory>,
+    ) -> FieldResult<Vec<FormBounty>> {
+        Ok(service::bounties(
+            &context.collab.data().tracker,
+            min_amount.unwrap_or(1).into(),
+            category.map(Into::into),
+        ))
+    }
+
     #[graphql(description = "Configuration for this instance of SpriteCollab.")]
     fn config(context: &Context) -> FieldResult<Config> {
         Ok(Config::from(&context.collab.data().sprite_config))
@@ -1,10 +1,45 @@
+use crate::assets::fs_check::AssetCategory;
+use crate::cache::{CacheBehaviour, ScCache};
+use crate::datafiles::sprite_config::SpriteConfig;
+use crate::datafiles::tracker::{MonsterFormCollector, Tracker};
+use crate::sprite_collab::SpriteCollab;
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
 use itertools::Itertools;
 use num_traits::PrimInt;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::cmp::{max, Reverse};
 use std::hash::Hash;
+use std::iter::once;
+use std::mem;
 
+/// A value found by [`fuzzy_find_matches`], with the key it was found by.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SearchMatch<T> {
+    pub value: T,
+    /// Higher is better. Matches with typos have a negative score, the negated number of typos.
+    pub score: i64,
+    pub key: String,
+    /// Indices of the characters of the key that matched the query. Empty for matches with
+    /// typos.
+    pub indices: Vec<usize>,
+}
+
+impl<T> SearchMatch<T> {
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> SearchMatch<U> {
+        SearchMatch {
+            value: f(self.value),
+            score: self.score,
+            key: self.key,
+            indices: self.indices,
+        }
+    }
+}
+
+/// Fuzzy-matches `query` against the keys and returns their values, sorted by best match. If
+/// nothing matches, keys (or single words of them) that are only a few typos away from the query
+/// are found instead, see [`typo_distance`].
 pub fn fuzzy_find<V, I, N, S1, S2>(iter: I, query: S2) -> impl Iterator<Item = N>
 where
     I: Iterator<Item = (S1, V)>,
@@ -14,40 +49,128 @@ where
     // but I fought with the borrow checker for long enough now...
     V: CloneToVec<N>,
     N: PrimInt + Hash,
+{
+    fuzzy_find_matches(iter, query)
+        .into_iter()
+        .map(|search_match| search_match.value)
+}
+
+/// Like [`fuzzy_find`], but also returns the score of the matches and which characters of the
+/// keys matched. Every value is only returned for its best match.
+pub fn fuzzy_find_matches<V, I, N, S1, S2>(iter: I, query: S2) -> Vec<SearchMatch<N>>
+where
+    I: Iterator<Item = (S1, V)>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+    V: CloneToVec<N>,
+    N: PrimInt + Hash,
 {
     let matcher = SkimMatcherV2::default();
-    let mut search_result = iter
+    let query = query.as_ref().to_lowercase();
+    let entries = iter.collect::<Vec<_>>();
+    let mut search_result = entries
+        .iter()
         .filter_map(|(k, v)| do_fuzzy_match(k, v.clone_to_vec(), &query, &matcher))
         .flatten()
-        .collect::<Vec<(i64, N)>>();
+        .collect::<Vec<SearchMatch<N>>>();
+
+    if search_result.is_empty() {
+        search_result = entries
+            .iter()
+            .filter_map(|(k, v)| do_typo_match(k, v.clone_to_vec(), &query))
+            .flatten()
+            .collect();
+    }
 
-    search_result.sort_by(|(score_a, _), (score_b, _)| score_b.cmp(score_a));
+    search_result.sort_by_key(|result| Reverse(result.score));
 
-    search_result.into_iter().map(|(_score, val)| val).unique()
+    search_result
+        .into_iter()
+        .unique_by(|search_match| search_match.value)
+        .collect()
 }
 
-fn do_fuzzy_match<S1, S2, II, I>(
+/// The name closest to `query`, if it is only a few typos away from it. `None` if the query is
+/// one of the names.
+pub fn did_you_mean<I, S1, S2>(names: I, query: S2) -> Option<S1>
+where
+    I: IntoIterator<Item = S1>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    let query = query.as_ref().to_lowercase();
+    let (distance, name) = names
+        .into_iter()
+        .map(|name| (typo_distance(&name.as_ref().to_lowercase(), &query), name))
+        .min_by_key(|(distance, _)| *distance)?;
+    if distance == 0 || distance > max_typos(&query) {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Optimal string alignment distance: the number of inserted, deleted and replaced characters
+/// and swapped neighbouring characters it takes to turn `a` into `b`.
+pub fn typo_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut before_previous: Vec<usize> = Vec::new();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, char_a) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, char_b) in b.iter().enumerate() {
+            let cost = usize::from(char_a != char_b);
+            let mut distance = (previous[j + 1] + 1)
+                .min(current[j] + 1)
+                .min(previous[j] + cost);
+            if i > 0 && j > 0 && *char_a == b[j - 1] && a[i - 1] == *char_b {
+                distance = distance.min(before_previous[j - 1] + 1);
+            }
+            current[j + 1] = distance;
+        }
+        before_previous = mem::replace(&mut previous, current);
+    }
+    previous[b.len()]
+}
+
+/// Typos tolerated by the fallback of [`fuzzy_find`]: None for queries shorter than three
+/// characters, then one per four characters.
+fn max_typos(query: &str) -> usize {
+    let len = query.chars().count();
+    if len < 3 {
+        0
+    } else {
+        max(1, len / 4)
+    }
+}
+
+fn do_fuzzy_match<S1, II, I>(
     key: S1,
     vals_brw: II,
-    query: &S2,
+    query: &str,
     matcher: &SkimMatcherV2,
-) -> Option<Vec<(i64, I)>>
+) -> Option<Vec<SearchMatch<I>>>
 where
     S1: AsRef<str>,
-    S2: AsRef<str>,
     II: IntoIterator<Item = I>,
     I: PrimInt,
 {
-    match matcher.fuzzy_match(&key.as_ref().to_lowercase(), &query.as_ref().to_lowercase()) {
+    match matcher.fuzzy_indices(&key.as_ref().to_lowercase(), query) {
         None => None,
-        Some(score) => {
+        Some((score, indices)) => {
             if score <= 0 {
                 None
             } else {
                 Some(
                     vals_brw
                         .into_iter()
-                        .map(|val| (score, val))
+                        .map(|value| SearchMatch {
+                            value,
+                            score,
+                            key: key.as_ref().to_string(),
+                            indices: indices.clone(),
+                        })
                         .collect::<Vec<_>>(),
                 )
             }
@@ -55,6 +178,36 @@ where
     }
 }
 
+/// Matches the whole key and its single words against the lowercase `query`. The score is the
+/// negated number of typos.
+fn do_typo_match<S1, II, I>(key: S1, vals_brw: II, query: &str) -> Option<Vec<SearchMatch<I>>>
+where
+    S1: AsRef<str>,
+    II: IntoIterator<Item = I>,
+    I: PrimInt,
+{
+    let lowercase_key = key.as_ref().to_lowercase();
+    let distance = once(lowercase_key.as_str())
+        .chain(lowercase_key.split_whitespace())
+        .map(|part| typo_distance(part, query))
+        .min()?;
+    if distance > max_typos(query) {
+        None
+    } else {
+        Some(
+            vals_brw
+                .into_iter()
+                .map(|value| SearchMatch {
+                    value,
+                    score: -(distance as i64),
+                    key: key.as_ref().to_string(),
+                    indices: Vec::new(),
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
 pub trait CloneToVec<T> {
     fn clone_to_vec(&self) -> Vec<T>;
 }
@@ -74,8 +227,140 @@ impl<T: Copy> CloneToVec<T> for Vec<T> {
     }
 }
 
-impl<'a, T: Copy> CloneToVec<T> for &'a Vec<T> {
+impl<T: Copy> CloneToVec<T> for &Vec<T> {
     fn clone_to_vec(&self) -> Vec<T> {
         self.to_vec()
     }
 }
+
+/// An emotion or action and all forms (monster ID and form path) that currently have it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AssetSearchEntry {
+    pub category: AssetCategory,
+    pub name: String,
+    pub forms: Vec<(i32, Vec<i32>)>,
+}
+
+/// Index of all emotions and actions from the sprite config and which forms have them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AssetSearchIndex(Vec<AssetSearchEntry>);
+
+impl AssetSearchIndex {
+    /// Returns the index from the cache, or builds it if it doesn't exist yet.
+    pub async fn get<C, E>(cache: &C, collab: &SpriteCollab) -> Result<Self, E>
+    where
+        C: ScCache<Error = E>,
+    {
+        cache
+            .cached("asset_search_index", || async {
+                let data = collab.data();
+                CacheBehaviour::Cache(Self::build(&data.tracker, &data.sprite_config))
+            })
+            .await
+    }
+
+    fn build(tracker: &Tracker, sprite_config: &SpriteConfig) -> Self {
+        let mut entries = sprite_config
+            .emotions
+            .iter()
+            .map(|name| (AssetCategory::Portrait, name))
+            .chain(
+                sprite_config
+                    .actions
+                    .iter()
+                    .map(|name| (AssetCategory::Sprite, name)),
+            )
+            .map(|(category, name)| AssetSearchEntry {
+                category,
+                name: name.clone(),
+                forms: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        for monster_idx in tracker.keys() {
+            let monster_idx = **monster_idx as i32;
+            if let Some(collector) = MonsterFormCollector::collect(tracker, monster_idx) {
+                for (form_path, group) in collector.map(|(path, _, group)| (path, group)) {
+                    for entry in entries.iter_mut() {
+                        let files = match entry.category {
+                            AssetCategory::Portrait => &group.portrait_files,
+                            AssetCategory::Sprite => &group.sprite_files,
+                        };
+                        if files.contains_key(&entry.name) {
+                            entry.forms.push((monster_idx, form_path.clone()));
+                        }
+                    }
+                }
+            }
+        }
+
+        Self(entries)
+    }
+
+    /// Fuzzy-matches the emotion and action names. Results are sorted by best match.
+    pub fn search<S: AsRef<str>>(&self, query: S) -> impl Iterator<Item = &AssetSearchEntry> {
+        fuzzy_find(
+            self.0
+                .iter()
+                .enumerate()
+                .map(|(idx, entry)| (&entry.name, vec![idx])),
+            query,
+        )
+        .map(|idx| &self.0[idx])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_typos() {
+        assert_eq!(typo_distance("pikachu", "pikachu"), 0);
+        assert_eq!(typo_distance("pikachu", "pikahcu"), 1);
+        assert_eq!(typo_distance("pikachu", "pikchu"), 1);
+        assert_eq!(typo_distance("pikachu", "pikaxhu"), 1);
+        assert_eq!(typo_distance("sneasel", "snaesle"), 2);
+        assert_eq!(typo_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn falls_back_to_typos() {
+        let names = [("Fixturemon", vec![1]), ("Shiny Sneasel Hisui", vec![2])];
+        assert_eq!(
+            fuzzy_find(names.iter().cloned(), "pikachu").collect::<Vec<i32>>(),
+            Vec::<i32>::new()
+        );
+        assert_eq!(
+            fuzzy_find(names.iter().cloned(), "fixturmeon").collect::<Vec<i32>>(),
+            vec![1]
+        );
+        assert_eq!(
+            fuzzy_find(names.iter().cloned(), "snaesel").collect::<Vec<i32>>(),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn returns_matched_characters() {
+        let names = [("Fixturemon", vec![1]), ("Secondmon", vec![2])];
+        let matches = fuzzy_find_matches(names.iter().cloned(), "fxm");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].value, 1);
+        assert_eq!(matches[0].key, "Fixturemon");
+        assert_eq!(matches[0].indices, vec![0, 2, 7]);
+        assert!(matches[0].score > 0);
+        let matches = fuzzy_find_matches(names.iter().cloned(), "secnodmon");
+        assert_eq!(matches[0].value, 2);
+        assert_eq!(matches[0].score, -1);
+        assert!(matches[0].indices.is_empty());
+    }
+
+    #[test]
+    fn suggests_close_names() {
+        let names = ["Fixturemon", "Sneasel"];
+        assert_eq!(did_you_mean(names, "Fixturmeon"), Some("Fixturemon"));
+        assert_eq!(did_you_mean(names, "fixturemon"), None);
+        assert_eq!(did_you_mean(names, "Pikachu"), None);
+    }
+}
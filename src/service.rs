@@ -0,0 +1,616 @@
+//! Resolving of the data that is served by the APIs. This is shared by the GraphQL resolvers
+//! in `schema.rs` and the REST API in `api.rs`, so both always return the same data.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::iter::once;
+
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use indexmap::IndexMap;
+use itertools::Itertools;
+use juniper::{graphql_value, FieldError, FieldResult};
+use serde::{Deserialize, Serialize};
+
+use crate::assets::fs_check::{
+    get_local_credits_file, iter_existing_portrait_files, iter_existing_sprite_files, AssetCategory,
+};
+use crate::assets::store::AssetStore;
+use crate::assets::url::{get_url, AssetType, AssetUrlBase};
+use crate::cache::{CacheBehaviour, ScCache};
+use crate::datafiles::anim_data_xml::AnimDataXml;
+use crate::datafiles::group_id::GroupId;
+use crate::datafiles::local_credits_file::LocalCreditRow;
+use crate::datafiles::sprite_config::{ActionName, Emotion};
+use crate::datafiles::tracker::{FormMatch, Group, MonsterFormCollector, Tracker};
+use crate::datafiles::{parse_credit_id, DataReadError};
+use crate::schema::{
+    Context, CopyOf, Credit, ErrorCode, FormBounty, MonsterBounty, MonsterContributor, MonsterForm,
+    Portrait, RelevantCredit, Sprite, SpriteUnion,
+};
+use crate::sprite_collab::SpriteCollab;
+
+pub fn monster_not_found(id: i32) -> FieldError {
+    ErrorCode::NotFound.error("Monster not found", graphql_value!({ "id": id }))
+}
+
+pub fn failed_credits_read(e: DataReadError) -> FieldError {
+    let e_as_str = e.to_string();
+    ErrorCode::Internal.error(
+        "Internal Server Error: Failed reading the credits file.",
+        graphql_value!({ "details": e_as_str }),
+    )
+}
+
+fn failed_xml_fetch<E: Debug>(e: E) -> FieldError {
+    let e_as_str = format!("{:?}", e);
+    ErrorCode::Internal.error(
+        "Internal Server Error: Failed processing the animation data from the AnimData.xml.",
+        graphql_value!({ "details": e_as_str }),
+    )
+}
+
+/// Parses a path of IDs seperated by /, as used in the SpriteCollab repository.
+pub fn parse_form_path(path: &str) -> FieldResult<Vec<i32>> {
+    path.split('/')
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<i32>())
+        .collect::<Result<Vec<i32>, _>>()
+        .map_err(|e| {
+            let e_dbg = format!("{:?}", e);
+            ErrorCode::InvalidPath.error("Invalid path.", graphql_value!({ "details": e_dbg }))
+        })
+}
+
+/// Returns the tracker entry of a monster.
+pub fn monster_group(tracker: &Tracker, monster_id: i32) -> FieldResult<&Group> {
+    tracker
+        .get(&GroupId(monster_id as i64))
+        .ok_or_else(|| monster_not_found(monster_id))
+}
+
+/// Returns all forms of a monster.
+pub fn monster_forms(tracker: &Tracker, monster_id: i32) -> FieldResult<Vec<MonsterForm>> {
+    match MonsterFormCollector::collect(tracker, monster_id) {
+        Some(collector) => Ok(collector
+            .map(|(path, name_path, v)| MonsterForm::new(monster_id, path, name_path, v))
+            .collect()),
+        None => Err(monster_not_found(monster_id)),
+    }
+}
+
+/// The bounties of at least `min_amount` points (in total, see [`MonsterBounty::total`]) of all
+/// forms, largest first. If `category` is set, only bounties for portraits or sprites.
+pub fn bounties(
+    tracker: &Tracker,
+    min_amount: i64,
+    category: Option<AssetCategory>,
+) -> Vec<FormBounty> {
+    let categories = match category {
+        Some(category) => vec![category],
+        None => vec![AssetCategory::Portrait, AssetCategory::Sprite],
+    };
+    let mut bounties = Vec::new();
+    for group_id in tracker.keys() {
+        let monster_id = **group_id as i32;
+        let collector = match MonsterFormCollector::collect(tracker, monster_id) {
+            Some(collector) => collector,
+            None => continue,
+        };
+        for (path, name_path, group) in collector.map(|form| form) {
+            for &category in &categories {
+                let bounty_spec = match category {
+                    AssetCategory::Portrait => &group.portrait_bounty,
+                    AssetCategory::Sprite => &group.sprite_bounty,
+                };
+                let bounty = MonsterBounty::new(group.modreward, bounty_spec);
+                if bounty.total() >= min_amount && bounty.total() > 0 {
+                    bounties.push(FormBounty {
+                        form: MonsterForm::new(monster_id, path.clone(), name_path.clone(), group),
+                        category: category.into(),
+                        bounty,
+                    });
+                }
+            }
+        }
+    }
+    bounties.sort_by_key(|bounty| Reverse(bounty.bounty.total()));
+    bounties
+}
+
+/// Finds the form of a monster that matches `needle`. Fails if the monster does not exist.
+pub fn find_form<N>(
+    tracker: &Tracker,
+    monster_id: i32,
+    needle: N,
+) -> FieldResult<Option<MonsterForm>>
+where
+    N: IntoIterator<Item = FormMatch>,
+{
+    match MonsterFormCollector::collect(tracker, monster_id) {
+        Some(collector) => Ok(collector
+            .find_form(needle)
+            .map(|(path, name_path, v)| MonsterForm::new(monster_id, path, name_path, v))),
+        None => Err(monster_not_found(monster_id)),
+    }
+}
+
+/// The path of a form (without the monster ID), eg. `0000/0001`.
+pub fn form_path(form_id: &[i32]) -> String {
+    let mut path = form_id.iter().map(|v| format!("{:04}", v)).join("/");
+    if path.ends_with('/') {
+        path.truncate(path.len() - 1);
+    }
+    path
+}
+
+/// The path of a form including the monster ID, eg. `0025/0000/0001`.
+pub fn full_form_path(monster_id: i32, form_id: &[i32]) -> String {
+    let mut path = once(format!("{:04}", monster_id))
+        .chain(form_id.iter().map(|v| format!("{:04}", v)))
+        .join("/");
+    if path.ends_with('/') {
+        path.truncate(path.len() - 1);
+    }
+    path
+}
+
+/// URL of the preview image of a form, `None` if it has neither portraits nor sprites.
+pub fn preview_url(url_base: &AssetUrlBase, form: &MonsterForm) -> Option<String> {
+    if form.data.portrait_files.is_empty() && form.data.sprite_files.is_empty() {
+        None
+    } else {
+        Some(get_url(
+            AssetType::Preview,
+            url_base,
+            form.id,
+            &form.form_id,
+        ))
+    }
+}
+
+/// Resolves a credit ID as it is used in the tracker. `None` if the ID is empty.
+pub async fn credit(context: &Context, raw_credit_id: &str) -> Option<Credit> {
+    let credit_id = parse_credit_id(raw_credit_id);
+    if credit_id.is_empty() {
+        None
+    } else {
+        Some(context.credits.load(credit_id).await)
+    }
+}
+
+/// Resolves a list of credit IDs as they are used in the tracker.
+pub async fn credits(context: &Context, raw_credit_ids: &[String]) -> Vec<Credit> {
+    join_all(
+        raw_credit_ids
+            .iter()
+            .map(parse_credit_id)
+            .map(|credit_id| context.credits.load(credit_id)),
+    )
+    .await
+}
+
+/// All entries of the credit names.
+pub fn all_credits(collab: &SpriteCollab) -> Vec<Credit> {
+    collab
+        .data()
+        .credit_names
+        .iter()
+        .map(Credit::from)
+        .collect()
+}
+
+/// All contributors to the portraits and sprites of all forms of a monster, in the order they
+/// are first credited. Contributions that are marked as obsolete in the credits.txt of a form
+/// are not included.
+pub async fn monster_contributors(
+    context: &Context,
+    monster_id: i32,
+) -> FieldResult<Vec<MonsterContributor>> {
+    let forms = monster_forms(&context.collab.data().tracker, monster_id)?;
+    // Credit ID -> full paths of the forms with portraits and with sprites by them.
+    let mut contributions: IndexMap<String, (Vec<String>, Vec<String>)> = IndexMap::new();
+    for form in &forms {
+        let form_path = full_form_path(form.id, &form.form_id);
+        for category in [AssetCategory::Portrait, AssetCategory::Sprite] {
+            let rows = get_local_credits_file(
+                context,
+                context.asset_store(),
+                category,
+                form.id,
+                &form.form_id,
+            )
+            .await?
+            .map_err(failed_credits_read)?;
+            for row in rows.into_iter().filter(|row| !row.obsolete) {
+                let credit_id = parse_credit_id(row.credit_id);
+                if credit_id.is_empty() {
+                    continue;
+                }
+                let (portrait_forms, sprite_forms) = contributions.entry(credit_id).or_default();
+                let category_forms = match category {
+                    AssetCategory::Portrait => portrait_forms,
+                    AssetCategory::Sprite => sprite_forms,
+                };
+                if category_forms.last() != Some(&form_path) {
+                    category_forms.push(form_path.clone());
+                }
+            }
+        }
+    }
+    Ok(join_all(contributions.into_iter().map(
+        |(credit_id, (portrait_forms, sprite_forms))| async move {
+            MonsterContributor {
+                credit: context.credits.load(credit_id).await,
+                portrait_forms,
+                sprite_forms,
+            }
+        },
+    ))
+    .await)
+}
+
+/// The contributors to the current portraits or sprites of a form, with the emotions or actions
+/// each of them is responsible for: Of every emotion or action, the author of the newest
+/// contribution that is not obsolete. Contributors whose contributions are all obsolete are not
+/// included, contributors whose contributions were all redone since have no emotions or actions.
+pub async fn relevant_credits(
+    context: &Context,
+    category: AssetCategory,
+    monster_id: i32,
+    form_id: &[i32],
+) -> FieldResult<Vec<RelevantCredit>> {
+    let mut rows = get_local_credits_file(
+        context,
+        context.asset_store(),
+        category,
+        monster_id,
+        form_id,
+    )
+    .await?
+    .map_err(failed_credits_read)?;
+    rows.retain(|row| !row.obsolete);
+    rows.sort_by_key(|row| row.date);
+    // Credit ID -> emotions or actions, in the order the credits first contributed.
+    let mut credits: IndexMap<String, Vec<String>> = IndexMap::new();
+    // Emotion or action -> the credit ID of its newest contribution.
+    let mut responsible: IndexMap<String, String> = IndexMap::new();
+    for row in rows {
+        let credit_id = parse_credit_id(row.credit_id);
+        if credit_id.is_empty() {
+            continue;
+        }
+        for item in row.items {
+            responsible.insert(item, credit_id.clone());
+        }
+        credits.entry(credit_id).or_default();
+    }
+    for (item, credit_id) in responsible {
+        credits.entry(credit_id).or_default().push(item);
+    }
+    Ok(
+        join_all(credits.into_iter().map(|(credit_id, items)| async move {
+            RelevantCredit {
+                credit: context.credits.load(credit_id).await,
+                items,
+            }
+        }))
+        .await,
+    )
+}
+
+/// A contribution to the current portraits or sprites of a form, with the credit names entry
+/// of its author. Exported by `/api/v1/credits/export`.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditExportRow {
+    pub form_path: String,
+    pub monster_name: String,
+    /// The full name of the form, excluding the monster name.
+    pub form_name: String,
+    /// `portrait` or `sprite`.
+    pub category: String,
+    pub credit_id: String,
+    pub name: Option<String>,
+    pub contact: Option<String>,
+    pub date: DateTime<Utc>,
+    pub license: String,
+    /// The emotions or actions of the contribution, comma-separated.
+    pub items: String,
+}
+
+/// The contributions to all forms of a monster, or of all monsters, for attribution files.
+/// Contributions that are marked as obsolete are not included, like in
+/// [`monster_contributors`].
+pub async fn credits_export(
+    context: &Context,
+    monster_id: Option<i32>,
+) -> FieldResult<Vec<CreditExportRow>> {
+    let cache_key = match monster_id {
+        Some(monster_id) => format!("/credits_export|{}", monster_id),
+        None => "/credits_export".to_string(),
+    };
+    context
+        .cached_may_fail_chain(cache_key, || async {
+            let tracker = context.collab.data().tracker.clone();
+            let monster_ids = match monster_id {
+                Some(monster_id) => vec![monster_id],
+                None => tracker.keys().map(|group_id| **group_id as i32).collect(),
+            };
+            let mut export = Vec::new();
+            for monster_id in monster_ids {
+                let monster_name = monster_group(&tracker, monster_id)?.name.clone();
+                for form in monster_forms(&tracker, monster_id)? {
+                    for category in [AssetCategory::Portrait, AssetCategory::Sprite] {
+                        let rows = get_local_credits_file(
+                            context,
+                            context.asset_store(),
+                            category,
+                            form.id,
+                            &form.form_id,
+                        )
+                        .await?
+                        .map_err(failed_credits_read)?;
+                        for row in rows.into_iter().filter(|row| !row.obsolete) {
+                            let credit_id = parse_credit_id(row.credit_id);
+                            if credit_id.is_empty() {
+                                continue;
+                            }
+                            let (name, contact) =
+                                match context.collab.data().credit_names.get(&credit_id) {
+                                    Some(credit) => (credit.name.clone(), credit.contact.clone()),
+                                    None => (None, None),
+                                };
+                            export.push(CreditExportRow {
+                                form_path: full_form_path(form.id, &form.form_id),
+                                monster_name: monster_name.clone(),
+                                form_name: form.name_path.join(" "),
+                                category: match category {
+                                    AssetCategory::Portrait => "portrait",
+                                    AssetCategory::Sprite => "sprite",
+                                }
+                                .to_string(),
+                                credit_id,
+                                name,
+                                contact,
+                                date: row.date,
+                                license: row.license,
+                                items: row.items.join(","),
+                            });
+                        }
+                    }
+                }
+            }
+            Ok(CacheBehaviour::Cache(export))
+        })
+        .await
+}
+
+/// The forms whose current portraits or sprites are under a license.
+#[derive(Serialize, Deserialize)]
+pub struct LicensedForms {
+    /// The license as it is written in the credits.txt files.
+    pub license: String,
+    pub portrait_forms: Vec<String>,
+    pub sprite_forms: Vec<String>,
+}
+
+/// The license of the newest contribution that is not obsolete.
+fn newest_license(rows: Vec<LocalCreditRow>) -> Option<String> {
+    rows.into_iter()
+        .filter(|row| !row.obsolete)
+        .max_by_key(|row| row.date)
+        .map(|row| row.license)
+}
+
+/// The license the current portraits or sprites of a form are under, according to its
+/// credits.txt. `None` if it has no history.
+pub async fn current_license(
+    context: &Context,
+    category: AssetCategory,
+    monster_id: i32,
+    form_id: &[i32],
+) -> FieldResult<Option<String>> {
+    let rows = get_local_credits_file(
+        context,
+        context.asset_store(),
+        category,
+        monster_id,
+        form_id,
+    )
+    .await?
+    .map_err(failed_credits_read)?;
+    Ok(newest_license(rows))
+}
+
+/// Groups all forms of all monsters by the license of their current portraits and sprites.
+pub async fn licensed_forms(context: &Context) -> FieldResult<Vec<LicensedForms>> {
+    context
+        .cached_may_fail_chain("/licensed_forms", || async {
+            let tracker = context.collab.data().tracker.clone();
+            // License -> full paths of the forms with portraits and with sprites under it.
+            let mut licenses: IndexMap<String, (Vec<String>, Vec<String>)> = IndexMap::new();
+            for group_id in tracker.keys() {
+                for form in monster_forms(&tracker, **group_id as i32)? {
+                    let form_path = full_form_path(form.id, &form.form_id);
+                    for category in [AssetCategory::Portrait, AssetCategory::Sprite] {
+                        let license =
+                            current_license(context, category, form.id, &form.form_id).await?;
+                        if let Some(license) = license {
+                            let (portrait_forms, sprite_forms) =
+                                licenses.entry(license).or_default();
+                            match category {
+                                AssetCategory::Portrait => portrait_forms.push(form_path.clone()),
+                                AssetCategory::Sprite => sprite_forms.push(form_path.clone()),
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(CacheBehaviour::Cache(
+                licenses
+                    .into_iter()
+                    .map(|(license, (portrait_forms, sprite_forms))| LicensedForms {
+                        license,
+                        portrait_forms,
+                        sprite_forms,
+                    })
+                    .collect(),
+            ))
+        })
+        .await
+}
+
+/// All existing (optionally flipped) portraits of a form.
+pub async fn portraits(
+    context: &Context,
+    group: &Group,
+    monster_id: i32,
+    form_id: &[i32],
+    flipped: bool,
+) -> FieldResult<Vec<Portrait>> {
+    Ok(iter_existing_portrait_files(
+        context,
+        context.asset_store(),
+        &group.portrait_files,
+        flipped,
+        monster_id,
+        form_id,
+    )
+    .await?
+    .into_iter()
+    .map(|(emotion, locked)| {
+        let emotion = context.collab.data().sprite_config.emotion(&emotion);
+        portrait(&context.url_base, monster_id, form_id, &emotion, locked)
+    })
+    .collect())
+}
+
+pub fn portrait(
+    url_base: &AssetUrlBase,
+    monster_id: i32,
+    form_id: &[i32],
+    emotion: &Emotion,
+    locked: bool,
+) -> Portrait {
+    let asset_type = if emotion.is_flipped() {
+        AssetType::PortraitFlipped(emotion.name())
+    } else {
+        AssetType::Portrait(emotion.name())
+    };
+    Portrait {
+        emotion: emotion.to_string(),
+        locked,
+        url: get_url(asset_type, url_base, monster_id, form_id),
+    }
+}
+
+pub fn sprite(
+    url_base: &AssetUrlBase,
+    monster_id: i32,
+    form_id: &[i32],
+    action: &ActionName,
+    locked: bool,
+) -> Sprite {
+    let action = action.as_str();
+    Sprite {
+        anim_url: get_url(AssetType::SpriteAnim(action), url_base, monster_id, form_id),
+        offsets_url: get_url(
+            AssetType::SpriteOffsets(action),
+            url_base,
+            monster_id,
+            form_id,
+        ),
+        shadows_url: get_url(
+            AssetType::SpriteShadows(action),
+            url_base,
+            monster_id,
+            form_id,
+        ),
+        action: action.to_string(),
+        locked,
+    }
+}
+
+async fn fetch_xml_and_make_action_map(
+    store: &dyn AssetStore,
+    monster_id: i32,
+    form_id: &[i32],
+) -> FieldResult<CacheBehaviour<HashMap<String, String>>> {
+    let xml = AnimDataXml::open_for_form(store, monster_id, form_id).map_err(failed_xml_fetch)?;
+    Ok(CacheBehaviour::Cache(xml.get_action_copies()))
+}
+
+/// Returns which actions of a form are copies of which other action, according to its
+/// AnimData.xml.
+///
+/// XXX: This isn't ideal, but Juniper is a bit silly about it's Sync requirements, so there's
+/// currently no way to do this truly async as far as I can tell.
+pub async fn sprite_action_copies(
+    context: &Context,
+    monster_id: i32,
+    form_id: &[i32],
+) -> FieldResult<HashMap<String, String>> {
+    context
+        .cached_may_fail_chain(
+            format!("/monster_actions|{}/{:?}", monster_id, form_id),
+            || fetch_xml_and_make_action_map(context.asset_store(), monster_id, form_id),
+        )
+        .await
+}
+
+/// All existing sprites of a form, in the order of the tracker.
+pub async fn sprite_actions(
+    context: &Context,
+    group: &Group,
+    monster_id: i32,
+    form_id: &[i32],
+) -> FieldResult<Vec<SpriteUnion>> {
+    if group.sprite_files.is_empty() {
+        return Ok(vec![]);
+    }
+    let action_copy_map = sprite_action_copies(context, monster_id, form_id).await?;
+    let mut normal_sprites: HashMap<String, Sprite> = iter_existing_sprite_files(
+        context,
+        context.asset_store(),
+        &group.sprite_files,
+        monster_id,
+        form_id,
+    )
+    .await?
+    .into_iter()
+    // Copy ofs shouldn't appear here since they shouldn't have any sheets, but if they
+    // do, we filter them out, since we explicitly add them below.
+    .filter(|(action, _)| !action_copy_map.contains_key(action))
+    .map(|(action, locked)| {
+        let name = context.collab.data().sprite_config.action(&action);
+        let sprite = sprite(&context.url_base, monster_id, form_id, &name, locked);
+        (action, sprite)
+    })
+    .collect();
+
+    let mut copy_of_sprites: HashMap<String, CopyOf> = action_copy_map
+        .into_iter()
+        .map(|(action, copy_of)| {
+            let copy = CopyOf {
+                locked: group.sprite_files.get(&action).copied().unwrap_or_default(),
+                action: action.clone(),
+                copy_of,
+            };
+            (action, copy)
+        })
+        .collect();
+
+    Ok(group
+        .sprite_files
+        .keys()
+        .filter_map(|k| {
+            if let Some(sprite) = normal_sprites.remove(k) {
+                Some(SpriteUnion::Sprite(sprite))
+            } else {
+                copy_of_sprites.remove(k).map(SpriteUnion::CopyOf)
+            }
+        })
+        .collect())
+}
@@ -1,36 +1,90 @@
 //! The actual client implementation for SpriteCollab.
-use std::cell::{BorrowError, Ref, RefCell};
 use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
 use std::future::Future;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock, RwLockReadGuard};
-use std::time::Duration;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex as StdMutex, RwLock};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Error};
+use arc_swap::{ArcSwap, Guard};
 use async_trait::async_trait;
 use chrono::{DateTime, FixedOffset, TimeZone, Utc};
 use fred::prelude::*;
-use fred::types::RedisKey;
-use git2::build::CheckoutBuilder;
-use git2::{Repository, ResetType};
+use fred::types::{ClusterHash, CustomCommand, Expiration, RedisKey, Scanner};
+use futures::StreamExt;
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{ErrorClass, FetchOptions, Repository, ResetType};
 use log::{debug, error, info, warn};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tokio::fs::{create_dir_all, remove_dir_all};
-use tokio::sync::Mutex;
-use tokio::time::timeout;
+use tokio::sync::{broadcast, watch, Mutex, Semaphore};
+use tokio::time::{sleep, timeout};
 
+use crate::activity::{find_activities, separate_issues, ExportedActivity};
+#[cfg(feature = "activity-store")]
+use crate::activity::{Activity, ActivityIssue};
+use crate::activity_exceptions::apply_credit_exceptions;
+#[cfg(feature = "activity-store")]
+use crate::activity_store::ActivityStore;
+use crate::api_keys::ApiKeyUsage;
+use crate::assets::store::{AssetStore, LocalAssetStore};
 use crate::cache::{CacheBehaviour, ScCache};
-use crate::config::Config;
+use crate::config::ServerConfig;
+use crate::datafiles::aliases::{read_aliases, Aliases};
 use crate::datafiles::credit_names::{read_credit_names, CreditNames};
 use crate::datafiles::group_id::GroupId;
+use crate::datafiles::integrity::{IntegrityIssueKind, IntegrityReport};
+use crate::datafiles::project_stats::ProjectStats;
+use crate::datafiles::refresh_report::RefreshReport;
 use crate::datafiles::sprite_config::{read_sprite_config, SpriteConfig};
-use crate::datafiles::tracker::{read_tracker, Group, MapImpl, Tracker};
+use crate::datafiles::tracker::{read_tracker, Group, MapImpl, Tracker, TrackerSearchIndex};
+use crate::datafiles::translations::{read_translations, Translations, TRANSLATIONS_FILE_NAME};
 use crate::datafiles::{read_and_report_error, try_read_in_anim_data_xml};
+use crate::disk_monitor::DiskMonitor;
+use crate::events::{find_contributions, ServerEvent, EVENTS_CAPACITY};
+use crate::git_gc::collect_garbage;
+use crate::mirror::download_data;
+#[cfg(test)]
+use crate::testing::MockCache;
+use crate::webhooks;
 
-const GIT_REPO_DIR: &str = "spritecollab";
+pub const GIT_REPO_DIR: &str = "spritecollab";
+/// Redis channel the served commit is published on after a refresh, so other instances that
+/// share the same Redis refresh too.
+const COMMITS_CHANNEL: &str = "scsrv|commits";
+/// How often a fetch that failed because of the network is retried in the same refresh.
+const FETCH_RETRIES: u32 = 3;
+/// Delay before the first retry of a fetch, it doubles with each retry.
+const FETCH_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Number of refreshes in a row that have to fail to update the repo for other reasons than the
+/// network, before it is deleted and cloned again.
+const MAX_STRUCTURAL_FAILURES: u32 = 3;
+/// Prefix of the keys of cache entries that are tagged with the commit they are calculated from.
+/// They are not flushed by refreshes, neither are other entries that don't depend on the data,
+/// eg. persisted queries, which use the prefix too.
+pub(crate) const VERSIONED_KEY_PREFIX: &str = "versioned|";
+/// Versioned entries expire after this many refresh intervals (but at least
+/// [`MIN_VERSIONED_TTL`]) without being requested. Their keys contain the options of the
+/// request, so entries of rare combinations and of old commits would pile up otherwise.
+const VERSIONED_TTL_REFRESHES: u32 = 4;
+const MIN_VERSIONED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Paths that are checked out if sparse checkouts are enabled.
+const SPARSE_CHECKOUT_PATHS: &[&str] = &[
+    "sprite",
+    "portrait",
+    "tracker.json",
+    "sprite_config.json",
+    "credit_names.txt",
+    "translations.json",
+];
+
+static STRUCTURAL_FAILURES: AtomicU32 = AtomicU32::new(0);
 
 #[derive(Eq, PartialEq)]
 enum State {
@@ -43,6 +97,17 @@ pub struct SpriteCollabData {
     pub sprite_config: SpriteConfig,
     pub tracker: Arc<Tracker>,
     pub credit_names: CreditNames,
+    /// Localized names of monsters and forms.
+    pub translations: Arc<Translations>,
+    /// Community nicknames of monsters and forms, found by the searches.
+    pub aliases: Arc<Aliases>,
+    /// The names the monster and form searches match against.
+    pub search_index: TrackerSearchIndex,
+    /// The commit the data was read from. Generated assets are served under it.
+    pub assets_commit: String,
+    /// Problems found by the integrity checks of the data.
+    pub integrity: IntegrityReport,
+    pub stats: ProjectStats,
 }
 
 impl SpriteCollabData {
@@ -50,14 +115,46 @@ impl SpriteCollabData {
         sprite_config: SpriteConfig,
         mut tracker: Tracker,
         credit_names: CreditNames,
+        translations: Translations,
+        aliases: Aliases,
+        assets_commit: String,
+        repo_path: Option<&Path>,
     ) -> SpriteCollabData {
         Self::sort_tracker_by_sprite_config(&mut tracker, &sprite_config);
+        // Mirrors have no asset files to check.
+        let integrity = match repo_path {
+            Some(repo_path) => {
+                IntegrityReport::check(repo_path, &sprite_config, &tracker, &credit_names)
+            }
+            None => IntegrityReport::default(),
+        };
+        let stats = ProjectStats::compute(&tracker, Utc::now());
+        let search_index = TrackerSearchIndex::build(&tracker, &translations, &aliases);
         Self {
             sprite_config,
             tracker: Arc::new(tracker),
             credit_names,
+            translations: Arc::new(translations),
+            aliases: Arc::new(aliases),
+            search_index,
+            assets_commit,
+            integrity,
+            stats,
         }
     }
+
+    /// No data, served until the first refresh succeeded if the server started without any.
+    fn empty() -> SpriteCollabData {
+        Self::new(
+            SpriteConfig::default(),
+            Tracker::default(),
+            CreditNames::default(),
+            Translations::default(),
+            Aliases::default(),
+            String::new(),
+            None,
+        )
+    }
 }
 
 impl SpriteCollabData {
@@ -98,25 +195,76 @@ impl SpriteCollabData {
 #[derive(Debug, Clone)]
 pub struct Meta {
     pub assets_commit: String,
+    pub branch: String,
     pub assets_update_date: DateTime<Utc>,
     pub update_checked_date: DateTime<Utc>,
+    /// `None` until the first refresh finished.
+    pub last_refresh_report: Option<RefreshReport>,
+    /// How long the last refresh took, whether it succeeded or not.
+    pub last_refresh_duration: Option<Duration>,
+    /// The error of the last refresh that failed. Unlike the error of the
+    /// [`Meta::last_refresh_report`], it is kept when later refreshes succeed.
+    pub last_error: Option<String>,
+    /// The commit that was served before [`Meta::assets_commit`], `None` until the served
+    /// commit changed.
+    pub previous_commit: Option<String>,
 }
 
 impl Meta {
     fn new() -> Self {
         Self {
             assets_commit: "".to_string(),
+            branch: ServerConfig::get().git_ref.clone(),
             assets_update_date: Utc::now(),
             update_checked_date: Utc::now(),
+            last_refresh_report: None,
+            last_refresh_duration: None,
+            last_error: None,
+            previous_commit: None,
+        }
+    }
+
+    /// Replaces the data of the served commit after a successful refresh, keeping the history.
+    fn served(
+        &mut self,
+        assets_commit: String,
+        assets_update_date: DateTime<Utc>,
+        unknown_credit_ids: Vec<String>,
+    ) {
+        if !self.assets_commit.is_empty() && self.assets_commit != assets_commit {
+            self.previous_commit = Some(std::mem::take(&mut self.assets_commit));
         }
+        self.last_refresh_report = Some(RefreshReport::succeeded(
+            assets_commit.clone(),
+            unknown_credit_ids,
+        ));
+        self.assets_commit = assets_commit;
+        self.branch = ServerConfig::get().git_ref.clone();
+        self.assets_update_date = assets_update_date;
+        self.update_checked_date = Utc::now();
     }
 }
 
 pub struct SpriteCollab {
     state: Mutex<State>,
-    meta: Mutex<RefCell<Meta>>,
-    current_data: RwLock<SpriteCollabData>,
-    redis: RedisClient,
+    meta: RwLock<Meta>,
+    /// Replaced as a whole by refreshes, so readers never wait for one.
+    current_data: ArcSwap<SpriteCollabData>,
+    /// Whether data was loaded, see [`SpriteCollab::is_ready`].
+    ready: AtomicBool,
+    cache: CacheStore,
+    /// Keys of the versioned cache entries that are currently being calculated.
+    in_flight: InFlightMap,
+    /// Limits how many versioned cache entries are calculated at the same time.
+    generation_slots: Semaphore,
+    /// Events for the clients of the `/events` stream.
+    events: broadcast::Sender<ServerEvent>,
+    api_key_usage: ApiKeyUsage,
+    disk_monitor: DiskMonitor,
+    /// The portrait and sprite files of the served data.
+    asset_store: Arc<dyn AssetStore>,
+    #[cfg(feature = "activity-store")]
+    activity_store: Option<ActivityStore>,
 }
 
 impl SpriteCollab {
@@ -133,68 +281,547 @@ impl SpriteCollab {
         let _: Option<()> = client.flushall(false).await.ok();
         info!("Connected to Redis.");
 
-        let meta = Mutex::new(RefCell::new(Meta::new()));
+        let meta = RwLock::new(Meta::new());
 
         // First try an ordinary data update.
         let current_data = match refresh_data(&meta).await {
-            Some(v) => RwLock::new(v),
+            data @ Some(_) => data,
+            None if ServerConfig::get().mirror_of.is_some() => loop {
+                error!("Failed getting the data from the primary instance. Trying again in 10 seconds.");
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                if let Some(value) = refresh_data(&meta).await {
+                    break Some(value);
+                }
+            },
             None => {
                 // Try going back in time in the repo and updating.
                 error!("Failed getting the newest data. Checking out old data until data processing works.");
-                let repo_path = PathBuf::from(Config::Workdir.get()).join(GIT_REPO_DIR);
+                let repo_path = ServerConfig::get().workdir.join(GIT_REPO_DIR);
                 loop {
-                    let new_commit = try_checkout_previous_commit(&repo_path)
-                        .expect("Failed checking out old commit.");
-                    warn!("Checked out old commit: {}", new_commit);
-                    if let Ok(value) = refresh_data_internal(&meta, false).await {
-                        break RwLock::new(value);
+                    match try_checkout_previous_commit(&repo_path) {
+                        Ok(new_commit) => {
+                            warn!("Checked out old commit: {}", new_commit);
+                            if let Ok(value) = refresh_data_internal(&meta, false).await {
+                                break Some(value);
+                            }
+                        }
+                        Err(e) => {
+                            // Eg. the repo could not be cloned. Serve no data instead of
+                            // crashing, the scheduler keeps trying to refresh.
+                            error!(
+                                "Failed checking out old commit: {}. Starting without data until a refresh succeeds.",
+                                e
+                            );
+                            break None;
+                        }
                     }
                 }
             }
         };
+        let ready = AtomicBool::new(current_data.is_some());
+        let current_data =
+            ArcSwap::from_pointee(current_data.unwrap_or_else(SpriteCollabData::empty));
+
+        #[cfg(feature = "activity-store")]
+        let activity_store = match &ServerConfig::get().activity_database_url {
+            Some(url) => Some(
+                ActivityStore::connect(url)
+                    .await
+                    .expect("Failed to connect to the activity database."),
+            ),
+            None => None,
+        };
 
         Arc::new(Self {
             state: Mutex::new(State::Ready),
             current_data,
-            redis: client,
+            ready,
+            cache: CacheStore::Redis(client),
+            in_flight: Default::default(),
+            generation_slots: Semaphore::new(ServerConfig::get().generation_workers),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            api_key_usage: Default::default(),
+            disk_monitor: Default::default(),
+            asset_store: Arc::new(LocalAssetStore::new(
+                ServerConfig::get().workdir.join(GIT_REPO_DIR),
+            )),
+            #[cfg(feature = "activity-store")]
+            activity_store,
             meta,
         })
     }
 
+    /// An instance for tests that serves `tracker` with the sample sprite config and credit
+    /// names, and stores its cache entries in `cache`.
+    #[cfg(test)]
+    pub(crate) async fn for_tests(tracker: Tracker, cache: Arc<MockCache>) -> Arc<Self> {
+        let repo_path = crate::assets::snapshots::fixtures_dir().join("spritecollab");
+        let data = SpriteCollabData::new(
+            read_sprite_config(repo_path.join("sprite_config.json"))
+                .await
+                .unwrap(),
+            tracker,
+            read_credit_names(repo_path.join("credit_names.txt"))
+                .await
+                .unwrap(),
+            Translations::default(),
+            Aliases::default(),
+            crate::testing::FIXTURE_COMMIT.to_string(),
+            None,
+        );
+        Arc::new(Self {
+            state: Mutex::new(State::Ready),
+            current_data: ArcSwap::from_pointee(data),
+            ready: AtomicBool::new(true),
+            cache: CacheStore::Mock(cache),
+            in_flight: Default::default(),
+            generation_slots: Semaphore::new(ServerConfig::get().generation_workers),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            api_key_usage: Default::default(),
+            disk_monitor: Default::default(),
+            asset_store: Arc::new(LocalAssetStore::new(
+                ServerConfig::get().workdir.join(GIT_REPO_DIR),
+            )),
+            #[cfg(feature = "activity-store")]
+            activity_store: None,
+            meta: RwLock::new(Meta::new()),
+        })
+    }
+
     /// Refreshes the data. Does nothing if already refreshing.
-    pub async fn refresh(slf: Arc<Self>) {
+    /// Returns whether the cache was flushed.
+    pub async fn refresh(slf: Arc<Self>) -> bool {
         let state_lock_result = timeout(Duration::from_secs(360), slf.state.lock()).await;
         match state_lock_result {
             Ok(mut state_lock) => {
                 if state_lock.deref() == &State::Refreshing {
-                    return;
+                    return false;
                 }
                 if let Some(new_data) = refresh_data(&slf.meta).await {
-                    let changed;
-                    {
-                        let mut lock_data = slf.current_data.write().unwrap();
-                        changed = lock_data.deref() == &new_data;
-                        *lock_data = new_data;
-                        *state_lock = State::Ready;
-                    }
+                    // Includes the commit, so every new commit flushes the generated assets.
+                    let changed = **slf.data() != new_data;
+                    let old = slf.current_data.swap(Arc::new(new_data));
+                    *state_lock = State::Ready;
+                    let was_ready = slf.ready.swap(true, AtomicOrdering::Relaxed);
+                    let old_commit = old.assets_commit.clone();
                     if changed {
-                        let _: Option<()> = slf.redis.flushall(false).await.ok();
+                        slf.flush_except_versioned().await;
+                    }
+                    if !was_ready {
+                        // There is no previous data to find activities and events in.
+                        info!("Loaded the data of commit {}.", slf.data().assets_commit);
+                        slf.publish_commit().await;
+                        return changed;
+                    }
+                    if slf.data().assets_commit != old_commit {
+                        slf.publish_commit().await;
+                        slf.handle_new_activities(old_commit.clone(), &old.tracker);
+                    }
+                    slf.send_refresh_events(changed, old_commit, &old.tracker);
+                    return changed;
+                }
+                false
+            }
+            Err(_) => {
+                warn!("BUG: State lock could not be acquired in SpriteCollab::refresh!");
+                false
+            }
+        }
+    }
+
+    /// Removes the objects of old commits from the repository, see [`crate::git_gc`]. Waits for
+    /// a running refresh to finish first.
+    pub async fn collect_garbage(&self) {
+        let _state_lock = self.state.lock().await;
+        let repo_path = ServerConfig::get().workdir.join(GIT_REPO_DIR);
+        match collect_garbage(&repo_path) {
+            Ok(stats) => info!(
+                "Collected garbage of the repo: {} objects kept, {} MiB reclaimed by the next run ({} MiB left).",
+                stats.objects,
+                stats.reclaimed() / 1024 / 1024,
+                stats.size_after / 1024 / 1024
+            ),
+            Err(e) => warn!("Failed to collect garbage of the repo: {:?}", e),
+        }
+    }
+
+    async fn publish_commit(&self) {
+        let redis = match self.cache.redis() {
+            Some(redis) => redis,
+            None => return,
+        };
+        let commit = self.data().assets_commit.clone();
+        let r: Result<i64, RedisError> = redis.publish(COMMITS_CHANNEL, commit).await;
+        if let Err(err) = r {
+            warn!("Failed publishing the new commit: {:?}", err);
+        }
+    }
+
+    /// Calls `on_new_commit` whenever another instance published a commit that this instance
+    /// does not serve yet. Runs until the connection to Redis is closed.
+    pub async fn listen_for_commits<F>(&self, on_new_commit: F)
+    where
+        F: Fn(String) + Send + Sync,
+    {
+        let redis = match self.cache.redis() {
+            Some(redis) => redis,
+            None => return,
+        };
+        // A connection that subscribed to a channel can't run other commands.
+        let subscriber = redis.clone_new();
+        subscriber.connect();
+        if let Err(err) = subscriber.wait_for_connect().await {
+            warn!(
+                "Failed connecting to Redis to listen for commits: {:?}",
+                err
+            );
+            return;
+        }
+        let mut messages = subscriber.message_rx();
+        let mut reconnects = subscriber.reconnect_rx();
+        loop {
+            // Subscriptions are lost when the connection is lost.
+            if let Err(err) = subscriber.subscribe(COMMITS_CHANNEL).await {
+                warn!(
+                    "Failed subscribing to the commits of other instances: {:?}",
+                    err
+                );
+            }
+            loop {
+                tokio::select! {
+                    message = messages.recv() => match message {
+                        Ok(message) => {
+                            let commit = match message.value.as_string() {
+                                Some(commit) => commit,
+                                None => continue,
+                            };
+                            let is_new = commit != self.data().assets_commit;
+                            if is_new {
+                                on_new_commit(commit);
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    },
+                    reconnect = reconnects.recv() => match reconnect {
+                        Err(broadcast::error::RecvError::Closed) => return,
+                        _ => break,
+                    },
+                }
+            }
+        }
+    }
+
+    pub fn api_key_usage(&self) -> &ApiKeyUsage {
+        &self.api_key_usage
+    }
+
+    pub fn disk_monitor(&self) -> &DiskMonitor {
+        &self.disk_monitor
+    }
+
+    pub fn asset_store(&self) -> &dyn AssetStore {
+        self.asset_store.as_ref()
+    }
+
+    /// The activity store, if it is configured.
+    #[cfg(feature = "activity-store")]
+    pub fn activity_store(&self) -> Option<&ActivityStore> {
+        self.activity_store.as_ref()
+    }
+
+    /// Subscribes to the events sent after refreshes.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
+    fn send_refresh_events(&self, changed: bool, old_commit: String, old_tracker: &Tracker) {
+        // Nobody is listening, don't bother looking for contributions.
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        let (commit, tracker, unknown_credit_ids) = {
+            let data = self.data();
+            (
+                data.assets_commit.clone(),
+                data.tracker.clone(),
+                data.integrity.unknown_credit_ids(),
+            )
+        };
+        let mut events = vec![ServerEvent::Refresh {
+            commit: commit.clone(),
+            changed,
+            date: Utc::now(),
+        }];
+        if commit != old_commit {
+            events.push(ServerEvent::Commit {
+                commit: commit.clone(),
+                previous_commit: old_commit,
+            });
+            events.extend(find_contributions(old_tracker, &tracker));
+            if !unknown_credit_ids.is_empty() {
+                events.push(ServerEvent::UnknownCreditIds {
+                    commit,
+                    credit_ids: unknown_credit_ids,
+                });
+            }
+        }
+        for event in events {
+            // Fails only if all clients disconnected in the meantime.
+            let _ = self.events.send(event);
+        }
+    }
+
+    /// Sends the activities of the new commit to the webhooks and stores them, if the activity
+    /// store is enabled. Both happen in the background.
+    fn handle_new_activities(
+        self: &Arc<Self>,
+        previous_commit: String,
+        previous_tracker: &Tracker,
+    ) {
+        #[cfg(feature = "activity-store")]
+        let store_enabled = self.activity_store.is_some();
+        #[cfg(not(feature = "activity-store"))]
+        let store_enabled = false;
+        if !store_enabled && ServerConfig::get().webhook_urls.is_empty() {
+            return;
+        }
+        let (commit, mut activities) = {
+            let data = self.data();
+            (
+                data.assets_commit.clone(),
+                find_activities(previous_tracker, &data.tracker),
+            )
+        };
+        apply_credit_exceptions(
+            &ServerConfig::get().activity_credit_exceptions,
+            &commit,
+            &mut activities,
+        );
+        let (activities, issues) = separate_issues(activities);
+        webhooks::send_activities(
+            &commit,
+            &previous_commit,
+            activities
+                .iter()
+                .cloned()
+                .map(|activity| ExportedActivity::new(&commit, activity))
+                .collect(),
+        );
+        #[cfg(feature = "activity-store")]
+        if store_enabled {
+            self.record_activities(commit, previous_commit, activities, issues);
+        }
+        #[cfg(not(feature = "activity-store"))]
+        let _ = issues;
+    }
+
+    /// Stores the activities of the new commit in the background.
+    #[cfg(feature = "activity-store")]
+    fn record_activities(
+        self: &Arc<Self>,
+        commit: String,
+        previous_commit: String,
+        activities: Vec<Activity>,
+        issues: Vec<ActivityIssue>,
+    ) {
+        let slf = self.clone();
+        tokio::spawn(async move {
+            let date = slf.with_meta(|meta| meta.assets_update_date);
+            if let Some(store) = &slf.activity_store {
+                if let Err(e) = store
+                    .record_commit(&commit, Some(&previous_commit), date, &activities, &issues)
+                    .await
+                {
+                    warn!("Failed storing the activities of {}: {:?}", commit, e);
+                }
+            }
+        });
+    }
+
+    /// Whether data was loaded. The server starts without data if neither the newest nor an
+    /// older commit of the repo could be loaded, eg. because it could not be cloned. Until a
+    /// refresh succeeds, it serves empty data and `/healthz` reports it as not ready.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(AtomicOrdering::Relaxed)
+    }
+
+    /// A snapshot of the served data. It stays the same during refreshes, which publish new
+    /// data as a whole, so callers should not keep it longer than for a single request.
+    pub fn data(&self) -> Guard<Arc<SpriteCollabData>> {
+        self.current_data.load()
+    }
+
+    /// Looks up a versioned cache entry, eg. of a generated asset, or calculates it. Entries are
+    /// tagged with the commit they were calculated from and survive refreshes. If the entry is
+    /// of an older commit, it is returned anyway (marked as stale) and `func` is run in the
+    /// background to replace it, so the caller does not have to wait for it. Only one caller
+    /// calculates an entry at a time, concurrent callers wait for its result.
+    pub async fn cached_stale_while_revalidate<Fn, Ft, T, E>(
+        self: &Arc<Self>,
+        cache_key: String,
+        func: Fn,
+    ) -> Result<Result<CachedVersion<T>, E>, Error>
+    where
+        Fn: (FnOnce() -> Ft) + Send + 'static,
+        Ft: Future<Output = Result<CacheBehaviour<T>, E>> + Send + 'static,
+        T: DeserializeOwned + Serialize + Send + Sync + 'static,
+        E: Debug + Send + 'static,
+    {
+        let version = self.data().assets_commit.clone();
+        let key = format!("{}{}", VERSIONED_KEY_PREFIX, cache_key);
+        if let Some(entry) = self.get_versioned::<T>(&key).await? {
+            if entry.version == version {
+                return Ok(Ok(CachedVersion::current(entry)));
+            }
+            // Regenerate it in the background, unless that is already happening.
+            if let InFlight::Leader(guard) = self.join_in_flight(&key) {
+                let slf = self.clone();
+                tokio::spawn(async move {
+                    let _guard = guard;
+                    match slf.generate(func).await {
+                        Ok(value) => {
+                            slf.store_versioned(&key, &version, value).await;
+                        }
+                        Err(err) => warn!(
+                            "Failed regenerating the stale cache entry '{}': {:?}",
+                            key, err
+                        ),
+                    }
+                });
+            }
+            return Ok(Ok(CachedVersion {
+                value: entry.value,
+                version: entry.version,
+                stale: true,
+            }));
+        }
+        match self.join_in_flight(&key) {
+            InFlight::Leader(_guard) => match self.generate(func).await {
+                Ok(value) => Ok(Ok(CachedVersion::current(VersionedEntry {
+                    value: self.store_versioned(&key, &version, value).await,
+                    version,
+                }))),
+                Err(e) => Ok(Err(e)),
+            },
+            InFlight::Waiter(mut done) => {
+                // Another request is already calculating it, use its result.
+                let _ = done.changed().await;
+                if let Some(entry) = self.get_versioned::<T>(&key).await? {
+                    if entry.version == version {
+                        return Ok(Ok(CachedVersion::current(entry)));
                     }
                 }
+                // It failed or was not cached, try again.
+                match self.generate(func).await {
+                    Ok(value) => Ok(Ok(CachedVersion::current(VersionedEntry {
+                        value: self.store_versioned(&key, &version, value).await,
+                        version,
+                    }))),
+                    Err(e) => Ok(Err(e)),
+                }
             }
-            Err(_) => warn!("BUG: State lock could not be acquired in SpriteCollab::refresh!"),
         }
     }
 
-    pub fn data(&self) -> RwLockReadGuard<'_, SpriteCollabData> {
-        self.current_data.read().unwrap()
+    /// Runs `func` once a generation slot is free.
+    async fn generate<Fn, Ft>(&self, func: Fn) -> Ft::Output
+    where
+        Fn: FnOnce() -> Ft,
+        Ft: Future,
+    {
+        // The semaphore is never closed.
+        let _permit = self.generation_slots.acquire().await.ok();
+        func().await
     }
 
-    pub async fn with_meta<F: FnOnce(Result<Ref<'_, Meta>, BorrowError>) -> R, R>(
+    async fn get_versioned<T: DeserializeOwned>(
         &self,
-        cb: F,
-    ) -> R {
-        cb(self.meta.lock().await.deref().try_borrow())
+        key: &str,
+    ) -> Result<Option<VersionedEntry<T>>, Error> {
+        // Every hit keeps the entry for another TTL.
+        let red_val = self.cache.get_and_expire(key, versioned_ttl()).await?;
+        Ok(match red_val {
+            Some(red_val) => Some(serde_json::from_str(&red_val)?),
+            None => None,
+        })
+    }
+
+    /// Registers that the entry with the key `key` is being calculated. If it already is, returns
+    /// a receiver that is notified once that is done instead.
+    fn join_in_flight(&self, key: &str) -> InFlight {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(done) = in_flight.get(key) {
+            return InFlight::Waiter(done.clone());
+        }
+        let (done_sender, done) = watch::channel(());
+        in_flight.insert(key.to_string(), done);
+        InFlight::Leader(InFlightGuard {
+            in_flight: self.in_flight.clone(),
+            key: key.to_string(),
+            _done_sender: done_sender,
+        })
+    }
+
+    async fn store_versioned<T: Serialize>(
+        &self,
+        key: &str,
+        version: &str,
+        value: CacheBehaviour<T>,
+    ) -> T {
+        if let CacheBehaviour::Cache(value) = &value {
+            let entry = VersionedEntryRef { version, value };
+            match serde_json::to_string(&entry) {
+                Ok(save_string) => {
+                    if let Err(err) = self
+                        .cache
+                        .set(key, save_string, Some(versioned_ttl()))
+                        .await
+                    {
+                        warn!(
+                            "Failed writing cache entry for '{}' to Redis (stage 2): {:?}",
+                            key, err
+                        );
+                    }
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed writing cache entry for '{}' to Redis (stage 1): {:?}",
+                        key, err
+                    );
+                }
+            }
+        }
+        value.into_inner()
+    }
+
+    /// Removes all entries from the cache, except for the versioned ones, which are replaced
+    /// when they are requested the next time, or expire, see [`VERSIONED_TTL_REFRESHES`].
+    pub(crate) async fn flush_except_versioned(&self) {
+        let keys = match self.cache_keys("").await {
+            Ok(keys) => keys,
+            Err(err) => {
+                warn!(
+                    "Failed listing the cache keys, flushing everything: {:?}",
+                    err
+                );
+                self.cache.flush().await;
+                return;
+            }
+        };
+        let keys = keys
+            .into_iter()
+            .filter(|key| !key.starts_with(VERSIONED_KEY_PREFIX))
+            .collect::<Vec<_>>();
+        for chunk in keys.chunks(1000) {
+            if let Err(err) = self.cache.del(chunk.to_vec()).await {
+                warn!("Failed flushing the cache: {:?}", err);
+            }
+        }
+    }
+
+    pub fn with_meta<F: FnOnce(&Meta) -> R, R>(&self, cb: F) -> R {
+        cb(self.meta.read().unwrap().deref())
     }
 }
 
@@ -214,7 +841,7 @@ impl ScCache for SpriteCollab {
         T: DeserializeOwned + Serialize + Send + Sync,
         E: Send,
     {
-        let red_val: Option<String> = self.redis.get(cache_key.as_ref()).await?;
+        let red_val = self.cache.get(cache_key.as_ref()).await?;
         if let Some(red_val) = red_val {
             Ok(Ok(serde_json::from_str(&red_val)?))
         } else {
@@ -223,11 +850,9 @@ impl ScCache for SpriteCollab {
                     let save_string = serde_json::to_string(&v);
                     match save_string {
                         Ok(save_string) => {
-                            let r: Result<(), RedisError> = self
-                                .redis
-                                .set(cache_key.as_ref(), save_string, None, None, false)
-                                .await;
-                            if let Err(err) = r {
+                            if let Err(err) =
+                                self.cache.set(cache_key.as_ref(), save_string, None).await
+                            {
                                 warn!(
                                     "Failed writing cache entry for '{}' to Redis (stage 2): {:?}",
                                     cache_key.as_ref(),
@@ -250,9 +875,205 @@ impl ScCache for SpriteCollab {
             }
         }
     }
+
+    async fn cache_keys(&self, prefix: &str) -> Result<Vec<String>, Self::Error> {
+        Ok(self.cache.keys(prefix).await?)
+    }
+
+    async fn evict(&self, cache_key: &str) -> Result<bool, Self::Error> {
+        let removed = self.cache.del(vec![cache_key.to_string()]).await?;
+        Ok(removed > 0)
+    }
 }
 
-async fn refresh_data(meta: &Mutex<RefCell<Meta>>) -> Option<SpriteCollabData> {
+/// Where the cache entries are stored.
+enum CacheStore {
+    Redis(RedisClient),
+    /// In memory, for tests.
+    #[cfg(test)]
+    Mock(Arc<MockCache>),
+}
+
+impl CacheStore {
+    /// The Redis client, `None` if the entries are not stored in Redis.
+    fn redis(&self) -> Option<&RedisClient> {
+        match self {
+            CacheStore::Redis(redis) => Some(redis),
+            #[cfg(test)]
+            CacheStore::Mock(_) => None,
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, RedisError> {
+        match self {
+            CacheStore::Redis(redis) => redis.get(key).await,
+            #[cfg(test)]
+            CacheStore::Mock(mock) => Ok(mock.get(key)),
+        }
+    }
+
+    /// Like [`CacheStore::get`], but also sets the entry to expire after `ttl`.
+    async fn get_and_expire(&self, key: &str, ttl: Duration) -> Result<Option<String>, RedisError> {
+        match self {
+            // fred has no interface for GETEX.
+            CacheStore::Redis(redis) => {
+                let args: Vec<RedisValue> = vec![
+                    key.into(),
+                    "EX".into(),
+                    (ttl.as_secs().max(1) as i64).into(),
+                ];
+                redis
+                    .custom(
+                        CustomCommand::new_static("GETEX", ClusterHash::FirstKey, false),
+                        args,
+                    )
+                    .await
+            }
+            // Entries never expire in tests.
+            #[cfg(test)]
+            CacheStore::Mock(mock) => Ok(mock.get(key)),
+        }
+    }
+
+    /// Sets an entry, that expires after `ttl` if it is set.
+    async fn set(&self, key: &str, value: String, ttl: Option<Duration>) -> Result<(), RedisError> {
+        match self {
+            CacheStore::Redis(redis) => {
+                redis
+                    .set(key, value, ttl.map(expiration), None, false)
+                    .await
+            }
+            #[cfg(test)]
+            CacheStore::Mock(mock) => {
+                mock.set(key, value);
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes entries, returns the number of removed entries.
+    async fn del(&self, keys: Vec<String>) -> Result<i64, RedisError> {
+        match self {
+            CacheStore::Redis(redis) => redis.del(keys).await,
+            #[cfg(test)]
+            CacheStore::Mock(mock) => Ok(keys.iter().filter(|key| mock.remove(key)).count() as i64),
+        }
+    }
+
+    /// All keys that start with `prefix`, sorted.
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>, RedisError> {
+        match self {
+            CacheStore::Redis(redis) => scan_keys(redis, prefix).await,
+            #[cfg(test)]
+            CacheStore::Mock(mock) => Ok(mock.keys(prefix)),
+        }
+    }
+
+    /// Removes all entries.
+    async fn flush(&self) {
+        match self {
+            CacheStore::Redis(redis) => {
+                let _: Option<()> = redis.flushall(false).await.ok();
+            }
+            #[cfg(test)]
+            CacheStore::Mock(mock) => mock.clear(),
+        }
+    }
+}
+
+/// All keys in Redis that start with `prefix`, sorted.
+async fn scan_keys(redis: &RedisClient, prefix: &str) -> Result<Vec<String>, RedisError> {
+    let mut keys = Vec::new();
+    let pattern = format!("{}*", escape_glob(prefix));
+    let mut pages = pin!(redis.scan(pattern, Some(1000), None));
+    while let Some(page) = pages.next().await {
+        let mut page = page?;
+        if let Some(results) = page.take_results() {
+            keys.extend(results.into_iter().filter_map(RedisKey::into_string));
+        }
+        page.next()?;
+    }
+    keys.sort();
+    Ok(keys)
+}
+
+fn expiration(ttl: Duration) -> Expiration {
+    Expiration::EX(ttl.as_secs().max(1) as i64)
+}
+
+/// How long versioned entries are kept without being requested.
+fn versioned_ttl() -> Duration {
+    (ServerConfig::get().refresh_interval * VERSIONED_TTL_REFRESHES).max(MIN_VERSIONED_TTL)
+}
+
+#[derive(Serialize)]
+struct VersionedEntryRef<'a, T> {
+    version: &'a str,
+    value: &'a T,
+}
+
+#[derive(Deserialize)]
+struct VersionedEntry<T> {
+    version: String,
+    value: T,
+}
+
+/// A value returned by [`SpriteCollab::cached_stale_while_revalidate`].
+pub struct CachedVersion<T> {
+    pub value: T,
+    /// The commit the value was calculated from.
+    pub version: String,
+    /// Whether the value is of an older commit than the served one.
+    pub stale: bool,
+}
+
+impl<T> CachedVersion<T> {
+    fn current(entry: VersionedEntry<T>) -> Self {
+        Self {
+            value: entry.value,
+            version: entry.version,
+            stale: false,
+        }
+    }
+}
+
+type InFlightMap = Arc<StdMutex<HashMap<String, watch::Receiver<()>>>>;
+
+enum InFlight {
+    /// Nobody else is calculating the entry. Others wait until the guard is dropped.
+    Leader(InFlightGuard),
+    /// Somebody else is calculating the entry. The receiver is notified once they are done.
+    Waiter(watch::Receiver<()>),
+}
+
+struct InFlightGuard {
+    in_flight: InFlightMap,
+    key: String,
+    /// Dropping it notifies all waiters.
+    _done_sender: watch::Sender<()>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(&self.key);
+        }
+    }
+}
+
+/// Escapes the special characters of Redis glob patterns.
+fn escape_glob(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+async fn refresh_data(meta: &RwLock<Meta>) -> Option<SpriteCollabData> {
     debug!("Refreshing data...");
     match refresh_data_internal(meta, true).await {
         Ok(v) => Some(v),
@@ -264,43 +1085,38 @@ async fn refresh_data(meta: &Mutex<RefCell<Meta>>) -> Option<SpriteCollabData> {
 }
 
 async fn refresh_data_internal(
-    meta: &Mutex<RefCell<Meta>>,
+    meta: &RwLock<Meta>,
     update: bool,
 ) -> Result<SpriteCollabData, Error> {
-    match refresh_data_internal_do(meta, update).await {
-        Ok(v) => Ok(v),
-        Err(e) => {
-            // Update at least the scan time
-            let meta_acq = meta.lock().await;
-            let mut meta_brw = meta_acq.try_borrow_mut()?;
-            meta_brw.update_checked_date = Utc::now();
-            Err(e)
-        }
+    let start = Instant::now();
+    let result = refresh_data_internal_do(meta, update).await;
+    let mut meta = meta.write().unwrap();
+    meta.last_refresh_duration = Some(start.elapsed());
+    if let Err(e) = &result {
+        // Update at least the scan time and report what went wrong
+        meta.update_checked_date = Utc::now();
+        meta.last_refresh_report = Some(RefreshReport::failed(
+            meta.assets_commit.clone(),
+            e,
+            &ServerConfig::get().workdir.join(GIT_REPO_DIR),
+        ));
+        meta.last_error = Some(e.to_string());
     }
+    result
 }
 
 async fn refresh_data_internal_do(
-    meta: &Mutex<RefCell<Meta>>,
+    meta: &RwLock<Meta>,
     update: bool,
 ) -> Result<SpriteCollabData, Error> {
-    let repo_path = PathBuf::from(Config::Workdir.get()).join(GIT_REPO_DIR);
+    let repo_path = ServerConfig::get().workdir.join(GIT_REPO_DIR);
+    if ServerConfig::get().mirror_of.is_some() {
+        return refresh_mirrored_data(meta, &repo_path).await;
+    }
     let repo;
     if repo_path.exists() {
         if update {
-            match try_update_repo(&repo_path) {
-                Ok(v) => repo = Some(v),
-                Err(clone_e) => {
-                    // If this fails, throw the repo away (if applicable) and clone it new.
-                    warn!(
-                        "Failed to update repo, deleting and cloning it again: {}",
-                        clone_e
-                    );
-                    if let Err(e) = remove_dir_all(&repo_path).await {
-                        warn!("Failed to delete repo directory: {}", e);
-                    }
-                    repo = Some(create_repo(&repo_path, &Config::GitRepo.get())?);
-                }
-            }
+            repo = Some(update_repo(&repo_path).await?);
         } else {
             if !repo_path.join(".git").exists() {
                 return Err(anyhow!("Missing .git directory"));
@@ -309,21 +1125,31 @@ async fn refresh_data_internal_do(
         }
     } else {
         create_dir_all(&repo_path).await?;
-        repo = Some(create_repo(&repo_path, &Config::GitRepo.get())?);
+        repo = Some(create_repo(&repo_path, &ServerConfig::get().git_repo)?);
     }
 
+    let assets_commit = repo
+        .as_ref()
+        .unwrap()
+        .head()?
+        .peel_to_commit()?
+        .id()
+        .to_string();
     let scd = SpriteCollabData::new(
         read_and_report_error(&repo_path.join("sprite_config.json"), read_sprite_config).await?,
         read_and_report_error(&repo_path.join("tracker.json"), read_tracker).await?,
         read_and_report_error(&repo_path.join("credit_names.txt"), read_credit_names).await?,
+        read_and_report_error(&translations_path(&repo_path), read_translations).await?,
+        read_configured_aliases().await?,
+        assets_commit,
+        Some(&repo_path),
     );
 
     // Also try to recursively read in all AnimData.xml files, for validation.
-    try_read_in_anim_data_xml(&scd.tracker).await?;
+    try_read_in_anim_data_xml(&LocalAssetStore::new(&repo_path), &scd.tracker).await?;
+    report_integrity(&scd.integrity);
 
     // Update metadata
-    let meta_acq = meta.lock().await;
-    let mut meta_brw = meta_acq.try_borrow_mut()?;
     let commit = repo.as_ref().unwrap().head()?.peel_to_commit()?;
     let commit_time_raw = commit.time();
     let commit_time = FixedOffset::east_opt(commit_time_raw.offset_minutes() * 60)
@@ -335,15 +1161,78 @@ async fn refresh_data_internal_do(
         )
         .unwrap();
 
-    *meta_brw = Meta {
-        assets_commit: commit.id().to_string(),
-        assets_update_date: Utc.from_utc_datetime(&commit_time.naive_utc()),
-        update_checked_date: Utc::now(),
+    meta.write().unwrap().served(
+        commit.id().to_string(),
+        Utc.from_utc_datetime(&commit_time.naive_utc()),
+        scd.integrity.unknown_credit_ids(),
+    );
+
+    Ok(scd)
+}
+
+/// Downloads the data from the primary instance, see [`crate::mirror`].
+async fn refresh_mirrored_data(
+    meta: &RwLock<Meta>,
+    repo_path: &Path,
+) -> Result<SpriteCollabData, Error> {
+    let assets_commit = download_data(repo_path).await?;
+    let scd = SpriteCollabData::new(
+        read_and_report_error(&repo_path.join("sprite_config.json"), read_sprite_config).await?,
+        read_and_report_error(&repo_path.join("tracker.json"), read_tracker).await?,
+        read_and_report_error(&repo_path.join("credit_names.txt"), read_credit_names).await?,
+        read_and_report_error(&translations_path(repo_path), read_translations).await?,
+        read_configured_aliases().await?,
+        assets_commit.clone(),
+        None,
+    );
+
+    let mut meta = meta.write().unwrap();
+    // The commit date is not known, so it is the date the mirror got the commit.
+    let assets_update_date = if meta.assets_commit != assets_commit {
+        Utc::now()
+    } else {
+        meta.assets_update_date
     };
+    // Mirrors don't check the integrity, the primary reports the unknown credit IDs.
+    meta.served(assets_commit, assets_update_date, vec![]);
 
     Ok(scd)
 }
 
+/// The translations of `SCSRV_TRANSLATIONS_FILE`, or of the repository.
+fn translations_path(repo_path: &Path) -> PathBuf {
+    ServerConfig::get()
+        .translations_file
+        .clone()
+        .unwrap_or_else(|| repo_path.join(TRANSLATIONS_FILE_NAME))
+}
+
+/// The aliases of `SCSRV_ALIASES_FILE`, none if it is not configured.
+async fn read_configured_aliases() -> Result<Aliases, Error> {
+    match &ServerConfig::get().aliases_file {
+        Some(path) => Ok(read_and_report_error(path, read_aliases).await?),
+        None => Ok(Aliases::default()),
+    }
+}
+
+/// Logs a summary of the problems found by the integrity checks.
+fn report_integrity(report: &IntegrityReport) {
+    if report.issues.is_empty() {
+        return;
+    }
+    warn!(
+        "Data integrity check found {} problems: {} missing files, {} untracked files, {} unknown credit IDs, {} inconsistent phases.",
+        report.issues.len(),
+        report.count(IntegrityIssueKind::MissingFile),
+        report.count(IntegrityIssueKind::UntrackedFile),
+        report.count(IntegrityIssueKind::UnknownCreditId),
+        report.count(IntegrityIssueKind::InconsistentPhase),
+    );
+    for issue in &report.issues {
+        debug!("{:?} in {}: {}", issue.kind, issue.form_path, issue.details);
+    }
+}
+
 fn try_checkout_previous_commit(path: &Path) -> Result<String, Error> {
     let repo = Repository::open(path)?;
     let reference = repo.head()?.peel_to_commit()?.parent(0)?;
@@ -356,22 +1245,165 @@ fn try_checkout_previous_commit(path: &Path) -> Result<String, Error> {
     Ok(name)
 }
 
+/// Updates the repo. Fetches that fail because of the network are retried with a backoff. If
+/// updating fails for another reason in [`MAX_STRUCTURAL_FAILURES`] refreshes in a row, the repo
+/// is thrown away and cloned again. Until then, the previous data stays served.
+async fn update_repo(repo_path: &Path) -> Result<Repository, Error> {
+    let mut retries = 0;
+    let result = loop {
+        match try_update_repo(repo_path) {
+            Err(e) if is_transient_git_error(&e) && retries < FETCH_RETRIES => {
+                let delay = FETCH_RETRY_DELAY * 2u32.pow(retries);
+                warn!(
+                    "Failed to fetch repo, retrying in {}s: {}",
+                    delay.as_secs(),
+                    e
+                );
+                sleep(delay).await;
+                retries += 1;
+            }
+            result => break result,
+        }
+    };
+    let e = match result {
+        Ok(repo) => {
+            STRUCTURAL_FAILURES.store(0, AtomicOrdering::Relaxed);
+            return Ok(repo);
+        }
+        Err(e) => e,
+    };
+    if is_transient_git_error(&e) {
+        warn!(
+            "Failed to fetch repo after {} retries, trying again on the next refresh: {}",
+            retries, e
+        );
+        return Err(e);
+    }
+    let failures = STRUCTURAL_FAILURES.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+    if failures < MAX_STRUCTURAL_FAILURES && repo_path.join(".git").exists() {
+        warn!(
+            "Failed to update repo ({}/{} failures before cloning it again): {}",
+            failures, MAX_STRUCTURAL_FAILURES, e
+        );
+        return Err(e);
+    }
+    warn!(
+        "Failed to update repo, deleting and cloning it again: {}",
+        e
+    );
+    STRUCTURAL_FAILURES.store(0, AtomicOrdering::Relaxed);
+    if let Err(e) = remove_dir_all(repo_path).await {
+        warn!("Failed to delete repo directory: {}", e);
+    }
+    create_repo(repo_path, &ServerConfig::get().git_repo)
+}
+
+/// Whether the error is caused by the network or the remote, so trying again later may work.
+fn is_transient_git_error(e: &Error) -> bool {
+    e.downcast_ref::<git2::Error>().is_some_and(|e| {
+        matches!(
+            e.class(),
+            ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssl | ErrorClass::Ssh
+        )
+    })
+}
+
 fn try_update_repo(path: &Path) -> Result<Repository, Error> {
     if !path.join(".git").exists() {
         return Err(anyhow!("Missing .git directory"));
     }
     let repo = Repository::open(path)?;
+    fetch_and_checkout_ref(&repo)?;
+    Ok(Repository::open(path)?) // libgit2's borrowing code is a bit dumb
+}
+
+/// Fetches the configured branch or tag and checks it out.
+fn fetch_and_checkout_ref(repo: &Repository) -> Result<(), Error> {
+    let config = ServerConfig::get();
     let mut remote = repo.find_remote("origin")?;
-    remote.fetch(&["master"], None, None)?;
+    remote.fetch(
+        &[&config.git_ref],
+        Some(&mut make_fetch_options(config.git_clone_depth)),
+        None,
+    )?;
     let reference = repo.find_reference("FETCH_HEAD")?;
     repo.set_head(reference.name().unwrap())?;
-    repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
-    Ok(Repository::open(path)?) // libgit2's borrowing code is a bit dumb
+    repo.checkout_head(Some(
+        make_checkout_builder(config.git_sparse_checkout).force(),
+    ))?;
+    Ok(())
 }
 
 fn create_repo(path: &Path, clone_url: &str) -> Result<Repository, Error> {
-    info!("Cloning SpriteCollab repo...");
-    let repo = Repository::clone(clone_url, path)?;
+    let config = ServerConfig::get();
+    let (depth, sparse) = (config.git_clone_depth, config.git_sparse_checkout);
+    match depth {
+        Some(depth) => info!("Cloning SpriteCollab repo (depth: {depth}, sparse: {sparse})..."),
+        None => info!("Cloning SpriteCollab repo (sparse: {sparse})..."),
+    }
+    let repo = RepoBuilder::new()
+        .fetch_options(make_fetch_options(depth))
+        .with_checkout(make_checkout_builder(sparse))
+        .clone(clone_url, path)?;
+    fetch_and_checkout_ref(&repo)?;
     info!("Cloning SpriteCollab repo. Done!");
     Ok(repo)
 }
+
+fn make_fetch_options<'a>(depth: Option<i32>) -> FetchOptions<'a> {
+    let mut fetch_options = FetchOptions::new();
+    if let Some(depth) = depth {
+        fetch_options.depth(depth);
+    }
+    fetch_options
+}
+
+fn make_checkout_builder<'a>(sparse: bool) -> CheckoutBuilder<'a> {
+    let mut checkout = CheckoutBuilder::new();
+    if sparse {
+        for path in SPARSE_CHECKOUT_PATHS {
+            checkout.path(*path);
+        }
+    }
+    checkout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{config, origin_path, SyntheticRepo};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn refreshes_to_new_commits() {
+        config();
+        let origin = SyntheticRepo::open(&origin_path());
+        let initial = origin.head();
+        let meta = RwLock::new(Meta::new());
+
+        let data = refresh_data_internal(&meta, true).await.unwrap();
+        assert_eq!(data.assets_commit, initial.to_string());
+        assert_eq!(data.tracker.get(&GroupId(1)).unwrap().name, "Fixturemon");
+
+        origin.edit_tracker(|tracker| tracker["0001"]["name"] = json!("Refreshmon"));
+        let updated = origin.commit("Rename 0001");
+        let data = refresh_data_internal(&meta, true).await.unwrap();
+        assert_eq!(data.assets_commit, updated.to_string());
+        assert_eq!(data.tracker.get(&GroupId(1)).unwrap().name, "Refreshmon");
+        let meta = meta.read().unwrap();
+        assert_eq!(meta.assets_commit, updated.to_string());
+        assert_eq!(meta.previous_commit, Some(initial.to_string()));
+        assert!(meta.last_refresh_duration.is_some());
+        assert_eq!(meta.last_error, None);
+        let commit_time = Repository::open(origin_path())
+            .unwrap()
+            .find_commit(updated)
+            .unwrap()
+            .time()
+            .seconds();
+        assert_eq!(
+            meta.assets_update_date,
+            DateTime::from_timestamp(commit_time, 0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Helpers for tests that need a SpriteCollab repository: [`SyntheticRepo`] builds a git
+//! repository from the sample data in `tests/fixtures/spritecollab`, whose history tests can
+//! extend, and [`config`] initializes a configuration whose workdir contains a clone of one.
+//! [`context`] makes a GraphQL context for the sample data, whose cache is a [`MockCache`].
+
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once};
+
+use async_trait::async_trait;
+use fred::types::RedisKey;
+use git2::{IndexAddOption, Oid, Repository, RepositoryInitOptions, Signature, Time};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::assets::snapshots::fixtures_dir;
+use crate::cache::{CacheBehaviour, ScCache};
+use crate::datafiles::tracker::Tracker;
+use crate::schema::Context;
+use crate::sprite_collab::GIT_REPO_DIR;
+use crate::{ServerConfig, SpriteCollab};
+
+/// Time of the first commit of a synthetic repository, each further commit is a minute later.
+const FIRST_COMMIT_TIME: i64 = 1_700_000_000;
+/// The commit [`context`] serves.
+pub const FIXTURE_COMMIT: &str = "0123456789abcdef0123456789abcdef01234567";
+/// Credits of the portraits and sprites of monster 0001 in the synthetic repositories.
+const CREDITS: &str = "2023-11-14 22:13:20.000000\tFixtureAuthor\tCUR\tCC_BY-NC\tNormal,Happy\n";
+
+static CONFIG_INIT: Once = Once::new();
+/// Paths handed out by [`unique_temp_path`] in this process.
+static TEMP_PATHS: AtomicUsize = AtomicUsize::new(0);
+
+/// A git repository with the sample SpriteCollab data, on the branch `master`.
+pub struct SyntheticRepo {
+    path: PathBuf,
+    repo: Repository,
+}
+
+impl SyntheticRepo {
+    /// Creates the repository in the directory `name` in the temporary directory, replacing
+    /// it if it exists. The first commit contains the sample data and credits files for the
+    /// portraits and sprites of monster 0001.
+    pub fn create(name: &str) -> Self {
+        let path = std::env::temp_dir().join(name);
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        copy_dir(&fixtures_dir().join("spritecollab"), &path);
+        let repo =
+            Repository::init_opts(&path, RepositoryInitOptions::new().initial_head("master"))
+                .unwrap();
+        let slf = Self { path, repo };
+        slf.write("portrait/0001/credits.txt", CREDITS);
+        slf.write("sprite/0001/credits.txt", CREDITS);
+        slf.commit("Initial commit");
+        slf
+    }
+
+    /// Opens a repository created with [`SyntheticRepo::create`].
+    pub fn open(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            repo: Repository::open(path).unwrap(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes a file of the working tree, `path` is relative to the repository.
+    pub fn write<C: AsRef<[u8]>>(&self, path: &str, content: C) {
+        let path = self.path.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Removes a file of the working tree, `path` is relative to the repository.
+    pub fn remove(&self, path: &str) {
+        fs::remove_file(self.path.join(path)).unwrap();
+    }
+
+    /// Changes the `tracker.json` of the working tree. It is edited as JSON, so tests can
+    /// also write invalid trackers.
+    pub fn edit_tracker<F: FnOnce(&mut Value)>(&self, edit: F) {
+        let path = self.path.join("tracker.json");
+        let mut tracker: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        edit(&mut tracker);
+        fs::write(path, serde_json::to_vec_pretty(&tracker).unwrap()).unwrap();
+    }
+
+    /// The `tracker.json` of the working tree.
+    pub fn tracker(&self) -> Tracker {
+        serde_json::from_slice(&fs::read(self.path.join("tracker.json")).unwrap()).unwrap()
+    }
+
+    /// The ID of the last commit.
+    pub fn head(&self) -> Oid {
+        self.repo.head().unwrap().peel_to_commit().unwrap().id()
+    }
+
+    /// Commits all changes of the working tree and returns the ID of the commit.
+    pub fn commit(&self, message: &str) -> Oid {
+        let mut index = self.repo.index().unwrap();
+        index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+        index.update_all(["*"], None).unwrap();
+        index.write().unwrap();
+        let tree = self.repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parent = self
+            .repo
+            .head()
+            .ok()
+            .map(|head| head.peel_to_commit().unwrap());
+        let commits = parent.as_ref().map_or(0, |parent| {
+            let mut walk = self.repo.revwalk().unwrap();
+            walk.push(parent.id()).unwrap();
+            walk.count() as i64
+        });
+        let signature = Signature::new(
+            "Fixture Author",
+            "fixture@example.com",
+            &Time::new(FIRST_COMMIT_TIME + commits * 60, 0),
+        )
+        .unwrap();
+        self.repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parent.iter().collect::<Vec<_>>(),
+            )
+            .unwrap()
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()));
+        } else {
+            fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+/// A path in the temporary directory named after `name`, that no other test uses, even in
+/// concurrent test runs. Nothing is created at the path.
+pub fn unique_temp_path(name: &str) -> PathBuf {
+    let n = TEMP_PATHS.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("{}-{}-{}", name, std::process::id(), n))
+}
+
+/// The synthetic repository the workdir of [`config`] is cloned from, its `git_repo`.
+pub fn origin_path() -> PathBuf {
+    std::env::temp_dir().join("scsrv-tests").join("origin")
+}
+
+/// Initializes the configuration of the tests, the first time it is called. The workdir
+/// contains a clone of a new [`SyntheticRepo`] at [`origin_path`], so refreshes fetch from it.
+pub fn config() -> &'static ServerConfig {
+    CONFIG_INIT.call_once(|| {
+        let base = origin_path().parent().unwrap().to_path_buf();
+        if base.exists() {
+            fs::remove_dir_all(&base).unwrap();
+        }
+        let origin = SyntheticRepo::create("scsrv-tests/origin");
+        let workdir = base.join("workdir");
+        Repository::clone(origin.path().to_str().unwrap(), workdir.join(GIT_REPO_DIR)).unwrap();
+        ServerConfig::init_for_tests(&[
+            ("address", "http://localhost:3000"),
+            ("git_repo", origin.path().to_str().unwrap()),
+            (
+                "git_assets_url",
+                "https://raw.githubusercontent.com/PMDCollab/SpriteCollab/{ref}",
+            ),
+            ("workdir", workdir.to_str().unwrap()),
+            ("redis_host", "localhost"),
+            ("redis_port", "6379"),
+        ]);
+    });
+    ServerConfig::get()
+}
+
+/// A cache that stores its entries in memory, as JSON like Redis, and counts its hits and
+/// misses.
+#[derive(Default)]
+pub struct MockCache {
+    entries: Mutex<HashMap<String, String>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl MockCache {
+    /// Number of lookups that found an entry.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of lookups that found no entry.
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let value = self.entries.lock().unwrap().get(key).cloned();
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
+    }
+
+    pub fn set(&self, key: &str, value: String) {
+        self.entries.lock().unwrap().insert(key.to_string(), value);
+    }
+
+    /// Removes an entry, returns whether it existed.
+    pub fn remove(&self, key: &str) -> bool {
+        self.entries.lock().unwrap().remove(key).is_some()
+    }
+
+    /// All keys that start with `prefix`, sorted.
+    pub fn keys(&self, prefix: &str) -> Vec<String> {
+        let mut keys = self
+            .entries
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl ScCache for MockCache {
+    type Error = serde_json::Error;
+
+    async fn cached_may_fail<S, Fn, Ft, T, E>(
+        &self,
+        cache_key: S,
+        func: Fn,
+    ) -> Result<Result<T, E>, Self::Error>
+    where
+        S: AsRef<str> + Into<RedisKey> + Send + Sync,
+        Fn: (FnOnce() -> Ft) + Send,
+        Ft: Future<Output = Result<CacheBehaviour<T>, E>> + Send,
+        T: DeserializeOwned + Serialize + Send + Sync,
+        E: Send,
+    {
+        if let Some(value) = self.get(cache_key.as_ref()) {
+            return Ok(Ok(serde_json::from_str(&value)?));
+        }
+        match func().await {
+            Ok(CacheBehaviour::Cache(v)) => {
+                self.set(cache_key.as_ref(), serde_json::to_string(&v)?);
+                Ok(Ok(v))
+            }
+            Ok(CacheBehaviour::NoCache(v)) => Ok(Ok(v)),
+            Err(e) => Ok(Err(e)),
+        }
+    }
+
+    async fn cache_keys(&self, prefix: &str) -> Result<Vec<String>, Self::Error> {
+        Ok(self.keys(prefix))
+    }
+
+    async fn evict(&self, cache_key: &str) -> Result<bool, Self::Error> {
+        Ok(self.remove(cache_key))
+    }
+}
+
+/// A GraphQL context that serves the sample data, with `edit_tracker` applied to its tracker
+/// (as JSON), and the cache of its [`SpriteCollab`].
+pub async fn context<F: FnOnce(&mut Value)>(edit_tracker: F) -> (Context, Arc<MockCache>) {
+    config();
+    let mut tracker: Value = serde_json::from_slice(
+        &fs::read(fixtures_dir().join("spritecollab").join("tracker.json")).unwrap(),
+    )
+    .unwrap();
+    edit_tracker(&mut tracker);
+    let cache = Arc::new(MockCache::default());
+    let collab =
+        SpriteCollab::for_tests(serde_json::from_value(tracker).unwrap(), cache.clone()).await;
+    (Context::new(collab), cache)
+}
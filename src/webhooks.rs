@@ -0,0 +1,237 @@
+//! Outgoing webhooks, configured with `SCSRV_WEBHOOK_URLS`: After a refresh to a new commit
+//! with activities, they are `POST`ed as JSON to every URL, either all activities of the commit
+//! at once (`SCSRV_WEBHOOK_MODE=batch`, the default) or one request per activity
+//! (`SCSRV_WEBHOOK_MODE=event`), so external services can react without polling.
+//!
+//! If `SCSRV_WEBHOOK_SECRET` is set, the body is signed with it: The header
+//! `X-SC-Signature-256` is `sha256=` followed by the hex encoded HMAC-SHA256 of the body.
+//!
+//! Failed deliveries (connection errors, timeouts and responses other than `2xx`) are retried
+//! with a backoff. Payloads that still can't be delivered are logged and dropped.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Method, Request, Uri};
+use log::{info, warn};
+use serde::Serialize;
+use tokio::time::{sleep, timeout};
+use url::Url;
+
+use crate::activity::ExportedActivity;
+use crate::assets::signed_urls::hmac_sha256;
+use crate::http_client::http_client;
+use crate::ServerConfig;
+
+pub const SIGNATURE_HEADER: &str = "X-SC-Signature-256";
+pub const EVENT_HEADER: &str = "X-SC-Event";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of retries of a failed delivery, the delay doubles after each one.
+const RETRIES: u32 = 4;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum WebhookMode {
+    /// One request with all activities of a commit.
+    #[default]
+    Batch,
+    /// One request per activity.
+    Event,
+}
+
+impl FromStr for WebhookMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "batch" => Ok(WebhookMode::Batch),
+            "event" => Ok(WebhookMode::Event),
+            _ => Err(format!("expected batch or event, got '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum WebhookPayload {
+    /// All activities of a new commit.
+    #[serde(rename_all = "camelCase")]
+    Activities {
+        commit: String,
+        previous_commit: String,
+        activities: Vec<ExportedActivity>,
+    },
+    /// A single activity of a new commit.
+    #[serde(rename_all = "camelCase")]
+    Activity {
+        commit: String,
+        previous_commit: String,
+        activity: ExportedActivity,
+    },
+}
+
+impl WebhookPayload {
+    /// The payloads of the activities of a commit, in the configured mode.
+    pub fn of_activities(
+        mode: WebhookMode,
+        commit: &str,
+        previous_commit: &str,
+        activities: Vec<ExportedActivity>,
+    ) -> Vec<Self> {
+        match mode {
+            WebhookMode::Batch => vec![WebhookPayload::Activities {
+                commit: commit.to_string(),
+                previous_commit: previous_commit.to_string(),
+                activities,
+            }],
+            WebhookMode::Event => activities
+                .into_iter()
+                .map(|activity| WebhookPayload::Activity {
+                    commit: commit.to_string(),
+                    previous_commit: previous_commit.to_string(),
+                    activity,
+                })
+                .collect(),
+        }
+    }
+
+    fn event(&self) -> &'static str {
+        match self {
+            WebhookPayload::Activities { .. } => "activities",
+            WebhookPayload::Activity { .. } => "activity",
+        }
+    }
+}
+
+/// Sends the activities of a new commit to all webhooks in the background. Each webhook gets
+/// the payloads in order.
+pub fn send_activities(commit: &str, previous_commit: &str, activities: Vec<ExportedActivity>) {
+    let config = ServerConfig::get();
+    if config.webhook_urls.is_empty() || activities.is_empty() {
+        return;
+    }
+    let payloads =
+        WebhookPayload::of_activities(config.webhook_mode, commit, previous_commit, activities);
+    for url in &config.webhook_urls {
+        let url = url.clone();
+        let payloads = payloads.clone();
+        tokio::spawn(async move {
+            for payload in payloads {
+                deliver(&url, &payload).await;
+            }
+        });
+    }
+}
+
+/// Delivers a payload, with retries.
+async fn deliver(url: &Url, payload: &WebhookPayload) {
+    let body = match serde_json::to_string(payload) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed serializing a webhook payload: {}", e);
+            return;
+        }
+    };
+    let mut delay = RETRY_DELAY;
+    for attempt in 0..=RETRIES {
+        match post(url, payload.event(), &body).await {
+            Ok(()) => {
+                if attempt > 0 {
+                    info!("Webhook to {} delivered after {} retries.", url, attempt);
+                }
+                return;
+            }
+            Err(e) if attempt < RETRIES => {
+                warn!(
+                    "Webhook to {} failed, retrying in {} seconds: {}",
+                    url,
+                    delay.as_secs(),
+                    e
+                );
+                sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => {
+                warn!(
+                    "Webhook to {} failed {} times, dropping it: {}. Payload: {}",
+                    url,
+                    RETRIES + 1,
+                    e,
+                    body
+                );
+            }
+        }
+    }
+}
+
+async fn post(url: &Url, event: &str, body: &str) -> Result<(), Error> {
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(url.as_str().parse::<Uri>()?)
+        .header(CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, event);
+    if let Some(secret) = &ServerConfig::get().webhook_secret {
+        request = request.header(SIGNATURE_HEADER, signature(secret, body));
+    }
+    let request = request.body(Full::new(Bytes::from(body.to_string())))?;
+    let response = timeout(REQUEST_TIMEOUT, http_client().request(request))
+        .await
+        .map_err(|_| anyhow!("timed out"))??;
+    let status = response.status();
+    // Read the body, so the connection can be reused.
+    response.into_body().collect().await?;
+    if !status.is_success() {
+        return Err(anyhow!("the webhook returned {}", status));
+    }
+    Ok(())
+}
+
+/// The value of the [`SIGNATURE_HEADER`].
+pub fn signature(secret: &str, body: &str) -> String {
+    format!(
+        "sha256={:x}",
+        hmac_sha256(secret.as_bytes(), body.as_bytes())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::fs_check::AssetCategory;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn makes_payloads_in_mode() {
+        let activity = ExportedActivity {
+            commit: "abc".to_string(),
+            monster_id: 25,
+            form_path: "0025".to_string(),
+            category: AssetCategory::Portrait,
+            credit: "1234".to_string(),
+            secondary_credits: vec![],
+            modified_date: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+        };
+        let activities = vec![activity.clone(), activity];
+
+        let batch =
+            WebhookPayload::of_activities(WebhookMode::Batch, "abc", "def", activities.clone());
+        assert_eq!(batch.len(), 1);
+        let json = serde_json::to_value(&batch[0]).unwrap();
+        assert_eq!(json["type"], "activities");
+        assert_eq!(json["previousCommit"], "def");
+        assert_eq!(json["activities"][0]["monsterId"], 25);
+
+        let events = WebhookPayload::of_activities(WebhookMode::Event, "abc", "def", activities);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event(), "activity");
+
+        assert_eq!(
+            signature("key", "The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}